@@ -17,28 +17,226 @@ export type BlockDefinition = {
   name: string,
   description: string,
   textures: Array<string>,
-  drawtype: number
+  drawtype: number,
+  -- Can players walk on it. (default true)
+  walkable: boolean?,
+  -- How much light it gives off, 0 to 14. (default 0)
+  light_source: number?,
+  -- "light" lets light through it, "none" doesn't. (default "none", air drawtype "light")
+  paramtype: string?,
+  -- Sunlight goes straight down through it without fading. (default false, air drawtype true)
+  sunlight_propagates: boolean?,
+  -- "none", "source" or "flowing". (default "none")
+  liquidtype: string?,
+  -- The flowing and source nodes of the liquid, liquids need both.
+  liquid_alternative_flowing: string?,
+  liquid_alternative_source: string?,
+  -- How many nodes it flows away from a source, 0 to 8. (default 8)
+  liquid_range: number?,
+  -- Two sources make a new one between them. (default true)
+  liquid_renewable: boolean?,
+  -- Like {cracky = 3, stone = 1}.
+  groups: {[string] : number}?,
+  -- Runs when the node's timer goes off, returning true starts it over.
+  on_timer: ((pos: Vector, elapsed: number) -> boolean?)?
+}
+
+export type ToolGroupCapability = {
+  -- How long it takes to dig each rating of the group, like {[1] = 3.0, [2] = 1.5}.
+  times: {[number] : number},
+  -- How many nodes it can dig before breaking, 0 is forever. (default 20)
+  uses: number?,
+  maxlevel: number?
+}
+
+export type ToolCapabilities = {
+  full_punch_interval: number?,
+  max_drop_level: number?,
+  groupcaps: {[string] : ToolGroupCapability}?,
+  damage_groups: {[string] : number}?
 }
 
 export type ItemDefinition = {
   name: string,
   description: string,
-  readable_name: string,
-  textures: Array<string>,
-  drawtype: number
+  -- "craftitem" or "tool". (default "craftitem")
+  type: string?,
+  -- How many fit in one stack. (default 99, tools 1)
+  stack_max: number?,
+  inventory_image: string?,
+  -- Falls back to inventory_image.
+  wield_image: string?,
+  tool_capabilities: ToolCapabilities?,
+  groups: {[string] : number}?
+}
+
+export type Vector = {x: number, y: number, z: number}
+
+export type Node = {
+  name: string,
+  param1: number?,
+  param2: number?
+}
+
+export type AbmDefinition = {
+  -- Shows up in errors.
+  label: string?,
+  -- Names and groups, like {"default:dirt", "group:soil"}.
+  nodenames: string | Array<string>,
+  -- If there are any, one of them has to be right around the node.
+  neighbors: (string | Array<string>)?,
+  -- In seconds. (default 10)
+  interval: number?,
+  -- Every matching node has a 1 in chance shot each interval. (default 50)
+  chance: number?,
+  action: (pos: Vector, node: Node, active_object_count: number, active_object_count_wider: number) -> nil
+}
+
+export type LbmDefinition = {
+  -- Like "mymod:convert_old_dirt".
+  name: string,
+  nodenames: string | Array<string>,
+  -- Otherwise it only runs on blocks last loaded before the LBM existed. (default false)
+  run_at_every_load: boolean?,
+  -- dtime_s is how long the block was unloaded for, in seconds.
+  action: (pos: Vector, node: Node, dtime_s: number) -> nil
 }
 
+export type NodeTimerRef = {
+  set: (self: NodeTimerRef, timeout: number, elapsed: number) -> (),
+  start: (self: NodeTimerRef, timeout: number) -> (),
+  stop: (self: NodeTimerRef) -> (),
+  get_timeout: (self: NodeTimerRef) -> number,
+  get_elapsed: (self: NodeTimerRef) -> number,
+  is_started: (self: NodeTimerRef) -> boolean
+}
+
+export type NoiseParams = {
+  offset: number?,
+  scale: number?,
+  spread: Vector?,
+  seed: number?,
+  octaves: number?,
+  -- C++ minetest's persist works too.
+  persistence: number?,
+  lacunarity: number?,
+  -- "perlin" or "simplex". (default "perlin")
+  noise_type: string?
+}
+
+export type BiomeDefinition = {
+  name: string,
+  -- Nodes default to the mapgen_ aliases.
+  node_top: string?,
+  depth_top: number?,
+  node_filler: string?,
+  depth_filler: number?,
+  node_stone: string?,
+  y_min: number?,
+  y_max: number?,
+  -- Roughly 0 to 100, the closest biome to a spot's heat and humidity wins.
+  heat_point: number?,
+  humidity_point: number?
+}
+
+export type OreDefinition = {
+  -- "scatter", "sheet" or "blob". (default "scatter")
+  ore_type: string?,
+  ore: string,
+  wherein: string | Array<string>,
+  -- One cluster per this many nodes.
+  clust_scarcity: number?,
+  clust_num_ores: number?,
+  clust_size: number?,
+  -- How thick a sheet is.
+  column_height: number?,
+  y_min: number?,
+  y_max: number?,
+  noise_params: NoiseParams?,
+  noise_threshold: number?
+}
+
+export type SchematicNode = {
+  name: string,
+  -- 0 is never placed, 255 is always. (default 255)
+  prob: number?,
+  param2: number?,
+  force_place: boolean?
+}
+
+export type SchematicSlice = {
+  -- From the bottom of the schematic, starting at 0.
+  ypos: number,
+  prob: number
+}
+
+export type Schematic = {
+  size: Vector,
+  -- In z, y, x order.
+  data: Array<SchematicNode>,
+  yslice_prob: Array<SchematicSlice>?
+}
+
+export type SchematicProbability = {
+  pos: Vector,
+  prob: number
+}
+
+export type DecorationDefinition = {
+  -- "simple" or "schematic". (default "simple")
+  deco_type: string?,
+  place_on: string | Array<string>,
+  -- Chance of one on every surface node, 0 to 1.
+  fill_ratio: number?,
+  biomes: (string | Array<string>)?,
+  y_min: number?,
+  y_max: number?,
+  decoration: (string | Array<string>)?,
+  height: number?,
+  height_max: number?,
+  -- A table, or the path to a .mts or .json file, relative to the world or game folder.
+  schematic: (Schematic | string)?,
+  -- Like "place_center_x, place_center_z".
+  flags: string?
+}
+
+export type PrivilegeDefinition = {
+  description: string,
+  give_to_singleplayer: boolean?
+}
+
+-- Privileges as a set, like {shout = true, interact = true}.
+export type PrivilegeSet = {[string] : boolean}
+
 -- A fancy closure.
 export type OnTick = (delta: number) -> nil
+export type OnJoinPlayer = (name: string) -> nil
+export type OnLeavePlayer = (name: string, timed_out: boolean) -> nil
 
 -- Singleton instances of raw data.
-_G.blocks  = _G.blocks  or {}
-_G.items   = _G.items   or {}
-_G.on_tick = _G.on_tick or {}
+_G.blocks          = _G.blocks          or {}
+_G.items           = _G.items           or {}
+_G.aliases         = _G.aliases         or {}
+_G.biomes          = _G.biomes          or {}
+_G.ores            = _G.ores            or {}
+_G.decorations     = _G.decorations     or {}
+_G.abms            = _G.abms            or {}
+_G.lbms            = _G.lbms            or {}
+_G.on_tick         = _G.on_tick         or {}
+_G.on_join_player  = _G.on_join_player  or {}
+_G.on_leave_player = _G.on_leave_player or {}
 
-local blocks:  {[string] : BlockDefinition} = _G.blocks
-local items:   {[string] : ItemDefinition}  = _G.items
-local on_tick: Array<OnTick>                = _G.on_tick
+local blocks:          {[string] : BlockDefinition} = _G.blocks
+local items:           {[string] : ItemDefinition}  = _G.items
+local aliases:         {[string] : string}          = _G.aliases
+local biomes:          Array<BiomeDefinition>       = _G.biomes
+local ores:            Array<OreDefinition>         = _G.ores
+local decorations:     Array<DecorationDefinition>  = _G.decorations
+local abms:            Array<AbmDefinition>         = _G.abms
+local lbms:            Array<LbmDefinition>         = _G.lbms
+local on_tick:         Array<OnTick>                = _G.on_tick
+local on_join_player:  Array<OnJoinPlayer>          = _G.on_join_player
+local on_leave_player: Array<OnLeavePlayer>         = _G.on_leave_player
 
 ----------
 -- Now we can ship the rest of the codebase back to the mod as a module.
@@ -69,14 +267,206 @@ end
 
 function minetest.register_item(definition: ItemDefinition)
   if (items[definition.name] ~= nil) then
-    error("error: " .. definition.name .. " is already a registered ")
+    error(definition.name .. " is already a registered item.")
+  end
+  if (blocks[definition.name] ~= nil) then
+    error(definition.name .. " is already a registered block.")
   end
+  items[definition.name] = definition
+  print("minetest: registered item [" .. definition.name .. "]")
+end
+
+function minetest.register_craftitem(definition: ItemDefinition)
+  definition.type = "craftitem"
+  minetest.register_item(definition)
+end
+
+function minetest.register_tool(definition: ItemDefinition)
+  definition.type = "tool"
+  definition.stack_max = definition.stack_max or 1
+  minetest.register_item(definition)
+end
+
+-- Another name for a block, like "mapgen_stone" for the stone the map is made of.
+function minetest.register_alias(alias: string, original: string)
+  if (aliases[alias] ~= nil) then
+    error(alias .. " is already a registered alias.")
+  end
+  aliases[alias] = original
+end
+
+function minetest.register_biome(definition: BiomeDefinition)
+  for _, biome in ipairs(biomes) do
+    if (biome.name == definition.name) then
+      error(definition.name .. " is already a registered biome.")
+    end
+  end
+  insert(biomes, definition)
+  print("minetest: registered biome [" .. definition.name .. "]")
+end
+
+-- Ores and decorations are placed in the order they're registered.
+function minetest.register_ore(definition: OreDefinition)
+  insert(ores, definition)
+end
+
+function minetest.register_decoration(definition: DecorationDefinition)
+  insert(decorations, definition)
+end
+
+-- The server runs these on the blocks around players.
+function minetest.register_abm(definition: AbmDefinition)
+  insert(abms, definition)
+end
+
+function minetest.register_lbm(definition: LbmDefinition)
+  for _, lbm in ipairs(lbms) do
+    if (lbm.name == definition.name) then
+      error(definition.name .. " is already a registered LBM.")
+    end
+  end
+  insert(lbms, definition)
 end
 
 function minetest.register_on_tick(tick_closure: OnTick)
   insert(on_tick, tick_closure)
 end
 
+function minetest.register_on_joinplayer(join_closure: OnJoinPlayer)
+  insert(on_join_player, join_closure)
+end
+
+function minetest.register_on_leaveplayer(leave_closure: OnLeavePlayer)
+  insert(on_leave_player, leave_closure)
+end
+
+----------
+-- Privileges.
+-- These are stored by the engine, so they only exist on the server.
+
+local function get_engine_function(name: string): any
+  local func = _G[name]
+  if (func == nil) then
+    error("minetest: " .. name .. " is only available on the server.")
+  end
+  return func
+end
+
+-- Accepts both {shout = true} and {"shout"}.
+local function privileges_to_array(privileges: PrivilegeSet | Array<string>): Array<string>
+  local array: Array<string> = {}
+  for key, value in pairs(privileges) do
+    if (type(key) == "number") then
+      insert(array, value)
+    elseif (value) then
+      insert(array, key)
+    end
+  end
+  return array
+end
+
+local function array_to_privileges(array: Array<string>): PrivilegeSet
+  local privileges: PrivilegeSet = {}
+  for _,name in ipairs(array) do
+    privileges[name] = true
+  end
+  return privileges
+end
+
+function minetest.register_privilege(name: string, definition: PrivilegeDefinition | string)
+  if (type(definition) == "string") then
+    definition = {description = definition}
+  end
+
+  local give_to_singleplayer = definition.give_to_singleplayer
+  if (give_to_singleplayer == nil) then
+    give_to_singleplayer = true
+  end
+
+  get_engine_function("engine_register_privilege")(name, definition.description or "", give_to_singleplayer)
+end
+
+function minetest.get_player_privs(name: string): PrivilegeSet
+  return array_to_privileges(get_engine_function("engine_get_player_privs")(name))
+end
+
+-- Returns if the player has all of them, and which ones they're missing.
+function minetest.check_player_privs(name: string, privileges: PrivilegeSet | Array<string>): (boolean, Array<string>)
+  return get_engine_function("engine_check_player_privs")(name, privileges_to_array(privileges))
+end
+
+-- Replaces all of the player's privileges.
+function minetest.set_player_privs(name: string, privileges: PrivilegeSet)
+  get_engine_function("engine_set_player_privs")(name, privileges_to_array(privileges))
+end
+
+
+----------
+-- Schematics.
+-- These write into the map, so they only exist on the server.
+
+-- Places the schematic with it's smallest corner at pos, only over air unless forced.
+-- rotation is "0", "90", "180", "270" or "random". (default "0")
+-- replacements swaps names, like {["default:tree"] = "default:jungletree"}.
+-- A schematic path is relative to the world or game folder.
+function minetest.place_schematic(pos: Vector, schematic: Schematic | string, rotation: string?, replacements: {[string] : string}?, force_placement: boolean?): boolean
+  return get_engine_function("engine_place_schematic")(pos, schematic, rotation, replacements, force_placement)
+end
+
+-- Saves the nodes between p1 and p2 to a .mts file, or a .json file.
+-- filename is relative to the world's schems folder, so placing it again is "schems/" .. filename.
+function minetest.create_schematic(p1: Vector, p2: Vector, probability_list: Array<SchematicProbability>?, filename: string, slice_prob_list: Array<SchematicSlice>?): boolean
+  return get_engine_function("engine_create_schematic")(p1, p2, probability_list, filename, slice_prob_list)
+end
+
+
+----------
+-- Nodes.
+-- These read and write the map, so they only exist on the server.
+
+-- Nodes in blocks which aren't loaded are "ignore".
+function minetest.get_node(pos: Vector): Node
+  local name, param1, param2 = get_engine_function("engine_get_node")(pos)
+  return {name = name, param1 = param1, param2 = param2}
+end
+
+-- Returns false if the block it's in isn't loaded.
+function minetest.set_node(pos: Vector, node: Node): boolean
+  return get_engine_function("engine_set_node")(pos, node.name, node.param1 or 0, node.param2 or 0)
+end
+
+function minetest.remove_node(pos: Vector): boolean
+  return minetest.set_node(pos, {name = "air"})
+end
+
+-- Timers are saved with the map, and stop when the node is replaced.
+function minetest.get_node_timer(pos: Vector): NodeTimerRef
+  local timer: NodeTimerRef = {
+    set = function(_self: NodeTimerRef, timeout: number, elapsed: number)
+      get_engine_function("engine_set_node_timer")(pos, timeout, elapsed)
+    end,
+    start = function(self: NodeTimerRef, timeout: number)
+      self:set(timeout, 0)
+    end,
+    stop = function(_self: NodeTimerRef)
+      get_engine_function("engine_stop_node_timer")(pos)
+    end,
+    get_timeout = function(_self: NodeTimerRef): number
+      local timeout, _, _ = get_engine_function("engine_get_node_timer")(pos)
+      return timeout
+    end,
+    get_elapsed = function(_self: NodeTimerRef): number
+      local _, elapsed, _ = get_engine_function("engine_get_node_timer")(pos)
+      return elapsed
+    end,
+    is_started = function(_self: NodeTimerRef): boolean
+      local _, _, started = get_engine_function("engine_get_node_timer")(pos)
+      return started
+    end
+  }
+  return timer
+end
+
 
 ----------
 -- API is returned as a module.
@@ -44,6 +44,11 @@ local minetest = require("api/api")
 local old_time_stamp: number = clock()
 
 local on_tick: minetest.Array<minetest.OnTick> = _G.on_tick
+local on_join_player: minetest.Array<minetest.OnJoinPlayer> = _G.on_join_player
+local on_leave_player: minetest.Array<minetest.OnLeavePlayer> = _G.on_leave_player
+local abms: minetest.Array<minetest.AbmDefinition> = _G.abms
+local lbms: minetest.Array<minetest.LbmDefinition> = _G.lbms
+local blocks: {[string] : minetest.BlockDefinition} = _G.blocks
 
 local function do_on_tick(delta: number)
   for _,func in ipairs(on_tick) do
@@ -61,4 +66,33 @@ _G.engine_on_tick_function = function(delta: number)
   do_on_tick(delta)
 
   old_time_stamp = time_stamp
+end
+
+_G.engine_on_join_player = function(name: string)
+  for _,func in ipairs(on_join_player) do
+    func(name)
+  end
+end
+
+_G.engine_on_leave_player = function(name: string, timed_out: boolean)
+  for _,func in ipairs(on_leave_player) do
+    func(name, timed_out)
+  end
+end
+
+_G.engine_run_abm = function(index: number, x: number, y: number, z: number, name: string, param1: number, param2: number)
+  -- There are no objects yet, so there are never any around.
+  abms[index].action({x = x, y = y, z = z}, {name = name, param1 = param1, param2 = param2}, 0, 0)
+end
+
+_G.engine_run_lbm = function(index: number, x: number, y: number, z: number, name: string, param1: number, param2: number, dtime_s: number)
+  lbms[index].action({x = x, y = y, z = z}, {name = name, param1 = param1, param2 = param2}, dtime_s)
+end
+
+_G.engine_run_node_timer = function(x: number, y: number, z: number, name: string, elapsed: number): boolean
+  local definition = blocks[name]
+  if (definition == nil or definition.on_timer == nil) then
+    return false
+  end
+  return definition.on_timer({x = x, y = y, z = z}, elapsed) == true
 end
\ No newline at end of file
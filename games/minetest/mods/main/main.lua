@@ -25,4 +25,9 @@ minetest.register_block({
   textures = {"default_stone.png"}
 })
 
+-- What the map generator builds the world out of.
+minetest.register_alias("mapgen_stone", "minetest:stone")
+minetest.register_alias("mapgen_dirt", "minetest:dirt")
+minetest.register_alias("mapgen_dirt_with_grass", "minetest:grass")
+
 print("lua: minetest/main loaded")
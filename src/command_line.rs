@@ -21,19 +21,31 @@ pub struct CommandLineInterface {
   #[arg(short, long, default_value_t = false)]
   pub server: bool,
 
-  /// Start server with a specific game.
-  #[arg(short, long, default_value_t = String::from("minetest"))]
-  pub game: String,
+  /// Run minetest in singleplayer, with a server running inside of the client.
+  #[arg(long, default_value_t = false)]
+  pub singleplayer: bool,
 
-  /// Start the server on a specific address.
-  #[arg(short, long, default_value_t = String::from("127.0.0.1"))]
-  pub address: String,
+  /// Start server with a specific game. (defaults to default_game in minetest.conf)
+  #[arg(short, long)]
+  pub game: Option<String>,
 
-  /// Start server on a specific port.
-  #[arg(short, long, default_value_t = 30_001)]
-  pub port: i32,
+  /// Start the server on a specific address. (defaults to address in minetest.conf)
+  #[arg(short, long)]
+  pub address: Option<String>,
 
-  /// The default name for your player. (this is a placholder)
-  #[arg(short, long, default_value_t = String::from("singleplayer"))]
-  pub client_name: String,
+  /// Start server on a specific port. (defaults to port in minetest.conf)
+  #[arg(short, long)]
+  pub port: Option<i32>,
+
+  /// The default name for your player. (defaults to name in minetest.conf)
+  #[arg(short, long)]
+  pub client_name: Option<String>,
+
+  /// The password for your player. (never read from or saved into minetest.conf)
+  #[arg(long)]
+  pub password: Option<String>,
+
+  /// Use a specific minetest.conf file.
+  #[arg(long, default_value_t = String::from("minetest.conf"))]
+  pub config: String,
 }
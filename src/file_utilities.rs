@@ -1,7 +1,7 @@
 use std::{
   fs::{self, File},
   io::BufReader,
-  path::Path,
+  path::{Component, Path, PathBuf},
 };
 
 ///
@@ -88,3 +88,142 @@ pub fn read_path_to_buf_read(path: &str) -> Result<BufReader<File>, String> {
     Err(e) => Err(format!("Path to BufReader failure. {}", e)),
   }
 }
+
+///
+/// Check that a path from a mod can't point outside of the folder it's in.
+///
+/// Absolute paths and ".." are refused.
+///
+fn check_mod_path(path: &str) -> Result<(), String> {
+  let is_relative = Path::new(path)
+    .components()
+    .all(|component| matches!(component, Component::Normal(_)));
+
+  match !path.is_empty() && is_relative {
+    true => Ok(()),
+    false => Err(format!(
+      "Mod path [{}] has to be relative, without any \"..\".",
+      path
+    )),
+  }
+}
+
+///
+/// Find a file a mod wants to read, relative to the folders it may read from.
+///
+/// The first folder it's in wins. Symlinks leading out of the
+/// folder don't count, the real path has to be inside of it.
+///
+pub fn find_mod_file(path: &str, directories: &[String]) -> Result<PathBuf, String> {
+  check_mod_path(path)?;
+
+  for directory in directories {
+    let root = match fs::canonicalize(directory) {
+      Ok(root) => root,
+      Err(_) => continue,
+    };
+
+    if let Ok(file) = fs::canonicalize(root.join(path)) {
+      if file.starts_with(&root) {
+        return Ok(file);
+      }
+    }
+  }
+
+  Err(format!(
+    "Mod path [{}] is not in any of [{}].",
+    path,
+    directories.join(", ")
+  ))
+}
+
+///
+/// Get where a file a mod wants to write goes, relative to directory.
+///
+/// The folders it needs get created, and like find_mod_file()
+/// it can't end up outside of directory through symlinks.
+///
+pub fn get_mod_write_path(path: &str, directory: &str) -> Result<PathBuf, String> {
+  check_mod_path(path)?;
+
+  let file = Path::new(directory).join(path);
+  let parent = match file.parent() {
+    Some(parent) => parent,
+    None => return Err(format!("Mod path [{}] has no folder.", path)),
+  };
+
+  if let Err(e) = fs::create_dir_all(parent) {
+    return Err(format!(
+      "Failed to create folder [{}]. {}",
+      parent.display(),
+      e
+    ));
+  }
+
+  let (root, real_parent) = match (fs::canonicalize(directory), fs::canonicalize(parent)) {
+    (Ok(root), Ok(real_parent)) => (root, real_parent),
+    (Err(e), _) | (_, Err(e)) => return Err(format!("Mod path [{}] is broken. {}", path, e)),
+  };
+
+  let is_symlink = fs::symlink_metadata(&file)
+    .map(|metadata| metadata.file_type().is_symlink())
+    .unwrap_or(false);
+
+  match real_parent.starts_with(&root) && !is_symlink {
+    true => Ok(file),
+    false => Err(format!(
+      "Mod path [{}] leads outside of [{}].",
+      path, directory
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::{find_mod_file, get_mod_write_path};
+
+  #[test]
+  fn test_mod_paths() {
+    let folder = std::env::temp_dir().join(format!("mod_path_test_{}", std::process::id()));
+    let world = folder.join("world");
+    let game = folder.join("game");
+    if let Err(e) = fs::create_dir_all(game.join("mods/test")) {
+      panic!("Unit test is broken. {}", e);
+    }
+    if let Err(e) = fs::write(game.join("mods/test/tree.mts"), b"tree") {
+      panic!("Unit test is broken. {}", e);
+    }
+    if let Err(e) = fs::write(folder.join("secret.txt"), b"secret") {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    let world = world.to_string_lossy().to_string();
+    let directories = vec![world.clone(), game.to_string_lossy().to_string()];
+
+    // Writes go inside the folder, making it if needed.
+    let written = match get_mod_write_path("schems/house.mts", &world) {
+      Ok(written) => written,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    if let Err(e) = fs::write(&written, b"house") {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    // Reads look in every folder.
+    assert!(find_mod_file("schems/house.mts", &directories).is_ok());
+    assert!(find_mod_file("mods/test/tree.mts", &directories).is_ok());
+    assert!(find_mod_file("mods/test/missing.mts", &directories).is_err());
+
+    // Nothing outside of them.
+    for path in ["../secret.txt", "mods/../../secret.txt", "", "/etc/passwd"] {
+      assert!(find_mod_file(path, &directories).is_err());
+      assert!(get_mod_write_path(path, &world).is_err());
+    }
+    let secret = folder.join("secret.txt").to_string_lossy().to_string();
+    assert!(find_mod_file(&secret, &directories).is_err());
+
+    let _ = fs::remove_dir_all(&folder);
+  }
+}
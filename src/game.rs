@@ -1,7 +1,15 @@
 mod client;
+mod database;
 mod delta_reporter;
+mod item;
 mod lua_engine;
+mod map;
+mod network;
+mod protocol;
+mod serial;
 mod server;
+mod settings;
+mod srp;
 
 use core::panic;
 use std::{
@@ -14,7 +22,10 @@ use spin_sleep_util::{interval, Interval, RateReporter};
 
 use crate::command_line::CommandLineInterface;
 
-use self::{client::Client, delta_reporter::DeltaReporter, server::Server};
+use self::{
+  client::Client, delta_reporter::DeltaReporter, network::loopback_transport::LoopbackTransport,
+  server::Server, settings::Settings,
+};
 
 ///
 /// The master container for the game.
@@ -38,6 +49,8 @@ use self::{client::Client, delta_reporter::DeltaReporter, server::Server};
 pub struct Game {
   should_close: Arc<RwLock<bool>>,
 
+  settings: Settings,
+
   goal_frames_per_second: f64,
   goal_ticks_per_second: f64,
 
@@ -47,6 +60,10 @@ pub struct Game {
   is_server: bool,
   is_client: bool,
 
+  // In singleplayer the loop runs at the FPS goal, so the
+  // Server collects delta time until it's due for a tick.
+  server_tick_accumulator: f64,
+
   interval: Interval,
   fps_reporter: RateReporter,
   delta_reporter: DeltaReporter,
@@ -69,11 +86,17 @@ impl Game {
     // Set up the environment logger.
     env_logger::init();
 
-    // 60 FPS goal for the moment.
-    let goal_frames_per_second = 60.0;
+    // Load up minetest.conf, then let the command line override it.
+    let mut settings = Settings::new(&cli.config);
+    settings.load();
+    settings.apply_command_line(&cli);
+
+    let goal_frames_per_second = settings.get_float("fps_max");
+    let goal_ticks_per_second = settings.get_float("tps_max");
+    let vsync_mode = settings.get_int("vsync").clamp(0, 3) as i8;
 
-    // 20 Tick Per Second goal.
-    let goal_ticks_per_second = 20.0;
+    // A dedicated server can't also be singleplayer.
+    let singleplayer = cli.singleplayer && !cli.server;
 
     let loop_helper_goal = match cli.server {
       true => goal_ticks_per_second,
@@ -84,12 +107,11 @@ impl Game {
     let fps_reporter = RateReporter::new(Duration::from_secs(1));
     let delta_reporter = DeltaReporter::new();
 
-    //todo: make this happen!
-    println!("we need a minetest.conf parser for vsync!");
-
     let mut new_game = Game {
       should_close: Arc::new(RwLock::new(false)),
 
+      settings,
+
       goal_frames_per_second,
       goal_ticks_per_second,
 
@@ -101,7 +123,10 @@ impl Game {
       is_client: !cli.server,
 
       // If this is a server we don't do any client things.
-      is_server: cli.server,
+      // Singleplayer runs both.
+      is_server: cli.server || singleplayer,
+
+      server_tick_accumulator: 0.0,
 
       interval,
       fps_reporter,
@@ -110,21 +135,33 @@ impl Game {
       delta: 0.0,
       current_fps: 0.0,
 
-      //todo: fix this when the minetest.conf parser is implemented
-      vsync_mode: 0,
+      vsync_mode,
     };
 
-    // We could parse the player's name instead from a file, or a first time ask. This is mutable after all.
-    new_game.client = match cli.server {
-      false => Some(Client::new(cli.client_name, cli.address.clone(), cli.port)),
-      true => None,
-    };
+    if singleplayer {
+      // Singleplayer: the Server and Client talk through memory, no UDP port needed.
+      let (server_transport, client_transport) = LoopbackTransport::new_pair();
+      new_game.server = Some(Server::new_singleplayer(
+        &new_game.settings,
+        server_transport,
+      ));
+      new_game.client = Some(Client::new_singleplayer(
+        &new_game.settings,
+        client_transport,
+      ));
+    } else {
+      // The player's name comes from minetest.conf or the command line. This is mutable after all.
+      new_game.client = match cli.server {
+        false => Some(Client::new(&new_game.settings)),
+        true => None,
+      };
 
-    // Can auto deploy server and treat this struct like a simplified dispatcher.
-    new_game.server = match cli.server {
-      true => Some(Server::new(cli.address, cli.port, cli.game)),
-      false => None,
-    };
+      // Can auto deploy server and treat this struct like a simplified dispatcher.
+      new_game.server = match cli.server {
+        true => Some(Server::new(&new_game.settings)),
+        false => None,
+      };
+    }
 
     // Automatically elegantly stops the game when CTRL+C is hit or user terminates the process.
 
@@ -156,6 +193,35 @@ impl Game {
       .set_period(Duration::from_secs_f64(1.0 / new_goal));
   }
 
+  ///
+  /// Check if this is running both the Server and the Client.
+  ///
+  pub fn is_singleplayer(&self) -> bool {
+    self.is_server && self.is_client
+  }
+
+  ///
+  /// Decide if the Server should tick this loop iteration.
+  ///
+  /// Returns the delta time the Server should tick with.
+  ///
+  fn server_tick_delta(&mut self) -> Option<f64> {
+    // A dedicated server's loop already runs at the TPS goal.
+    if !self.is_singleplayer() {
+      return Some(self.delta);
+    }
+
+    self.server_tick_accumulator += self.delta;
+
+    if self.server_tick_accumulator >= 1.0 / self.goal_ticks_per_second {
+      let server_delta = self.server_tick_accumulator;
+      self.server_tick_accumulator = 0.0;
+      Some(server_delta)
+    } else {
+      None
+    }
+  }
+
   ///
   /// This simply returns the current delta time.
   ///
@@ -216,9 +282,13 @@ impl Game {
     //* Begin server/client on_tick()
 
     if self.is_server {
+      let server_delta = self.server_tick_delta();
+
       match &mut self.server {
         Some(server) => {
-          server.on_tick(self.delta);
+          if let Some(server_delta) = server_delta {
+            server.on_tick(server_delta);
+          }
 
           if server.shutdown_is_approved() {
             self.shutdown_game()
@@ -257,11 +327,15 @@ impl Game {
         let mut new_title = "AMONGUSTESTLOLOLOLOLOLOLOLOLOLOLOLOLOL | ".to_string();
         new_title.push_str(format!("{:.1}", fps * 3600.).as_str());
         new_title.push_str(" FPH");
+        if let Some(error) = client.get_connection_error() {
+          new_title.push_str(" | ");
+          new_title.push_str(&error.to_string());
+        }
         client.get_window_handler().set_title(&new_title);
       }
     }
 
-    if self.vsync_mode == 0 || self.is_server {
+    if self.vsync_mode == 0 || !self.is_client {
       self.interval.tick();
     }
   }
@@ -281,6 +355,9 @@ impl Game {
         Err(e) => panic!("Minetest: Failed to enter main loop. {}", e),
       }
     }
+
+    // Write minetest.conf back to disk on the way out.
+    self.settings.save();
   }
 }
 
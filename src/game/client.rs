@@ -1,14 +1,21 @@
 mod client_connection;
+mod client_map;
+mod greedy_mesher;
 mod keyboard;
+mod mesher;
+mod mesher_thread;
 mod mouse;
 mod render_engine;
 mod window_handler;
 
-use glam::{vec3a, vec4, Vec3A};
+use ahash::AHashMap;
+use glam::{vec3a, vec4, IVec3, Vec3, Vec3A};
 
 use self::{
-  client_connection::ClientConnection,
+  client_connection::{ClientConnection, ConnectionError},
+  client_map::ClientMap,
   keyboard::KeyboardController,
+  mesher::BlockMesh,
   mouse::MouseController,
   render_engine::{instanced_render_matrix::InstanceMatrixRGBA, RenderEngine},
   window_handler::WindowHandler,
@@ -16,7 +23,15 @@ use self::{
 
 const TESTING_LIMIT: usize = 100;
 
-use super::lua_engine::LuaEngine;
+use super::{
+  lua_engine::LuaEngine, map::block_to_node_position,
+  network::loopback_transport::LoopbackTransport, protocol::Packet, settings::Settings,
+};
+
+///
+/// How often the server hears where the camera is, in seconds.
+///
+const PLAYER_MOVE_INTERVAL: f64 = 0.1;
 
 ///
 /// The Client component for the engine.
@@ -43,6 +58,12 @@ pub struct Client {
 
   quit_received: bool,
 
+  player_move_timer: f64,
+
+  // The map the server sent, and the uploaded mesh and texture of every layer of every MapBlock.
+  client_map: ClientMap,
+  block_meshes: AHashMap<IVec3, Vec<(u64, String)>>,
+
   // ! TESTING
   spin_test: f64,
 
@@ -50,19 +71,49 @@ pub struct Client {
 }
 
 impl Client {
-  pub fn new(client_name: String, address: String, port: i32) -> Self {
+  pub fn new(settings: &Settings) -> Self {
+    // Set up a blank client connection.
+    let connection = ClientConnection::new(
+      settings.get_string("address"),
+      settings.get_int("port") as i32,
+      settings.get_string("name"),
+      settings.get_string("password"),
+    );
+
+    Self::new_with_connection(settings, connection)
+  }
+
+  ///
+  /// Create a Client which talks to a Server inside the same process.
+  ///
+  /// The Server holds the other side of the LoopbackTransport.
+  ///
+  pub fn new_singleplayer(settings: &Settings, transport: LoopbackTransport) -> Self {
+    let connection = ClientConnection::new_loopback(
+      transport,
+      settings.get_string("name"),
+      settings.get_string("password"),
+    );
+
+    Self::new_with_connection(settings, connection)
+  }
+
+  fn new_with_connection(settings: &Settings, connection: ClientConnection) -> Self {
     // Input engines.
-    let mut mouse = MouseController::new();
+    let mut mouse = MouseController::new(settings.get_float("mouse_sensitivity") as f32);
     let keyboard = KeyboardController::new();
 
     // Set up the window handler.
-    let window_handler = WindowHandler::new(&mut mouse);
+    let window_handler = WindowHandler::new(
+      &mut mouse,
+      settings.get_int("screen_w") as u32,
+      settings.get_int("screen_h") as u32,
+    );
 
     // Set up the render engine.
-    let render_engine = RenderEngine::new(&window_handler);
+    let render_engine = RenderEngine::new(&window_handler, settings.get_float("fov") as f32);
 
-    // Set up a blank client connection.
-    let connection = ClientConnection::new(address, port);
+    let client_name = settings.get_string("name");
 
     // Finally create the Client-side luau virtual machine.
     let lua_engine = LuaEngine::new(false);
@@ -79,6 +130,11 @@ impl Client {
 
       quit_received: false,
 
+      player_move_timer: 0.0,
+
+      client_map: ClientMap::new(settings.get_bool("greedy_meshing")),
+      block_meshes: AHashMap::new(),
+
       // ! TESTING
       spin_test: 0.0,
       color_fun: 0.0,
@@ -128,6 +184,13 @@ impl Client {
     self.quit_received
   }
 
+  ///
+  /// Get why the Client is not connected to the server, if something went wrong.
+  ///
+  pub fn get_connection_error(&self) -> Option<&ConnectionError> {
+    self.connection.get_error()
+  }
+
   ///
   /// Borrow the WindowHandler mutably.
   ///
@@ -135,6 +198,65 @@ impl Client {
     &mut self.window_handler
   }
 
+  ///
+  /// Hand the map data from the server to the ClientMap,
+  /// and upload every MapBlock mesh which is finished.
+  ///
+  fn update_map(&mut self) {
+    for packet in self.connection.map_packets.drain(..) {
+      // New node definitions wipe the map, so the old meshes go too.
+      if matches!(packet, Packet::NodeDefinitions { .. }) {
+        for (_, layers) in self.block_meshes.drain() {
+          for (mesh_id, _) in layers {
+            self.render_engine.delete_mesh(mesh_id);
+          }
+        }
+      }
+      self.client_map.receive_packet(packet);
+    }
+
+    self.client_map.update();
+
+    for (block_position, block_mesh) in self.client_map.receive_meshes() {
+      self.store_block_mesh(block_position, block_mesh);
+    }
+  }
+
+  ///
+  /// Swap out the mesh of a MapBlock for a new one.
+  ///
+  fn store_block_mesh(&mut self, block_position: IVec3, block_mesh: BlockMesh) {
+    for (mesh_id, _) in self
+      .block_meshes
+      .remove(&block_position)
+      .unwrap_or_default()
+    {
+      self.render_engine.delete_mesh(mesh_id);
+    }
+
+    // Nothing to draw, wgpu won't take empty buffers anyway.
+    if block_mesh.is_empty() {
+      return;
+    }
+
+    let layers = block_mesh
+      .layers
+      .into_iter()
+      .map(|layer| {
+        let name = format!(
+          "mapblock_{}_{}_{}_{}",
+          block_position.x, block_position.y, block_position.z, layer.texture
+        );
+        let mesh_id = self
+          .render_engine
+          .create_mesh(&name, layer.vertices, layer.indices);
+        (mesh_id, layer.texture)
+      })
+      .collect();
+
+    self.block_meshes.insert(block_position, layers);
+  }
+
   ///
   /// Tick tock.
   ///
@@ -153,9 +275,10 @@ impl Client {
       .update(delta, &mut self.mouse, &mut self.keyboard);
 
     // Poll any incoming network traffic. (non blocking)
-    if self.connection.is_connected() {
-      self.connection.receive(delta);
-    }
+    // This also drives the handshake, so it must run before we're connected.
+    self.connection.receive(delta);
+
+    self.update_map();
 
     //todo: probably should do user input here
 
@@ -220,6 +343,19 @@ impl Client {
 
     self.render_engine.get_camera().set_position(&camera_pos);
 
+    // The server sends the map around where we are, and where we look first.
+    self.player_move_timer += delta;
+    if self.player_move_timer >= PLAYER_MOVE_INTERVAL && self.connection.is_connected() {
+      self.player_move_timer = 0.0;
+
+      let camera = self.render_engine.get_camera();
+      let packet = Packet::PlayerMove {
+        position: camera.get_world_position(),
+        rotation: Vec3::from(*camera.get_rotation()),
+      };
+      self.connection.send_packet(&packet);
+    }
+
     // Update the RenderEngine with the WindowHandler.
     self.render_engine.update(&self.window_handler, delta);
 
@@ -311,6 +447,37 @@ impl Client {
       Vec3A::new(1.0, 1.0, 1.0),
     );
 
+    // The map. Greedy meshes stretch faces over many nodes, so their textures repeat.
+    let tiled = self.client_map.is_greedy_meshing();
+    for (block_position, layers) in &self.block_meshes {
+      let translation = Vec3A::from(block_to_node_position(*block_position).as_vec3());
+
+      for (mesh_id, texture) in layers {
+        // todo: the server doesn't send media yet, so tf.png stands in for missing textures.
+        let texture_id = self
+          .render_engine
+          .find_texture_id(texture)
+          .unwrap_or(debug_mesh_texture);
+
+        match tiled {
+          true => self.render_engine.render_mesh_tiled(
+            *mesh_id,
+            texture_id,
+            translation,
+            Vec3A::ZERO,
+            Vec3A::ONE,
+          ),
+          false => self.render_engine.render_mesh(
+            *mesh_id,
+            texture_id,
+            translation,
+            Vec3A::ZERO,
+            Vec3A::ONE,
+          ),
+        }
+      }
+    }
+
     self.render_engine.process_not_instanced_render_calls();
 
     // * Begin instanced.
@@ -1,11 +1,87 @@
-use std::time::Duration;
-
-use message_io::{
-  events::EventReceiver,
-  network::{Endpoint, ToRemoteAddr, Transport},
-  node::{self, NodeHandler, NodeTask, StoredNetEvent, StoredNodeEvent},
+use std::fmt::Display;
+
+use crate::game::{
+  network::{
+    channel_layer::ChannelLayer,
+    loopback_transport::{LoopbackTransport, LOOPBACK_PEER_ID},
+    udp_transport::UdpTransport,
+    NetworkTransport, PeerId,
+  },
+  protocol::Packet,
+  serial::{deserialize, serialize},
+  srp::{compute_verifier, generate_salt, SrpClient},
 };
 
+// How long the server gets to answer the handshake, in seconds.
+// The handshake is reliable, the ChannelLayer does the resending.
+const HANDSHAKE_TIMEOUT: f64 = 10.0;
+
+// How long to wait before the first reconnect, in seconds.
+// This doubles with every attempt.
+const RECONNECT_DELAY: f64 = 2.0;
+const MAX_RECONNECT_ATTEMPTS: u32 = 3;
+
+// How often we ping the server, and how long it can be silent, in seconds.
+const PING_INTERVAL: f64 = 3.0;
+const CONNECTION_TIMEOUT: f64 = 10.0;
+
+///
+/// Where the ClientConnection is in it's life.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+  // Shaking hands with the server.
+  Connecting,
+  // In the game.
+  Connected,
+  // Lost the server, waiting to try again.
+  Reconnecting,
+  // Gave up, or left on purpose.
+  Lost,
+}
+
+///
+/// Why the ClientConnection is not connected.
+///
+/// This is what the Client shows to the player.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionError {
+  InvalidAddress(String),
+  HandshakeTimedOut,
+  TimedOut,
+  Disconnected(String),
+  AuthenticationFailed(String),
+}
+
+impl ConnectionError {
+  ///
+  /// If trying again might fix it.
+  ///
+  pub fn is_recoverable(&self) -> bool {
+    matches!(
+      self,
+      ConnectionError::HandshakeTimedOut | ConnectionError::TimedOut
+    )
+  }
+}
+
+impl Display for ConnectionError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ConnectionError::InvalidAddress(e) => write!(f, "Could not connect: {}", e),
+      ConnectionError::HandshakeTimedOut => write!(f, "The server did not answer."),
+      ConnectionError::TimedOut => write!(f, "Lost connection to the server."),
+      ConnectionError::Disconnected(reason) => {
+        write!(f, "Disconnected by the server: {}", reason)
+      }
+      ConnectionError::AuthenticationFailed(reason) => {
+        write!(f, "Authentication failed: {}", reason)
+      }
+    }
+  }
+}
+
 ///
 /// ClientConnection and Client can be considered 1 entity.
 ///
@@ -14,87 +90,242 @@ use message_io::{
 pub struct ClientConnection {
   address: String,
   port: i32,
+  player_name: String,
+  password: String,
+
+  state: ConnectionState,
+  error: Option<ConnectionError>,
 
-  connected: bool,
+  reconnect_attempts: u32,
+  retry_timer: f64,
+  retry_delay: f64,
 
-  handshake_timeout: f64,
+  ping_timer: f64,
+  time_since_heard: f64,
 
-  ping_resend_delta: f64,
-  ping_waiting_receive: bool,
-  ping_timeout: f64,
+  // A loopback connection can't be opened back up.
+  can_reconnect: bool,
 
-  lost_connection: bool,
+  // The SRP login in progress, and what the server has to answer with.
+  srp: Option<SrpClient>,
+  expected_server_proof: Option<Vec<u8>>,
 
-  end_point: Endpoint,
-  task: NodeTask,
-  handler: NodeHandler<()>,
-  event_receiver: EventReceiver<StoredNodeEvent<()>>,
+  server_peer_id: PeerId,
+  channels: Option<ChannelLayer>,
+
+  // Node definitions, MapBlocks and node changes, the Client hands these to the ClientMap.
+  pub map_packets: Vec<Packet>,
 }
 
 impl ClientConnection {
-  pub fn new(address: String, port: i32) -> Self {
-    let remote_address = match Self::get_socket(&address, port).to_remote_addr() {
-      Ok(address) => address,
-      Err(e) => panic!("ClientConnection: Socket get failure. {}", e),
-    };
-
-    let transport_protocol = Transport::Udp;
+  pub fn new(address: String, port: i32, player_name: String, password: String) -> Self {
+    let mut new_connection = Self::new_blank(address, port, player_name, password, true);
 
     // todo: will need to be initialized by the gui component.
+    new_connection.open_connection();
 
-    let (handler, listener) = node::split();
-
-    // todo: fixme: this is udp, why are we doing a match here?
-    // todo: If this fails, the user probably doesn't have a network
-    // todo: adapter!
-    let (server_id, local_address) = match handler
-      .network()
-      .connect(transport_protocol, remote_address)
-    {
-      Ok((end_point, local_address)) => {
-        // UDP is connectionless, but it's still good to know it's working.
-        println!(
-          "ClientConnection: established connection to server at id [{}], local address [{}]",
-          end_point, local_address
-        );
-        (end_point, local_address)
-      }
-      Err(e) => panic!("{}", e),
-    };
+    new_connection
+  }
 
-    let (task, event_receiver) = listener.enqueue();
-    let end_point = server_id;
+  ///
+  /// Create a ClientConnection which talks to an in-process Server.
+  ///
+  /// This is used for singleplayer.
+  ///
+  pub fn new_loopback(transport: LoopbackTransport, player_name: String, password: String) -> Self {
+    println!("ClientConnection: running in singleplayer loopback mode.");
 
-    // Can possibly be used as a handshake
-    // ! Note: this literally is the handshake right now
-    // self.send_data(self.end_point.un no >:(  wrap(), "MINETEST_HAND_SHAKE");
+    let mut new_connection =
+      Self::new_blank("loopback".to_string(), 0, player_name, password, false);
+    new_connection.use_transport(Box::new(transport), LOOPBACK_PEER_ID);
 
+    new_connection
+  }
+
+  fn new_blank(
+    address: String,
+    port: i32,
+    player_name: String,
+    password: String,
+    can_reconnect: bool,
+  ) -> Self {
     ClientConnection {
       address,
       port,
+      player_name,
+      password,
+
+      state: ConnectionState::Connecting,
+      error: None,
 
-      connected: false,
+      reconnect_attempts: 0,
+      retry_timer: 0.0,
+      retry_delay: RECONNECT_DELAY,
 
-      handshake_timeout: 0.0,
+      ping_timer: 0.0,
+      time_since_heard: 0.0,
 
-      ping_resend_delta: 0.0,
-      ping_waiting_receive: false,
-      ping_timeout: 0.0,
+      can_reconnect,
 
-      lost_connection: false,
+      srp: None,
+      expected_server_proof: None,
 
-      end_point,
-      task,
-      handler,
-      event_receiver,
+      server_peer_id: 0,
+      channels: None,
+
+      map_packets: vec![],
     }
   }
 
+  ///
+  /// Open a fresh UDP socket to the server and start shaking hands.
+  ///
+  /// A fresh socket means the server sees a brand new peer, so none of
+  /// the old channel state can get in the way.
+  ///
+  fn open_connection(&mut self) {
+    // Close the old socket first.
+    self.channels = None;
+
+    match UdpTransport::connect(&self.address, self.port) {
+      Ok((transport, server_peer_id)) => self.use_transport(Box::new(transport), server_peer_id),
+      Err(e) => self.lose_connection(ConnectionError::InvalidAddress(e)),
+    }
+  }
+
+  ///
+  /// Start talking through a transport.
+  ///
+  fn use_transport(&mut self, transport: Box<dyn NetworkTransport>, server_peer_id: PeerId) {
+    self.server_peer_id = server_peer_id;
+    self.channels = Some(ChannelLayer::new(transport));
+
+    self.state = ConnectionState::Connecting;
+    self.srp = None;
+    self.expected_server_proof = None;
+    self.send_handshake();
+  }
+
+  fn send_handshake(&mut self) {
+    self.retry_timer = 0.0;
+
+    self.send_packet(&Packet::Handshake {
+      player_name: self.player_name.clone(),
+    });
+  }
+
+  ///
+  /// The server wants us to prove who we are.
+  ///
+  /// A new player registers a verifier made from their password,
+  /// a registered player starts an SRP login.
+  ///
+  fn auth_request_reaction(&mut self, registered: bool) {
+    if registered {
+      let srp = SrpClient::new(&self.player_name);
+      let client_public = srp.get_public_ephemeral();
+      self.srp = Some(srp);
+      self.send_packet(&Packet::AuthStart { client_public });
+    } else {
+      println!("ClientConnection: registering [{}].", self.player_name);
+      let salt = generate_salt();
+      let verifier = compute_verifier(&self.player_name, &self.password, &salt);
+      self.send_packet(&Packet::AuthRegister { salt, verifier });
+    }
+  }
+
+  ///
+  /// Answer the server's SRP challenge.
+  ///
+  fn auth_challenge_reaction(&mut self, salt: Vec<u8>, server_public: Vec<u8>) {
+    let srp = match self.srp.take() {
+      Some(srp) => srp,
+      None => return,
+    };
+
+    match srp.process_challenge(&self.password, &salt, &server_public) {
+      Ok((proof, expected_server_proof)) => {
+        self.expected_server_proof = Some(expected_server_proof);
+        self.send_packet(&Packet::AuthProof { proof });
+      }
+      Err(e) => self.lose_connection(ConnectionError::AuthenticationFailed(e)),
+    }
+  }
+
+  ///
+  /// Make sure the server really knows our verifier, and isn't pretending.
+  ///
+  fn auth_server_proof_reaction(&mut self, proof: Vec<u8>) {
+    let expected_server_proof = match self.expected_server_proof.take() {
+      Some(expected_server_proof) => expected_server_proof,
+      None => return,
+    };
+
+    if !SrpClient::verify_server_proof(&expected_server_proof, &proof) {
+      self.lose_connection(ConnectionError::AuthenticationFailed(
+        "The server could not prove who it is.".to_string(),
+      ));
+    }
+  }
+
+  ///
+  /// Something went wrong. Try again later if we can, otherwise give up.
+  ///
+  fn lose_connection(&mut self, error: ConnectionError) {
+    println!("ClientConnection: {}", error);
+
+    let try_again = self.can_reconnect
+      && error.is_recoverable()
+      && self.reconnect_attempts < MAX_RECONNECT_ATTEMPTS;
+
+    if try_again {
+      self.state = ConnectionState::Reconnecting;
+      self.retry_timer = 0.0;
+      self.retry_delay = RECONNECT_DELAY * 2.0_f64.powi(self.reconnect_attempts as i32);
+      self.reconnect_attempts += 1;
+      println!(
+        "ClientConnection: reconnecting in {} seconds.",
+        self.retry_delay
+      );
+    } else {
+      self.state = ConnectionState::Lost;
+    }
+
+    self.error = Some(error);
+  }
+
   ///
   /// Get if the Client is connected to a server.
   ///
   pub fn is_connected(&self) -> bool {
-    self.connected
+    self.state == ConnectionState::Connected
+  }
+
+  ///
+  /// Get where the connection is in it's life.
+  ///
+  pub fn get_state(&self) -> ConnectionState {
+    self.state
+  }
+
+  ///
+  /// Get why we're not connected, if something went wrong.
+  ///
+  pub fn get_error(&self) -> Option<&ConnectionError> {
+    self.error.as_ref()
+  }
+
+  ///
+  /// Tell the server we're leaving.
+  ///
+  pub fn disconnect(&mut self) {
+    if self.state == ConnectionState::Connected {
+      self.send_packet(&Packet::Disconnect {
+        reason: "Quit.".to_string(),
+      });
+    }
+
+    self.state = ConnectionState::Lost;
   }
 
   ///
@@ -112,104 +343,153 @@ impl ClientConnection {
   }
 
   ///
-  /// Construct the address & port into a parsable socket string.
+  /// Send a Packet to the server (ServerConnection).
   ///
-  fn get_socket(address: &str, port: i32) -> String {
-    let mut socket = address.to_owned();
-    socket.push(':');
-    socket.push_str(port.to_string().as_str());
+  pub fn send_packet(&mut self, packet: &Packet) {
+    let channels = match self.channels.as_mut() {
+      Some(channels) => channels,
+      None => return,
+    };
 
-    socket
-  }
+    let data = match serialize(packet) {
+      Ok(data) => data,
+      Err(e) => {
+        println!("ClientConnection: failed to serialize {:?}. {}", packet, e);
+        return;
+      }
+    };
 
-  ///
-  /// Send raw data to the EndPoint (ServerConnection).
-  ///
-  fn send_data(&self, end_point: Endpoint, data: &str) {
-    self.handler.network().send(end_point, data.as_bytes());
+    if let Err(e) = channels.send(
+      self.server_peer_id,
+      packet.channel(),
+      packet.is_reliable(),
+      &data,
+    ) {
+      println!("ClientConnection: failed to send to the server. {}", e);
+    }
   }
 
   ///
-  /// A procedure to react to a network event.
+  /// A procedure to react to a network message.
   ///
-  pub fn event_reaction(&mut self, event: StoredNetEvent) {
-    // We don't need to match, we're using UDP which is connectionless.
-    if let StoredNetEvent::Message(end_point, raw_message) = event {
-      // todo: use https://github.com/serde-rs/bytes
-      let receieved_string = match String::from_utf8(raw_message) {
-        Ok(new_string) => new_string,
-        Err(_) => {
-          println!("ClientConnection: message buffer attack detected, bailing on deserialization!");
-          "".to_string()
-        }
-      };
+  pub fn event_reaction(&mut self, peer_id: PeerId, raw_message: Vec<u8>) {
+    // Only the server is allowed to talk to us.
+    if peer_id != self.server_peer_id {
+      return;
+    }
 
-      match receieved_string.as_str() {
-        "hi" => println!("ClientConnection: The server says hi."),
-        "MINETEST_HAND_SHAKE_CONFIRMED" => {
-          // Received handshake with the server.
-          if !self.connected {
-            self.connected = true;
-            self.handshake_timeout = 0.0;
-            println!("ClientConnection: ClientConnection received handshake from ServerConnection.");
-          }
-
-          // ! Do not enable this unless you want the server to
-          // ! shutdown as soon as you connect.
-          // self.send_data(end_point, "MINETEST_SHUT_DOWN_REQUEST");
-        }
-        "MINETEST_PING_CONFIRMATION" => {
-          println!("ClientConnection: ClientConnection ping received from ServerConnection.");
-          self.ping_timeout = 0.0;
-          self.ping_waiting_receive = false;
-          self.ping_resend_delta = 0.0;
+    let packet = match deserialize(&raw_message) {
+      Ok(packet) => packet,
+      Err(e) => {
+        println!("ClientConnection: rejected packet from server. {}", e);
+        return;
+      }
+    };
+
+    self.time_since_heard = 0.0;
+
+    // The server is answering, give it time to finish.
+    if self.state == ConnectionState::Connecting {
+      self.retry_timer = 0.0;
+    }
+
+    match packet {
+      // Received handshake with the server.
+      Packet::HandshakeConfirmed if self.state == ConnectionState::Connecting => {
+        // A server which skipped proving itself doesn't get to let us in.
+        if self.expected_server_proof.is_some() {
+          self.lose_connection(ConnectionError::AuthenticationFailed(
+            "The server did not prove who it is.".to_string(),
+          ));
+          return;
         }
-        _ => (),
+
+        self.state = ConnectionState::Connected;
+        self.error = None;
+        self.reconnect_attempts = 0;
+        self.ping_timer = 0.0;
+        println!("ClientConnection: ClientConnection received handshake from ServerConnection.");
+
+        // ! Do not enable this unless you want the server to
+        // ! shutdown as soon as you connect.
+        // self.send_packet(&Packet::ShutdownRequest);
+      }
+      Packet::AuthRequest { registered } if self.state == ConnectionState::Connecting => {
+        self.auth_request_reaction(registered)
+      }
+      Packet::AuthChallenge {
+        salt,
+        server_public,
+      } if self.state == ConnectionState::Connecting => {
+        self.auth_challenge_reaction(salt, server_public)
+      }
+      Packet::AuthServerProof { proof } if self.state == ConnectionState::Connecting => {
+        self.auth_server_proof_reaction(proof)
+      }
+      Packet::AuthRejected { reason } => {
+        self.lose_connection(ConnectionError::AuthenticationFailed(reason));
+      }
+      Packet::PingConfirmation => (),
+      Packet::ChatMessage { sender, message } => {
+        println!("ClientConnection: <{}> {}", sender, message)
+      }
+      Packet::Disconnect { reason } => {
+        self.lose_connection(ConnectionError::Disconnected(reason));
+      }
+      Packet::NodeDefinitions { .. } | Packet::BlockData { .. } | Packet::NodeChanged { .. }
+        if self.state == ConnectionState::Connected =>
+      {
+        self.map_packets.push(packet)
       }
+      // todo: the client does not do anything with players yet.
+      _ => (),
     }
   }
 
   ///
-  /// Will automatically calculate if the server has failed to provide a handshake.
-  /// aka: the server is not online.
+  /// Give up if the server never answers the handshake.
   ///
-  fn check_handshake(&mut self, delta: f64) {
-    // Handshake timeout, aka server connection timeout
-    if !self.connected {
-      self.handshake_timeout += delta;
+  /// Only the one handshake goes out, the ChannelLayer
+  /// resends it until the server acknowledges it.
+  ///
+  fn do_handshake_logic(&mut self, delta: f64) {
+    self.retry_timer += delta;
 
-      // 3 second timeout.
-      // todo: make this not a panic.
-      if self.handshake_timeout >= 3.0 {
-        panic!("ClientConnection: ClientConnection attempt to connect to server timed out.")
-      }
+    if self.retry_timer >= HANDSHAKE_TIMEOUT {
+      self.lose_connection(ConnectionError::HandshakeTimedOut);
     }
   }
 
   ///
-  /// Will automatically calculate if the server has lost connection to the client.
+  /// Keep pinging the server, and notice when it goes silent.
   ///
   fn do_ping_timeout_logic(&mut self, delta: f64) {
-    // If we're not connected, don't attempt to do this.
-    if self.connected {
-      if self.ping_waiting_receive {
-        // We're waiting for the server to respond.
-        self.ping_timeout += delta;
-
-        // 3 second timeout.
-        // todo: make this not a panic.
-        if self.ping_timeout >= 3.0 {
-          panic!("ClientConnection: ClientConnection connection to server timed out.")
-        }
-      } else {
-        // Wait 3 seconds before pinging the server again.
-        self.ping_resend_delta += delta;
+    self.time_since_heard += delta;
 
-        if self.ping_resend_delta >= 3.0 {
-          self.ping_waiting_receive = true;
-          self.send_data(self.end_point, "MINETEST_PING_REQUEST");
-        }
-      }
+    if self.time_since_heard >= CONNECTION_TIMEOUT {
+      self.lose_connection(ConnectionError::TimedOut);
+      return;
+    }
+
+    self.ping_timer += delta;
+    if self.ping_timer >= PING_INTERVAL {
+      self.ping_timer = 0.0;
+      self.send_packet(&Packet::PingRequest);
+    }
+  }
+
+  ///
+  /// Wait out the reconnect delay, then open a fresh connection.
+  ///
+  fn do_reconnect_logic(&mut self, delta: f64) {
+    self.retry_timer += delta;
+
+    if self.retry_timer >= self.retry_delay {
+      println!(
+        "ClientConnection: reconnect attempt {} of {}.",
+        self.reconnect_attempts, MAX_RECONNECT_ATTEMPTS
+      );
+      self.open_connection();
     }
   }
 
@@ -217,25 +497,202 @@ impl ClientConnection {
   /// Non-blocking event receiver for network events.
   ///
   pub fn receive(&mut self, delta: f64) {
-    if let Some(event) = self.event_receiver.receive_timeout(Duration::new(0, 0)) {
-      match event {
-        StoredNodeEvent::Network(new_event) => self.event_reaction(new_event),
-        // todo: figure out what a signal is!
-        StoredNodeEvent::Signal(_) => todo!(),
+    let mut gave_up = false;
+    if let Some(channels) = self.channels.as_mut() {
+      channels.update(delta);
+      gave_up = channels
+        .take_timed_out_peers()
+        .contains(&self.server_peer_id);
+    }
+
+    // The server stopped acknowledging anything we send.
+    if gave_up {
+      match self.state {
+        ConnectionState::Connecting => self.lose_connection(ConnectionError::HandshakeTimedOut),
+        ConnectionState::Connected => self.lose_connection(ConnectionError::TimedOut),
+        ConnectionState::Reconnecting | ConnectionState::Lost => (),
       }
     }
 
-    self.check_handshake(delta);
-    self.do_ping_timeout_logic(delta);
+    loop {
+      let message = match self.channels.as_mut() {
+        Some(channels) => channels.receive(),
+        None => None,
+      };
+
+      match message {
+        Some((peer_id, raw_message)) => self.event_reaction(peer_id, raw_message),
+        None => break,
+      }
+    }
+
+    match self.state {
+      ConnectionState::Connecting => self.do_handshake_logic(delta),
+      ConnectionState::Connected => self.do_ping_timeout_logic(delta),
+      ConnectionState::Reconnecting => self.do_reconnect_logic(delta),
+      ConnectionState::Lost => (),
+    }
   }
 }
 
 impl Drop for ClientConnection {
   fn drop(&mut self) {
-    // ClientConnection must stop the handler entity or the Client
-    // will not shut down.
-    println!("Clientconnection: Shutting down network handler.");
-    NodeHandler::stop(&self.handler);
+    self.disconnect();
     println!("ClientConnection dropped!")
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use crate::game::{
+    network::{
+      channel_layer::ChannelLayer,
+      loopback_transport::{LoopbackTransport, LOOPBACK_PEER_ID},
+    },
+    protocol::Packet,
+    serial::{deserialize, serialize},
+  };
+
+  use super::{ClientConnection, ConnectionError, ConnectionState};
+
+  fn new_pair() -> (ClientConnection, ChannelLayer) {
+    let (client_side, server_side) = LoopbackTransport::new_pair();
+    (
+      ClientConnection::new_loopback(client_side, "sam".to_string(), "hunter2".to_string()),
+      ChannelLayer::new(Box::new(server_side)),
+    )
+  }
+
+  fn server_send(server: &mut ChannelLayer, packet: &Packet) {
+    let data = match serialize(packet) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    if let Err(e) = server.send(
+      LOOPBACK_PEER_ID,
+      packet.channel(),
+      packet.is_reliable(),
+      &data,
+    ) {
+      panic!("Unit test is broken. {}", e);
+    }
+  }
+
+  #[test]
+  fn test_handshake_gives_up_without_panic() {
+    let (mut client, mut server) = new_pair();
+
+    // Waiting on the server doesn't pile up more handshakes.
+    for _ in 0..50 {
+      client.receive(0.1);
+    }
+    let mut handshakes = 0;
+    while let Some((_, data)) = server.receive() {
+      if let Ok(Packet::Handshake { .. }) = deserialize(&data) {
+        handshakes += 1;
+      }
+    }
+    assert_eq!(handshakes, 1);
+    assert_eq!(client.get_state(), ConnectionState::Connecting);
+
+    for _ in 0..1000 {
+      client.receive(0.1);
+    }
+
+    assert_eq!(client.get_state(), ConnectionState::Lost);
+    assert_eq!(
+      client.get_error(),
+      Some(&ConnectionError::HandshakeTimedOut)
+    );
+  }
+
+  #[test]
+  fn test_connection_lost_and_disconnected() {
+    let (mut client, mut server) = new_pair();
+
+    server_send(&mut server, &Packet::HandshakeConfirmed);
+    client.receive(0.1);
+    assert!(client.is_connected());
+
+    // The server going silent doesn't crash us.
+    for _ in 0..200 {
+      client.receive(0.1);
+    }
+    assert_eq!(client.get_state(), ConnectionState::Lost);
+    assert_eq!(client.get_error(), Some(&ConnectionError::TimedOut));
+
+    // Getting kicked is reported with the reason.
+    let (mut client, mut server) = new_pair();
+    server_send(&mut server, &Packet::HandshakeConfirmed);
+    server_send(
+      &mut server,
+      &Packet::Disconnect {
+        reason: "Bye.".to_string(),
+      },
+    );
+    client.receive(0.1);
+
+    assert_eq!(client.get_state(), ConnectionState::Lost);
+    assert_eq!(
+      client.get_error(),
+      Some(&ConnectionError::Disconnected("Bye.".to_string()))
+    );
+  }
+
+  #[test]
+  fn test_map_packets() {
+    let (mut client, mut server) = new_pair();
+
+    // Map data from a server we're not logged into yet is dropped.
+    let block_data = Packet::BlockData {
+      position: IVec3::ZERO,
+      data: vec![1, 2, 3],
+    };
+    server_send(&mut server, &block_data);
+    client.receive(0.1);
+    assert!(client.map_packets.is_empty());
+
+    server_send(&mut server, &Packet::HandshakeConfirmed);
+    server_send(&mut server, &block_data);
+    server_send(&mut server, &Packet::PingConfirmation);
+    client.receive(0.1);
+    assert_eq!(client.map_packets, vec![block_data]);
+  }
+
+  #[test]
+  fn test_authentication_rejected() {
+    let (mut client, mut server) = new_pair();
+
+    server_send(&mut server, &Packet::AuthRequest { registered: true });
+    client.receive(0.1);
+
+    // The client started logging in.
+    let mut started = false;
+    while let Some((_, data)) = server.receive() {
+      if let Ok(Packet::AuthStart { .. }) = deserialize(&data) {
+        started = true;
+      }
+    }
+    assert!(started);
+
+    server_send(
+      &mut server,
+      &Packet::AuthRejected {
+        reason: "Wrong password.".to_string(),
+      },
+    );
+    client.receive(0.1);
+
+    // Not something reconnecting can fix.
+    assert_eq!(client.get_state(), ConnectionState::Lost);
+    assert_eq!(
+      client.get_error(),
+      Some(&ConnectionError::AuthenticationFailed(
+        "Wrong password.".to_string()
+      ))
+    );
+  }
+}
@@ -0,0 +1,285 @@
+use std::sync::Arc;
+
+use ahash::AHashSet;
+use glam::IVec3;
+
+use crate::game::{
+  map::{
+    map_serialization::deserialize_block,
+    node::{Node, CONTENT_UNKNOWN},
+    node_def_manager::{NodeDefManager, NodeDefinition},
+    node_to_block_position, Map,
+  },
+  protocol::Packet,
+};
+
+use super::{
+  mesher::{BlockMesh, MeshInput},
+  mesher_thread::MesherThread,
+};
+
+///
+/// Get everything one step away on any axis, the middle included.
+///
+fn neighbourhood(center: IVec3) -> impl Iterator<Item = IVec3> {
+  (-1..=1).flat_map(move |z| {
+    (-1..=1).flat_map(move |y| (-1..=1).map(move |x| center + IVec3::new(x, y, z)))
+  })
+}
+
+///
+/// The part of the map the server sent to the Client.
+///
+/// MapBlocks and node changes go in with receive_packet(),
+/// update() sends every block which looks different now
+/// off to the MesherThread, and the finished meshes come
+/// out of receive_meshes().
+///
+pub struct ClientMap {
+  map: Map,
+  node_def_manager: Arc<NodeDefManager>,
+  mesher_thread: MesherThread,
+  greedy_meshing: bool,
+}
+
+impl ClientMap {
+  pub fn new(greedy_meshing: bool) -> Self {
+    let node_def_manager = Arc::new(NodeDefManager::new());
+
+    ClientMap {
+      map: Map::new(),
+      node_def_manager: node_def_manager.clone(),
+      mesher_thread: MesherThread::new(node_def_manager, greedy_meshing),
+      greedy_meshing,
+    }
+  }
+
+  pub fn get_map(&self) -> &Map {
+    &self.map
+  }
+
+  pub fn get_node_def_manager(&self) -> &NodeDefManager {
+    &self.node_def_manager
+  }
+
+  ///
+  /// Check if meshes come from greedy_mesh_block(), which tiles textures.
+  ///
+  pub fn is_greedy_meshing(&self) -> bool {
+    self.greedy_meshing
+  }
+
+  ///
+  /// Get how many meshes are waiting on the MesherThread.
+  ///
+  pub fn get_mesh_queue_size(&self) -> usize {
+    self.mesher_thread.get_queue_size()
+  }
+
+  ///
+  /// React to map data from the server.
+  ///
+  pub fn receive_packet(&mut self, packet: Packet) {
+    match packet {
+      Packet::NodeDefinitions { definitions } => self.set_node_definitions(definitions),
+      Packet::BlockData { position, data } => {
+        match deserialize_block(&data, &self.node_def_manager) {
+          Ok(block) => self.map.insert_block(position, block),
+          Err(e) => println!("ClientMap: bad MapBlock {} from server. {}", position, e),
+        }
+      }
+      Packet::NodeChanged {
+        position,
+        name,
+        param1,
+        param2,
+      } => {
+        let content = self
+          .node_def_manager
+          .get_id(&name)
+          .unwrap_or(CONTENT_UNKNOWN);
+        self
+          .map
+          .set_node(position, Node::new_with_params(content, param1, param2));
+      }
+      _ => (),
+    }
+  }
+
+  ///
+  /// Start over with the node types the server has.
+  ///
+  /// Ids can change, so whatever the old map had is thrown out.
+  ///
+  fn set_node_definitions(&mut self, definitions: Vec<NodeDefinition>) {
+    let count = definitions.len();
+    let node_def_manager = match NodeDefManager::from_definitions(definitions) {
+      Ok(node_def_manager) => Arc::new(node_def_manager),
+      Err(e) => {
+        println!("ClientMap: bad node definitions from server. {}", e);
+        return;
+      }
+    };
+
+    println!("ClientMap: received {} node definitions.", count);
+
+    self.map = Map::new();
+    self.node_def_manager = node_def_manager.clone();
+    self.mesher_thread = MesherThread::new(node_def_manager, self.greedy_meshing);
+  }
+
+  ///
+  /// Send every MapBlock which changed off to be meshed.
+  ///
+  /// Faces on the edge of a block depend on it's neighbours,
+  /// the ones on the corners too with smooth lighting,
+  /// so they get meshed again when needed.
+  ///
+  pub fn update(&mut self) {
+    let mut outdated = AHashSet::new();
+
+    for block_position in self.map.take_replaced_blocks() {
+      outdated.extend(neighbourhood(block_position));
+    }
+
+    for position in self.map.take_changed_nodes() {
+      outdated.extend(neighbourhood(position).map(node_to_block_position));
+    }
+
+    // Nothing gets saved on the client.
+    self.map.take_dirty_blocks();
+
+    let mut outdated: Vec<IVec3> = outdated.into_iter().collect();
+    outdated.sort_by_key(|block_position| (block_position.x, block_position.y, block_position.z));
+
+    for block_position in outdated {
+      if let Some(input) = MeshInput::from_map(&self.map, block_position) {
+        self.mesher_thread.request_mesh(block_position, input);
+      }
+    }
+  }
+
+  ///
+  /// Take every mesh which is finished. (non blocking)
+  ///
+  /// A block can show up more than once, the last one is the newest.
+  ///
+  pub fn receive_meshes(&mut self) -> Vec<(IVec3, BlockMesh)> {
+    self.mesher_thread.receive_meshes()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use glam::IVec3;
+
+  use crate::game::{
+    map::{
+      map_block::MapBlock,
+      map_serialization::serialize_block,
+      node::{Node, CONTENT_AIR},
+      node_def_manager::{new_test_node_def_manager, NodeDefinition},
+    },
+    protocol::Packet,
+  };
+
+  use super::ClientMap;
+
+  ///
+  /// Update and wait for every mesh to come back.
+  ///
+  fn update_and_wait(client_map: &mut ClientMap) -> Vec<IVec3> {
+    client_map.update();
+
+    let mut received = vec![];
+    let started = Instant::now();
+    while client_map.get_mesh_queue_size() > 0 {
+      if started.elapsed() > Duration::from_secs(10) {
+        panic!("Unit test is broken. mesher thread never finished.");
+      }
+      received.extend(
+        client_map
+          .receive_meshes()
+          .into_iter()
+          .map(|(block_position, _)| block_position),
+      );
+      std::thread::sleep(Duration::from_millis(1));
+    }
+    received.sort_by_key(|block_position| (block_position.x, block_position.y, block_position.z));
+    received
+  }
+
+  #[test]
+  fn test_client_map() {
+    let server_node_def_manager = new_test_node_def_manager([NodeDefinition::new("test:stone")]);
+    let block_data =
+      |node: Node| match serialize_block(&MapBlock::new(node), &server_node_def_manager) {
+        Ok(data) => data,
+        Err(e) => panic!("Unit test is broken. {}", e),
+      };
+
+    let mut client_map = ClientMap::new(true);
+    client_map.receive_packet(Packet::NodeDefinitions {
+      definitions: server_node_def_manager.get_definitions(),
+    });
+    assert!(client_map
+      .get_node_def_manager()
+      .get_id("test:stone")
+      .is_some());
+
+    // A new block gets meshed, along with any loaded neighbours.
+    client_map.receive_packet(Packet::BlockData {
+      position: IVec3::ZERO,
+      data: block_data(Node::air()),
+    });
+    assert_eq!(update_and_wait(&mut client_map), vec![IVec3::ZERO]);
+
+    client_map.receive_packet(Packet::BlockData {
+      position: IVec3::X,
+      data: block_data(Node::air()),
+    });
+    assert_eq!(
+      update_and_wait(&mut client_map),
+      vec![IVec3::ZERO, IVec3::X]
+    );
+
+    // A node in the middle of a block only changes that block.
+    let stone = |position: IVec3| Packet::NodeChanged {
+      position,
+      name: "test:stone".to_string(),
+      param1: 0,
+      param2: 0,
+    };
+    client_map.receive_packet(stone(IVec3::new(5, 5, 5)));
+    assert_eq!(update_and_wait(&mut client_map), vec![IVec3::ZERO]);
+    assert_eq!(
+      client_map.get_map().get_node(IVec3::new(5, 5, 5)),
+      client_map
+        .get_node_def_manager()
+        .get_id("test:stone")
+        .map(Node::new)
+    );
+
+    // On the edge the block next to it has to know too.
+    client_map.receive_packet(stone(IVec3::new(15, 5, 5)));
+    assert_eq!(
+      update_and_wait(&mut client_map),
+      vec![IVec3::ZERO, IVec3::X]
+    );
+
+    // Nothing new, nothing to do.
+    assert!(update_and_wait(&mut client_map).is_empty());
+
+    // New definitions mean the old ids are useless.
+    client_map.receive_packet(Packet::NodeDefinitions {
+      definitions: vec![],
+    });
+    assert_eq!(client_map.get_map().get_block_count(), 0);
+    assert_eq!(
+      client_map.get_node_def_manager().get_id("air"),
+      Some(CONTENT_AIR)
+    );
+  }
+}
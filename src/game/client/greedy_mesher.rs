@@ -0,0 +1,490 @@
+use glam::IVec3;
+
+use crate::game::map::{
+  map_block::{index_to_local, MAP_BLOCK_SIZE, MAP_BLOCK_VOLUME},
+  node::CONTENT_IGNORE,
+  node_def_manager::{DrawType, NodeDefManager},
+};
+
+use super::mesher::{
+  get_face_texture, get_node_brightness, is_face_visible, is_opaque, BlockMesh, CubeFace,
+  MeshInput, CUBE_FACES,
+};
+
+///
+/// How bright a corner is with 0 to 3 of the nodes around it letting light through.
+///
+/// Two sides blocking the corner hide whatever is in between them.
+///
+const AMBIENT_OCCLUSION: [f32; 4] = [0.5, 0.7, 0.85, 1.0];
+
+///
+/// What a node does to the corners of the faces next to it.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CornerSample {
+  // Nothing is known about ignore, it gets meshed again once it's loaded.
+  Ignore,
+  Solid,
+  Light(f32),
+}
+
+impl CornerSample {
+  ///
+  /// Look at every node of a MeshInput once, instead of 4 times per corner.
+  ///
+  fn from_input(input: &MeshInput, node_def_manager: &NodeDefManager) -> Vec<Self> {
+    input
+      .get_nodes()
+      .iter()
+      .map(|node| {
+        if node.content == CONTENT_IGNORE {
+          CornerSample::Ignore
+        } else if is_opaque(node_def_manager.get_or_unknown(node.content)) {
+          CornerSample::Solid
+        } else {
+          CornerSample::Light(get_node_brightness(*node))
+        }
+      })
+      .collect()
+  }
+}
+
+///
+/// What a face looks like, only faces which look the same get merged.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FaceLook<'a> {
+  texture: &'a str,
+  brightness: [f32; 4],
+}
+
+impl FaceLook<'_> {
+  ///
+  /// Check if the light is the same over the whole face.
+  ///
+  /// Merging faces with shading across them would stretch it.
+  ///
+  fn is_flat(&self) -> bool {
+    self
+      .brightness
+      .iter()
+      .all(|corner| *corner == self.brightness[0])
+  }
+}
+
+///
+/// Get which axis a face points along, and the two it spreads out on.
+///
+fn get_axes(direction: IVec3) -> (usize, usize, usize) {
+  if direction.x != 0 {
+    (0, 1, 2)
+  } else if direction.y != 0 {
+    (1, 0, 2)
+  } else {
+    (2, 0, 1)
+  }
+}
+
+///
+/// Get how bright a corner of a face is, smooth lighting style.
+///
+/// The light is averaged from the 4 nodes in front of the face
+/// which touch the corner, solid ones darken it instead.
+///
+fn get_corner_brightness(
+  samples: &[CornerSample],
+  local: IVec3,
+  face: &CubeFace,
+  corner: usize,
+) -> f32 {
+  let (_, u_axis, v_axis) = get_axes(face.direction);
+  let front = local + face.direction;
+
+  let mut side_u = IVec3::ZERO;
+  side_u[u_axis] = face.corners[corner][u_axis].signum() as i32;
+  let mut side_v = IVec3::ZERO;
+  side_v[v_axis] = face.corners[corner][v_axis].signum() as i32;
+
+  let mut light = 0.0;
+  let mut lit = 0;
+  let mut solid = [false; 3];
+
+  for (i, position) in [
+    front,
+    front + side_u,
+    front + side_v,
+    front + side_u + side_v,
+  ]
+  .into_iter()
+  .enumerate()
+  {
+    // Can't see around two solid sides into the diagonal.
+    if i == 3 && solid[0] && solid[1] {
+      solid[2] = true;
+      continue;
+    }
+
+    match samples[MeshInput::get_index(position)] {
+      CornerSample::Ignore => (),
+      CornerSample::Solid => {
+        if i > 0 {
+          solid[i - 1] = true;
+        }
+      }
+      CornerSample::Light(brightness) => {
+        light += brightness;
+        lit += 1;
+      }
+    }
+  }
+
+  let occlusion = match solid[0] && solid[1] {
+    true => 0,
+    false => 3 - solid.iter().filter(|solid| **solid).count(),
+  };
+
+  match lit {
+    0 => 0.0,
+    _ => light / lit as f32 * AMBIENT_OCCLUSION[occlusion] * face.shade,
+  }
+}
+
+///
+/// Build the mesh of a MapBlock, merging faces which sit next
+/// to each other and look the same into bigger ones.
+///
+/// Corners get smooth lighting and ambient occlusion. Faces with
+/// shading across them are drawn one by one, like mesh_block() does.
+///
+pub fn greedy_mesh_block(input: &MeshInput, node_def_manager: &NodeDefManager) -> BlockMesh {
+  let mut mesh = BlockMesh::default();
+  let size = MAP_BLOCK_SIZE as usize;
+  let samples = CornerSample::from_input(input, node_def_manager);
+
+  // Every flat face, by side, then layer, row and column. Shaded ones go in right away.
+  let mut looks: Vec<Option<FaceLook>> = vec![None; CUBE_FACES.len() * MAP_BLOCK_VOLUME];
+
+  for index in 0..MAP_BLOCK_VOLUME {
+    let local = index_to_local(index);
+    let node = input.get_node(local);
+    let definition = node_def_manager.get_or_unknown(node.content);
+    if definition.drawtype != DrawType::Regular {
+      continue;
+    }
+
+    for (side, face) in CUBE_FACES.iter().enumerate() {
+      let neighbour = input.get_node(local + face.direction);
+      if !is_face_visible(
+        node,
+        neighbour,
+        node_def_manager.get_or_unknown(neighbour.content),
+      ) {
+        continue;
+      }
+
+      let look = FaceLook {
+        texture: get_face_texture(definition, face.texture_index),
+        brightness: [0, 1, 2, 3].map(|corner| get_corner_brightness(&samples, local, face, corner)),
+      };
+
+      if look.is_flat() {
+        let (normal_axis, u_axis, v_axis) = get_axes(face.direction);
+        let slot = (local[normal_axis] as usize * size + local[v_axis] as usize) * size
+          + local[u_axis] as usize;
+        looks[side * MAP_BLOCK_VOLUME + slot] = Some(look);
+      } else {
+        mesh.push_face(face, look.texture, local, local, look.brightness);
+      }
+    }
+  }
+
+  for (side, face) in CUBE_FACES.iter().enumerate() {
+    let (normal_axis, u_axis, v_axis) = get_axes(face.direction);
+
+    for layer in 0..size {
+      let to_local = |u: usize, v: usize| {
+        let mut local = IVec3::ZERO;
+        local[normal_axis] = layer as i32;
+        local[u_axis] = u as i32;
+        local[v_axis] = v as i32;
+        local
+      };
+
+      let start = side * MAP_BLOCK_VOLUME + layer * size * size;
+      let layer_looks = &mut looks[start..start + size * size];
+
+      // Grow every face as wide as it goes, then as tall as the whole row goes.
+      for v in 0..size {
+        let mut u = 0;
+        while u < size {
+          let look = match layer_looks[v * size + u] {
+            Some(look) => look,
+            None => {
+              u += 1;
+              continue;
+            }
+          };
+
+          let mut width = 1;
+          while u + width < size && layer_looks[v * size + u + width] == Some(look) {
+            width += 1;
+          }
+
+          let mut height = 1;
+          while v + height < size
+            && layer_looks[(v + height) * size + u..(v + height) * size + u + width]
+              .iter()
+              .all(|other| *other == Some(look))
+          {
+            height += 1;
+          }
+
+          for row in v..v + height {
+            layer_looks[row * size + u..row * size + u + width].fill(None);
+          }
+
+          mesh.push_face(
+            face,
+            look.texture,
+            to_local(u, v),
+            to_local(u + width - 1, v + height - 1),
+            look.brightness,
+          );
+
+          u += width;
+        }
+      }
+    }
+  }
+
+  mesh
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use glam::IVec3;
+
+  use crate::game::{
+    client::mesher::{mesh_block, BlockMesh, MeshInput},
+    map::{
+      lighting::{light_block, LIGHT_SUN},
+      map_block::{MapBlock, MAP_BLOCK_SIZE},
+      mapgen::{Mapgen, MapgenNodes, MapgenParams},
+      node::{Node, CONTENT_AIR, CONTENT_UNKNOWN},
+      node_def_manager::{new_test_node_def_manager, NodeDefManager, NodeDefinition},
+      Map,
+    },
+  };
+
+  use super::greedy_mesh_block;
+
+  fn new_node_def_manager() -> NodeDefManager {
+    let definitions = ["stone", "dirt", "grass"]
+      .iter()
+      .map(|name| NodeDefinition {
+        textures: vec![format!("test_{}.png", name)],
+        ..NodeDefinition::new(&format!("test:{}", name))
+      });
+
+    new_test_node_def_manager(definitions)
+  }
+
+  fn mesh_at(
+    map: &Map,
+    node_def_manager: &NodeDefManager,
+    block_position: IVec3,
+    mesher: fn(&MeshInput, &NodeDefManager) -> BlockMesh,
+  ) -> BlockMesh {
+    match MeshInput::from_map(map, block_position) {
+      Some(input) => mesher(&input, node_def_manager),
+      None => panic!(
+        "Unit test is broken. MapBlock {} is not loaded.",
+        block_position
+      ),
+    }
+  }
+
+  #[test]
+  fn test_greedy_mesh_block() {
+    let node_def_manager = new_node_def_manager();
+    let stone = Node::new(
+      node_def_manager
+        .get_id("test:stone")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+    let sunlit_air = Node::new_with_params(CONTENT_AIR, LIGHT_SUN, 0);
+
+    // A flat floor in the sun is one big face, instead of one for every node.
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(sunlit_air));
+    map.insert_block(IVec3::NEG_Y, MapBlock::new(stone));
+    let mesh = mesh_at(&map, &node_def_manager, IVec3::NEG_Y, greedy_mesh_block);
+    assert_eq!(mesh.get_vertex_count(), 4);
+    assert_eq!(
+      mesh_at(&map, &node_def_manager, IVec3::NEG_Y, mesh_block).get_vertex_count(),
+      (MAP_BLOCK_SIZE * MAP_BLOCK_SIZE * 4) as usize
+    );
+
+    // It covers the whole block, and the texture repeats on every node.
+    let vertices = &mesh.layers[0].vertices;
+    assert!(vertices
+      .iter()
+      .any(|vertex| vertex.position == [-0.5, 15.5, -0.5]));
+    assert!(vertices
+      .iter()
+      .any(|vertex| vertex.position == [15.5, 15.5, 15.5]));
+    assert!(vertices
+      .iter()
+      .any(|vertex| vertex.texture_coordinates == [16.0, 16.0]));
+    assert!(vertices.iter().all(|vertex| vertex.color == [1.0; 3]));
+
+    // A node on the floor shades the corners around it.
+    map.set_node(IVec3::new(8, 0, 8), stone);
+    let floor = mesh_at(&map, &node_def_manager, IVec3::NEG_Y, greedy_mesh_block);
+    let shaded: Vec<f32> = floor.layers[0]
+      .vertices
+      .iter()
+      .map(|vertex| vertex.color[0])
+      .filter(|brightness| *brightness < 1.0)
+      .collect();
+    assert!(!shaded.is_empty());
+    assert!(floor.get_vertex_count() > 4);
+    assert!(
+      floor.get_vertex_count()
+        < mesh_at(&map, &node_def_manager, IVec3::NEG_Y, mesh_block).get_vertex_count()
+    );
+
+    // Different textures never merge.
+    let dirt = Node::new(
+      node_def_manager
+        .get_id("test:dirt")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(sunlit_air));
+    map.insert_block(IVec3::NEG_Y, MapBlock::new(stone));
+    for x in 0..MAP_BLOCK_SIZE / 2 {
+      for z in 0..MAP_BLOCK_SIZE {
+        map.set_node(IVec3::new(x, -1, z), dirt);
+      }
+    }
+    let mesh = mesh_at(&map, &node_def_manager, IVec3::NEG_Y, greedy_mesh_block);
+    assert_eq!(mesh.layers.len(), 2);
+    assert_eq!(mesh.get_vertex_count(), 2 * 4);
+  }
+
+  ///
+  /// Generate 7 by 5 by 7 MapBlocks around a block,
+  /// lit from the top down like the server does it.
+  ///
+  fn generate_terrain(
+    node_def_manager: &NodeDefManager,
+    center: IVec3,
+    generate: impl Fn(IVec3) -> MapBlock,
+  ) -> Map {
+    let mut map = Map::new();
+
+    for y in (center.y - 2..=center.y + 2).rev() {
+      for z in center.z - 3..=center.z + 3 {
+        for x in center.x - 3..=center.x + 3 {
+          let block_position = IVec3::new(x, y, z);
+          map.insert_block(block_position, generate(block_position));
+          light_block(&mut map, node_def_manager, block_position, |_, _| true);
+        }
+      }
+    }
+
+    map
+  }
+
+  ///
+  /// Mesh every block with all it's neighbours loaded with
+  /// both meshers, and print how they compare.
+  ///
+  fn compare_meshers(terrain: &str, map: &Map, node_def_manager: &NodeDefManager) {
+    let inputs: Vec<MeshInput> = map
+      .get_block_positions()
+      .into_iter()
+      .filter(|block_position| {
+        [IVec3::X, IVec3::Y, IVec3::Z].iter().all(|axis| {
+          map.has_block(*block_position + *axis) && map.has_block(*block_position - *axis)
+        })
+      })
+      .filter_map(|block_position| MeshInput::from_map(map, block_position))
+      .collect();
+
+    let run = |mesher: fn(&MeshInput, &NodeDefManager) -> BlockMesh| {
+      let mut vertices = 0;
+      let mut best = Duration::MAX;
+
+      for _ in 0..5 {
+        let started = Instant::now();
+        vertices = inputs
+          .iter()
+          .map(|input| mesher(input, node_def_manager).get_vertex_count())
+          .sum();
+        best = best.min(started.elapsed());
+      }
+
+      (vertices, best / inputs.len() as u32)
+    };
+
+    let (naive_vertices, naive_time) = run(mesh_block);
+    let (greedy_vertices, greedy_time) = run(greedy_mesh_block);
+
+    println!("{} terrain, {} blocks:", terrain, inputs.len());
+    println!(
+      "  naive:  {} vertices, {:?} per block",
+      naive_vertices, naive_time
+    );
+    println!(
+      "  greedy: {} vertices, {:?} per block",
+      greedy_vertices, greedy_time
+    );
+
+    assert!(greedy_vertices <= naive_vertices);
+  }
+
+  ///
+  /// Compare the greedy mesher against the naive one.
+  ///
+  /// Run it with: make bench-meshers
+  ///
+  #[test]
+  #[ignore = "benchmark, run with make bench-meshers"]
+  fn bench_meshers() {
+    let node_def_manager = new_node_def_manager();
+    let get_id = |name: &str| node_def_manager.get_id(name).unwrap_or(CONTENT_UNKNOWN);
+
+    let stone = Node::new(get_id("test:stone"));
+    let flat = generate_terrain(&node_def_manager, IVec3::ZERO, |block_position| {
+      match block_position.y < 0 {
+        true => MapBlock::new(stone),
+        false => MapBlock::new(Node::air()),
+      }
+    });
+    compare_meshers("flat", &flat, &node_def_manager);
+
+    let nodes = MapgenNodes {
+      stone: get_id("test:stone"),
+      dirt: get_id("test:dirt"),
+      grass: get_id("test:grass"),
+      cobble: get_id("test:stone"),
+      mossy_cobble: get_id("test:stone"),
+    };
+    let mapgen = match Mapgen::new(MapgenParams::new(777), nodes) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    let surface = mapgen.get_surface_height(0, 0).div_euclid(MAP_BLOCK_SIZE);
+    let hills = generate_terrain(
+      &node_def_manager,
+      IVec3::new(0, surface, 0),
+      |block_position| mapgen.generate_block(block_position),
+    );
+    compare_meshers("mapgen", &hills, &node_def_manager);
+  }
+}
@@ -0,0 +1,462 @@
+use glam::{IVec3, Vec3};
+
+use crate::game::map::{
+  block_to_node_position,
+  lighting::{blend_light, light_to_brightness},
+  map_block::{index_to_local, MAP_BLOCK_SIZE, MAP_BLOCK_VOLUME},
+  node::{Node, CONTENT_IGNORE},
+  node_def_manager::{DrawType, NodeDefManager, NodeDefinition},
+  Map,
+};
+
+use super::render_engine::mesh::Vertex;
+
+///
+/// How wide a MeshInput is, a MapBlock with a node of it's neighbours all around.
+///
+pub const MESH_INPUT_SIZE: i32 = MAP_BLOCK_SIZE + 2;
+
+///
+/// One side of a cube.
+///
+/// The corners go bottom left, bottom right, top right, top left
+/// when looking at it from outside, so they wind counter clockwise.
+/// The texture is picked the same way as C++ minetest tiles:
+/// +Y, -Y, +X, -X, +Z, -Z, with the last one used for any that are left.
+///
+pub struct CubeFace {
+  pub direction: IVec3,
+  pub corners: [Vec3; 4],
+  pub texture_index: usize,
+  // Same as C++ minetest's face shading, so the sides can be told apart.
+  pub shade: f32,
+}
+
+pub const CUBE_FACES: [CubeFace; 6] = [
+  CubeFace {
+    direction: IVec3::Y,
+    corners: [
+      Vec3::new(0.5, 0.5, -0.5),
+      Vec3::new(-0.5, 0.5, -0.5),
+      Vec3::new(-0.5, 0.5, 0.5),
+      Vec3::new(0.5, 0.5, 0.5),
+    ],
+    texture_index: 0,
+    shade: 1.0,
+  },
+  CubeFace {
+    direction: IVec3::NEG_Y,
+    corners: [
+      Vec3::new(-0.5, -0.5, -0.5),
+      Vec3::new(0.5, -0.5, -0.5),
+      Vec3::new(0.5, -0.5, 0.5),
+      Vec3::new(-0.5, -0.5, 0.5),
+    ],
+    texture_index: 1,
+    shade: 0.447_213,
+  },
+  CubeFace {
+    direction: IVec3::X,
+    corners: [
+      Vec3::new(0.5, -0.5, 0.5),
+      Vec3::new(0.5, -0.5, -0.5),
+      Vec3::new(0.5, 0.5, -0.5),
+      Vec3::new(0.5, 0.5, 0.5),
+    ],
+    texture_index: 2,
+    shade: 0.670_820,
+  },
+  CubeFace {
+    direction: IVec3::NEG_X,
+    corners: [
+      Vec3::new(-0.5, -0.5, -0.5),
+      Vec3::new(-0.5, -0.5, 0.5),
+      Vec3::new(-0.5, 0.5, 0.5),
+      Vec3::new(-0.5, 0.5, -0.5),
+    ],
+    texture_index: 3,
+    shade: 0.670_820,
+  },
+  CubeFace {
+    direction: IVec3::Z,
+    corners: [
+      Vec3::new(-0.5, -0.5, 0.5),
+      Vec3::new(0.5, -0.5, 0.5),
+      Vec3::new(0.5, 0.5, 0.5),
+      Vec3::new(-0.5, 0.5, 0.5),
+    ],
+    texture_index: 4,
+    shade: 0.836_660,
+  },
+  CubeFace {
+    direction: IVec3::NEG_Z,
+    corners: [
+      Vec3::new(0.5, -0.5, -0.5),
+      Vec3::new(-0.5, -0.5, -0.5),
+      Vec3::new(-0.5, 0.5, -0.5),
+      Vec3::new(0.5, 0.5, -0.5),
+    ],
+    texture_index: 5,
+    shade: 0.836_660,
+  },
+];
+
+///
+/// Everything the mesher needs to build the mesh of a MapBlock.
+///
+/// It's a copy of the block with one node of every neighbour around it,
+/// so it can go off to the mesher thread without the Map.
+/// Neighbours which aren't loaded are ignore.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshInput {
+  nodes: Vec<Node>,
+}
+
+impl MeshInput {
+  ///
+  /// Copy a MapBlock and the edges of it's neighbours out of the map.
+  ///
+  /// Returns None if the block isn't loaded.
+  ///
+  pub fn from_map(map: &Map, block_position: IVec3) -> Option<Self> {
+    let block = map.get_block(block_position)?;
+    let origin = block_to_node_position(block_position);
+
+    let mut nodes =
+      Vec::with_capacity((MESH_INPUT_SIZE * MESH_INPUT_SIZE * MESH_INPUT_SIZE) as usize);
+    for z in -1..=MAP_BLOCK_SIZE {
+      for y in -1..=MAP_BLOCK_SIZE {
+        for x in -1..=MAP_BLOCK_SIZE {
+          let local = IVec3::new(x, y, z);
+          let inside =
+            local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(MAP_BLOCK_SIZE)).all();
+
+          nodes.push(match inside {
+            true => block.get_node(local),
+            false => map.get_node_or_ignore(origin + local),
+          });
+        }
+      }
+    }
+
+    Some(MeshInput { nodes })
+  }
+
+  ///
+  /// Get where a node is stored, from -1 to MAP_BLOCK_SIZE on every axis.
+  ///
+  pub fn get_index(local_position: IVec3) -> usize {
+    let position = local_position + IVec3::ONE;
+    (position.z * MESH_INPUT_SIZE * MESH_INPUT_SIZE + position.y * MESH_INPUT_SIZE + position.x)
+      as usize
+  }
+
+  ///
+  /// Get a node, from -1 to MAP_BLOCK_SIZE on every axis.
+  ///
+  pub fn get_node(&self, local_position: IVec3) -> Node {
+    self.nodes[Self::get_index(local_position)]
+  }
+
+  ///
+  /// Get every node, in the order get_index() goes by.
+  ///
+  pub fn get_nodes(&self) -> &[Node] {
+    &self.nodes
+  }
+}
+
+///
+/// The part of a block's mesh which uses one texture.
+///
+/// Vertices are relative to the lowest corner node of the block.
+///
+#[derive(Clone, Debug)]
+pub struct MeshLayer {
+  pub texture: String,
+  pub vertices: Vec<Vertex>,
+  pub indices: Vec<u32>,
+}
+
+///
+/// The mesh of a MapBlock, one layer for every texture it uses.
+///
+/// Layers are never empty, a block with nothing to draw has none.
+///
+#[derive(Clone, Debug, Default)]
+pub struct BlockMesh {
+  pub layers: Vec<MeshLayer>,
+}
+
+impl BlockMesh {
+  ///
+  /// Get the layer for a texture, adding it if it's not there yet.
+  ///
+  fn get_layer(&mut self, texture: &str) -> &mut MeshLayer {
+    let index = match self
+      .layers
+      .iter()
+      .position(|layer| layer.texture == texture)
+    {
+      Some(index) => index,
+      None => {
+        self.layers.push(MeshLayer {
+          texture: texture.to_string(),
+          vertices: vec![],
+          indices: vec![],
+        });
+        self.layers.len() - 1
+      }
+    };
+    &mut self.layers[index]
+  }
+
+  ///
+  /// Add one side of a box of nodes, from the first node to the last one.
+  ///
+  /// The texture repeats once per node. brightness goes in the same
+  /// order as the corners of the face.
+  ///
+  pub fn push_face(
+    &mut self,
+    face: &CubeFace,
+    texture: &str,
+    first: IVec3,
+    last: IVec3,
+    brightness: [f32; 4],
+  ) {
+    let corners = face.corners.map(|corner| {
+      Vec3::select(
+        corner.cmpgt(Vec3::ZERO),
+        last.as_vec3() + corner,
+        first.as_vec3() + corner,
+      )
+    });
+
+    let width = corners[0].distance(corners[1]);
+    let height = corners[1].distance(corners[2]);
+    let texture_coordinates = [[0.0, height], [width, height], [width, 0.0], [0.0, 0.0]];
+
+    let layer = self.get_layer(texture);
+    let start = layer.vertices.len() as u32;
+
+    for ((corner, texture_coordinates), brightness) in
+      corners.iter().zip(texture_coordinates).zip(brightness)
+    {
+      layer.vertices.push(Vertex::new(
+        corner.to_array(),
+        texture_coordinates,
+        [brightness; 3],
+      ));
+    }
+
+    // Split along the brighter diagonal, or shading smears the wrong way.
+    let order = match brightness[0] + brightness[2] < brightness[1] + brightness[3] {
+      true => [1, 2, 3, 1, 3, 0],
+      false => [0, 1, 2, 0, 2, 3],
+    };
+    layer
+      .indices
+      .extend(order.iter().map(|index| start + index));
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.layers.is_empty()
+  }
+
+  pub fn get_vertex_count(&self) -> usize {
+    self.layers.iter().map(|layer| layer.vertices.len()).sum()
+  }
+
+  pub fn get_index_count(&self) -> usize {
+    self.layers.iter().map(|layer| layer.indices.len()).sum()
+  }
+}
+
+///
+/// Check if a node hides the faces of the nodes next to it.
+///
+pub fn is_opaque(definition: &NodeDefinition) -> bool {
+  definition.drawtype == DrawType::Regular && !definition.light_propagates
+}
+
+///
+/// Check if the face of a node towards a neighbour can be seen.
+///
+/// Nothing is drawn against opaque nodes, or against the same kind
+/// of node, so water doesn't have walls inside of it. Ignore hides
+/// faces too, they show up when the neighbour gets loaded.
+///
+pub fn is_face_visible(node: Node, neighbour: Node, neighbour_definition: &NodeDefinition) -> bool {
+  neighbour.content != node.content
+    && neighbour.content != CONTENT_IGNORE
+    && !is_opaque(neighbour_definition)
+}
+
+///
+/// Get the texture of one side of a node.
+///
+pub fn get_face_texture(definition: &NodeDefinition, texture_index: usize) -> &str {
+  match definition
+    .textures
+    .get(texture_index.min(definition.textures.len().saturating_sub(1)))
+  {
+    Some(texture) => texture,
+    None => "",
+  }
+}
+
+///
+/// Get how bright the light in a node is.
+///
+/// Always drawn at noon until the client knows the time of day.
+///
+pub fn get_node_brightness(node: Node) -> f32 {
+  light_to_brightness(blend_light(node.param1, 1.0))
+}
+
+///
+/// Build the mesh of a MapBlock, every face which can be seen.
+///
+/// Only regular nodes are drawn for now.
+///
+pub fn mesh_block(input: &MeshInput, node_def_manager: &NodeDefManager) -> BlockMesh {
+  let mut mesh = BlockMesh::default();
+
+  for index in 0..MAP_BLOCK_VOLUME {
+    let local = index_to_local(index);
+    let node = input.get_node(local);
+    let definition = node_def_manager.get_or_unknown(node.content);
+    if definition.drawtype != DrawType::Regular {
+      continue;
+    }
+
+    for face in &CUBE_FACES {
+      let neighbour = input.get_node(local + face.direction);
+      if !is_face_visible(
+        node,
+        neighbour,
+        node_def_manager.get_or_unknown(neighbour.content),
+      ) {
+        continue;
+      }
+
+      mesh.push_face(
+        face,
+        get_face_texture(definition, face.texture_index),
+        local,
+        local,
+        [get_node_brightness(neighbour) * face.shade; 4],
+      );
+    }
+  }
+
+  mesh
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::{MapBlock, MAP_BLOCK_SIZE},
+    node::{Node, CONTENT_UNKNOWN},
+    node_def_manager::{new_test_node_def_manager, NodeDefManager, NodeDefinition},
+    Map,
+  };
+
+  use super::{mesh_block, MeshInput};
+
+  fn new_node_def_manager() -> NodeDefManager {
+    let definitions = vec![
+      NodeDefinition::new("test:stone"),
+      NodeDefinition {
+        textures: vec![
+          "test_grass.png".to_string(),
+          "test_dirt.png".to_string(),
+          "test_grass_side.png".to_string(),
+        ],
+        ..NodeDefinition::new("test:grass")
+      },
+      NodeDefinition {
+        textures: vec!["test_glass.png".to_string()],
+        light_propagates: true,
+        ..NodeDefinition::new("test:glass")
+      },
+    ];
+
+    new_test_node_def_manager(definitions)
+  }
+
+  #[test]
+  fn test_mesh_block() {
+    let node_def_manager = new_node_def_manager();
+    let get_id = |name: &str| node_def_manager.get_id(name).unwrap_or(CONTENT_UNKNOWN);
+    let stone = Node::new(get_id("test:stone"));
+    let grass = Node::new(get_id("test:grass"));
+    let glass = Node::new(get_id("test:glass"));
+
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+
+    let mesh_at = |map: &Map, block_position: IVec3| match MeshInput::from_map(map, block_position)
+    {
+      Some(input) => mesh_block(&input, &node_def_manager),
+      None => panic!(
+        "Unit test is broken. MapBlock {} is not loaded.",
+        block_position
+      ),
+    };
+
+    // Nothing to draw in air.
+    assert!(mesh_at(&map, IVec3::ZERO).is_empty());
+    assert!(MeshInput::from_map(&map, IVec3::X).is_none());
+
+    // A lone node has all 6 sides, two next to each other hide 2.
+    map.set_node(IVec3::new(4, 4, 4), stone);
+    let mesh = mesh_at(&map, IVec3::ZERO);
+    assert_eq!(mesh.get_vertex_count(), 6 * 4);
+    assert_eq!(mesh.get_index_count(), 6 * 6);
+
+    map.set_node(IVec3::new(5, 4, 4), stone);
+    assert_eq!(mesh_at(&map, IVec3::ZERO).get_vertex_count(), 10 * 4);
+
+    // Light gets through glass, so the stone behind it shows.
+    // Glass on glass doesn't, neither does glass against stone.
+    map.set_node(IVec3::new(6, 4, 4), glass);
+    map.set_node(IVec3::new(7, 4, 4), glass);
+    let mesh = mesh_at(&map, IVec3::ZERO);
+    assert_eq!(mesh.get_vertex_count(), (10 + 4 + 5) * 4);
+    assert_eq!(mesh.layers.len(), 2);
+
+    // Grass has a top, a bottom, and the last texture for every side.
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+    map.set_node(IVec3::new(1, 1, 1), grass);
+    let mesh = mesh_at(&map, IVec3::ZERO);
+    let textures: Vec<(&str, usize)> = mesh
+      .layers
+      .iter()
+      .map(|layer| (layer.texture.as_str(), layer.vertices.len() / 4))
+      .collect();
+    assert_eq!(
+      textures,
+      vec![
+        ("test_grass.png", 1),
+        ("test_dirt.png", 1),
+        ("test_grass_side.png", 4)
+      ]
+    );
+
+    // A full block only shows the sides which face loaded air.
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(stone));
+    assert!(mesh_at(&map, IVec3::ZERO).is_empty());
+    map.insert_block(IVec3::Y, MapBlock::new(Node::air()));
+    assert_eq!(
+      mesh_at(&map, IVec3::ZERO).get_vertex_count(),
+      (MAP_BLOCK_SIZE * MAP_BLOCK_SIZE * 4) as usize
+    );
+  }
+}
@@ -0,0 +1,207 @@
+use std::{
+  sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+};
+
+use glam::IVec3;
+
+use crate::game::map::node_def_manager::NodeDefManager;
+
+use super::{
+  greedy_mesher::greedy_mesh_block,
+  mesher::{mesh_block, BlockMesh, MeshInput},
+};
+
+///
+/// A worker thread which builds MapBlock meshes, so meshing
+/// never holds up drawing.
+///
+/// MeshInputs go in with request_mesh(), finished BlockMeshes
+/// come back out of receive_meshes() in the same order. So if a
+/// block is requested twice, the newest mesh is always the last one.
+///
+/// greedy_meshing picks greedy_mesh_block() over mesh_block().
+///
+pub struct MesherThread {
+  job_sender: Option<Sender<(IVec3, MeshInput)>>,
+  result_receiver: Receiver<(IVec3, BlockMesh)>,
+  worker: Option<JoinHandle<()>>,
+
+  // Requested but not received yet.
+  in_flight: usize,
+}
+
+impl MesherThread {
+  pub fn new(node_def_manager: Arc<NodeDefManager>, greedy_meshing: bool) -> Self {
+    let (job_sender, job_receiver) = mpsc::channel::<(IVec3, MeshInput)>();
+    let (result_sender, result_receiver) = mpsc::channel();
+
+    let spawned = thread::Builder::new()
+      .name("mesher".to_string())
+      .spawn(move || {
+        Self::work(
+          &node_def_manager,
+          greedy_meshing,
+          &job_receiver,
+          &result_sender,
+        )
+      });
+
+    let worker = match spawned {
+      Ok(handle) => handle,
+      Err(e) => panic!("MesherThread: failed to spawn thread. {}", e),
+    };
+
+    MesherThread {
+      job_sender: Some(job_sender),
+      result_receiver,
+      worker: Some(worker),
+
+      in_flight: 0,
+    }
+  }
+
+  ///
+  /// The worker loop, runs until the job queue is dropped.
+  ///
+  fn work(
+    node_def_manager: &NodeDefManager,
+    greedy_meshing: bool,
+    job_receiver: &Receiver<(IVec3, MeshInput)>,
+    result_sender: &Sender<(IVec3, BlockMesh)>,
+  ) {
+    while let Ok((block_position, input)) = job_receiver.recv() {
+      let mesh = match greedy_meshing {
+        true => greedy_mesh_block(&input, node_def_manager),
+        false => mesh_block(&input, node_def_manager),
+      };
+
+      if result_sender.send((block_position, mesh)).is_err() {
+        return;
+      }
+    }
+  }
+
+  ///
+  /// Queue up a MapBlock to be meshed.
+  ///
+  pub fn request_mesh(&mut self, block_position: IVec3, input: MeshInput) {
+    let job_sender = match &self.job_sender {
+      Some(job_sender) => job_sender,
+      None => return,
+    };
+
+    if let Err(e) = job_sender.send((block_position, input)) {
+      panic!("MesherThread: the worker is gone. {}", e);
+    }
+
+    self.in_flight += 1;
+  }
+
+  ///
+  /// Get how many meshes are waiting to be received.
+  ///
+  pub fn get_queue_size(&self) -> usize {
+    self.in_flight
+  }
+
+  ///
+  /// Take every mesh which is finished. (non blocking)
+  ///
+  pub fn receive_meshes(&mut self) -> Vec<(IVec3, BlockMesh)> {
+    let finished: Vec<(IVec3, BlockMesh)> = self.result_receiver.try_iter().collect();
+
+    self.in_flight -= finished.len();
+
+    finished
+  }
+}
+
+impl Drop for MesherThread {
+  fn drop(&mut self) {
+    // Hanging up the queue is what tells the worker to stop.
+    self.job_sender = None;
+
+    if let Some(worker) = self.worker.take() {
+      if worker.join().is_err() {
+        println!("MesherThread: the worker panicked.");
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+  };
+
+  use glam::IVec3;
+
+  use crate::game::{
+    client::mesher::{mesh_block, MeshInput},
+    map::{
+      map_block::MapBlock,
+      node::{Node, CONTENT_UNKNOWN},
+      node_def_manager::{new_test_node_def_manager, NodeDefinition},
+      Map,
+    },
+  };
+
+  use super::MesherThread;
+
+  #[test]
+  fn test_mesher_thread() {
+    let node_def_manager = Arc::new(new_test_node_def_manager([NodeDefinition::new(
+      "test:stone",
+    )]));
+    let stone = Node::new(
+      node_def_manager
+        .get_id("test:stone")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+    map.insert_block(IVec3::X, MapBlock::new(stone));
+
+    let mut thread = MesherThread::new(node_def_manager.clone(), false);
+    let mut inputs = vec![];
+
+    // The same block twice, with a node placed in between.
+    for node_position in [IVec3::new(3, 3, 3), IVec3::new(3, 4, 3)] {
+      map.set_node(node_position, stone);
+      let input = match MeshInput::from_map(&map, IVec3::ZERO) {
+        Some(input) => input,
+        None => panic!("Unit test is broken. MapBlock is not loaded."),
+      };
+      thread.request_mesh(IVec3::ZERO, input.clone());
+      inputs.push(input);
+    }
+    assert_eq!(thread.get_queue_size(), 2);
+
+    let mut received = vec![];
+    let started = Instant::now();
+    while received.len() < inputs.len() {
+      if started.elapsed() > Duration::from_secs(10) {
+        panic!("Unit test is broken. mesher thread never finished.");
+      }
+      received.append(&mut thread.receive_meshes());
+      std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // Same meshes as meshing right here, in the order they were asked for.
+    for ((block_position, mesh), input) in received.iter().zip(&inputs) {
+      let reference = mesh_block(input, &node_def_manager);
+      assert_eq!(*block_position, IVec3::ZERO);
+      assert_eq!(mesh.get_vertex_count(), reference.get_vertex_count());
+    }
+    assert_eq!(received[0].1.get_vertex_count(), 6 * 4);
+    assert_eq!(received[1].1.get_vertex_count(), 10 * 4);
+    assert_eq!(thread.get_queue_size(), 0);
+  }
+}
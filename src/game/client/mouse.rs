@@ -8,12 +8,12 @@ pub struct MouseController {
 }
 
 impl MouseController {
-  pub fn new() -> Self {
+  pub fn new(sensitivity: f32) -> Self {
     MouseController {
       position: IVec2::new(0, 0),
       relative_position: IVec2::new(0, 0),
       relative_mode: false,
-      sensitivity: 0.01,
+      sensitivity,
     }
   }
 
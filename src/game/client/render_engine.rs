@@ -3,7 +3,7 @@ mod color_uniform;
 mod depth_buffer;
 mod instance_trigger;
 pub mod instanced_render_matrix;
-mod mesh;
+pub mod mesh;
 mod mesh_trs_uniform;
 mod model;
 mod model_loader;
@@ -113,7 +113,7 @@ pub struct RenderEngine {
 }
 
 impl RenderEngine {
-  pub fn new(window_handler: &WindowHandler) -> Self {
+  pub fn new(window_handler: &WindowHandler, fov: f32) -> Self {
     // This is written verbosely so you can read what's going on easier.
 
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -294,7 +294,7 @@ impl RenderEngine {
     // Initial creation and updating of the Camera.
     let mut camera = Camera::new(
       Vec3A::new(0.0, 0.0, -2.0),
-      65.0,
+      fov,
       &device,
       window_handler,
       mesh_trs_uniform.get_buffer(),
@@ -721,7 +721,11 @@ impl RenderEngine {
         match self.textures.get(&texture_id) {
           Some(texture) => {
             // Now activate the used texture's bind group.
-            render_pass.set_bind_group(0, texture.get_wgpu_diffuse_bind_group(), &[]);
+            let bind_group = match not_instanced_mesh_render_call.is_tiled() {
+              true => texture.get_wgpu_tiled_bind_group(),
+              false => texture.get_wgpu_diffuse_bind_group(),
+            };
+            render_pass.set_bind_group(0, bind_group, &[]);
 
             self
               .mesh_trs_uniform
@@ -1377,6 +1381,37 @@ impl RenderEngine {
     new_id
   }
 
+  ///
+  /// Create a Mesh from raw vertex and index data and store it.
+  ///
+  /// The data can't be empty, wgpu won't make empty buffers.
+  ///
+  /// Returns the Mesh ID.
+  ///
+  pub fn create_mesh(
+    &mut self,
+    name: &str,
+    mut vertices: Vec<Vertex>,
+    mut indices: Vec<u32>,
+  ) -> u64 {
+    let mut new_mesh = Mesh::new(name);
+    new_mesh.push_vertex_vec(&mut vertices);
+    new_mesh.push_index_vec(&mut indices);
+    new_mesh.generate_wgpu_buffers(&mut self.device);
+    self.store_mesh(name, new_mesh)
+  }
+
+  ///
+  /// Remove a Mesh from the render engine, freeing it's buffers.
+  ///
+  pub fn delete_mesh(&mut self, mesh_id: u64) {
+    if let Some(mesh) = self.meshes.remove(&mesh_id) {
+      if self.mesh_name_to_id.get(mesh.get_name()) == Some(&mesh_id) {
+        self.mesh_name_to_id.remove(mesh.get_name());
+      }
+    }
+  }
+
   ///
   /// Store a Model into the render engine for usage.
   ///
@@ -1445,6 +1480,13 @@ impl RenderEngine {
     }
   }
 
+  ///
+  /// Get Texture ID from the literal &str representation, if it exists.
+  ///
+  pub fn find_texture_id(&self, name: &str) -> Option<u64> {
+    self.texture_name_to_id.get(name).copied()
+  }
+
   ///
   /// Render a mesh not instanced.
   ///
@@ -1465,6 +1507,28 @@ impl RenderEngine {
     ))
   }
 
+  ///
+  /// Render a mesh not instanced, with UVs past 0 to 1 repeating the texture.
+  ///
+  /// This is what greedy meshed MapBlocks need.
+  ///
+  pub fn render_mesh_tiled(
+    &mut self,
+    mesh_id: u64,
+    texture_id: u64,
+    translation: Vec3A,
+    rotation: Vec3A,
+    scale: Vec3A,
+  ) {
+    self.mesh_render_queue.push_back(MeshRenderCall::new_tiled(
+      mesh_id,
+      texture_id,
+      translation,
+      rotation,
+      scale,
+    ))
+  }
+
   ///
   /// Render a model, not instanced.
   ///
@@ -70,7 +70,7 @@ impl Camera {
       rotation: Vec3A::new(0.0, 0.0, 0.0),
       up: glam::Vec3A::Y,
       aspect_ratio: window_handler.get_width() as f32 / window_handler.get_height() as f32,
-      fov_y: 90.0,
+      fov_y,
       z_near: 0.1,
       z_far: 100.0,
 
@@ -84,7 +84,7 @@ impl Camera {
   }
 
   ///
-  /// Set the FOV of the Camera.
+  /// Set the FOV of the Camera. (in degrees)
   ///
   pub fn set_fov(&mut self, new_fov: f32) {
     self.fov_y = new_fov;
@@ -104,6 +104,15 @@ impl Camera {
     &self.eye
   }
 
+  ///
+  /// Get where the Camera is in the world.
+  ///
+  /// The view translates by eye, so the world sits at the opposite of it.
+  ///
+  pub fn get_world_position(&self) -> Vec3 {
+    -Vec3::from(self.eye)
+  }
+
   ///
   /// Set the Camera's rotation.
   ///
@@ -145,7 +154,11 @@ impl Camera {
 
     let translation = Mat4::from_translation(Vec3::from(self.eye));
 
-    let projection = Mat4::perspective_infinite_rh(self.fov_y, self.aspect_ratio, self.z_near);
+    let projection = Mat4::perspective_infinite_rh(
+      self.fov_y.to_radians(),
+      self.aspect_ratio,
+      self.z_near,
+    );
 
     self.camera_uniform.projection = (projection * rotation * translation).to_cols_array_2d();
 
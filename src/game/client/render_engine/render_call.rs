@@ -9,6 +9,7 @@ pub struct MeshRenderCall {
   translation: Vec3A,
   rotation: Vec3A,
   scale: Vec3A,
+  tiled: bool,
 }
 
 ///
@@ -30,6 +31,23 @@ impl MeshRenderCall {
       translation,
       rotation,
       scale,
+      tiled: false,
+    }
+  }
+
+  ///
+  /// A MeshRenderCall which repeats the Texture past it's edges.
+  ///
+  pub fn new_tiled(
+    mesh_id: u64,
+    texture_id: u64,
+    translation: Vec3A,
+    rotation: Vec3A,
+    scale: Vec3A,
+  ) -> Self {
+    MeshRenderCall {
+      tiled: true,
+      ..Self::new(mesh_id, texture_id, translation, rotation, scale)
     }
   }
 
@@ -67,6 +85,13 @@ impl MeshRenderCall {
   pub fn get_scale(&self) -> &Vec3A {
     &self.scale
   }
+
+  ///
+  /// Check if the Texture repeats, for UVs past 0 to 1.
+  ///
+  pub fn is_tiled(&self) -> bool {
+    self.tiled
+  }
 }
 
 ///
@@ -8,10 +8,12 @@ pub struct Texture {
   dimensions: UVec2,
 
   diffuse_bind_group: wgpu::BindGroup,
+  tiled_bind_group: wgpu::BindGroup,
 
   texture: wgpu::Texture,
   view: wgpu::TextureView,
   sampler: wgpu::Sampler,
+  tiled_sampler: wgpu::Sampler,
 }
 
 impl Texture {
@@ -98,6 +100,17 @@ impl Texture {
       ..Default::default()
     });
 
+    // Merged map faces tile one texture over many nodes, so they get their own sampler.
+    let tiled_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+      address_mode_u: wgpu::AddressMode::Repeat,
+      address_mode_v: wgpu::AddressMode::Repeat,
+      address_mode_w: wgpu::AddressMode::Repeat,
+      mag_filter: wgpu::FilterMode::Nearest,
+      min_filter: wgpu::FilterMode::Nearest,
+      mipmap_filter: wgpu::FilterMode::Nearest,
+      ..Default::default()
+    });
+
     let mut diffuse_bind_group_name = name.clone();
     diffuse_bind_group_name.push_str("_diffuse_bind_group");
 
@@ -116,15 +129,35 @@ impl Texture {
       label: Some(&diffuse_bind_group_name),
     });
 
+    let mut tiled_bind_group_name = name.clone();
+    tiled_bind_group_name.push_str("_tiled_bind_group");
+
+    let tiled_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+      layout: &Texture::get_wgpu_bind_group_layout(device),
+      entries: &[
+        wgpu::BindGroupEntry {
+          binding: 0,
+          resource: wgpu::BindingResource::TextureView(&view),
+        },
+        wgpu::BindGroupEntry {
+          binding: 1,
+          resource: wgpu::BindingResource::Sampler(&tiled_sampler),
+        },
+      ],
+      label: Some(&tiled_bind_group_name),
+    });
+
     Texture {
       name,
       dimensions: UVec2::new(dimensions.0, dimensions.1),
 
       diffuse_bind_group,
+      tiled_bind_group,
 
       texture,
       view,
       sampler,
+      tiled_sampler,
     }
   }
 
@@ -142,6 +175,13 @@ impl Texture {
     &self.diffuse_bind_group
   }
 
+  ///
+  /// Get the wgpu bind group which repeats the Texture past it's edges.
+  ///
+  pub fn get_wgpu_tiled_bind_group(&self) -> &wgpu::BindGroup {
+    &self.tiled_bind_group
+  }
+
   ///
   /// Static function to tell wgpu how to use the Texture.
   ///
@@ -42,7 +42,7 @@ pub struct WindowHandler {
 }
 
 impl WindowHandler {
-  pub fn new(mouse: &mut MouseController) -> Self {
+  pub fn new(mouse: &mut MouseController, width: u32, height: u32) -> Self {
     // We're going to do this line by line,
     // in case any of this fails.
 
@@ -59,7 +59,7 @@ impl WindowHandler {
       Err(e) => panic!("WindowHandler: Failed to initialize video subsystem. {}", e),
     };
 
-    let size = UVec2::new(512, 512);
+    let size = UVec2::new(width, height);
 
     let window = match video_subsystem
       .window("AMONGUSTESTLOLOLOLOLOLOLOLOLOLOLOLOLOL", size.x, size.y)
@@ -0,0 +1,99 @@
+use rusqlite::{params_from_iter, types::Value as SqliteValue, Connection, Row};
+use sea_query::{Value, Values};
+
+// Helpers for running sea-query statements through rusqlite.
+//
+// sea-query builds the SQL and it's parameters, rusqlite runs them.
+// Values are always bound, never pasted into the SQL.
+
+///
+/// Open (or create) a SQLite database file.
+///
+pub fn open_database(path: &str) -> Result<Connection, String> {
+  match Connection::open(path) {
+    Ok(connection) => Ok(connection),
+    Err(e) => Err(format!("failed to open database [{}]. {}", path, e)),
+  }
+}
+
+///
+/// Turn sea-query values into values rusqlite can bind.
+///
+pub fn to_sqlite_values(values: &Values) -> Vec<SqliteValue> {
+  values
+    .iter()
+    .map(|value| match value {
+      Value::Bool(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::TinyInt(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::SmallInt(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::Int(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::BigInt(v) => v.map_or(SqliteValue::Null, SqliteValue::Integer),
+      Value::TinyUnsigned(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::SmallUnsigned(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::Unsigned(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::BigUnsigned(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Integer(v as i64)),
+      Value::Float(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Real(v as f64)),
+      Value::Double(v) => v.map_or(SqliteValue::Null, SqliteValue::Real),
+      Value::String(v) => match v {
+        Some(v) => SqliteValue::Text(v.as_ref().clone()),
+        None => SqliteValue::Null,
+      },
+      Value::Char(v) => v.map_or(SqliteValue::Null, |v| SqliteValue::Text(v.to_string())),
+      Value::Bytes(v) => match v {
+        Some(v) => SqliteValue::Blob(v.as_ref().clone()),
+        None => SqliteValue::Null,
+      },
+      #[allow(unreachable_patterns)]
+      _ => panic!("Database: unsupported value type {:?}.", value),
+    })
+    .collect()
+}
+
+///
+/// Run a statement that doesn't return rows.
+///
+/// Returns how many rows were changed.
+///
+pub fn execute(database: &Connection, (sql, values): (String, Values)) -> Result<usize, String> {
+  match database.execute(&sql, params_from_iter(to_sqlite_values(&values))) {
+    Ok(changed) => Ok(changed),
+    Err(e) => Err(format!("failed to run [{}]. {}", sql, e)),
+  }
+}
+
+///
+/// Run a query and turn every row into something with a mapper.
+///
+pub fn query<T, F>(
+  database: &Connection,
+  (sql, values): (String, Values),
+  mapper: F,
+) -> Result<Vec<T>, String>
+where
+  F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
+{
+  let mut statement = match database.prepare(&sql) {
+    Ok(statement) => statement,
+    Err(e) => return Err(format!("failed to prepare [{}]. {}", sql, e)),
+  };
+
+  let rows = match statement.query_map(params_from_iter(to_sqlite_values(&values)), mapper) {
+    Ok(rows) => rows,
+    Err(e) => return Err(format!("failed to run [{}]. {}", sql, e)),
+  };
+
+  match rows.collect::<rusqlite::Result<Vec<T>>>() {
+    Ok(rows) => Ok(rows),
+    Err(e) => Err(format!("failed to read rows of [{}]. {}", sql, e)),
+  }
+}
+
+///
+/// Seconds since the unix epoch.
+///
+pub fn unix_time() -> i64 {
+  match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+    Ok(duration) => duration.as_secs() as i64,
+    Err(_) => 0,
+  }
+}
@@ -0,0 +1,2 @@
+pub mod item_def_manager;
+pub mod item_stack;
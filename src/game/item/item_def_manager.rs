@@ -0,0 +1,364 @@
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::game::map::node_def_manager::{is_valid_node_name, DrawType, NodeDefManager};
+
+///
+/// How many items fit in a stack when a definition doesn't say.
+///
+pub const DEFAULT_STACK_MAX: u16 = 99;
+
+///
+/// What kind of item something is.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemType {
+  // The item form of a registered node.
+  Node,
+  CraftItem,
+  Tool,
+}
+
+impl ItemType {
+  ///
+  /// Parse the type field of a Lua ItemDefinition.
+  ///
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "node" => Some(ItemType::Node),
+      "craftitem" => Some(ItemType::CraftItem),
+      "tool" => Some(ItemType::Tool),
+      _ => None,
+    }
+  }
+}
+
+///
+/// How well a tool digs one group of nodes.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ToolGroupCapability {
+  // How long it takes to dig a node of each rating, in seconds.
+  pub times: Vec<(i64, f32)>,
+  // How many nodes it can dig before it breaks, 0 is forever.
+  pub uses: u32,
+  pub max_level: i32,
+}
+
+///
+/// What a tool can do, same shape as C++ minetest's tool_capabilities.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ToolCapabilities {
+  pub full_punch_interval: f32,
+  pub max_drop_level: i32,
+  pub group_capabilities: Vec<(String, ToolGroupCapability)>,
+  pub damage_groups: Vec<(String, i64)>,
+}
+
+impl Default for ToolCapabilities {
+  fn default() -> Self {
+    ToolCapabilities {
+      full_punch_interval: 1.0,
+      max_drop_level: 0,
+      group_capabilities: vec![],
+      damage_groups: vec![],
+    }
+  }
+}
+
+///
+/// Everything the engine knows about a type of item.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ItemDefinition {
+  pub name: String,
+  pub description: String,
+  pub item_type: ItemType,
+  pub stack_max: u16,
+  pub inventory_image: String,
+  pub wield_image: String,
+  pub tool_capabilities: Option<ToolCapabilities>,
+  pub groups: Vec<(String, i64)>,
+}
+
+impl ItemDefinition {
+  ///
+  /// A definition with the defaults a mod would get.
+  ///
+  /// Tools don't stack.
+  ///
+  pub fn new(name: &str, item_type: ItemType) -> Self {
+    ItemDefinition {
+      name: name.to_string(),
+      description: String::new(),
+      item_type,
+      stack_max: match item_type {
+        ItemType::Tool => 1,
+        _ => DEFAULT_STACK_MAX,
+      },
+      inventory_image: String::new(),
+      wield_image: String::new(),
+      tool_capabilities: None,
+      groups: vec![],
+    }
+  }
+
+  ///
+  /// Get the rating of a group, 0 if it's not in it.
+  ///
+  pub fn get_group(&self, group: &str) -> i64 {
+    self
+      .groups
+      .iter()
+      .find(|(name, _)| name == group)
+      .map_or(0, |(_, rating)| *rating)
+  }
+
+  ///
+  /// Check the definition makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if !is_valid_node_name(&self.name) {
+      return Err(format!(
+        "[{}] is not a valid item name, it must look like [modname:itemname].",
+        self.name
+      ));
+    }
+
+    if self.stack_max == 0 {
+      return Err(format!("[{}] has a stack_max of 0.", self.name));
+    }
+
+    if self.item_type == ItemType::Tool && self.stack_max != 1 {
+      return Err(format!("[{}] is a tool, tools can't stack.", self.name));
+    }
+
+    if self.item_type != ItemType::Tool && self.tool_capabilities.is_some() {
+      return Err(format!(
+        "[{}] has tool_capabilities but isn't a tool.",
+        self.name
+      ));
+    }
+
+    if let Some(tool_capabilities) = &self.tool_capabilities {
+      if tool_capabilities.full_punch_interval < 0.0 {
+        return Err(format!(
+          "[{}] has a negative full_punch_interval.",
+          self.name
+        ));
+      }
+    }
+
+    for (group, _) in &self.groups {
+      if group.is_empty() {
+        return Err(format!("[{}] has a group with no name.", self.name));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// The registry of every item type.
+///
+/// Every node gets an item too, so it can be held in an inventory.
+///
+pub struct ItemDefManager {
+  definitions: AHashMap<String, ItemDefinition>,
+}
+
+impl ItemDefManager {
+  pub fn new() -> Self {
+    ItemDefManager {
+      definitions: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Create an ItemDefManager from the definitions mods registered,
+  /// plus an item for every node.
+  ///
+  pub fn from_definitions(
+    definitions: Vec<ItemDefinition>,
+    node_def_manager: &NodeDefManager,
+  ) -> Result<Self, String> {
+    let mut new_manager = Self::new();
+
+    for definition in definitions {
+      if definition.item_type == ItemType::Node {
+        return Err(format!(
+          "[{}] can't be registered as a node item, use minetest.register_block().",
+          definition.name
+        ));
+      }
+
+      if node_def_manager.get_id(&definition.name).is_some() {
+        return Err(format!(
+          "[{}] is already a registered block.",
+          definition.name
+        ));
+      }
+
+      new_manager.register(definition)?;
+    }
+
+    for (_, node_name) in node_def_manager.get_name_id_mapping() {
+      let node_definition = match node_def_manager.get_id(&node_name) {
+        Some(id) => node_def_manager.get_or_unknown(id),
+        None => continue,
+      };
+
+      // Nobody can hold air.
+      if node_definition.drawtype == DrawType::Air {
+        continue;
+      }
+
+      // Engine nodes like "unknown" don't have a mod name.
+      if !is_valid_node_name(&node_definition.name) {
+        continue;
+      }
+
+      new_manager.register(ItemDefinition {
+        description: node_definition.description.clone(),
+        inventory_image: node_definition
+          .textures
+          .first()
+          .cloned()
+          .unwrap_or_default(),
+        groups: node_definition.groups.clone(),
+        ..ItemDefinition::new(&node_definition.name, ItemType::Node)
+      })?;
+    }
+
+    Ok(new_manager)
+  }
+
+  ///
+  /// Register an item type.
+  ///
+  pub fn register(&mut self, definition: ItemDefinition) -> Result<(), String> {
+    definition.validate()?;
+
+    if self.definitions.contains_key(&definition.name) {
+      return Err(format!(
+        "[{}] is already a registered item.",
+        definition.name
+      ));
+    }
+
+    self.definitions.insert(definition.name.clone(), definition);
+
+    Ok(())
+  }
+
+  ///
+  /// Get the definition of an item.
+  ///
+  pub fn get(&self, name: &str) -> Option<&ItemDefinition> {
+    self.definitions.get(name)
+  }
+
+  ///
+  /// Get how many of an item fit in one stack.
+  ///
+  /// Unknown items stack like craftitems.
+  ///
+  pub fn get_stack_max(&self, name: &str) -> u16 {
+    self
+      .definitions
+      .get(name)
+      .map_or(DEFAULT_STACK_MAX, |definition| definition.stack_max)
+  }
+
+  ///
+  /// Get how many item types are registered.
+  ///
+  pub fn get_count(&self) -> usize {
+    self.definitions.len()
+  }
+}
+
+impl Default for ItemDefManager {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::game::map::node_def_manager::{NodeDefManager, NodeDefinition};
+
+  use super::{ItemDefManager, ItemDefinition, ItemType, ToolCapabilities};
+
+  #[test]
+  fn test_item_def_manager() {
+    let stone = NodeDefinition {
+      description: "Stone".to_string(),
+      textures: vec!["default_stone.png".to_string()],
+      ..NodeDefinition::new("test:stone")
+    };
+    let node_def_manager = match NodeDefManager::from_definitions(vec![stone]) {
+      Ok(node_def_manager) => node_def_manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    let pick = ItemDefinition {
+      tool_capabilities: Some(ToolCapabilities::default()),
+      ..ItemDefinition::new("test:pick", ItemType::Tool)
+    };
+    let apple = ItemDefinition::new("test:apple", ItemType::CraftItem);
+
+    let item_def_manager =
+      match ItemDefManager::from_definitions(vec![pick, apple], &node_def_manager) {
+        Ok(item_def_manager) => item_def_manager,
+        Err(e) => panic!("Unit test is broken. {}", e),
+      };
+
+    // Nodes become items, air and the engine nodes don't.
+    assert_eq!(item_def_manager.get_count(), 3);
+    assert_eq!(
+      item_def_manager.get("test:stone").map(|d| d.item_type),
+      Some(ItemType::Node)
+    );
+    assert_eq!(
+      item_def_manager
+        .get("test:stone")
+        .map(|d| d.inventory_image.as_str()),
+      Some("default_stone.png")
+    );
+    assert!(item_def_manager.get("air").is_none());
+
+    assert_eq!(item_def_manager.get_stack_max("test:pick"), 1);
+    assert_eq!(item_def_manager.get_stack_max("test:apple"), 99);
+
+    // Can't take a node's name.
+    let fake_stone = ItemDefinition::new("test:stone", ItemType::CraftItem);
+    assert!(ItemDefManager::from_definitions(vec![fake_stone], &node_def_manager).is_err());
+  }
+
+  #[test]
+  fn test_item_definition_validation() {
+    let mut item_def_manager = ItemDefManager::new();
+
+    let stacking_tool = ItemDefinition {
+      stack_max: 5,
+      ..ItemDefinition::new("test:pick", ItemType::Tool)
+    };
+    assert!(item_def_manager.register(stacking_tool).is_err());
+
+    let digging_apple = ItemDefinition {
+      tool_capabilities: Some(ToolCapabilities::default()),
+      ..ItemDefinition::new("test:apple", ItemType::CraftItem)
+    };
+    assert!(item_def_manager.register(digging_apple).is_err());
+
+    let bad_name = ItemDefinition::new("apple", ItemType::CraftItem);
+    assert!(item_def_manager.register(bad_name).is_err());
+
+    let apple = ItemDefinition::new("test:apple", ItemType::CraftItem);
+    assert!(item_def_manager.register(apple.clone()).is_ok());
+    assert!(item_def_manager.register(apple).is_err());
+  }
+}
@@ -0,0 +1,400 @@
+use ahash::AHashMap;
+use mlua::{Lua, Table, UserData, UserDataMethods, Value};
+
+use super::item_def_manager::ItemDefManager;
+
+///
+/// Tools break once their wear goes past this.
+///
+pub const MAX_WEAR: u32 = 65_535;
+
+///
+/// A stack of items, like a slot in an inventory.
+///
+/// An empty stack has no name and a count of 0.
+///
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ItemStack {
+  name: String,
+  count: u16,
+  wear: u16,
+  metadata: AHashMap<String, String>,
+}
+
+impl ItemStack {
+  pub fn new(name: &str, count: u16) -> Self {
+    let mut new_stack = ItemStack {
+      name: name.to_string(),
+      count,
+      wear: 0,
+      metadata: AHashMap::new(),
+    };
+
+    // A stack of nothing is just empty.
+    if new_stack.name.is_empty() || count == 0 {
+      new_stack.clear();
+    }
+
+    new_stack
+  }
+
+  ///
+  /// Parse a stack from "name [count] [wear]", like "default:stone 99".
+  ///
+  pub fn from_string(item_string: &str) -> Result<Self, String> {
+    let mut parts = item_string.split_whitespace();
+
+    let name = match parts.next() {
+      Some(name) => name,
+      None => return Ok(ItemStack::default()),
+    };
+
+    let count = match parts.next() {
+      Some(count) => match count.parse::<u16>() {
+        Ok(count) => count,
+        Err(e) => return Err(format!("invalid count in [{}]. {}", item_string, e)),
+      },
+      None => 1,
+    };
+
+    let wear = match parts.next() {
+      Some(wear) => match wear.parse::<u16>() {
+        Ok(wear) => wear,
+        Err(e) => return Err(format!("invalid wear in [{}]. {}", item_string, e)),
+      },
+      None => 0,
+    };
+
+    let mut new_stack = ItemStack::new(name, count);
+    new_stack.set_wear(wear);
+
+    Ok(new_stack)
+  }
+
+  ///
+  /// Turn the stack back into "name [count] [wear]".
+  ///
+  /// Metadata is not part of the string.
+  ///
+  pub fn to_item_string(&self) -> String {
+    match (self.count, self.wear) {
+      (0, _) => String::new(),
+      (1, 0) => self.name.clone(),
+      (count, 0) => format!("{} {}", self.name, count),
+      (count, wear) => format!("{} {} {}", self.name, count, wear),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.count == 0
+  }
+
+  ///
+  /// Turn this into an empty stack.
+  ///
+  pub fn clear(&mut self) {
+    self.name.clear();
+    self.count = 0;
+    self.wear = 0;
+    self.metadata.clear();
+  }
+
+  pub fn get_name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn set_name(&mut self, name: &str) {
+    self.name = name.to_string();
+    if self.name.is_empty() {
+      self.clear();
+    }
+  }
+
+  pub fn get_count(&self) -> u16 {
+    self.count
+  }
+
+  ///
+  /// Set how many items are in the stack, 0 empties it.
+  ///
+  pub fn set_count(&mut self, count: u16) {
+    self.count = count;
+    if count == 0 {
+      self.clear();
+    }
+  }
+
+  pub fn get_wear(&self) -> u16 {
+    self.wear
+  }
+
+  pub fn set_wear(&mut self, wear: u16) {
+    self.wear = wear;
+  }
+
+  ///
+  /// Wear the tool down.
+  ///
+  /// Returns true if it broke, which empties the stack.
+  ///
+  pub fn add_wear(&mut self, amount: u32) -> bool {
+    if self.is_empty() {
+      return false;
+    }
+
+    let wear = self.wear as u32 + amount;
+    if wear > MAX_WEAR {
+      self.clear();
+      return true;
+    }
+
+    self.wear = wear as u16;
+    false
+  }
+
+  ///
+  /// Get a metadata value, empty if it's not set.
+  ///
+  pub fn get_meta(&self, key: &str) -> &str {
+    match self.metadata.get(key) {
+      Some(value) => value,
+      None => "",
+    }
+  }
+
+  ///
+  /// Set a metadata value, an empty value removes it.
+  ///
+  pub fn set_meta(&mut self, key: &str, value: &str) {
+    if value.is_empty() {
+      self.metadata.remove(key);
+    } else {
+      self.metadata.insert(key.to_string(), value.to_string());
+    }
+  }
+
+  ///
+  /// Take some items off of the stack.
+  ///
+  pub fn take_item(&mut self, count: u16) -> ItemStack {
+    let taken_count = count.min(self.count);
+    let mut taken = self.clone();
+    taken.set_count(taken_count);
+    self.set_count(self.count - taken_count);
+    taken
+  }
+
+  ///
+  /// Check if two stacks can be merged into one.
+  ///
+  pub fn can_stack_with(&self, other: &ItemStack) -> bool {
+    self.is_empty()
+      || other.is_empty()
+      || (self.name == other.name && self.wear == other.wear && self.metadata == other.metadata)
+  }
+
+  ///
+  /// Put another stack on top of this one.
+  ///
+  /// Returns what didn't fit.
+  ///
+  pub fn add_item(&mut self, mut other: ItemStack, item_def_manager: &ItemDefManager) -> ItemStack {
+    if other.is_empty() || !self.can_stack_with(&other) {
+      return other;
+    }
+
+    let stack_max = item_def_manager.get_stack_max(other.get_name());
+
+    if self.is_empty() {
+      *self = other.take_item(stack_max);
+      return other;
+    }
+
+    let space = stack_max.saturating_sub(self.count);
+    let moved = other.take_item(space);
+    self.count += moved.count;
+
+    other
+  }
+}
+
+impl UserData for ItemStack {
+  fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    methods.add_method("is_empty", |_, this, ()| Ok(this.is_empty()));
+    methods.add_method_mut("clear", |_, this, ()| {
+      this.clear();
+      Ok(())
+    });
+
+    methods.add_method("get_name", |_, this, ()| Ok(this.get_name().to_string()));
+    methods.add_method_mut("set_name", |_, this, name: String| {
+      this.set_name(&name);
+      Ok(())
+    });
+
+    methods.add_method("get_count", |_, this, ()| Ok(this.get_count()));
+    methods.add_method_mut("set_count", |_, this, count: u16| {
+      this.set_count(count);
+      Ok(())
+    });
+
+    methods.add_method("get_wear", |_, this, ()| Ok(this.get_wear()));
+    methods.add_method_mut("set_wear", |_, this, wear: u16| {
+      this.set_wear(wear);
+      Ok(())
+    });
+    methods.add_method_mut("add_wear", |_, this, amount: u32| Ok(this.add_wear(amount)));
+
+    methods.add_method("get_meta", |_, this, key: String| {
+      Ok(this.get_meta(&key).to_string())
+    });
+    methods.add_method_mut("set_meta", |_, this, (key, value): (String, String)| {
+      this.set_meta(&key, &value);
+      Ok(())
+    });
+
+    methods.add_method_mut("take_item", |_, this, count: Option<u16>| {
+      Ok(this.take_item(count.unwrap_or(1)))
+    });
+
+    methods.add_method("to_string", |_, this, ()| Ok(this.to_item_string()));
+  }
+}
+
+///
+/// The ItemStack() constructor in Lua.
+///
+/// Takes an item string, a table like {name = "default:stone", count = 5},
+/// another ItemStack, or nothing.
+///
+pub fn item_stack_from_lua<'lua>(_: &'lua Lua, value: Value<'lua>) -> mlua::Result<ItemStack> {
+  match value {
+    Value::Nil => Ok(ItemStack::default()),
+    Value::String(item_string) => {
+      ItemStack::from_string(item_string.to_str()?).map_err(mlua::Error::RuntimeError)
+    }
+    Value::Table(table) => item_stack_from_table(&table),
+    Value::UserData(user_data) => Ok(user_data.borrow::<ItemStack>()?.clone()),
+    other => Err(mlua::Error::RuntimeError(format!(
+      "can't make an ItemStack out of a {}.",
+      other.type_name()
+    ))),
+  }
+}
+
+fn item_stack_from_table(table: &Table) -> mlua::Result<ItemStack> {
+  let name: String = table.get::<_, Option<String>>("name")?.unwrap_or_default();
+  let count: u16 = table.get::<_, Option<u16>>("count")?.unwrap_or(1);
+
+  let mut new_stack = ItemStack::new(&name, count);
+  new_stack.set_wear(table.get::<_, Option<u16>>("wear")?.unwrap_or(0));
+
+  if let Some(metadata) = table.get::<_, Option<Table>>("metadata")? {
+    for pair in metadata.pairs::<String, String>() {
+      let (key, value) = pair?;
+      new_stack.set_meta(&key, &value);
+    }
+  }
+
+  Ok(new_stack)
+}
+
+#[cfg(test)]
+mod tests {
+  use mlua::Lua;
+
+  use crate::game::item::item_def_manager::{ItemDefManager, ItemDefinition, ItemType};
+
+  use super::{item_stack_from_lua, ItemStack};
+
+  #[test]
+  fn test_item_stack() {
+    let mut stack = match ItemStack::from_string("test:pick 1 65000") {
+      Ok(stack) => stack,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(stack.get_name(), "test:pick");
+    assert_eq!(stack.get_wear(), 65000);
+    assert_eq!(stack.to_item_string(), "test:pick 1 65000");
+
+    assert!(!stack.add_wear(535));
+    assert!(stack.add_wear(1));
+    assert!(stack.is_empty());
+
+    assert!(ItemStack::from_string("test:stone lots").is_err());
+    assert_eq!(ItemStack::from_string(""), Ok(ItemStack::default()));
+
+    let mut stack = ItemStack::new("test:stone", 10);
+    stack.set_meta("owner", "sam");
+    let taken = stack.take_item(4);
+    assert_eq!(taken.get_count(), 4);
+    assert_eq!(taken.get_meta("owner"), "sam");
+    assert_eq!(stack.get_count(), 6);
+
+    stack.set_count(0);
+    assert!(stack.is_empty());
+    assert_eq!(stack.get_meta("owner"), "");
+  }
+
+  #[test]
+  fn test_item_stack_add_item() {
+    let mut item_def_manager = ItemDefManager::new();
+    let definition = ItemDefinition {
+      stack_max: 10,
+      ..ItemDefinition::new("test:apple", ItemType::CraftItem)
+    };
+    if let Err(e) = item_def_manager.register(definition) {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    let mut stack = ItemStack::new("test:apple", 7);
+    let leftover = stack.add_item(ItemStack::new("test:apple", 5), &item_def_manager);
+    assert_eq!(stack.get_count(), 10);
+    assert_eq!(leftover.get_count(), 2);
+
+    // Different items don't mix.
+    let leftover = stack.add_item(ItemStack::new("test:pear", 1), &item_def_manager);
+    assert_eq!(leftover, ItemStack::new("test:pear", 1));
+  }
+
+  #[test]
+  fn test_item_stack_lua() {
+    let lua = Lua::new();
+
+    let constructor = match lua.create_function(item_stack_from_lua) {
+      Ok(constructor) => constructor,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    if let Err(e) = lua.globals().set("ItemStack", constructor) {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    let code = r#"
+      local stack = ItemStack("test:stone 5")
+      stack:set_count(stack:get_count() + 1)
+      stack:set_meta("owner", "sam")
+
+      local copy = ItemStack(stack)
+      local taken = copy:take_item(2)
+      assert(taken:get_count() == 2 and copy:get_count() == 4)
+      assert(taken:get_meta("owner") == "sam")
+
+      local tool = ItemStack({name = "test:pick", wear = 100})
+      assert(tool:add_wear(70000) == true and tool:is_empty())
+
+      return stack
+    "#;
+
+    let stack: ItemStack = match lua.load(code).eval::<mlua::AnyUserData>() {
+      Ok(user_data) => match user_data.borrow::<ItemStack>() {
+        Ok(stack) => stack.clone(),
+        Err(e) => panic!("Unit test is broken. {}", e),
+      },
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    assert_eq!(stack.get_name(), "test:stone");
+    assert_eq!(stack.get_count(), 6);
+    assert_eq!(stack.get_meta("owner"), "sam");
+  }
+}
@@ -1,13 +1,40 @@
 mod lua_file_helpers;
+pub mod mapgen_tables;
 
 use core::panic;
 
 use configparser::ini::Ini;
-use mlua::Lua;
+use glam::IVec3;
+use mlua::{FromLuaMulti, Function, IntoLuaMulti, Lua, Table};
 
-use crate::file_utilities::read_file_to_string;
+use crate::{
+  file_utilities::read_file_to_string,
+  game::{
+    item::{
+      item_def_manager::{ItemDefinition, ItemType, ToolCapabilities, ToolGroupCapability},
+      item_stack::item_stack_from_lua,
+    },
+    map::{
+      block_modifier::{AbmDefinition, LbmDefinition},
+      mapgen::{biome::BiomeDefinition, decoration::DecorationDefinition, ore::OreDefinition},
+      node::Node,
+      node_def_manager::{DrawType, LiquidType, NodeDefinition},
+    },
+  },
+};
 
-use self::lua_file_helpers::{check_game, get_game_mod_folders, get_game_path};
+use self::{
+  lua_file_helpers::{check_game, get_game_mod_folders, get_game_path},
+  mapgen_tables::{
+    biome_definition_from_table, decoration_definition_from_table, names_from_field,
+    ore_definition_from_table,
+  },
+};
+
+///
+/// Where games are installed, run-in-place for now.
+///
+pub const GAMES_DIR: &str = "./games";
 
 ///
 /// LuaEngine encapsulates the LuauJIT virtual machine.
@@ -18,6 +45,8 @@ pub struct LuaEngine {
   lua: Lua,
   output_code_string: bool,
   server_vm: bool,
+  // The game.conf of the loaded game, empty until one is loaded.
+  game_conf: Ini,
 }
 
 impl LuaEngine {
@@ -26,8 +55,12 @@ impl LuaEngine {
       lua: Lua::new(),
       output_code_string: false,
       server_vm,
+      game_conf: Ini::new(),
     };
 
+    // Both sides need to be able to make ItemStacks.
+    new_engine.register_function("ItemStack", item_stack_from_lua);
+
     new_engine.generate_internal();
 
     new_engine
@@ -40,6 +73,302 @@ impl LuaEngine {
     self.run_code(format!("_G.engine_on_tick_function({})", delta))
   }
 
+  ///
+  /// Run the global on_join_player function in the LuauJIT VM environment.
+  ///
+  pub fn on_join_player(&self, player_name: &str) {
+    self.call_internal("engine_on_join_player", player_name.to_string())
+  }
+
+  ///
+  /// Run the global on_leave_player function in the LuauJIT VM environment.
+  ///
+  pub fn on_leave_player(&self, player_name: &str, timed_out: bool) {
+    self.call_internal(
+      "engine_on_leave_player",
+      (player_name.to_string(), timed_out),
+    )
+  }
+
+  ///
+  /// Run the action of the ABM registered at an index on a node.
+  ///
+  pub fn run_abm(&self, abm: usize, position: IVec3, node_name: &str, node: Node) {
+    self.call_internal(
+      "engine_run_abm",
+      (
+        abm + 1,
+        position.x,
+        position.y,
+        position.z,
+        node_name.to_string(),
+        node.param1,
+        node.param2,
+      ),
+    )
+  }
+
+  ///
+  /// Run the action of the LBM registered at an index on a node.
+  ///
+  /// The block was gone for dtime seconds before it loaded.
+  ///
+  pub fn run_lbm(&self, lbm: usize, position: IVec3, node_name: &str, node: Node, dtime: u32) {
+    self.call_internal(
+      "engine_run_lbm",
+      (
+        lbm + 1,
+        position.x,
+        position.y,
+        position.z,
+        node_name.to_string(),
+        node.param1,
+        node.param2,
+        dtime,
+      ),
+    )
+  }
+
+  ///
+  /// Run the on_timer of a node whose timer went off.
+  ///
+  /// Returns true if the timer should start over.
+  ///
+  pub fn run_node_timer(&self, position: IVec3, node_name: &str, elapsed: f32) -> bool {
+    self.call_internal(
+      "engine_run_node_timer",
+      (
+        position.x,
+        position.y,
+        position.z,
+        node_name.to_string(),
+        elapsed,
+      ),
+    )
+  }
+
+  ///
+  /// Call one of the hidden engine functions with arguments.
+  ///
+  /// This is used instead of run_code() when the arguments come
+  /// from players, so they can never be ran as code.
+  ///
+  fn call_internal<'lua, A, R>(&'lua self, function_name: &str, arguments: A) -> R
+  where
+    A: IntoLuaMulti<'lua>,
+    R: FromLuaMulti<'lua>,
+  {
+    let function: Function = match self.lua.globals().get(function_name) {
+      Ok(function) => function,
+      Err(e) => panic!(
+        "LuaEngine: missing internal function [{}]. {}",
+        function_name, e
+      ),
+    };
+
+    match function.call::<_, R>(arguments) {
+      Ok(result) => result,
+      Err(err) => panic!("LuaEngine: A fatal error has occurred! {}", err),
+    }
+  }
+
+  ///
+  /// Expose a Rust function to Lua as a global.
+  ///
+  /// The api wraps these into the minetest table,
+  /// mods are not supposed to call them directly.
+  ///
+  pub fn register_function<'lua, A, R, F>(&'lua self, function_name: &str, function: F)
+  where
+    A: FromLuaMulti<'lua>,
+    R: IntoLuaMulti<'lua>,
+    F: Fn(&'lua Lua, A) -> mlua::Result<R> + 'static,
+  {
+    let function = match self.lua.create_function(function) {
+      Ok(function) => function,
+      Err(e) => panic!(
+        "LuaEngine: failed to create function [{}]. {}",
+        function_name, e
+      ),
+    };
+
+    if let Err(e) = self.lua.globals().set(function_name, function) {
+      panic!(
+        "LuaEngine: failed to register function [{}]. {}",
+        function_name, e
+      );
+    }
+  }
+
+  ///
+  /// Read back every block definition mods registered
+  /// with minetest.register_block().
+  ///
+  /// This only checks the types of the fields, NodeDefManager
+  /// checks if the values make sense.
+  ///
+  pub fn get_registered_blocks(&self) -> Result<Vec<NodeDefinition>, String> {
+    let blocks: Table = match self.lua.globals().get("blocks") {
+      Ok(blocks) => blocks,
+      Err(e) => return Err(format!("LuaEngine: missing block table. {}", e)),
+    };
+
+    let mut definitions = vec![];
+
+    for pair in blocks.pairs::<String, Table>() {
+      let (name, table) = match pair {
+        Ok(pair) => pair,
+        Err(e) => return Err(format!("LuaEngine: malformed block table. {}", e)),
+      };
+
+      match block_definition_from_table(&table) {
+        Ok(definition) => definitions.push(definition),
+        Err(e) => {
+          return Err(format!(
+            "LuaEngine: invalid block definition [{}]. {}",
+            name, e
+          ))
+        }
+      }
+    }
+
+    Ok(definitions)
+  }
+
+  ///
+  /// Read back every item definition mods registered with
+  /// minetest.register_item(), register_craftitem() and register_tool().
+  ///
+  pub fn get_registered_items(&self) -> Result<Vec<ItemDefinition>, String> {
+    let items: Table = match self.lua.globals().get("items") {
+      Ok(items) => items,
+      Err(e) => return Err(format!("LuaEngine: missing item table. {}", e)),
+    };
+
+    let mut definitions = vec![];
+
+    for pair in items.pairs::<String, Table>() {
+      let (name, table) = match pair {
+        Ok(pair) => pair,
+        Err(e) => return Err(format!("LuaEngine: malformed item table. {}", e)),
+      };
+
+      match item_definition_from_table(&table) {
+        Ok(definition) => definitions.push(definition),
+        Err(e) => {
+          return Err(format!(
+            "LuaEngine: invalid item definition [{}]. {}",
+            name, e
+          ))
+        }
+      }
+    }
+
+    Ok(definitions)
+  }
+
+  ///
+  /// Read back every alias mods registered with minetest.register_alias().
+  ///
+  pub fn get_registered_aliases(&self) -> Result<Vec<(String, String)>, String> {
+    let aliases: Table = match self.lua.globals().get("aliases") {
+      Ok(aliases) => aliases,
+      Err(e) => return Err(format!("LuaEngine: missing alias table. {}", e)),
+    };
+
+    let mut registered = vec![];
+
+    for pair in aliases.pairs::<String, String>() {
+      match pair {
+        Ok(pair) => registered.push(pair),
+        Err(e) => return Err(format!("LuaEngine: malformed alias table. {}", e)),
+      }
+    }
+
+    registered.sort();
+
+    Ok(registered)
+  }
+
+  ///
+  /// Read back a list of definitions, in the order mods registered them.
+  ///
+  fn get_registered_list<T>(
+    &self,
+    list_name: &str,
+    from_table: impl Fn(&Table) -> mlua::Result<T>,
+  ) -> Result<Vec<T>, String> {
+    let list: Table = match self.lua.globals().get(list_name) {
+      Ok(list) => list,
+      Err(e) => return Err(format!("LuaEngine: missing {} table. {}", list_name, e)),
+    };
+
+    let mut definitions = vec![];
+
+    for (i, table) in list.sequence_values::<Table>().enumerate() {
+      let table = match table {
+        Ok(table) => table,
+        Err(e) => return Err(format!("LuaEngine: malformed {} table. {}", list_name, e)),
+      };
+
+      match from_table(&table) {
+        Ok(definition) => definitions.push(definition),
+        Err(e) => {
+          return Err(format!(
+            "LuaEngine: invalid definition #{} in {}. {}",
+            i + 1,
+            list_name,
+            e
+          ))
+        }
+      }
+    }
+
+    Ok(definitions)
+  }
+
+  ///
+  /// Read back every biome mods registered with minetest.register_biome().
+  ///
+  pub fn get_registered_biomes(&self) -> Result<Vec<BiomeDefinition>, String> {
+    self.get_registered_list("biomes", biome_definition_from_table)
+  }
+
+  ///
+  /// Read back every ore mods registered with minetest.register_ore().
+  ///
+  pub fn get_registered_ores(&self) -> Result<Vec<OreDefinition>, String> {
+    self.get_registered_list("ores", ore_definition_from_table)
+  }
+
+  ///
+  /// Read back every decoration mods registered with minetest.register_decoration().
+  ///
+  /// Schematic files are looked for in directories.
+  ///
+  pub fn get_registered_decorations(
+    &self,
+    directories: &[String],
+  ) -> Result<Vec<DecorationDefinition>, String> {
+    self.get_registered_list("decorations", |table| {
+      decoration_definition_from_table(table, directories)
+    })
+  }
+
+  ///
+  /// Read back every ABM mods registered with minetest.register_abm().
+  ///
+  pub fn get_registered_abms(&self) -> Result<Vec<AbmDefinition>, String> {
+    self.get_registered_list("abms", abm_definition_from_table)
+  }
+
+  ///
+  /// Read back every LBM mods registered with minetest.register_lbm().
+  ///
+  pub fn get_registered_lbms(&self) -> Result<Vec<LbmDefinition>, String> {
+    self.get_registered_list("lbms", lbm_definition_from_table)
+  }
+
   ///
   /// Generates the on_tick(delta: number) function so it becomes a secret and hidden engine component.
   ///
@@ -130,6 +459,15 @@ impl LuaEngine {
     };
 
     println!("we got: {}", real_game_name);
+
+    self.game_conf = config;
+  }
+
+  ///
+  /// Get a value from the [config] section of the loaded game's game.conf.
+  ///
+  pub fn get_game_setting(&self, key: &str) -> Option<String> {
+    self.game_conf.get("config", key)
   }
 
   ///
@@ -184,9 +522,8 @@ impl LuaEngine {
       panic!("LuaEngine: tried to load game lua files on a client LuaEngine!")
     }
 
-    // Todo: Maybe this can be a compile time const?
     // We can choose between run-in-place or system installed.
-    let games_dir = String::from("./games");
+    let games_dir = String::from(GAMES_DIR);
 
     // Comes from lua_file_helpers.
     check_game(&games_dir, &game_name);
@@ -199,3 +536,247 @@ impl LuaEngine {
     self.load_game_files(&games_dir, &game_name);
   }
 }
+
+///
+/// Turn a Lua BlockDefinition table into a NodeDefinition.
+///
+fn block_definition_from_table(table: &Table) -> mlua::Result<NodeDefinition> {
+  let name: String = table.get("name")?;
+  let defaults = NodeDefinition::new(&name);
+
+  let drawtype = match table.get::<_, Option<i64>>("drawtype")? {
+    Some(number) => match DrawType::from_number(number) {
+      Some(drawtype) => drawtype,
+      None => {
+        return Err(mlua::Error::RuntimeError(format!(
+          "unknown drawtype {}.",
+          number
+        )))
+      }
+    },
+    None => defaults.drawtype,
+  };
+
+  let light_source = match table.get::<_, Option<i64>>("light_source")? {
+    Some(light_source) => match u8::try_from(light_source) {
+      Ok(light_source) => light_source,
+      Err(_) => {
+        return Err(mlua::Error::RuntimeError(format!(
+          "light_source {} is out of range.",
+          light_source
+        )))
+      }
+    },
+    None => defaults.light_source,
+  };
+
+  // Air-like nodes let light through unless told otherwise.
+  let is_air = drawtype == DrawType::Air;
+  let light_propagates = match table.get::<_, Option<String>>("paramtype")?.as_deref() {
+    Some("light") => true,
+    Some("none") => false,
+    Some(paramtype) => {
+      return Err(mlua::Error::RuntimeError(format!(
+        "unknown paramtype [{}].",
+        paramtype
+      )))
+    }
+    None => is_air,
+  };
+
+  let liquidtype = match table.get::<_, Option<String>>("liquidtype")? {
+    Some(name) => match LiquidType::from_name(&name) {
+      Some(liquidtype) => liquidtype,
+      None => {
+        return Err(mlua::Error::RuntimeError(format!(
+          "unknown liquidtype [{}].",
+          name
+        )))
+      }
+    },
+    None => defaults.liquidtype,
+  };
+
+  let liquid_range = match table.get::<_, Option<i64>>("liquid_range")? {
+    Some(liquid_range) => match u8::try_from(liquid_range) {
+      Ok(liquid_range) => liquid_range,
+      Err(_) => {
+        return Err(mlua::Error::RuntimeError(format!(
+          "liquid_range {} is out of range.",
+          liquid_range
+        )))
+      }
+    },
+    None => defaults.liquid_range,
+  };
+
+  Ok(NodeDefinition {
+    description: table
+      .get::<_, Option<String>>("description")?
+      .unwrap_or_default(),
+    drawtype,
+    textures: table
+      .get::<_, Option<Vec<String>>>("textures")?
+      .unwrap_or_default(),
+    walkable: table
+      .get::<_, Option<bool>>("walkable")?
+      .unwrap_or(defaults.walkable),
+    light_source,
+    light_propagates,
+    sunlight_propagates: table
+      .get::<_, Option<bool>>("sunlight_propagates")?
+      .unwrap_or(is_air),
+    liquidtype,
+    liquid_alternative_flowing: table
+      .get::<_, Option<String>>("liquid_alternative_flowing")?
+      .unwrap_or_default(),
+    liquid_alternative_source: table
+      .get::<_, Option<String>>("liquid_alternative_source")?
+      .unwrap_or_default(),
+    liquid_range,
+    liquid_renewable: table
+      .get::<_, Option<bool>>("liquid_renewable")?
+      .unwrap_or(defaults.liquid_renewable),
+    groups: groups_from_table(table, "groups")?,
+    ..defaults
+  })
+}
+
+///
+/// Turn a Lua AbmDefinition table into an AbmDefinition.
+///
+fn abm_definition_from_table(table: &Table) -> mlua::Result<AbmDefinition> {
+  let defaults = AbmDefinition::new(names_from_field(table, "nodenames")?);
+
+  Ok(AbmDefinition {
+    label: table.get::<_, Option<String>>("label")?.unwrap_or_default(),
+    neighbors: names_from_field(table, "neighbors")?,
+    interval: table
+      .get::<_, Option<f64>>("interval")?
+      .unwrap_or(defaults.interval),
+    chance: table
+      .get::<_, Option<u32>>("chance")?
+      .unwrap_or(defaults.chance),
+    ..defaults
+  })
+}
+
+///
+/// Turn a Lua LbmDefinition table into an LbmDefinition.
+///
+fn lbm_definition_from_table(table: &Table) -> mlua::Result<LbmDefinition> {
+  Ok(LbmDefinition {
+    name: table.get("name")?,
+    nodenames: names_from_field(table, "nodenames")?,
+    run_at_every_load: table
+      .get::<_, Option<bool>>("run_at_every_load")?
+      .unwrap_or(false),
+  })
+}
+
+///
+/// Read a {[string] : number} groups table.
+///
+fn groups_from_table(table: &Table, field: &str) -> mlua::Result<Vec<(String, i64)>> {
+  let mut groups = vec![];
+  if let Some(group_table) = table.get::<_, Option<Table>>(field)? {
+    for pair in group_table.pairs::<String, i64>() {
+      groups.push(pair?);
+    }
+  }
+  groups.sort();
+  Ok(groups)
+}
+
+///
+/// Turn a Lua ToolCapabilities table into ToolCapabilities.
+///
+fn tool_capabilities_from_table(table: &Table) -> mlua::Result<ToolCapabilities> {
+  let defaults = ToolCapabilities::default();
+
+  let mut group_capabilities = vec![];
+  if let Some(groupcaps) = table.get::<_, Option<Table>>("groupcaps")? {
+    for pair in groupcaps.pairs::<String, Table>() {
+      let (group, capability) = pair?;
+
+      let mut times = vec![];
+      if let Some(times_table) = capability.get::<_, Option<Table>>("times")? {
+        for pair in times_table.pairs::<i64, f32>() {
+          times.push(pair?);
+        }
+      }
+      times.sort_by_key(|(rating, _)| *rating);
+
+      group_capabilities.push((
+        group,
+        ToolGroupCapability {
+          times,
+          uses: capability.get::<_, Option<u32>>("uses")?.unwrap_or(20),
+          max_level: capability.get::<_, Option<i32>>("maxlevel")?.unwrap_or(1),
+        },
+      ));
+    }
+  }
+  group_capabilities.sort_by(|a, b| a.0.cmp(&b.0));
+
+  Ok(ToolCapabilities {
+    full_punch_interval: table
+      .get::<_, Option<f32>>("full_punch_interval")?
+      .unwrap_or(defaults.full_punch_interval),
+    max_drop_level: table
+      .get::<_, Option<i32>>("max_drop_level")?
+      .unwrap_or(defaults.max_drop_level),
+    group_capabilities,
+    damage_groups: groups_from_table(table, "damage_groups")?,
+  })
+}
+
+///
+/// Turn a Lua ItemDefinition table into an ItemDefinition.
+///
+fn item_definition_from_table(table: &Table) -> mlua::Result<ItemDefinition> {
+  let name: String = table.get("name")?;
+
+  let type_name = table
+    .get::<_, Option<String>>("type")?
+    .unwrap_or_else(|| "craftitem".to_string());
+  let item_type = match ItemType::from_name(&type_name) {
+    Some(item_type) => item_type,
+    None => {
+      return Err(mlua::Error::RuntimeError(format!(
+        "unknown item type [{}].",
+        type_name
+      )))
+    }
+  };
+
+  let defaults = ItemDefinition::new(&name, item_type);
+
+  let tool_capabilities = match table.get::<_, Option<Table>>("tool_capabilities")? {
+    Some(tool_capabilities) => Some(tool_capabilities_from_table(&tool_capabilities)?),
+    None => None,
+  };
+
+  let inventory_image = table
+    .get::<_, Option<String>>("inventory_image")?
+    .unwrap_or_default();
+
+  // Same as C++ minetest, the wield image falls back to the inventory image.
+  let wield_image = table
+    .get::<_, Option<String>>("wield_image")?
+    .unwrap_or_else(|| inventory_image.clone());
+
+  Ok(ItemDefinition {
+    description: table
+      .get::<_, Option<String>>("description")?
+      .unwrap_or_default(),
+    stack_max: table
+      .get::<_, Option<u16>>("stack_max")?
+      .unwrap_or(defaults.stack_max),
+    inventory_image,
+    wield_image,
+    tool_capabilities,
+    groups: groups_from_table(table, "groups")?,
+    ..defaults
+  })
+}
@@ -0,0 +1,305 @@
+///
+/// Turns the tables from minetest.register_biome(), register_ore()
+/// and register_decoration() into the mapgen's definitions.
+///
+/// Field names follow C++ minetest's lua_api so mods carry over.
+///
+use glam::{IVec3, Vec3};
+use mlua::{Table, Value};
+
+use crate::{
+  file_utilities::find_mod_file,
+  game::map::mapgen::{
+    biome::BiomeDefinition,
+    decoration::{DecorationDefinition, DecorationType},
+    noise::{NoiseParams, NoiseType},
+    ore::{OreDefinition, OreType},
+    schematic::{Schematic, SchematicNode},
+  },
+};
+
+fn runtime_error<T>(message: String) -> mlua::Result<T> {
+  Err(mlua::Error::RuntimeError(message))
+}
+
+///
+/// Read a field that can be one name or a list of them, like wherein.
+///
+pub fn names_from_field(table: &Table, field: &str) -> mlua::Result<Vec<String>> {
+  match table.get::<_, Value>(field)? {
+    Value::Nil => Ok(vec![]),
+    Value::String(name) => Ok(vec![name.to_str()?.to_string()]),
+    Value::Table(names) => names.sequence_values::<String>().collect(),
+    other => runtime_error(format!(
+      "[{}] should be a name or a list of names, not a {}.",
+      field,
+      other.type_name()
+    )),
+  }
+}
+
+///
+/// Read a {x = 1, y = 2, z = 3} table.
+///
+pub fn vec3_from_table(table: &Table) -> mlua::Result<Vec3> {
+  Ok(Vec3::new(table.get("x")?, table.get("y")?, table.get("z")?))
+}
+
+///
+/// Turn a Lua NoiseParams table into NoiseParams.
+///
+pub fn noise_params_from_table(table: &Table) -> mlua::Result<NoiseParams> {
+  let noise_type = match table.get::<_, Option<String>>("noise_type")? {
+    Some(name) => match name.as_str() {
+      "perlin" => NoiseType::Perlin,
+      "simplex" => NoiseType::Simplex,
+      _ => return runtime_error(format!("unknown noise_type [{}].", name)),
+    },
+    None => NoiseType::Perlin,
+  };
+
+  let spread = match table.get::<_, Option<Table>>("spread")? {
+    Some(spread) => vec3_from_table(&spread)?,
+    None => Vec3::splat(250.0),
+  };
+
+  // C++ minetest calls it persist, take both.
+  let persistence = match table.get::<_, Option<f32>>("persistence")? {
+    Some(persistence) => persistence,
+    None => table.get::<_, Option<f32>>("persist")?.unwrap_or(0.6),
+  };
+
+  Ok(NoiseParams {
+    noise_type,
+    offset: table.get::<_, Option<f32>>("offset")?.unwrap_or(0.0),
+    scale: table.get::<_, Option<f32>>("scale")?.unwrap_or(1.0),
+    spread,
+    seed: table.get::<_, Option<i32>>("seed")?.unwrap_or(0),
+    octaves: table.get::<_, Option<u32>>("octaves")?.unwrap_or(1),
+    persistence,
+    lacunarity: table.get::<_, Option<f32>>("lacunarity")?.unwrap_or(2.0),
+  })
+}
+
+///
+/// Turn a Lua BiomeDefinition table into a BiomeDefinition.
+///
+pub fn biome_definition_from_table(table: &Table) -> mlua::Result<BiomeDefinition> {
+  let name: String = table.get("name")?;
+  let defaults = BiomeDefinition::new(&name);
+
+  Ok(BiomeDefinition {
+    node_top: table
+      .get::<_, Option<String>>("node_top")?
+      .unwrap_or(defaults.node_top),
+    depth_top: table
+      .get::<_, Option<i32>>("depth_top")?
+      .unwrap_or(defaults.depth_top),
+    node_filler: table
+      .get::<_, Option<String>>("node_filler")?
+      .unwrap_or(defaults.node_filler),
+    depth_filler: table
+      .get::<_, Option<i32>>("depth_filler")?
+      .unwrap_or(defaults.depth_filler),
+    node_stone: table
+      .get::<_, Option<String>>("node_stone")?
+      .unwrap_or(defaults.node_stone),
+    y_min: table
+      .get::<_, Option<i32>>("y_min")?
+      .unwrap_or(defaults.y_min),
+    y_max: table
+      .get::<_, Option<i32>>("y_max")?
+      .unwrap_or(defaults.y_max),
+    heat_point: table
+      .get::<_, Option<f32>>("heat_point")?
+      .unwrap_or(defaults.heat_point),
+    humidity_point: table
+      .get::<_, Option<f32>>("humidity_point")?
+      .unwrap_or(defaults.humidity_point),
+    ..defaults
+  })
+}
+
+///
+/// Turn a Lua OreDefinition table into an OreDefinition.
+///
+pub fn ore_definition_from_table(table: &Table) -> mlua::Result<OreDefinition> {
+  let type_name = table
+    .get::<_, Option<String>>("ore_type")?
+    .unwrap_or_else(|| "scatter".to_string());
+  let ore_type = match OreType::from_name(&type_name) {
+    Some(ore_type) => ore_type,
+    None => return runtime_error(format!("unknown ore_type [{}].", type_name)),
+  };
+
+  let ore: String = table.get("ore")?;
+  let defaults = OreDefinition::new(ore_type, &ore, "mapgen_stone");
+
+  let wherein = names_from_field(table, "wherein")?;
+
+  let noise_params = match table.get::<_, Option<Table>>("noise_params")? {
+    Some(noise_params) => Some(noise_params_from_table(&noise_params)?),
+    None => None,
+  };
+
+  Ok(OreDefinition {
+    wherein: if wherein.is_empty() {
+      defaults.wherein.clone()
+    } else {
+      wherein
+    },
+    clust_scarcity: table
+      .get::<_, Option<u32>>("clust_scarcity")?
+      .unwrap_or(defaults.clust_scarcity),
+    clust_num_ores: table
+      .get::<_, Option<u32>>("clust_num_ores")?
+      .unwrap_or(defaults.clust_num_ores),
+    clust_size: table
+      .get::<_, Option<i32>>("clust_size")?
+      .unwrap_or(defaults.clust_size),
+    column_height: table
+      .get::<_, Option<i32>>("column_height")?
+      .unwrap_or(defaults.column_height),
+    y_min: table
+      .get::<_, Option<i32>>("y_min")?
+      .unwrap_or(defaults.y_min),
+    y_max: table
+      .get::<_, Option<i32>>("y_max")?
+      .unwrap_or(defaults.y_max),
+    noise_params,
+    noise_threshold: table
+      .get::<_, Option<f32>>("noise_threshold")?
+      .unwrap_or(defaults.noise_threshold),
+    ..defaults
+  })
+}
+
+///
+/// Turn a Lua schematic table into a Schematic.
+///
+/// Same layout as C++ minetest:
+/// { size = {x = 3, y = 5, z = 3}, data = { {name = "default:tree", prob = 255}, ... },
+///   yslice_prob = { {ypos = 2, prob = 127}, ... } }
+///
+pub fn schematic_from_table(table: &Table) -> mlua::Result<Schematic> {
+  let size_table: Table = table.get("size")?;
+  let size = vec3_from_table(&size_table)?;
+  let size = IVec3::new(size.x as i32, size.y as i32, size.z as i32);
+
+  let mut nodes = vec![];
+  let data: Table = table.get("data")?;
+
+  for node_table in data.sequence_values::<Table>() {
+    let node_table = node_table?;
+    let name: String = node_table.get("name")?;
+
+    nodes.push(SchematicNode {
+      probability: node_table.get::<_, Option<u8>>("prob")?.unwrap_or(255),
+      param2: node_table.get::<_, Option<u8>>("param2")?.unwrap_or(0),
+      force_place: node_table
+        .get::<_, Option<bool>>("force_place")?
+        .unwrap_or(false),
+      ..SchematicNode::new(&name)
+    });
+  }
+
+  let mut schematic = Schematic {
+    size,
+    nodes,
+    ..Schematic::new(IVec3::ZERO)
+  };
+
+  if let Some(slices) = table.get::<_, Option<Table>>("yslice_prob")? {
+    schematic.y_slice_probabilities = vec![255; size.y.max(0) as usize];
+
+    for slice in slices.sequence_values::<Table>() {
+      let slice = slice?;
+      let y: i32 = slice.get("ypos")?;
+      let probability = slice.get::<_, Option<u8>>("prob")?.unwrap_or(255);
+
+      match schematic.y_slice_probabilities.get_mut(y as usize) {
+        Some(slice_probability) => *slice_probability = probability,
+        None => {
+          return runtime_error(format!(
+            "yslice_prob ypos {} is outside of the schematic.",
+            y
+          ))
+        }
+      }
+    }
+  }
+
+  Ok(schematic)
+}
+
+///
+/// Read a schematic that's either a table, or the path to a .mts or .json file.
+///
+/// Paths are relative to one of directories, mods can't read anything else.
+///
+pub fn schematic_from_value(value: Value, directories: &[String]) -> mlua::Result<Schematic> {
+  match value {
+    Value::Table(table) => schematic_from_table(&table),
+    Value::String(path) => find_mod_file(path.to_str()?, directories)
+      .and_then(|path| Schematic::load_file(&path.to_string_lossy()))
+      .map_err(mlua::Error::RuntimeError),
+    other => runtime_error(format!(
+      "a schematic should be a table or a file path, not a {}.",
+      other.type_name()
+    )),
+  }
+}
+
+///
+/// Turn a Lua DecorationDefinition table into a DecorationDefinition.
+///
+/// Schematic paths are relative to one of directories.
+///
+pub fn decoration_definition_from_table(
+  table: &Table,
+  directories: &[String],
+) -> mlua::Result<DecorationDefinition> {
+  let type_name = table
+    .get::<_, Option<String>>("deco_type")?
+    .unwrap_or_else(|| "simple".to_string());
+  let deco_type = match DecorationType::from_name(&type_name) {
+    Some(deco_type) => deco_type,
+    None => return runtime_error(format!("unknown deco_type [{}].", type_name)),
+  };
+
+  let defaults = DecorationDefinition::new(deco_type, "");
+
+  let schematic = match table.get::<_, Value>("schematic")? {
+    Value::Nil => None,
+    schematic => Some(schematic_from_value(schematic, directories)?),
+  };
+
+  // Like "place_center_x, place_center_z".
+  let flags = table.get::<_, Option<String>>("flags")?.unwrap_or_default();
+  let has_flag = |flag: &str| flags.split(',').any(|part| part.trim() == flag);
+
+  Ok(DecorationDefinition {
+    place_on: names_from_field(table, "place_on")?,
+    fill_ratio: table
+      .get::<_, Option<f32>>("fill_ratio")?
+      .unwrap_or(defaults.fill_ratio),
+    biomes: names_from_field(table, "biomes")?,
+    y_min: table
+      .get::<_, Option<i32>>("y_min")?
+      .unwrap_or(defaults.y_min),
+    y_max: table
+      .get::<_, Option<i32>>("y_max")?
+      .unwrap_or(defaults.y_max),
+    decoration: names_from_field(table, "decoration")?,
+    height: table
+      .get::<_, Option<i32>>("height")?
+      .unwrap_or(defaults.height),
+    height_max: table
+      .get::<_, Option<i32>>("height_max")?
+      .unwrap_or(defaults.height_max),
+    schematic,
+    place_center_x: has_flag("place_center_x"),
+    place_center_z: has_flag("place_center_z"),
+    ..defaults
+  })
+}
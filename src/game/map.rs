@@ -0,0 +1,485 @@
+pub mod block_modifier;
+pub mod legacy_map_serialization;
+pub mod lighting;
+pub mod liquid;
+pub mod map_block;
+pub mod map_database;
+pub mod map_serialization;
+pub mod mapgen;
+pub mod node;
+pub mod node_def_manager;
+pub mod palette;
+
+use std::collections::VecDeque;
+
+use ahash::{AHashMap, AHashSet};
+use glam::IVec3;
+
+use self::{
+  map_block::{index_to_local, local_to_index, MapBlock, NodeTimer, MAP_BLOCK_SIZE},
+  node::Node,
+};
+
+///
+/// The world, as a sparse collection of MapBlocks.
+///
+/// Shared by the Server and the Client. Blocks which
+/// don't exist are simply not in the map yet.
+///
+/// Blocks which changed since they were last saved are
+/// marked dirty. Anything that hands out a mutable block
+/// marks it, whether it really changes or not.
+///
+/// Positions where liquid might have to flow wait in the
+/// liquid queue until liquid::transform_liquids() gets to them.
+///
+/// Node timers counting up and block timestamps don't make
+/// a block dirty, only timers starting, stopping or going off do.
+///
+/// Nodes set with set_node() and blocks put in with insert_block()
+/// are remembered until they're taken, so clients can be told.
+/// Changes made straight to a block from get_block_mut() are not.
+///
+pub struct Map {
+  blocks: AHashMap<IVec3, MapBlock>,
+  dirty_blocks: AHashSet<IVec3>,
+  liquid_queue: VecDeque<IVec3>,
+  queued_liquids: AHashSet<IVec3>,
+  changed_nodes: AHashSet<IVec3>,
+  replaced_blocks: AHashSet<IVec3>,
+}
+
+impl Map {
+  pub fn new() -> Self {
+    Map {
+      blocks: AHashMap::new(),
+      dirty_blocks: AHashSet::new(),
+      liquid_queue: VecDeque::new(),
+      queued_liquids: AHashSet::new(),
+      changed_nodes: AHashSet::new(),
+      replaced_blocks: AHashSet::new(),
+    }
+  }
+
+  ///
+  /// Get a MapBlock by it's block position.
+  ///
+  pub fn get_block(&self, block_position: IVec3) -> Option<&MapBlock> {
+    self.blocks.get(&block_position)
+  }
+
+  ///
+  /// Get a mutable MapBlock by it's block position.
+  ///
+  pub fn get_block_mut(&mut self, block_position: IVec3) -> Option<&mut MapBlock> {
+    let block = self.blocks.get_mut(&block_position)?;
+    self.dirty_blocks.insert(block_position);
+    Some(block)
+  }
+
+  ///
+  /// Get a MapBlock, creating one full of ignore if it doesn't exist.
+  ///
+  pub fn get_or_create_block(&mut self, block_position: IVec3) -> &mut MapBlock {
+    self.dirty_blocks.insert(block_position);
+    self.blocks.entry(block_position).or_default()
+  }
+
+  ///
+  /// Put a new MapBlock into the map, replacing whatever was there.
+  ///
+  pub fn insert_block(&mut self, block_position: IVec3, block: MapBlock) {
+    self.dirty_blocks.insert(block_position);
+    self.replaced_blocks.insert(block_position);
+    self.blocks.insert(block_position, block);
+  }
+
+  ///
+  /// Put a MapBlock which was just loaded from disk into the map.
+  ///
+  /// Unlike insert_block(), it's not dirty, it's already saved.
+  ///
+  pub fn insert_loaded_block(&mut self, block_position: IVec3, block: MapBlock) {
+    self.dirty_blocks.remove(&block_position);
+    self.blocks.insert(block_position, block);
+  }
+
+  ///
+  /// Take a MapBlock out of the map.
+  ///
+  /// Save it first if it's dirty, it's forgotten.
+  ///
+  pub fn remove_block(&mut self, block_position: IVec3) -> Option<MapBlock> {
+    self.dirty_blocks.remove(&block_position);
+    self.blocks.remove(&block_position)
+  }
+
+  ///
+  /// Check if a MapBlock changed since it was last saved.
+  ///
+  pub fn is_block_dirty(&self, block_position: IVec3) -> bool {
+    self.dirty_blocks.contains(&block_position)
+  }
+
+  ///
+  /// Mark a MapBlock as changed since it was last saved.
+  ///
+  pub fn mark_block_dirty(&mut self, block_position: IVec3) {
+    if self.blocks.contains_key(&block_position) {
+      self.dirty_blocks.insert(block_position);
+    }
+  }
+
+  ///
+  /// Get every dirty MapBlock position, and mark them all as saved.
+  ///
+  pub fn take_dirty_blocks(&mut self) -> Vec<IVec3> {
+    self.dirty_blocks.drain().collect()
+  }
+
+  ///
+  /// Get every node set since the last time they were taken.
+  ///
+  pub fn take_changed_nodes(&mut self) -> Vec<IVec3> {
+    self.changed_nodes.drain().collect()
+  }
+
+  ///
+  /// Get every MapBlock put in with insert_block() since the last time they were taken.
+  ///
+  pub fn take_replaced_blocks(&mut self) -> Vec<IVec3> {
+    self.replaced_blocks.drain().collect()
+  }
+
+  ///
+  /// Check if a MapBlock is in the map.
+  ///
+  pub fn has_block(&self, block_position: IVec3) -> bool {
+    self.blocks.contains_key(&block_position)
+  }
+
+  ///
+  /// Get how many MapBlocks are in the map.
+  ///
+  pub fn get_block_count(&self) -> usize {
+    self.blocks.len()
+  }
+
+  ///
+  /// Get the positions of every MapBlock in the map.
+  ///
+  pub fn get_block_positions(&self) -> Vec<IVec3> {
+    self.blocks.keys().copied().collect()
+  }
+
+  ///
+  /// Queue up a position for liquid to be worked out at,
+  /// along with every position next to it.
+  ///
+  pub fn queue_liquid_update(&mut self, position: IVec3) {
+    for offset in [
+      IVec3::ZERO,
+      IVec3::X,
+      IVec3::NEG_X,
+      IVec3::Y,
+      IVec3::NEG_Y,
+      IVec3::Z,
+      IVec3::NEG_Z,
+    ] {
+      if self.queued_liquids.insert(position + offset) {
+        self.liquid_queue.push_back(position + offset);
+      }
+    }
+  }
+
+  ///
+  /// Take the next position out of the liquid queue.
+  ///
+  pub fn pop_liquid_update(&mut self) -> Option<IVec3> {
+    let position = self.liquid_queue.pop_front()?;
+    self.queued_liquids.remove(&position);
+    Some(position)
+  }
+
+  ///
+  /// Get how many positions are waiting in the liquid queue.
+  ///
+  pub fn get_liquid_queue_size(&self) -> usize {
+    self.liquid_queue.len()
+  }
+
+  ///
+  /// Set the timestamp of a MapBlock, without making it dirty.
+  ///
+  pub fn set_block_timestamp(&mut self, block_position: IVec3, timestamp: u32) {
+    if let Some(block) = self.blocks.get_mut(&block_position) {
+      block.set_timestamp(timestamp);
+    }
+  }
+
+  ///
+  /// Get the timer of the node at a position in the world, if it has one running.
+  ///
+  pub fn get_node_timer(&self, position: IVec3) -> Option<NodeTimer> {
+    self
+      .blocks
+      .get(&node_to_block_position(position))?
+      .get_node_timer(local_to_index(node_to_local_position(position)))
+  }
+
+  ///
+  /// Start (or restart) the timer of the node at a position in the world.
+  ///
+  /// Returns false if the MapBlock it's in doesn't exist.
+  ///
+  pub fn set_node_timer(&mut self, position: IVec3, timer: NodeTimer) -> bool {
+    match self.get_block_mut(node_to_block_position(position)) {
+      Some(block) => {
+        block.set_node_timer(local_to_index(node_to_local_position(position)), timer);
+        true
+      }
+      None => false,
+    }
+  }
+
+  ///
+  /// Stop the timer of the node at a position in the world.
+  ///
+  pub fn remove_node_timer(&mut self, position: IVec3) {
+    let block_position = node_to_block_position(position);
+    let index = local_to_index(node_to_local_position(position));
+
+    let has_timer = self
+      .blocks
+      .get(&block_position)
+      .is_some_and(|block| block.get_node_timer(index).is_some());
+    if !has_timer {
+      return;
+    }
+
+    if let Some(block) = self.get_block_mut(block_position) {
+      block.remove_node_timer(index);
+    }
+  }
+
+  ///
+  /// Move every node timer in a MapBlock forward, and take out the ones which went off.
+  ///
+  /// The block needs saving either way, so the time they ran for isn't lost.
+  ///
+  pub fn step_node_timers(&mut self, block_position: IVec3, delta: f32) -> Vec<(IVec3, NodeTimer)> {
+    let block = match self.blocks.get_mut(&block_position) {
+      Some(block) => block,
+      None => return vec![],
+    };
+
+    if !block.get_node_timers().is_empty() {
+      self.dirty_blocks.insert(block_position);
+    }
+
+    let elapsed = block.step_node_timers(delta);
+
+    let corner = block_to_node_position(block_position);
+    elapsed
+      .into_iter()
+      .map(|(index, timer)| (corner + index_to_local(index), timer))
+      .collect()
+  }
+
+  ///
+  /// Get the node at a position in the world.
+  ///
+  /// Returns None if the MapBlock it's in doesn't exist.
+  ///
+  pub fn get_node(&self, position: IVec3) -> Option<Node> {
+    self
+      .blocks
+      .get(&node_to_block_position(position))
+      .map(|block| block.get_node(node_to_local_position(position)))
+  }
+
+  ///
+  /// Get the node at a position in the world, or ignore if it doesn't exist.
+  ///
+  pub fn get_node_or_ignore(&self, position: IVec3) -> Node {
+    self.get_node(position).unwrap_or_else(Node::ignore)
+  }
+
+  ///
+  /// Set the node at a position in the world.
+  ///
+  /// Returns false if the MapBlock it's in doesn't exist.
+  ///
+  pub fn set_node(&mut self, position: IVec3, node: Node) -> bool {
+    match self.get_block_mut(node_to_block_position(position)) {
+      Some(block) => {
+        block.set_node(node_to_local_position(position), node);
+        self.changed_nodes.insert(position);
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+impl Default for Map {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+///
+/// Get the position of the MapBlock a node is in.
+///
+pub fn node_to_block_position(position: IVec3) -> IVec3 {
+  IVec3::new(
+    position.x.div_euclid(MAP_BLOCK_SIZE),
+    position.y.div_euclid(MAP_BLOCK_SIZE),
+    position.z.div_euclid(MAP_BLOCK_SIZE),
+  )
+}
+
+///
+/// Get the position of a node inside of the MapBlock it's in.
+///
+pub fn node_to_local_position(position: IVec3) -> IVec3 {
+  IVec3::new(
+    position.x.rem_euclid(MAP_BLOCK_SIZE),
+    position.y.rem_euclid(MAP_BLOCK_SIZE),
+    position.z.rem_euclid(MAP_BLOCK_SIZE),
+  )
+}
+
+///
+/// Get the position of the lowest corner node of a MapBlock.
+///
+pub fn block_to_node_position(block_position: IVec3) -> IVec3 {
+  block_position * MAP_BLOCK_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use super::{
+    map_block::{MapBlock, NodeTimer, MAP_BLOCK_SIZE},
+    node::Node,
+    node_to_block_position, node_to_local_position, Map,
+  };
+
+  #[test]
+  fn test_node_positions() {
+    assert_eq!(
+      node_to_block_position(IVec3::new(0, 15, 16)),
+      IVec3::new(0, 0, 1)
+    );
+    assert_eq!(
+      node_to_block_position(IVec3::new(-1, -16, -17)),
+      IVec3::new(-1, -1, -2)
+    );
+    assert_eq!(
+      node_to_local_position(IVec3::new(-1, -16, -17)),
+      IVec3::new(15, 0, 15)
+    );
+  }
+
+  #[test]
+  fn test_map() {
+    let mut map = Map::new();
+    let stone = Node::new(200);
+    let position = IVec3::new(-5, 20, 100);
+
+    // Nothing is there yet.
+    assert_eq!(map.get_node(position), None);
+    assert_eq!(map.get_node_or_ignore(position), Node::ignore());
+    assert!(!map.set_node(position, stone));
+
+    map.insert_block(node_to_block_position(position), MapBlock::new(Node::air()));
+    assert!(map.set_node(position, stone));
+    assert_eq!(map.get_node(position), Some(stone));
+    assert_eq!(map.get_node(position + IVec3::X), Some(Node::air()));
+    assert_eq!(map.get_block_count(), 1);
+
+    // Creating a block fills it with ignore.
+    map.get_or_create_block(IVec3::ZERO);
+    assert_eq!(map.get_node(IVec3::ZERO), Some(Node::ignore()));
+    assert_eq!(map.get_block_count(), 2);
+  }
+
+  #[test]
+  fn test_map_dirty_blocks() {
+    let mut map = Map::new();
+    let block_position = IVec3::new(1, 2, 3);
+
+    // Blocks from disk are already saved, anything else needs saving.
+    map.insert_loaded_block(block_position, MapBlock::new(Node::air()));
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+    assert!(!map.is_block_dirty(block_position));
+    assert_eq!(map.take_dirty_blocks(), vec![IVec3::ZERO]);
+    assert!(map.take_dirty_blocks().is_empty());
+
+    map.set_node(block_position * MAP_BLOCK_SIZE, Node::new(200));
+    assert!(map.is_block_dirty(block_position));
+    assert_eq!(map.take_dirty_blocks(), vec![block_position]);
+  }
+
+  #[test]
+  fn test_map_changes() {
+    let mut map = Map::new();
+    let position = IVec3::new(3, -4, 5);
+
+    // Loading isn't a change, a new block is.
+    map.insert_loaded_block(IVec3::new(0, -1, 0), MapBlock::new(Node::air()));
+    assert!(map.take_replaced_blocks().is_empty());
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+    assert_eq!(map.take_replaced_blocks(), vec![IVec3::ZERO]);
+
+    // Setting a node twice is still one change.
+    map.set_node(position, Node::new(200));
+    map.set_node(position, Node::new(201));
+    assert!(!map.set_node(IVec3::new(0, 100, 0), Node::new(200)));
+    assert_eq!(map.take_changed_nodes(), vec![position]);
+    assert!(map.take_changed_nodes().is_empty());
+  }
+
+  #[test]
+  fn test_map_node_timers() {
+    let mut map = Map::new();
+    let position = IVec3::new(-1, 17, 3);
+    let block_position = node_to_block_position(position);
+
+    assert!(!map.set_node_timer(position, NodeTimer::new(2.0, 0.0)));
+    map.insert_loaded_block(block_position, MapBlock::new(Node::air()));
+
+    assert!(map.set_node_timer(position, NodeTimer::new(2.0, 0.0)));
+    assert_eq!(map.take_dirty_blocks(), vec![block_position]);
+
+    // Counting up needs saving too, or a restart would start them over.
+    assert!(map.step_node_timers(block_position, 1.0).is_empty());
+    assert_eq!(map.take_dirty_blocks(), vec![block_position]);
+    assert_eq!(map.get_node_timer(position), Some(NodeTimer::new(2.0, 1.0)));
+
+    assert_eq!(
+      map.step_node_timers(block_position, 1.0),
+      vec![(position, NodeTimer::new(2.0, 2.0))]
+    );
+    assert!(map.is_block_dirty(block_position));
+    assert_eq!(map.get_node_timer(position), None);
+
+    // No timers, nothing to save.
+    map.take_dirty_blocks();
+    assert!(map.step_node_timers(block_position, 1.0).is_empty());
+    assert!(!map.is_block_dirty(block_position));
+
+    map.set_block_timestamp(block_position, 50);
+    map.take_dirty_blocks();
+    map.remove_node_timer(position);
+    assert!(!map.is_block_dirty(block_position));
+    assert_eq!(
+      map
+        .get_block(block_position)
+        .map(|block| block.get_timestamp()),
+      Some(50)
+    );
+  }
+}
@@ -0,0 +1,654 @@
+///
+/// Active block modifiers (ABMs) and loading block modifiers (LBMs),
+/// how mods change nodes nobody is touching.
+///
+/// ABMs go off every interval seconds, each node they match in the
+/// blocks around players has a 1 in chance shot at being picked.
+///
+/// LBMs go off once on the nodes they match in a block as it loads,
+/// if the block was last around before the LBM existed.
+/// With run_at_every_load they go off on every load instead.
+///
+/// The actions live in Lua, this only works out where they should run.
+///
+use std::collections::HashMap;
+
+use ahash::AHashSet;
+use glam::IVec3;
+
+use super::{
+  block_to_node_position,
+  map_block::{index_to_local, MapBlock, BLOCK_TIMESTAMP_UNDEFINED, MAP_BLOCK_VOLUME},
+  mapgen::pseudo_random::PseudoRandom,
+  node::{ContentId, Node},
+  node_def_manager::{is_valid_node_name, NodeDefManager},
+  Map,
+};
+
+///
+/// An ABM from minetest.register_abm(), without it's action.
+///
+/// Names can be node names, aliases, or groups like "group:flammable".
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct AbmDefinition {
+  pub label: String,
+  pub nodenames: Vec<String>,
+  // At least one of these has to be around the node, if there are any.
+  pub neighbors: Vec<String>,
+  // In seconds.
+  pub interval: f64,
+  pub chance: u32,
+}
+
+impl AbmDefinition {
+  ///
+  /// A definition with the defaults a mod would get.
+  ///
+  pub fn new(nodenames: Vec<String>) -> Self {
+    AbmDefinition {
+      label: String::new(),
+      nodenames,
+      neighbors: vec![],
+      interval: 10.0,
+      chance: 50,
+    }
+  }
+
+  ///
+  /// Check the definition makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if self.nodenames.is_empty() {
+      return Err(format!("ABM [{}] has no nodenames.", self.label));
+    }
+
+    if self.interval.is_nan() || self.interval <= 0.0 {
+      return Err(format!(
+        "ABM [{}] has interval {}, it has to be above 0.",
+        self.label, self.interval
+      ));
+    }
+
+    if self.chance == 0 {
+      return Err(format!(
+        "ABM [{}] has chance 0, it has to be at least 1.",
+        self.label
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// An LBM from minetest.register_lbm(), without it's action.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct LbmDefinition {
+  pub name: String,
+  pub nodenames: Vec<String>,
+  pub run_at_every_load: bool,
+}
+
+impl LbmDefinition {
+  ///
+  /// Check the definition makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if !is_valid_node_name(&self.name) {
+      return Err(format!(
+        "[{}] is not a valid LBM name, it must look like [modname:lbmname].",
+        self.name
+      ));
+    }
+
+    if self.nodenames.is_empty() {
+      return Err(format!("LBM [{}] has no nodenames.", self.name));
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// Somewhere an ABM or LBM should run.
+///
+/// The modifier is the index it was registered at.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModifierTrigger {
+  pub modifier: usize,
+  pub position: IVec3,
+  pub node: Node,
+}
+
+///
+/// Turn a list of names and groups into the content ids they mean.
+///
+/// Names the game doesn't have simply don't match anything.
+///
+fn resolve_names(names: &[String], node_def_manager: &NodeDefManager) -> AHashSet<ContentId> {
+  names
+    .iter()
+    .flat_map(|name| node_def_manager.get_ids(name))
+    .collect()
+}
+
+struct ActiveBlockModifier {
+  contents: AHashSet<ContentId>,
+  neighbors: AHashSet<ContentId>,
+  interval: f64,
+  chance: u32,
+  timer: f64,
+}
+
+///
+/// Every registered ABM, with the time until each goes off next.
+///
+pub struct ActiveBlockModifiers {
+  abms: Vec<ActiveBlockModifier>,
+}
+
+impl ActiveBlockModifiers {
+  pub fn new() -> Self {
+    ActiveBlockModifiers { abms: vec![] }
+  }
+
+  ///
+  /// Create the ABMs mods registered, in the order they registered them.
+  ///
+  pub fn from_definitions(
+    definitions: &[AbmDefinition],
+    node_def_manager: &NodeDefManager,
+  ) -> Result<Self, String> {
+    let mut abms = vec![];
+
+    for definition in definitions {
+      definition.validate()?;
+
+      abms.push(ActiveBlockModifier {
+        contents: resolve_names(&definition.nodenames, node_def_manager),
+        neighbors: resolve_names(&definition.neighbors, node_def_manager),
+        interval: definition.interval,
+        chance: definition.chance,
+        timer: 0.0,
+      });
+    }
+
+    Ok(ActiveBlockModifiers { abms })
+  }
+
+  ///
+  /// Get how many ABMs are registered.
+  ///
+  pub fn get_count(&self) -> usize {
+    self.abms.len()
+  }
+
+  ///
+  /// Move every ABM's timer forward, and get the ones whose interval came up.
+  ///
+  pub fn step(&mut self, delta: f64) -> Vec<usize> {
+    let mut due = vec![];
+    for (index, abm) in self.abms.iter_mut().enumerate() {
+      abm.timer += delta;
+      if abm.timer >= abm.interval {
+        abm.timer -= abm.interval;
+        due.push(index);
+      }
+    }
+    due
+  }
+
+  ///
+  /// Check if an ABM still wants a node.
+  ///
+  /// Nodes can change between being picked and their turn coming up.
+  ///
+  pub fn matches(&self, abm: usize, content: ContentId) -> bool {
+    self
+      .abms
+      .get(abm)
+      .is_some_and(|abm| abm.contents.contains(&content))
+  }
+
+  ///
+  /// Pick the nodes in a MapBlock which some ABMs go off on this time.
+  ///
+  pub fn find_triggers(
+    &self,
+    map: &Map,
+    block_position: IVec3,
+    abms: &[usize],
+    random: &mut PseudoRandom,
+  ) -> Vec<ModifierTrigger> {
+    let block = match map.get_block(block_position) {
+      Some(block) => block,
+      None => return vec![],
+    };
+
+    // Most blocks have none of the nodes at all.
+    let palette = block.get_content_palette();
+    let abms: Vec<(usize, &ActiveBlockModifier)> = abms
+      .iter()
+      .filter_map(|index| Some((*index, self.abms.get(*index)?)))
+      .filter(|(_, abm)| palette.iter().any(|content| abm.contents.contains(content)))
+      .collect();
+    if abms.is_empty() {
+      return vec![];
+    }
+
+    let corner = block_to_node_position(block_position);
+    let mut triggers = vec![];
+    for index in 0..MAP_BLOCK_VOLUME {
+      let node = block.get_node_at_index(index);
+      let position = corner + index_to_local(index);
+
+      for (abm_index, abm) in &abms {
+        if !abm.contents.contains(&node.content) {
+          continue;
+        }
+        // A 1 in chance roll.
+        let roll = random.next_u64() % abm.chance as u64;
+        if roll > 0 {
+          continue;
+        }
+        if !abm.neighbors.is_empty() && !Self::has_neighbor(map, position, &abm.neighbors) {
+          continue;
+        }
+
+        triggers.push(ModifierTrigger {
+          modifier: *abm_index,
+          position,
+          node,
+        });
+      }
+    }
+    triggers
+  }
+
+  ///
+  /// Check if any of the 26 nodes around a position is one of some contents.
+  ///
+  fn has_neighbor(map: &Map, position: IVec3, neighbors: &AHashSet<ContentId>) -> bool {
+    for z in -1..=1 {
+      for y in -1..=1 {
+        for x in -1..=1 {
+          let offset = IVec3::new(x, y, z);
+          if offset != IVec3::ZERO
+            && neighbors.contains(&map.get_node_or_ignore(position + offset).content)
+          {
+            return true;
+          }
+        }
+      }
+    }
+    false
+  }
+}
+
+impl Default for ActiveBlockModifiers {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+struct LoadingBlockModifier {
+  contents: AHashSet<ContentId>,
+  run_at_every_load: bool,
+  // The game time the LBM first showed up.
+  introduction_time: u32,
+}
+
+///
+/// Every registered LBM, with when each of them first showed up in the world.
+///
+pub struct LoadingBlockModifiers {
+  lbms: Vec<LoadingBlockModifier>,
+}
+
+impl LoadingBlockModifiers {
+  pub fn new() -> Self {
+    LoadingBlockModifiers { lbms: vec![] }
+  }
+
+  ///
+  /// Create the LBMs mods registered, in the order they registered them.
+  ///
+  /// LBMs the world hasn't seen before get introduced at the game time,
+  /// which is written into introduction_times to be saved with the world.
+  ///
+  pub fn from_definitions(
+    definitions: &[LbmDefinition],
+    node_def_manager: &NodeDefManager,
+    introduction_times: &mut HashMap<String, u32>,
+    game_time: u32,
+  ) -> Result<Self, String> {
+    let mut lbms = vec![];
+    let mut names = AHashSet::new();
+
+    for definition in definitions {
+      definition.validate()?;
+
+      if !names.insert(definition.name.as_str()) {
+        return Err(format!(
+          "[{}] is already a registered LBM.",
+          definition.name
+        ));
+      }
+
+      // These never look at it, so they never get one.
+      let introduction_time = if definition.run_at_every_load {
+        0
+      } else {
+        *introduction_times
+          .entry(definition.name.clone())
+          .or_insert(game_time)
+      };
+
+      lbms.push(LoadingBlockModifier {
+        contents: resolve_names(&definition.nodenames, node_def_manager),
+        run_at_every_load: definition.run_at_every_load,
+        introduction_time,
+      });
+    }
+
+    Ok(LoadingBlockModifiers { lbms })
+  }
+
+  ///
+  /// Get how many LBMs are registered.
+  ///
+  pub fn get_count(&self) -> usize {
+    self.lbms.len()
+  }
+
+  ///
+  /// Check if an LBM still wants a node.
+  ///
+  pub fn matches(&self, lbm: usize, content: ContentId) -> bool {
+    self
+      .lbms
+      .get(lbm)
+      .is_some_and(|lbm| lbm.contents.contains(&content))
+  }
+
+  ///
+  /// Find every node in a MapBlock which just loaded that LBMs go off on.
+  ///
+  /// Blocks with no timestamp could be from anywhere, every LBM goes off on them.
+  ///
+  pub fn find_triggers(&self, block: &MapBlock, block_position: IVec3) -> Vec<ModifierTrigger> {
+    let timestamp = block.get_timestamp();
+    let palette = block.get_content_palette();
+
+    let lbms: Vec<(usize, &LoadingBlockModifier)> = self
+      .lbms
+      .iter()
+      .enumerate()
+      .filter(|(_, lbm)| {
+        lbm.run_at_every_load
+          || timestamp == BLOCK_TIMESTAMP_UNDEFINED
+          || timestamp < lbm.introduction_time
+      })
+      .filter(|(_, lbm)| palette.iter().any(|content| lbm.contents.contains(content)))
+      .collect();
+    if lbms.is_empty() {
+      return vec![];
+    }
+
+    let corner = block_to_node_position(block_position);
+    let mut triggers = vec![];
+    for index in 0..MAP_BLOCK_VOLUME {
+      let node = block.get_node_at_index(index);
+
+      for (lbm_index, lbm) in &lbms {
+        if lbm.contents.contains(&node.content) {
+          triggers.push(ModifierTrigger {
+            modifier: *lbm_index,
+            position: corner + index_to_local(index),
+            node,
+          });
+        }
+      }
+    }
+    triggers
+  }
+}
+
+impl Default for LoadingBlockModifiers {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::MapBlock,
+    mapgen::pseudo_random::PseudoRandom,
+    node::{Node, CONTENT_UNKNOWN},
+    node_def_manager::{new_test_node_def_manager, NodeDefManager, NodeDefinition},
+    Map,
+  };
+
+  use super::{
+    AbmDefinition, ActiveBlockModifiers, LbmDefinition, LoadingBlockModifiers, ModifierTrigger,
+  };
+
+  fn new_node_def_manager() -> NodeDefManager {
+    let definitions = ["test:stone", "test:dirt", "test:water"]
+      .iter()
+      .map(|name| NodeDefinition {
+        groups: if *name == "test:water" {
+          vec![]
+        } else {
+          vec![("soil".to_string(), 1)]
+        },
+        ..NodeDefinition::new(name)
+      });
+
+    new_test_node_def_manager(definitions)
+  }
+
+  fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+  }
+
+  #[test]
+  fn test_abm_triggers() {
+    let node_def_manager = new_node_def_manager();
+    let get_id = |name: &str| node_def_manager.get_id(name).unwrap_or(CONTENT_UNKNOWN);
+    let dirt = Node::new(get_id("test:dirt"));
+    let water = Node::new(get_id("test:water"));
+
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+    map.set_node(IVec3::new(1, 1, 1), dirt);
+    map.set_node(IVec3::new(8, 8, 8), dirt);
+    map.set_node(IVec3::new(9, 9, 9), water);
+
+    let definitions = vec![
+      AbmDefinition {
+        interval: 2.0,
+        chance: 1,
+        ..AbmDefinition::new(names(&["group:soil"]))
+      },
+      AbmDefinition {
+        interval: 1.0,
+        chance: 1,
+        neighbors: names(&["test:water"]),
+        ..AbmDefinition::new(names(&["test:dirt"]))
+      },
+    ];
+    let mut abms = match ActiveBlockModifiers::from_definitions(&definitions, &node_def_manager) {
+      Ok(abms) => abms,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(abms.get_count(), 2);
+
+    assert_eq!(abms.step(0.5), Vec::<usize>::new());
+    assert_eq!(abms.step(0.5), vec![1]);
+    assert_eq!(abms.step(1.0), vec![0, 1]);
+
+    let mut random = PseudoRandom::new(0);
+    let triggers = abms.find_triggers(&map, IVec3::ZERO, &[0, 1], &mut random);
+    assert_eq!(
+      triggers,
+      vec![
+        ModifierTrigger {
+          modifier: 0,
+          position: IVec3::new(1, 1, 1),
+          node: dirt,
+        },
+        ModifierTrigger {
+          modifier: 0,
+          position: IVec3::new(8, 8, 8),
+          node: dirt,
+        },
+        // Only this dirt has water next to it.
+        ModifierTrigger {
+          modifier: 1,
+          position: IVec3::new(8, 8, 8),
+          node: dirt,
+        },
+      ]
+    );
+
+    assert!(abms.matches(0, dirt.content));
+    assert!(!abms.matches(0, water.content));
+    assert!(!abms.matches(5, dirt.content));
+
+    // Blocks without any of the nodes, or without the block at all, have nothing.
+    map.insert_block(IVec3::X, MapBlock::new(water));
+    assert!(abms
+      .find_triggers(&map, IVec3::X, &[0, 1], &mut random)
+      .is_empty());
+    assert!(abms
+      .find_triggers(&map, IVec3::Y, &[0, 1], &mut random)
+      .is_empty());
+
+    // A 1 in 2 chance picks some of them.
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(dirt));
+    let sometimes = vec![AbmDefinition {
+      chance: 2,
+      ..AbmDefinition::new(names(&["test:dirt"]))
+    }];
+    let abms = match ActiveBlockModifiers::from_definitions(&sometimes, &node_def_manager) {
+      Ok(abms) => abms,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    let count = abms
+      .find_triggers(&map, IVec3::ZERO, &[0], &mut random)
+      .len();
+    assert!(count > 1500 && count < 2600);
+
+    let broken = vec![AbmDefinition {
+      chance: 0,
+      ..AbmDefinition::new(names(&["test:dirt"]))
+    }];
+    assert!(ActiveBlockModifiers::from_definitions(&broken, &node_def_manager).is_err());
+    assert!(ActiveBlockModifiers::from_definitions(
+      &[AbmDefinition::new(vec![])],
+      &node_def_manager
+    )
+    .is_err());
+  }
+
+  #[test]
+  fn test_lbm_triggers() {
+    let node_def_manager = new_node_def_manager();
+    let dirt = Node::new(
+      node_def_manager
+        .get_id("test:dirt")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+
+    let definitions = vec![
+      LbmDefinition {
+        name: "test:convert".to_string(),
+        nodenames: names(&["test:dirt"]),
+        run_at_every_load: false,
+      },
+      LbmDefinition {
+        name: "test:always".to_string(),
+        nodenames: names(&["group:soil"]),
+        run_at_every_load: true,
+      },
+    ];
+
+    // test:convert was around since 100, the game time is 500 now.
+    let mut introduction_times = HashMap::from([("test:convert".to_string(), 100)]);
+    let lbms = match LoadingBlockModifiers::from_definitions(
+      &definitions,
+      &node_def_manager,
+      &mut introduction_times,
+      500,
+    ) {
+      Ok(lbms) => lbms,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(lbms.get_count(), 2);
+    assert_eq!(introduction_times.len(), 1);
+
+    let mut block = MapBlock::new(Node::air());
+    block.set_node(IVec3::new(1, 2, 3), dirt);
+    let position = IVec3::new(17, 2, 3);
+
+    // Saved before test:convert showed up, so both go off.
+    block.set_timestamp(50);
+    let triggers = lbms.find_triggers(&block, IVec3::X);
+    assert_eq!(
+      triggers,
+      vec![
+        ModifierTrigger {
+          modifier: 0,
+          position,
+          node: dirt,
+        },
+        ModifierTrigger {
+          modifier: 1,
+          position,
+          node: dirt,
+        },
+      ]
+    );
+
+    // Saved after, only the one that always runs does.
+    block.set_timestamp(200);
+    let triggers = lbms.find_triggers(&block, IVec3::X);
+    assert_eq!(triggers.len(), 1);
+    assert_eq!(triggers[0].modifier, 1);
+
+    let new_lbm = vec![LbmDefinition {
+      name: "test:new".to_string(),
+      nodenames: names(&["test:stone"]),
+      run_at_every_load: false,
+    }];
+    if let Err(e) = LoadingBlockModifiers::from_definitions(
+      &new_lbm,
+      &node_def_manager,
+      &mut introduction_times,
+      500,
+    ) {
+      panic!("Unit test is broken. {}", e);
+    }
+    assert_eq!(introduction_times.get("test:new"), Some(&500));
+    assert_eq!(introduction_times.get("test:convert"), Some(&100));
+
+    let twice = vec![definitions[0].clone(), definitions[0].clone()];
+    assert!(LoadingBlockModifiers::from_definitions(
+      &twice,
+      &node_def_manager,
+      &mut introduction_times,
+      500
+    )
+    .is_err());
+  }
+}
@@ -0,0 +1,440 @@
+///
+/// Reads MapBlocks out of a C++ minetest map.sqlite.
+///
+/// Versions 25 to 28 look like this, numbers are big endian:
+/// * version - u8
+/// * flags - u8
+/// * lighting complete - u16, version 27 and up
+/// * content width - u8, always 2
+/// * params width - u8, always 2
+/// * nodes - zlib compressed, every param0 as a u16,
+///   then every param1, then every param2
+/// * node metadata - zlib compressed
+/// * static objects
+/// * timestamp - u32
+/// * name id mapping - which node name every param0 means
+/// * node timers - u8 length of one timer, always 10, u16 count, then
+///   the node index as a u16 and the timeout and elapsed time as
+///   i32 milliseconds, for every timer
+///
+/// Version 29 zstd compresses everything after the version byte in one go,
+/// moves the timestamp and name id mapping up in front of the nodes,
+/// and stops compressing the node metadata on it's own.
+///
+/// The nodes, timestamp and node timers are brought over,
+/// metadata and objects are skipped.
+///
+use std::io::Read;
+
+use flate2::bufread::ZlibDecoder;
+
+use super::{
+  map_block::{MapBlock, NodeTimer, MAP_BLOCK_VOLUME},
+  node::{ContentId, Node, CONTENT_UNKNOWN},
+  node_def_manager::NodeDefManager,
+};
+
+pub const LEGACY_MIN_VERSION: u8 = 25;
+pub const LEGACY_MAX_VERSION: u8 = 29;
+
+///
+/// Walks through the bytes of a C++ MapBlock.
+///
+struct LegacyReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> LegacyReader<'a> {
+  fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+    if self.data.len() - self.position < count {
+      return Err("MapBlock ends too early.".to_string());
+    }
+
+    let bytes = &self.data[self.position..self.position + count];
+    self.position += count;
+    Ok(bytes)
+  }
+
+  fn read_u8(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn read_u16(&mut self) -> Result<u16, String> {
+    let bytes = self.take(2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+  }
+
+  fn read_u32(&mut self) -> Result<u32, String> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  ///
+  /// Decompress the zlib stream which starts here, and skip past it.
+  ///
+  fn read_zlib(&mut self) -> Result<Vec<u8>, String> {
+    let mut decoder = ZlibDecoder::new(&self.data[self.position..]);
+    let mut bulk = vec![];
+    if let Err(e) = decoder.read_to_end(&mut bulk) {
+      return Err(format!("MapBlock won't decompress. {}", e));
+    }
+
+    self.position += decoder.total_in() as usize;
+    Ok(bulk)
+  }
+
+  ///
+  /// Skip past uncompressed node metadata, nothing uses it yet.
+  ///
+  fn skip_node_metadata(&mut self) -> Result<(), String> {
+    let version = self.read_u8()?;
+    if version == 0 {
+      return Ok(());
+    }
+
+    for _ in 0..self.read_u16()? {
+      let _index = self.read_u16()?;
+      for _ in 0..self.read_u32()? {
+        let key_length = self.read_u16()? as usize;
+        self.take(key_length)?;
+        let value_length = self.read_u32()? as usize;
+        self.take(value_length)?;
+        if version >= 2 {
+          let _private = self.read_u8()?;
+        }
+      }
+
+      // Every node's inventory is text which ends like this.
+      let end = b"EndInventory\n";
+      match self.data[self.position..]
+        .windows(end.len())
+        .position(|window| window == end)
+      {
+        Some(found) => self.position += found + end.len(),
+        None => return Err("MapBlock has an inventory which never ends.".to_string()),
+      }
+    }
+    Ok(())
+  }
+
+  ///
+  /// Skip past the static objects, nothing uses them yet.
+  ///
+  fn skip_static_objects(&mut self) -> Result<(), String> {
+    let _version = self.read_u8()?;
+    for _ in 0..self.read_u16()? {
+      // The type, then the position as 3 i32s.
+      self.take(1 + 12)?;
+      let length = self.read_u16()? as usize;
+      self.take(length)?;
+    }
+    Ok(())
+  }
+
+  ///
+  /// Read which node name every param0 in the block means.
+  ///
+  fn read_name_id_mapping(&mut self) -> Result<Vec<(u16, String)>, String> {
+    let version = self.read_u8()?;
+    if version != 0 {
+      return Err(format!("unsupported name id mapping version {}.", version));
+    }
+
+    let mut mapping = vec![];
+    for _ in 0..self.read_u16()? {
+      let id = self.read_u16()?;
+      let length = self.read_u16()? as usize;
+      let name = String::from_utf8_lossy(self.take(length)?).to_string();
+      mapping.push((id, name));
+    }
+    Ok(mapping)
+  }
+
+  ///
+  /// Read every node timer, by node index.
+  ///
+  fn read_node_timers(&mut self) -> Result<Vec<(usize, NodeTimer)>, String> {
+    let timer_length = self.read_u8()?;
+    if timer_length != 10 {
+      return Err(format!("unsupported node timer length {}.", timer_length));
+    }
+
+    let mut timers = vec![];
+    for _ in 0..self.read_u16()? {
+      let index = self.read_u16()? as usize;
+      let timeout = self.read_u32()? as i32 as f32 / 1000.0;
+      let elapsed = self.read_u32()? as i32 as f32 / 1000.0;
+      if index < MAP_BLOCK_VOLUME {
+        timers.push((index, NodeTimer::new(timeout, elapsed)));
+      }
+    }
+    Ok(timers)
+  }
+}
+
+///
+/// Check if some bytes from a map.sqlite came from C++ minetest.
+///
+pub fn is_legacy_block(data: &[u8]) -> bool {
+  data
+    .first()
+    .is_some_and(|version| (LEGACY_MIN_VERSION..=LEGACY_MAX_VERSION).contains(version))
+}
+
+///
+/// Turn a C++ minetest MapBlock into a MapBlock.
+///
+/// Node names are matched up with the game's nodes and aliases,
+/// anything the game doesn't have turns into unknown nodes.
+///
+pub fn deserialize_legacy_block(
+  data: &[u8],
+  node_def_manager: &NodeDefManager,
+) -> Result<MapBlock, String> {
+  let version = match data.first() {
+    Some(version) => *version,
+    None => return Err("MapBlock is empty.".to_string()),
+  };
+  if !is_legacy_block(data) {
+    return Err(format!("unsupported C++ MapBlock version {}.", version));
+  }
+
+  let decompressed;
+  let mut reader = if version >= 29 {
+    decompressed = match zstd::stream::decode_all(&data[1..]) {
+      Ok(decompressed) => decompressed,
+      Err(e) => return Err(format!("MapBlock won't decompress. {}", e)),
+    };
+    LegacyReader {
+      data: &decompressed,
+      position: 0,
+    }
+  } else {
+    LegacyReader { data, position: 1 }
+  };
+
+  let _flags = reader.read_u8()?;
+  if version >= 27 {
+    let _lighting_complete = reader.read_u16()?;
+  }
+
+  let mut timestamp = 0;
+  let mut mapping = None;
+  if version >= 29 {
+    timestamp = reader.read_u32()?;
+    mapping = Some(reader.read_name_id_mapping()?);
+  }
+
+  let content_width = reader.read_u8()?;
+  let params_width = reader.read_u8()?;
+  if content_width != 2 || params_width != 2 {
+    return Err(format!(
+      "unsupported content width {} and params width {}.",
+      content_width, params_width
+    ));
+  }
+
+  let bulk = if version >= 29 {
+    reader.take(MAP_BLOCK_VOLUME * 4)?.to_vec()
+  } else {
+    reader.read_zlib()?
+  };
+  if bulk.len() < MAP_BLOCK_VOLUME * 4 {
+    return Err("MapBlock ends too early.".to_string());
+  }
+
+  let mapping = match mapping {
+    Some(mapping) => {
+      reader.skip_node_metadata()?;
+      reader.skip_static_objects()?;
+      mapping
+    }
+    None => {
+      let _node_metadata = reader.read_zlib()?;
+      reader.skip_static_objects()?;
+      timestamp = reader.read_u32()?;
+      reader.read_name_id_mapping()?
+    }
+  };
+  let node_timers = reader.read_node_timers()?;
+
+  // Every C++ id that shows up, as an id of this game.
+  let mut contents: Vec<(u16, ContentId)> = mapping
+    .iter()
+    .map(|(id, name)| {
+      (
+        *id,
+        node_def_manager.get_id(name).unwrap_or(CONTENT_UNKNOWN),
+      )
+    })
+    .collect();
+  contents.sort_by_key(|(id, _)| *id);
+
+  let param1_start = MAP_BLOCK_VOLUME * 2;
+  let param2_start = param1_start + MAP_BLOCK_VOLUME;
+
+  let mut block = MapBlock::new(Node::air());
+  for index in 0..MAP_BLOCK_VOLUME {
+    let id = u16::from_be_bytes([bulk[index * 2], bulk[index * 2 + 1]]);
+
+    // Ids without a name can't be trusted to mean anything.
+    let content = match contents.binary_search_by_key(&id, |(id, _)| *id) {
+      Ok(found) => contents[found].1,
+      Err(_) => CONTENT_UNKNOWN,
+    };
+
+    block.set_node_at_index(
+      index,
+      Node::new_with_params(
+        content,
+        bulk[param1_start + index],
+        bulk[param2_start + index],
+      ),
+    );
+  }
+
+  // C++ minetest and this engine both count game time in seconds from the world's creation.
+  block.set_timestamp(timestamp);
+  for (index, timer) in node_timers {
+    block.set_node_timer(index, timer);
+  }
+
+  block.compact();
+  Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::ZlibEncoder, Compression};
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::{NodeTimer, MAP_BLOCK_VOLUME},
+    node::{Node, CONTENT_AIR, CONTENT_UNKNOWN},
+    node_def_manager::{NodeDefManager, NodeDefinition},
+  };
+
+  use super::{deserialize_legacy_block, is_legacy_block};
+
+  fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    match encoder.write_all(data).and_then(|_| encoder.finish()) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+
+  ///
+  /// Write a block the way C++ minetest would.
+  ///
+  /// Node 0 is C++ id 0, node 1 is id 2 with params, node 2 is id 5
+  /// which has no name, everything else is id 1.
+  ///
+  fn legacy_block(version: u8) -> Vec<u8> {
+    let mut nodes = vec![];
+    for index in 0..MAP_BLOCK_VOLUME {
+      let id: u16 = match index {
+        0 => 0,
+        1 => 2,
+        2 => 5,
+        _ => 1,
+      };
+      nodes.extend_from_slice(&id.to_be_bytes());
+    }
+    nodes.extend((0..MAP_BLOCK_VOLUME).map(|index| if index == 1 { 15 } else { 0 }));
+    nodes.extend((0..MAP_BLOCK_VOLUME).map(|index| if index == 1 { 3 } else { 0 }));
+
+    let mut mapping = vec![0, 0, 3];
+    for (id, name) in [(0u16, "test:stone"), (1, "air"), (2, "test:gone")] {
+      mapping.extend_from_slice(&id.to_be_bytes());
+      mapping.extend_from_slice(&(name.len() as u16).to_be_bytes());
+      mapping.extend_from_slice(name.as_bytes());
+    }
+
+    // One static object, then the node timers.
+    let static_objects =
+      b"\x00\x00\x01\x07\x00\x00\x00\x01\x00\x00\x00\x02\x00\x00\x00\x03\x00\x03abc";
+    // One timer on node 3, 1.5 seconds into 5.
+    let node_timers = b"\x0a\x00\x01\x00\x03\x00\x00\x13\x88\x00\x00\x05\xdc";
+    let timestamp = b"\x00\x00\x00\x64";
+
+    let mut data = vec![version];
+    if version >= 29 {
+      let mut inner = vec![0x08, 0xff, 0xff];
+      inner.extend_from_slice(timestamp);
+      inner.extend_from_slice(&mapping);
+      inner.extend_from_slice(&[2, 2]);
+      inner.extend_from_slice(&nodes);
+      // Node metadata on node 0 with one private variable, and an empty inventory.
+      inner
+        .extend_from_slice(b"\x02\x00\x01\x00\x00\x00\x00\x00\x01\x00\x01k\x00\x00\x00\x01v\x01");
+      inner.extend_from_slice(b"List main 0\nWidth 0\nEndInventoryList\nEndInventory\n");
+      inner.extend_from_slice(static_objects);
+      inner.extend_from_slice(node_timers);
+
+      match zstd::stream::encode_all(inner.as_slice(), 0) {
+        Ok(compressed) => data.extend(compressed),
+        Err(e) => panic!("Unit test is broken. {}", e),
+      }
+    } else {
+      data.push(0x08);
+      if version >= 27 {
+        data.extend_from_slice(&[0xff, 0xff]);
+      }
+      data.extend_from_slice(&[2, 2]);
+      data.extend(zlib(&nodes));
+      data.extend(zlib(&[0]));
+      data.extend_from_slice(static_objects);
+      data.extend_from_slice(timestamp);
+      data.extend_from_slice(&mapping);
+      data.extend_from_slice(node_timers);
+    }
+    data
+  }
+
+  #[test]
+  fn test_legacy_block() {
+    let definition = NodeDefinition {
+      textures: vec!["test.png".to_string()],
+      ..NodeDefinition::new("test:stone")
+    };
+    let node_def_manager = match NodeDefManager::from_definitions(vec![definition]) {
+      Ok(node_def_manager) => node_def_manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    let stone = node_def_manager
+      .get_id("test:stone")
+      .unwrap_or(CONTENT_UNKNOWN);
+
+    for version in [25, 27, 28, 29] {
+      let data = legacy_block(version);
+      assert!(is_legacy_block(&data));
+
+      let block = match deserialize_legacy_block(&data, &node_def_manager) {
+        Ok(block) => block,
+        Err(e) => panic!("Unit test is broken at version {}. {}", version, e),
+      };
+
+      assert_eq!(block.get_node(IVec3::ZERO), Node::new(stone));
+      // Nodes the game doesn't have stay as unknown, params and all.
+      assert_eq!(
+        block.get_node(IVec3::new(1, 0, 0)),
+        Node::new_with_params(CONTENT_UNKNOWN, 15, 3)
+      );
+      assert_eq!(block.get_node(IVec3::new(2, 0, 0)).content, CONTENT_UNKNOWN);
+      assert_eq!(block.get_node(IVec3::new(3, 0, 0)).content, CONTENT_AIR);
+      assert_eq!(block.get_node(IVec3::new(15, 15, 15)).content, CONTENT_AIR);
+      assert_eq!(block.get_timestamp(), 100);
+      assert_eq!(block.get_node_timer(3), Some(NodeTimer::new(5.0, 1.5)));
+
+      assert!(deserialize_legacy_block(&data[0..data.len() / 2], &node_def_manager).is_err());
+    }
+
+    assert!(!is_legacy_block(&[1]));
+    assert!(!is_legacy_block(&[24]));
+    assert!(deserialize_legacy_block(&[24, 0, 2, 2], &node_def_manager).is_err());
+  }
+}
@@ -0,0 +1,620 @@
+use std::collections::VecDeque;
+
+use glam::IVec3;
+
+use super::{
+  block_to_node_position,
+  map_block::{index_to_local, MAP_BLOCK_SIZE, MAP_BLOCK_VOLUME},
+  node::Node,
+  node_def_manager::{NodeDefManager, NodeDefinition, LIGHT_MAX},
+  Map,
+};
+
+///
+/// Light straight from the sky, one brighter than anything else.
+///
+/// Sunlight going down through nodes with sunlight_propagates stays
+/// at this, anywhere else it spreads out like any other light.
+///
+pub const LIGHT_SUN: u8 = 15;
+
+///
+/// Every direction light spreads to.
+///
+const NEIGHBOURS: [IVec3; 6] = [
+  IVec3::X,
+  IVec3::NEG_X,
+  IVec3::Y,
+  IVec3::NEG_Y,
+  IVec3::Z,
+  IVec3::NEG_Z,
+];
+
+///
+/// The two halves of param1.
+///
+/// * Day - sunlight and light sources, the low 4 bits.
+/// * Night - only light sources, the high 4 bits.
+///
+/// The client blends them with the day night ratio.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightBank {
+  Day,
+  Night,
+}
+
+impl LightBank {
+  pub const ALL: [LightBank; 2] = [LightBank::Day, LightBank::Night];
+}
+
+///
+/// Get the light of one bank out of a param1.
+///
+pub fn get_param1_light(param1: u8, bank: LightBank) -> u8 {
+  match bank {
+    LightBank::Day => param1 & 0x0F,
+    LightBank::Night => param1 >> 4,
+  }
+}
+
+///
+/// Put the light of one bank into a param1, keeping the other.
+///
+pub fn set_param1_light(param1: u8, bank: LightBank, light: u8) -> u8 {
+  match bank {
+    LightBank::Day => (param1 & 0xF0) | (light & 0x0F),
+    LightBank::Night => (param1 & 0x0F) | ((light & 0x0F) << 4),
+  }
+}
+
+///
+/// Get how bright a node is.
+///
+/// Only nodes which let light through keep it in param1,
+/// anything else is as bright as the light it gives off.
+///
+pub fn get_node_light(node: Node, definition: &NodeDefinition, bank: LightBank) -> u8 {
+  if definition.light_propagates {
+    get_param1_light(node.param1, bank)
+  } else {
+    definition.light_source
+  }
+}
+
+///
+/// Mix the two banks of a param1 into the light to draw with.
+///
+/// day_night_ratio is 1.0 at noon and 0.0 at midnight.
+///
+pub fn blend_light(param1: u8, day_night_ratio: f32) -> u8 {
+  let day = get_param1_light(param1, LightBank::Day).min(LIGHT_MAX) as f32;
+  let night = get_param1_light(param1, LightBank::Night).min(LIGHT_MAX);
+
+  let day = (day * day_night_ratio.clamp(0.0, 1.0)).round() as u8;
+  day.max(night)
+}
+
+///
+/// Turn a light level into how bright a vertex is, from 0.0 to 1.0.
+///
+/// Every level down is a bit darker than the last, like C++ minetest.
+///
+pub fn light_to_brightness(light: u8) -> f32 {
+  0.8_f32.powi((LIGHT_MAX - light.min(LIGHT_MAX)) as i32)
+}
+
+///
+/// Get how much of the day light shows at a time of day.
+///
+/// time_of_day goes from 0.0 at midnight to 0.5 at noon and back to 1.0.
+///
+pub fn time_to_day_night_ratio(time_of_day: f32) -> f32 {
+  (0.5 - (time_of_day * std::f32::consts::TAU).cos() * 1.5).clamp(0.0, 1.0)
+}
+
+///
+/// Set the light of a node in the map, if it can hold light.
+///
+fn set_light(
+  map: &mut Map,
+  definition: &NodeDefinition,
+  position: IVec3,
+  mut node: Node,
+  bank: LightBank,
+  light: u8,
+) {
+  if definition.light_propagates && get_param1_light(node.param1, bank) != light {
+    node.param1 = set_param1_light(node.param1, bank, light);
+    map.set_node(position, node);
+  }
+}
+
+///
+/// Flood fill light out from every position in the queue.
+///
+/// Stops at MapBlocks which aren't loaded, they get their
+/// light pulled in by light_block() when they show up.
+///
+fn spread_light(
+  map: &mut Map,
+  node_def_manager: &NodeDefManager,
+  bank: LightBank,
+  queue: Vec<IVec3>,
+) {
+  let mut queue = VecDeque::from(queue);
+
+  while let Some(position) = queue.pop_front() {
+    let light = match map.get_node(position) {
+      Some(node) => get_node_light(node, node_def_manager.get_or_unknown(node.content), bank),
+      None => continue,
+    };
+    if light <= 1 {
+      continue;
+    }
+
+    for direction in NEIGHBOURS {
+      let neighbour_position = position + direction;
+      let neighbour = match map.get_node(neighbour_position) {
+        Some(neighbour) => neighbour,
+        None => continue,
+      };
+
+      let definition = node_def_manager.get_or_unknown(neighbour.content);
+      if !definition.light_propagates {
+        continue;
+      }
+
+      let new_light = if bank == LightBank::Day
+        && direction == IVec3::NEG_Y
+        && light == LIGHT_SUN
+        && definition.sunlight_propagates
+      {
+        LIGHT_SUN
+      } else {
+        (light - 1).min(LIGHT_MAX)
+      };
+
+      if get_node_light(neighbour, definition, bank) < new_light {
+        set_light(
+          map,
+          definition,
+          neighbour_position,
+          neighbour,
+          bank,
+          new_light,
+        );
+        queue.push_back(neighbour_position);
+      }
+    }
+  }
+}
+
+///
+/// Take away light which came from the positions in the removal queue.
+///
+/// Every node which is lit by something else gets put into
+/// relight, so spread_light() can fill the hole back in.
+///
+fn unspread_light(
+  map: &mut Map,
+  node_def_manager: &NodeDefManager,
+  bank: LightBank,
+  mut removal: VecDeque<(IVec3, u8)>,
+  relight: &mut Vec<IVec3>,
+) {
+  while let Some((position, light)) = removal.pop_front() {
+    for direction in NEIGHBOURS {
+      let neighbour_position = position + direction;
+      let neighbour = match map.get_node(neighbour_position) {
+        Some(neighbour) => neighbour,
+        None => continue,
+      };
+
+      let definition = node_def_manager.get_or_unknown(neighbour.content);
+      let neighbour_light = get_node_light(neighbour, definition, bank);
+      if neighbour_light == 0 {
+        continue;
+      }
+
+      // Sunlight going straight down came from here, even though it's as bright.
+      let sunlight_from_here = bank == LightBank::Day
+        && direction == IVec3::NEG_Y
+        && light == LIGHT_SUN
+        && neighbour_light == LIGHT_SUN;
+
+      if neighbour_light < light || sunlight_from_here {
+        if definition.light_propagates {
+          set_light(
+            map,
+            definition,
+            neighbour_position,
+            neighbour,
+            bank,
+            definition.light_source,
+          );
+          removal.push_back((neighbour_position, neighbour_light));
+        }
+        if definition.light_source > 0 {
+          relight.push(neighbour_position);
+        }
+      } else {
+        relight.push(neighbour_position);
+      }
+    }
+  }
+}
+
+///
+/// Light up a MapBlock which was just put into the map.
+///
+/// Sunlight comes down from the block above if it's loaded,
+/// is_open_to_sky(x, z) says if it would for columns where it's not.
+/// Light from loaded neighbours gets pulled in, and this block's
+/// light gets pushed out into them.
+///
+pub fn light_block(
+  map: &mut Map,
+  node_def_manager: &NodeDefManager,
+  block_position: IVec3,
+  is_open_to_sky: impl Fn(i32, i32) -> bool,
+) {
+  let origin = block_to_node_position(block_position);
+  let mut day_queue = vec![];
+  let mut night_queue = vec![];
+
+  // Start from dark, with only the light sources lit.
+  match map.get_block_mut(block_position) {
+    Some(block) => {
+      for index in 0..MAP_BLOCK_VOLUME {
+        let mut node = block.get_node_at_index(index);
+        let definition = node_def_manager.get_or_unknown(node.content);
+
+        if definition.light_propagates {
+          for bank in LightBank::ALL {
+            node.param1 = set_param1_light(node.param1, bank, definition.light_source);
+          }
+          block.set_node_at_index(index, node);
+        }
+
+        if definition.light_source > 0 {
+          let position = origin + index_to_local(index);
+          day_queue.push(position);
+          night_queue.push(position);
+        }
+      }
+    }
+    None => return,
+  }
+
+  // Sunlight straight down every column the sky can see.
+  let mut day_removal = VecDeque::new();
+  for z in 0..MAP_BLOCK_SIZE {
+    for x in 0..MAP_BLOCK_SIZE {
+      let above = origin + IVec3::new(x, MAP_BLOCK_SIZE, z);
+      let sunlit = match map.get_node(above) {
+        Some(node) => {
+          let definition = node_def_manager.get_or_unknown(node.content);
+          get_node_light(node, definition, LightBank::Day) == LIGHT_SUN
+        }
+        None => is_open_to_sky(above.x, above.z),
+      };
+
+      let mut bottom_sunlit = sunlit;
+      if sunlit {
+        for y in (0..MAP_BLOCK_SIZE).rev() {
+          let position = origin + IVec3::new(x, y, z);
+          let node = map.get_node_or_ignore(position);
+          let definition = node_def_manager.get_or_unknown(node.content);
+
+          if !definition.light_propagates || !definition.sunlight_propagates {
+            bottom_sunlit = false;
+            break;
+          }
+
+          set_light(map, definition, position, node, LightBank::Day, LIGHT_SUN);
+          day_queue.push(position);
+        }
+      }
+
+      // The block below might have thought the sky was open.
+      let below = origin + IVec3::new(x, -1, z);
+      if let Some(node) = map.get_node(below) {
+        let definition = node_def_manager.get_or_unknown(node.content);
+        if !bottom_sunlit && get_node_light(node, definition, LightBank::Day) == LIGHT_SUN {
+          set_light(
+            map,
+            definition,
+            below,
+            node,
+            LightBank::Day,
+            definition.light_source,
+          );
+          day_removal.push_back((below, LIGHT_SUN));
+        }
+      }
+    }
+  }
+
+  // Pull in the light of every loaded neighbour.
+  for direction in NEIGHBOURS {
+    for a in 0..MAP_BLOCK_SIZE {
+      for b in 0..MAP_BLOCK_SIZE {
+        let local = match direction {
+          IVec3::X => IVec3::new(MAP_BLOCK_SIZE, a, b),
+          IVec3::NEG_X => IVec3::new(-1, a, b),
+          IVec3::Y => IVec3::new(a, MAP_BLOCK_SIZE, b),
+          IVec3::NEG_Y => IVec3::new(a, -1, b),
+          IVec3::Z => IVec3::new(a, b, MAP_BLOCK_SIZE),
+          _ => IVec3::new(a, b, -1),
+        };
+        let position = origin + local;
+
+        if let Some(node) = map.get_node(position) {
+          let definition = node_def_manager.get_or_unknown(node.content);
+          if get_node_light(node, definition, LightBank::Day) > 1 {
+            day_queue.push(position);
+          }
+          if get_node_light(node, definition, LightBank::Night) > 1 {
+            night_queue.push(position);
+          }
+        }
+      }
+    }
+  }
+
+  unspread_light(
+    map,
+    node_def_manager,
+    LightBank::Day,
+    day_removal,
+    &mut day_queue,
+  );
+  spread_light(map, node_def_manager, LightBank::Day, day_queue);
+  spread_light(map, node_def_manager, LightBank::Night, night_queue);
+}
+
+///
+/// Fix up the light around nodes which were just changed.
+///
+/// changes holds every changed position along with the node
+/// which was there before, lighting included.
+///
+pub fn update_lighting(
+  map: &mut Map,
+  node_def_manager: &NodeDefManager,
+  changes: &[(IVec3, Node)],
+) {
+  for bank in LightBank::ALL {
+    let mut removal = VecDeque::new();
+    let mut relight = vec![];
+
+    for (position, old_node) in changes {
+      let node = match map.get_node(*position) {
+        Some(node) => node,
+        None => continue,
+      };
+
+      let old_light = get_node_light(
+        *old_node,
+        node_def_manager.get_or_unknown(old_node.content),
+        bank,
+      );
+      if old_light > 0 {
+        removal.push_back((*position, old_light));
+      }
+
+      let definition = node_def_manager.get_or_unknown(node.content);
+      set_light(
+        map,
+        definition,
+        *position,
+        node,
+        bank,
+        definition.light_source,
+      );
+      if definition.light_source > 0 {
+        relight.push(*position);
+      }
+
+      // Whatever is lit around it can shine in.
+      for direction in NEIGHBOURS {
+        relight.push(*position + direction);
+      }
+    }
+
+    unspread_light(map, node_def_manager, bank, removal, &mut relight);
+    spread_light(map, node_def_manager, bank, relight);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::MapBlock,
+    node::{Node, CONTENT_UNKNOWN},
+    node_def_manager::{new_test_node_def_manager, DrawType, NodeDefManager, NodeDefinition},
+    Map,
+  };
+
+  use super::{
+    blend_light, get_node_light, get_param1_light, light_block, light_to_brightness,
+    set_param1_light, time_to_day_night_ratio, update_lighting, LightBank, LIGHT_SUN,
+  };
+
+  fn new_node_def_manager() -> NodeDefManager {
+    let torch = NodeDefinition {
+      drawtype: DrawType::BlockBox,
+      light_source: 14,
+      light_propagates: true,
+      ..NodeDefinition::new("test:torch")
+    };
+
+    new_test_node_def_manager([NodeDefinition::new("test:stone"), torch])
+  }
+
+  fn get_light(
+    map: &Map,
+    node_def_manager: &NodeDefManager,
+    position: IVec3,
+    bank: LightBank,
+  ) -> u8 {
+    let node = map.get_node_or_ignore(position);
+    get_node_light(node, node_def_manager.get_or_unknown(node.content), bank)
+  }
+
+  ///
+  /// Set a node and fix the light up like the server does.
+  ///
+  fn set_node(map: &mut Map, node_def_manager: &NodeDefManager, position: IVec3, node: Node) {
+    let old_node = map.get_node_or_ignore(position);
+    map.set_node(position, node);
+    update_lighting(map, node_def_manager, &[(position, old_node)]);
+  }
+
+  #[test]
+  fn test_light_values() {
+    let param1 = set_param1_light(
+      set_param1_light(0, LightBank::Day, LIGHT_SUN),
+      LightBank::Night,
+      3,
+    );
+    assert_eq!(get_param1_light(param1, LightBank::Day), LIGHT_SUN);
+    assert_eq!(get_param1_light(param1, LightBank::Night), 3);
+
+    // Sunlight fades at night, light sources don't.
+    assert_eq!(blend_light(param1, 1.0), 14);
+    assert_eq!(blend_light(param1, 0.5), 7);
+    assert_eq!(blend_light(param1, 0.0), 3);
+
+    assert_eq!(light_to_brightness(14), 1.0);
+    assert!(light_to_brightness(0) < light_to_brightness(1));
+
+    assert_eq!(time_to_day_night_ratio(0.0), 0.0);
+    assert_eq!(time_to_day_night_ratio(0.5), 1.0);
+    assert!((time_to_day_night_ratio(0.25) - 0.5).abs() < 0.01);
+  }
+
+  #[test]
+  fn test_sunlight() {
+    let node_def_manager = new_node_def_manager();
+    let stone = Node::new(
+      node_def_manager
+        .get_id("test:stone")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+    let mut map = Map::new();
+
+    // An open sky over two blocks of air with a stone floor.
+    for block_position in [IVec3::ZERO, IVec3::NEG_Y] {
+      map.insert_block(block_position, MapBlock::new(Node::air()));
+    }
+    for x in 0..16 {
+      for z in 0..16 {
+        map.set_node(IVec3::new(x, -10, z), stone);
+      }
+    }
+    light_block(&mut map, &node_def_manager, IVec3::ZERO, |_, _| true);
+    light_block(&mut map, &node_def_manager, IVec3::NEG_Y, |_, _| true);
+
+    let day =
+      |map: &Map, position: IVec3| get_light(map, &node_def_manager, position, LightBank::Day);
+    assert_eq!(day(&map, IVec3::new(5, 15, 5)), LIGHT_SUN);
+    assert_eq!(day(&map, IVec3::new(5, -9, 5)), LIGHT_SUN);
+    assert_eq!(day(&map, IVec3::new(5, -11, 5)), 0);
+    assert_eq!(
+      get_light(
+        &map,
+        &node_def_manager,
+        IVec3::new(5, -9, 5),
+        LightBank::Night
+      ),
+      0
+    );
+
+    // A roof node shades the column below it, the light around leaks in.
+    set_node(&mut map, &node_def_manager, IVec3::new(5, 5, 5), stone);
+    assert_eq!(day(&map, IVec3::new(5, 4, 5)), 14);
+    assert_eq!(day(&map, IVec3::new(5, -9, 5)), 14);
+    assert_eq!(day(&map, IVec3::new(6, -9, 5)), LIGHT_SUN);
+
+    set_node(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(5, 5, 5),
+      Node::air(),
+    );
+    assert_eq!(day(&map, IVec3::new(5, -9, 5)), LIGHT_SUN);
+
+    // A whole roof put on later takes the sun away from the block below.
+    let mut roofed = MapBlock::new(Node::air());
+    for x in 0..16 {
+      for z in 0..16 {
+        roofed.set_node(IVec3::new(x, 0, z), stone);
+      }
+    }
+    map.insert_block(IVec3::Y, roofed);
+    light_block(&mut map, &node_def_manager, IVec3::Y, |_, _| true);
+
+    assert_eq!(day(&map, IVec3::new(5, 17, 5)), LIGHT_SUN);
+    assert_eq!(day(&map, IVec3::new(5, 15, 5)), 0);
+    assert_eq!(day(&map, IVec3::new(5, -9, 5)), 0);
+  }
+
+  #[test]
+  fn test_light_sources() {
+    let node_def_manager = new_node_def_manager();
+    let torch = Node::new(
+      node_def_manager
+        .get_id("test:torch")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+    let mut map = Map::new();
+
+    // Two dark blocks side by side.
+    for block_position in [IVec3::ZERO, IVec3::X] {
+      map.insert_block(block_position, MapBlock::new(Node::air()));
+      light_block(&mut map, &node_def_manager, block_position, |_, _| false);
+    }
+
+    let night =
+      |map: &Map, position: IVec3| get_light(map, &node_def_manager, position, LightBank::Night);
+
+    // Light spreads across the border, one less every node.
+    set_node(&mut map, &node_def_manager, IVec3::new(15, 8, 8), torch);
+    assert_eq!(night(&map, IVec3::new(15, 8, 8)), 14);
+    assert_eq!(night(&map, IVec3::new(16, 8, 8)), 13);
+    assert_eq!(night(&map, IVec3::new(20, 8, 8)), 9);
+    assert_eq!(night(&map, IVec3::new(16, 9, 9)), 11);
+    assert_eq!(
+      get_light(
+        &map,
+        &node_def_manager,
+        IVec3::new(20, 8, 8),
+        LightBank::Day
+      ),
+      9
+    );
+
+    // A second torch keeps its own light when the first one goes.
+    set_node(&mut map, &node_def_manager, IVec3::new(25, 8, 8), torch);
+    set_node(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(15, 8, 8),
+      Node::air(),
+    );
+    assert_eq!(night(&map, IVec3::new(15, 8, 8)), 4);
+    assert_eq!(night(&map, IVec3::new(20, 8, 8)), 9);
+    assert_eq!(night(&map, IVec3::new(5, 8, 8)), 0);
+
+    // A block showing up next to a torch gets lit by it.
+    map.insert_block(IVec3::new(2, 0, 0), MapBlock::new(Node::air()));
+    light_block(&mut map, &node_def_manager, IVec3::new(2, 0, 0), |_, _| {
+      false
+    });
+    assert_eq!(night(&map, IVec3::new(32, 8, 8)), 7);
+  }
+}
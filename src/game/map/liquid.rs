@@ -0,0 +1,525 @@
+use glam::IVec3;
+
+use super::{
+  lighting::update_lighting,
+  node::{ContentId, Node, CONTENT_IGNORE},
+  node_def_manager::{DrawType, LiquidType, NodeDefManager, NodeDefinition, LIQUID_RANGE_MAX},
+  Map,
+};
+
+///
+/// Flowing liquid keeps it's level in the low 3 bits of param2,
+/// 7 right next to a source and 0 at the very edge.
+///
+pub const LIQUID_LEVEL_MASK: u8 = 0x07;
+pub const LIQUID_LEVEL_MAX: u8 = 7;
+
+///
+/// Set in param2 when flowing liquid is falling down.
+///
+pub const LIQUID_FLOW_DOWN: u8 = 0x08;
+
+///
+/// The level a source counts as, one above any flowing liquid.
+///
+pub const LIQUID_LEVEL_SOURCE: u8 = LIQUID_LEVEL_MAX + 1;
+
+///
+/// The directions liquid spreads out to on the ground.
+///
+const HORIZONTAL_NEIGHBOURS: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+///
+/// A liquid's source and flowing nodes, and how it behaves.
+///
+/// The source node's definition has the final say on range and renewable.
+///
+struct LiquidKind {
+  source: ContentId,
+  flowing: ContentId,
+  range: u8,
+  renewable: bool,
+}
+
+impl LiquidKind {
+  ///
+  /// Work out which liquid a liquid node belongs to.
+  ///
+  /// Returns None if it's not a liquid, or it's other half isn't registered.
+  ///
+  fn from_definition(
+    definition: &NodeDefinition,
+    node_def_manager: &NodeDefManager,
+  ) -> Option<Self> {
+    if definition.liquidtype == LiquidType::None {
+      return None;
+    }
+
+    let source = node_def_manager.get_id(&definition.liquid_alternative_source)?;
+    let flowing = node_def_manager.get_id(&definition.liquid_alternative_flowing)?;
+    let source_definition = node_def_manager.get_or_unknown(source);
+
+    Some(LiquidKind {
+      source,
+      flowing,
+      range: source_definition.liquid_range.min(LIQUID_RANGE_MAX),
+      renewable: source_definition.liquid_renewable,
+    })
+  }
+
+  ///
+  /// Get the level of a node if it's this liquid.
+  ///
+  fn get_level(&self, node: Node) -> Option<u8> {
+    if node.content == self.source {
+      Some(LIQUID_LEVEL_SOURCE)
+    } else if node.content == self.flowing {
+      Some(node.param2 & LIQUID_LEVEL_MASK)
+    } else {
+      None
+    }
+  }
+}
+
+///
+/// Check if liquid can flow into a node.
+///
+fn is_floodable(node: Node, definition: &NodeDefinition) -> bool {
+  node.content != CONTENT_IGNORE
+    && definition.drawtype == DrawType::Air
+    && definition.liquidtype == LiquidType::None
+}
+
+///
+/// Check if the liquid at a position spreads out to the sides.
+///
+/// Sources always do, flowing liquid only once it can't fall any further.
+///
+fn spreads_sideways(
+  map: &Map,
+  node_def_manager: &NodeDefManager,
+  position: IVec3,
+  kind: &LiquidKind,
+  level: u8,
+) -> bool {
+  if level == LIQUID_LEVEL_SOURCE {
+    return true;
+  }
+
+  match map.get_node(position - IVec3::Y) {
+    Some(below) => {
+      let falling_into = kind
+        .get_level(below)
+        .is_some_and(|level| level != LIQUID_LEVEL_SOURCE);
+      !falling_into && !is_floodable(below, node_def_manager.get_or_unknown(below.content))
+    }
+    None => true,
+  }
+}
+
+///
+/// Work out what the node at a position should be, going by the liquid around it.
+///
+fn get_flowed_node(
+  map: &Map,
+  node_def_manager: &NodeDefManager,
+  position: IVec3,
+  kind: &LiquidKind,
+) -> Node {
+  let falling = map
+    .get_node(position + IVec3::Y)
+    .and_then(|above| kind.get_level(above))
+    .is_some();
+
+  let mut sources = 0;
+  let mut max_level = None;
+  for direction in HORIZONTAL_NEIGHBOURS {
+    let neighbour_position = position + direction;
+    let level = match map
+      .get_node(neighbour_position)
+      .and_then(|node| kind.get_level(node))
+    {
+      Some(level) => level,
+      None => continue,
+    };
+
+    if level == LIQUID_LEVEL_SOURCE {
+      sources += 1;
+    }
+
+    if level > 0 && spreads_sideways(map, node_def_manager, neighbour_position, kind, level) {
+      max_level = max_level.max(Some(level - 1));
+    }
+  }
+
+  // Two sources next to each other fill in the gap, if there's something under it.
+  let supported = match map.get_node(position - IVec3::Y) {
+    Some(below) => match kind.get_level(below) {
+      Some(level) => level == LIQUID_LEVEL_SOURCE,
+      None => !is_floodable(below, node_def_manager.get_or_unknown(below.content)),
+    },
+    None => false,
+  };
+  if kind.renewable && sources >= 2 && supported {
+    return Node::new(kind.source);
+  }
+
+  if falling {
+    return Node::new_with_params(kind.flowing, 0, LIQUID_LEVEL_MAX | LIQUID_FLOW_DOWN);
+  }
+
+  let min_level = LIQUID_LEVEL_SOURCE - kind.range;
+  match max_level {
+    Some(level) if kind.range > 0 && level >= min_level => {
+      Node::new_with_params(kind.flowing, 0, level)
+    }
+    _ => Node::air(),
+  }
+}
+
+///
+/// Find a liquid next to a position that could flow into it.
+///
+fn find_liquid_kind(
+  map: &Map,
+  node_def_manager: &NodeDefManager,
+  position: IVec3,
+) -> Option<LiquidKind> {
+  let mut directions = vec![IVec3::Y];
+  directions.extend(HORIZONTAL_NEIGHBOURS);
+
+  directions.into_iter().find_map(|direction| {
+    let node = map.get_node(position + direction)?;
+    LiquidKind::from_definition(
+      node_def_manager.get_or_unknown(node.content),
+      node_def_manager,
+    )
+  })
+}
+
+///
+/// Work through the map's liquid queue, the same way C++ minetest's liquid loop does.
+///
+/// Only what was queued before this started gets done, so liquid
+/// spreads one node further every call. max_updates caps how many
+/// positions get looked at, whatever's left waits for the next call.
+///
+/// Gives back how many nodes changed.
+///
+pub fn transform_liquids(
+  map: &mut Map,
+  node_def_manager: &NodeDefManager,
+  max_updates: usize,
+) -> usize {
+  let count = map.get_liquid_queue_size().min(max_updates);
+  let mut changes = vec![];
+
+  for _ in 0..count {
+    let position = match map.pop_liquid_update() {
+      Some(position) => position,
+      None => break,
+    };
+    let node = match map.get_node(position) {
+      Some(node) => node,
+      None => continue,
+    };
+    let definition = node_def_manager.get_or_unknown(node.content);
+
+    let kind = match definition.liquidtype {
+      // Sources never change by themselves.
+      LiquidType::Source => continue,
+      LiquidType::Flowing => LiquidKind::from_definition(definition, node_def_manager),
+      LiquidType::None if is_floodable(node, definition) => {
+        find_liquid_kind(map, node_def_manager, position)
+      }
+      LiquidType::None => continue,
+    };
+    let kind = match kind {
+      Some(kind) => kind,
+      None => continue,
+    };
+
+    let new_node = get_flowed_node(map, node_def_manager, position, &kind);
+    if new_node.content == node.content && new_node.param2 == node.param2 {
+      continue;
+    }
+
+    map.set_node(position, new_node);
+    map.queue_liquid_update(position);
+    changes.push((position, node));
+  }
+
+  update_lighting(map, node_def_manager, &changes);
+
+  changes.len()
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::MapBlock,
+    node::{Node, CONTENT_AIR, CONTENT_UNKNOWN},
+    node_def_manager::{new_test_node_def_manager, LiquidType, NodeDefManager, NodeDefinition},
+    Map,
+  };
+
+  use super::{transform_liquids, LIQUID_FLOW_DOWN, LIQUID_LEVEL_MAX};
+
+  fn liquid(name: &str, liquidtype: LiquidType, range: u8, renewable: bool) -> NodeDefinition {
+    NodeDefinition {
+      walkable: false,
+      light_propagates: true,
+      liquidtype,
+      liquid_alternative_source: format!("{}_source", name),
+      liquid_alternative_flowing: format!("{}_flowing", name),
+      liquid_range: range,
+      liquid_renewable: renewable,
+      ..NodeDefinition::new(&format!(
+        "{}_{}",
+        name,
+        match liquidtype {
+          LiquidType::Source => "source",
+          _ => "flowing",
+        }
+      ))
+    }
+  }
+
+  fn new_node_def_manager() -> NodeDefManager {
+    new_test_node_def_manager([
+      NodeDefinition::new("test:stone"),
+      liquid("test:water", LiquidType::Source, 8, true),
+      liquid("test:water", LiquidType::Flowing, 8, true),
+      liquid("test:lava", LiquidType::Source, 2, false),
+      liquid("test:lava", LiquidType::Flowing, 2, false),
+    ])
+  }
+
+  ///
+  /// A 3x1x3 area of MapBlocks, air with a stone floor at y = 0.
+  ///
+  fn new_map(node_def_manager: &NodeDefManager) -> Map {
+    let stone = Node::new(
+      node_def_manager
+        .get_id("test:stone")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+    let mut map = Map::new();
+
+    for x in -1..=1 {
+      for z in -1..=1 {
+        let mut block = MapBlock::new(Node::air());
+        for local_x in 0..16 {
+          for local_z in 0..16 {
+            block.set_node(IVec3::new(local_x, 0, local_z), stone);
+          }
+        }
+        map.insert_block(IVec3::new(x, 0, z), block);
+      }
+    }
+
+    map
+  }
+
+  fn place(map: &mut Map, node_def_manager: &NodeDefManager, position: IVec3, name: &str) {
+    let content = node_def_manager.get_id(name).unwrap_or(CONTENT_UNKNOWN);
+    map.set_node(position, Node::new(content));
+    map.queue_liquid_update(position);
+  }
+
+  fn settle(map: &mut Map, node_def_manager: &NodeDefManager) {
+    for _ in 0..100 {
+      transform_liquids(map, node_def_manager, 10000);
+      if map.get_liquid_queue_size() == 0 {
+        return;
+      }
+    }
+    panic!("Unit test is broken. Liquid never settled.");
+  }
+
+  ///
+  /// Get the name and param2 of a node, to check against.
+  ///
+  fn get(map: &Map, node_def_manager: &NodeDefManager, position: IVec3) -> (String, u8) {
+    let node = map.get_node_or_ignore(position);
+    (
+      node_def_manager.get_or_unknown(node.content).name.clone(),
+      node.param2,
+    )
+  }
+
+  #[test]
+  fn test_liquid_spread() {
+    let node_def_manager = new_node_def_manager();
+    let mut map = new_map(&node_def_manager);
+    let flowing = |level: u8| ("test:water_flowing".to_string(), level);
+
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(8, 1, 8),
+      "test:water_source",
+    );
+
+    // Nothing happens until the liquid loop runs, then one step at a time.
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(9, 1, 8)).0, "air");
+    transform_liquids(&mut map, &node_def_manager, 10000);
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(9, 1, 8)),
+      flowing(7)
+    );
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(10, 1, 8)).0, "air");
+
+    settle(&mut map, &node_def_manager);
+
+    // A diamond, one level lower every node away from the source.
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(10, 1, 8)),
+      flowing(6)
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(9, 1, 9)),
+      flowing(6)
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(16, 1, 8)),
+      flowing(0)
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(12, 1, 12)),
+      flowing(0)
+    );
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(17, 1, 8)).0, "air");
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(13, 1, 12)).0, "air");
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(8, 2, 8)).0, "air");
+
+    // Take the source away and it all dries up.
+    map.set_node(IVec3::new(8, 1, 8), Node::air());
+    map.queue_liquid_update(IVec3::new(8, 1, 8));
+    settle(&mut map, &node_def_manager);
+
+    for x in 0..=17 {
+      assert_eq!(
+        map.get_node_or_ignore(IVec3::new(x, 1, 8)).content,
+        CONTENT_AIR
+      );
+    }
+  }
+
+  #[test]
+  fn test_liquid_falling() {
+    let node_def_manager = new_node_def_manager();
+    let mut map = new_map(&node_def_manager);
+
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(8, 10, 8),
+      "test:water_source",
+    );
+    settle(&mut map, &node_def_manager);
+
+    // The source and the flowing liquid next to it fall straight down
+    // without spreading, then spread out on the floor.
+    let falling = (
+      "test:water_flowing".to_string(),
+      LIQUID_LEVEL_MAX | LIQUID_FLOW_DOWN,
+    );
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(8, 5, 8)), falling);
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(9, 5, 8)), falling);
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(9, 1, 8)), falling);
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(9, 10, 8)),
+      ("test:water_flowing".to_string(), LIQUID_LEVEL_MAX)
+    );
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(10, 10, 8)).0, "air");
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(10, 5, 8)).0, "air");
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(10, 1, 8)),
+      ("test:water_flowing".to_string(), 6)
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(16, 1, 8)),
+      ("test:water_flowing".to_string(), 0)
+    );
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(17, 1, 8)).0, "air");
+  }
+
+  #[test]
+  fn test_liquid_range_and_renewable() {
+    let node_def_manager = new_node_def_manager();
+    let mut map = new_map(&node_def_manager);
+
+    // Water fills the gap between two sources with a new one.
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(4, 1, 4),
+      "test:water_source",
+    );
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(6, 1, 4),
+      "test:water_source",
+    );
+
+    // Lava only goes 2 nodes, and never makes more of itself.
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(-8, 1, -8),
+      "test:lava_source",
+    );
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(-6, 1, -8),
+      "test:lava_source",
+    );
+    settle(&mut map, &node_def_manager);
+
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(5, 1, 4)).0,
+      "test:water_source"
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(-7, 1, -8)),
+      ("test:lava_flowing".to_string(), 7)
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(-10, 1, -8)),
+      ("test:lava_flowing".to_string(), 6)
+    );
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(-11, 1, -8)).0,
+      "air"
+    );
+  }
+
+  #[test]
+  fn test_liquid_update_cap() {
+    let node_def_manager = new_node_def_manager();
+    let mut map = new_map(&node_def_manager);
+
+    place(
+      &mut map,
+      &node_def_manager,
+      IVec3::new(8, 1, 8),
+      "test:water_source",
+    );
+    let queued = map.get_liquid_queue_size();
+
+    transform_liquids(&mut map, &node_def_manager, 2);
+    assert!(map.get_liquid_queue_size() >= queued - 2);
+    assert_eq!(get(&map, &node_def_manager, IVec3::new(8, 1, 9)).0, "air");
+
+    settle(&mut map, &node_def_manager);
+    assert_eq!(
+      get(&map, &node_def_manager, IVec3::new(8, 1, 9)),
+      ("test:water_flowing".to_string(), 7)
+    );
+  }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+
+use glam::IVec3;
+
+use super::{
+  node::{ContentId, Node},
+  palette::PaletteArray,
+};
+
+///
+/// How many nodes a MapBlock is along each axis.
+///
+pub const MAP_BLOCK_SIZE: i32 = 16;
+
+///
+/// How many nodes are in a MapBlock.
+///
+pub const MAP_BLOCK_VOLUME: usize = (MAP_BLOCK_SIZE * MAP_BLOCK_SIZE * MAP_BLOCK_SIZE) as usize;
+
+///
+/// The timestamp of a MapBlock which was never saved by anything that knew the time.
+///
+pub const BLOCK_TIMESTAMP_UNDEFINED: u32 = u32::MAX;
+
+///
+/// A timer on a single node, which runs the node's on_timer when it goes off.
+///
+/// Both are in seconds, elapsed counts up to timeout.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeTimer {
+  pub timeout: f32,
+  pub elapsed: f32,
+}
+
+impl NodeTimer {
+  pub fn new(timeout: f32, elapsed: f32) -> Self {
+    NodeTimer { timeout, elapsed }
+  }
+}
+
+///
+/// A 16x16x16 chunk of nodes, the unit the map is stored,
+/// generated and sent in.
+///
+/// Content, param1 and param2 are each palette compressed on
+/// their own. A block of only stone with the same light everywhere
+/// holds three single value palettes and nothing else.
+///
+/// The timestamp is the game time the block was last around for,
+/// LBMs use it to tell if a block has seen them yet.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapBlock {
+  content: PaletteArray<ContentId>,
+  param1: PaletteArray<u8>,
+  param2: PaletteArray<u8>,
+  // By node index.
+  node_timers: BTreeMap<usize, NodeTimer>,
+  timestamp: u32,
+}
+
+impl MapBlock {
+  ///
+  /// Create a MapBlock filled with one node.
+  ///
+  pub fn new(node: Node) -> Self {
+    MapBlock {
+      content: PaletteArray::new(MAP_BLOCK_VOLUME, node.content),
+      param1: PaletteArray::new(MAP_BLOCK_VOLUME, node.param1),
+      param2: PaletteArray::new(MAP_BLOCK_VOLUME, node.param2),
+      node_timers: BTreeMap::new(),
+      timestamp: BLOCK_TIMESTAMP_UNDEFINED,
+    }
+  }
+
+  ///
+  /// Get the node at a position inside of this block.
+  ///
+  pub fn get_node(&self, local_position: IVec3) -> Node {
+    self.get_node_at_index(local_to_index(local_position))
+  }
+
+  ///
+  /// Set the node at a position inside of this block.
+  ///
+  pub fn set_node(&mut self, local_position: IVec3, node: Node) {
+    self.set_node_at_index(local_to_index(local_position), node);
+  }
+
+  ///
+  /// Get the node at an index in z, y, x order.
+  ///
+  pub fn get_node_at_index(&self, index: usize) -> Node {
+    Node {
+      content: self.content.get(index),
+      param1: self.param1.get(index),
+      param2: self.param2.get(index),
+    }
+  }
+
+  ///
+  /// Set the node at an index in z, y, x order.
+  ///
+  pub fn set_node_at_index(&mut self, index: usize, node: Node) {
+    self.content.set(index, node.content);
+    self.param1.set(index, node.param1);
+    self.param2.set(index, node.param2);
+  }
+
+  ///
+  /// Fill the whole block with one node.
+  ///
+  pub fn fill(&mut self, node: Node) {
+    self.content.fill(node.content);
+    self.param1.fill(node.param1);
+    self.param2.fill(node.param2);
+  }
+
+  ///
+  /// Get every different content id in this block.
+  ///
+  /// Might have a few which aren't used anymore until compact() is ran.
+  ///
+  pub fn get_content_palette(&self) -> &[ContentId] {
+    self.content.get_palette()
+  }
+
+  ///
+  /// Check if the whole block is one node.
+  ///
+  pub fn is_uniform(&self) -> bool {
+    self.content.is_uniform() && self.param1.is_uniform() && self.param2.is_uniform()
+  }
+
+  ///
+  /// Shrink the palettes down to what's actually used.
+  ///
+  pub fn compact(&mut self) {
+    self.content.compact();
+    self.param1.compact();
+    self.param2.compact();
+  }
+
+  ///
+  /// Get the timer of the node at an index, if it has one running.
+  ///
+  pub fn get_node_timer(&self, index: usize) -> Option<NodeTimer> {
+    self.node_timers.get(&index).copied()
+  }
+
+  ///
+  /// Start (or restart) the timer of the node at an index.
+  ///
+  pub fn set_node_timer(&mut self, index: usize, timer: NodeTimer) {
+    self.node_timers.insert(index, timer);
+  }
+
+  ///
+  /// Stop the timer of the node at an index.
+  ///
+  pub fn remove_node_timer(&mut self, index: usize) {
+    self.node_timers.remove(&index);
+  }
+
+  ///
+  /// Get every running node timer, by node index.
+  ///
+  pub fn get_node_timers(&self) -> &BTreeMap<usize, NodeTimer> {
+    &self.node_timers
+  }
+
+  ///
+  /// Move every node timer forward, and take out the ones which went off.
+  ///
+  /// The timers which went off come back with how long they really ran.
+  ///
+  pub fn step_node_timers(&mut self, delta: f32) -> Vec<(usize, NodeTimer)> {
+    let mut elapsed = vec![];
+    self.node_timers.retain(|index, timer| {
+      timer.elapsed += delta;
+      if timer.elapsed >= timer.timeout {
+        elapsed.push((*index, *timer));
+        return false;
+      }
+      true
+    });
+    elapsed
+  }
+
+  ///
+  /// Get the game time this block was last around for.
+  ///
+  pub fn get_timestamp(&self) -> u32 {
+    self.timestamp
+  }
+
+  pub fn set_timestamp(&mut self, timestamp: u32) {
+    self.timestamp = timestamp;
+  }
+
+  ///
+  /// Get roughly how many bytes this block takes up.
+  ///
+  pub fn get_memory_usage(&self) -> usize {
+    self.content.get_memory_usage()
+      + self.param1.get_memory_usage()
+      + self.param2.get_memory_usage()
+  }
+}
+
+impl Default for MapBlock {
+  fn default() -> Self {
+    Self::new(Node::ignore())
+  }
+}
+
+///
+/// Check if a position is inside of a MapBlock.
+///
+pub fn is_valid_local_position(local_position: IVec3) -> bool {
+  local_position.cmpge(IVec3::ZERO).all()
+    && local_position.cmplt(IVec3::splat(MAP_BLOCK_SIZE)).all()
+}
+
+///
+/// Turn a position inside of a MapBlock into an index.
+///
+/// Same z, y, x order as C++ minetest.
+///
+pub fn local_to_index(local_position: IVec3) -> usize {
+  if !is_valid_local_position(local_position) {
+    panic!(
+      "MapBlock: local position {} is out of bounds.",
+      local_position
+    );
+  }
+
+  (local_position.z * MAP_BLOCK_SIZE * MAP_BLOCK_SIZE
+    + local_position.y * MAP_BLOCK_SIZE
+    + local_position.x) as usize
+}
+
+///
+/// Turn an index back into a position inside of a MapBlock.
+///
+pub fn index_to_local(index: usize) -> IVec3 {
+  let index = index as i32;
+  IVec3::new(
+    index % MAP_BLOCK_SIZE,
+    (index / MAP_BLOCK_SIZE) % MAP_BLOCK_SIZE,
+    index / (MAP_BLOCK_SIZE * MAP_BLOCK_SIZE),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use crate::game::map::node::{Node, CONTENT_AIR};
+
+  use super::{index_to_local, local_to_index, MapBlock, NodeTimer, MAP_BLOCK_VOLUME};
+
+  #[test]
+  fn test_map_block() {
+    let stone = Node::new(200);
+    let mut block = MapBlock::new(stone);
+
+    // A single material block doesn't store any indices.
+    assert!(block.is_uniform());
+    let uniform_size = block.get_memory_usage();
+    assert!(uniform_size < 256);
+
+    let torch = Node::new_with_params(201, 14, 3);
+    block.set_node(IVec3::new(1, 2, 3), torch);
+    block.set_node(IVec3::new(15, 15, 15), Node::air());
+
+    assert_eq!(block.get_node(IVec3::new(1, 2, 3)), torch);
+    assert_eq!(block.get_node(IVec3::new(15, 15, 15)).content, CONTENT_AIR);
+    assert_eq!(block.get_node(IVec3::new(0, 0, 0)), stone);
+    assert!(!block.is_uniform());
+
+    // 3 different contents only needs 2 bits each.
+    assert!(block.get_memory_usage() < uniform_size + MAP_BLOCK_VOLUME);
+
+    // Putting it back and compacting shrinks it again.
+    block.set_node(IVec3::new(1, 2, 3), stone);
+    block.set_node(IVec3::new(15, 15, 15), stone);
+    block.compact();
+    assert!(block.is_uniform());
+    assert_eq!(block, MapBlock::new(stone));
+
+    for index in [0, 1, 16, 256, 4095] {
+      assert_eq!(local_to_index(index_to_local(index)), index);
+    }
+  }
+
+  #[test]
+  fn test_node_timers() {
+    let mut block = MapBlock::new(Node::air());
+    block.set_node_timer(1, NodeTimer::new(1.0, 0.0));
+    block.set_node_timer(2, NodeTimer::new(3.0, 1.5));
+
+    assert!(block.step_node_timers(0.5).is_empty());
+    assert_eq!(block.get_node_timer(1), Some(NodeTimer::new(1.0, 0.5)));
+
+    // Only the one that went off comes out.
+    assert_eq!(
+      block.step_node_timers(0.75),
+      vec![(1, NodeTimer::new(1.0, 1.25))]
+    );
+    assert_eq!(block.get_node_timer(1), None);
+    assert_eq!(block.get_node_timers().len(), 1);
+
+    block.remove_node_timer(2);
+    assert!(block.get_node_timers().is_empty());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_map_block_out_of_bounds() {
+    let block = MapBlock::default();
+    block.get_node(IVec3::new(16, 0, 0));
+  }
+}
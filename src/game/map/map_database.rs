@@ -0,0 +1,244 @@
+use glam::IVec3;
+use rusqlite::Connection;
+use sea_query::{ColumnDef, Expr, Iden, OnConflict, Query, SqliteQueryBuilder, Table, Values};
+
+use crate::game::database::{execute, open_database, query};
+
+///
+/// The blocks table, same layout as C++ minetest's map.sqlite.
+///
+#[derive(Iden)]
+enum Blocks {
+  Table,
+  Pos,
+  Data,
+}
+
+///
+/// Turn a block position into the single number it's stored under.
+///
+/// Same as C++ minetest, every axis gets 12 bits.
+///
+pub fn block_position_to_key(block_position: IVec3) -> i64 {
+  block_position.z as i64 * 0x1000000 + block_position.y as i64 * 0x1000 + block_position.x as i64
+}
+
+///
+/// Turn a stored number back into a block position.
+///
+pub fn key_to_block_position(key: i64) -> IVec3 {
+  // Each axis is a signed 12 bit number.
+  let unsigned_to_signed = |value: i64| {
+    if value < 2048 {
+      value
+    } else {
+      value - 4096
+    }
+  };
+
+  let x = unsigned_to_signed(key.rem_euclid(4096));
+  let key = (key - x) / 4096;
+  let y = unsigned_to_signed(key.rem_euclid(4096));
+  let key = (key - y) / 4096;
+  let z = unsigned_to_signed(key.rem_euclid(4096));
+
+  IVec3::new(x as i32, y as i32, z as i32)
+}
+
+///
+/// The map.sqlite of a world, which holds every MapBlock
+/// that has ever been generated.
+///
+/// This only moves bytes around, map_serialization turns
+/// them into MapBlocks.
+///
+pub struct MapDatabase {
+  database: Connection,
+}
+
+impl MapDatabase {
+  ///
+  /// Open (or create) the map database at a path.
+  ///
+  pub fn new(path: &str) -> Self {
+    let database = match open_database(path) {
+      Ok(database) => database,
+      Err(e) => panic!("MapDatabase: {}", e),
+    };
+
+    println!("MapDatabase: using [{}].", path);
+
+    Self::new_with_database(database)
+  }
+
+  ///
+  /// Create a map database which only lives in memory.
+  ///
+  pub fn new_in_memory() -> Self {
+    let database = match Connection::open_in_memory() {
+      Ok(database) => database,
+      Err(e) => panic!("MapDatabase: failed to open memory database. {}", e),
+    };
+
+    Self::new_with_database(database)
+  }
+
+  fn new_with_database(database: Connection) -> Self {
+    let new_map_database = MapDatabase { database };
+    new_map_database.create_tables();
+    new_map_database
+  }
+
+  fn create_tables(&self) {
+    let statement = Table::create()
+      .table(Blocks::Table)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(Blocks::Pos)
+          .big_integer()
+          .not_null()
+          .primary_key(),
+      )
+      .col(ColumnDef::new(Blocks::Data).binary().not_null())
+      .build(SqliteQueryBuilder);
+
+    if let Err(e) = execute(&self.database, (statement, Values(vec![]))) {
+      panic!("MapDatabase: {}", e);
+    }
+  }
+
+  ///
+  /// Save a block's bytes, replacing what was there.
+  ///
+  pub fn save_block(&self, block_position: IVec3, data: Vec<u8>) -> Result<(), String> {
+    let mut insert = Query::insert();
+    insert
+      .into_table(Blocks::Table)
+      .columns([Blocks::Pos, Blocks::Data]);
+
+    if let Err(e) = insert.values([block_position_to_key(block_position).into(), data.into()]) {
+      return Err(format!(
+        "failed to build block {} query. {}",
+        block_position, e
+      ));
+    }
+
+    let statement = insert
+      .on_conflict(
+        OnConflict::column(Blocks::Pos)
+          .update_column(Blocks::Data)
+          .to_owned(),
+      )
+      .build(SqliteQueryBuilder);
+
+    execute(&self.database, statement)?;
+    Ok(())
+  }
+
+  ///
+  /// Save a lot of blocks at once.
+  ///
+  /// They all go in one transaction, which is a lot faster than one each.
+  ///
+  pub fn save_blocks(&self, blocks: Vec<(IVec3, Vec<u8>)>) -> Result<(), String> {
+    if let Err(e) = self.database.execute_batch("BEGIN") {
+      return Err(format!("failed to start saving blocks. {}", e));
+    }
+
+    for (block_position, data) in blocks {
+      if let Err(e) = self.save_block(block_position, data) {
+        let _ = self.database.execute_batch("ROLLBACK");
+        return Err(e);
+      }
+    }
+
+    match self.database.execute_batch("COMMIT") {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("failed to finish saving blocks. {}", e)),
+    }
+  }
+
+  ///
+  /// Load a block's bytes, if it was ever saved.
+  ///
+  pub fn load_block(&self, block_position: IVec3) -> Result<Option<Vec<u8>>, String> {
+    let statement = Query::select()
+      .column(Blocks::Data)
+      .from(Blocks::Table)
+      .and_where(Expr::col(Blocks::Pos).eq(block_position_to_key(block_position)))
+      .build(SqliteQueryBuilder);
+
+    let mut rows = query(&self.database, statement, |row| row.get::<_, Vec<u8>>(0))?;
+    Ok(rows.pop())
+  }
+
+  ///
+  /// Get the position of every saved block.
+  ///
+  pub fn get_block_positions(&self) -> Result<Vec<IVec3>, String> {
+    let statement = Query::select()
+      .column(Blocks::Pos)
+      .from(Blocks::Table)
+      .build(SqliteQueryBuilder);
+
+    let keys = query(&self.database, statement, |row| row.get::<_, i64>(0))?;
+    Ok(keys.into_iter().map(key_to_block_position).collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use super::{block_position_to_key, key_to_block_position, MapDatabase};
+
+  #[test]
+  fn test_block_keys() {
+    for block_position in [
+      IVec3::ZERO,
+      IVec3::new(1, 2, 3),
+      IVec3::new(-1, -2, -3),
+      IVec3::new(2047, -2048, 100),
+      IVec3::new(-2048, 2047, -1),
+    ] {
+      assert_eq!(
+        key_to_block_position(block_position_to_key(block_position)),
+        block_position
+      );
+    }
+
+    // Same numbers as C++ minetest.
+    assert_eq!(block_position_to_key(IVec3::new(1, 1, 1)), 0x1001001);
+    assert_eq!(block_position_to_key(IVec3::new(-1, 0, 0)), -1);
+  }
+
+  #[test]
+  fn test_map_database() {
+    let database = MapDatabase::new_in_memory();
+    let position = IVec3::new(4, -2, 9);
+
+    match database.load_block(position) {
+      Ok(data) => assert_eq!(data, None),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    let saved = database
+      .save_block(position, vec![1, 2, 3])
+      .and_then(|_| database.save_blocks(vec![(position, vec![4, 5]), (IVec3::ZERO, vec![6])]));
+    if let Err(e) = saved {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    match database.load_block(position) {
+      Ok(data) => assert_eq!(data, Some(vec![4, 5])),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    let mut positions = match database.get_block_positions() {
+      Ok(positions) => positions,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    positions.sort_by_key(|position| position.to_array());
+    assert_eq!(positions, vec![IVec3::ZERO, position]);
+  }
+}
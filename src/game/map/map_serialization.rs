@@ -0,0 +1,294 @@
+///
+/// Turns MapBlocks into bytes for the map database, and back.
+///
+/// Content ids change whenever the game's nodes change, so
+/// every block carries the names of the nodes it uses:
+/// * version - u8
+/// * zlib compressed:
+///   * name count - u16
+///   * names - u16 length then the bytes, for every name
+///   * content - a u16 index into the names for every node
+///   * param1 - a u8 for every node
+///   * param2 - a u8 for every node
+///   * timestamp - u32, version 2 and up
+///   * node timer count - u16, version 2 and up
+///   * node timers - the node index as a u16, then the timeout
+///     and elapsed time as i32 milliseconds, for every timer
+///
+/// Numbers are big endian and nodes are in z, y, x order, same as C++ minetest.
+///
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use super::{
+  map_block::{MapBlock, NodeTimer, MAP_BLOCK_VOLUME},
+  node::{ContentId, Node, CONTENT_UNKNOWN},
+  node_def_manager::NodeDefManager,
+};
+
+///
+/// Bump this whenever the layout changes.
+///
+pub const MAP_BLOCK_SERIALIZATION_VERSION: u8 = 2;
+
+///
+/// The oldest layout which can still be loaded.
+///
+pub const MAP_BLOCK_MIN_SERIALIZATION_VERSION: u8 = 1;
+
+///
+/// Turn a MapBlock into bytes.
+///
+pub fn serialize_block(
+  block: &MapBlock,
+  node_def_manager: &NodeDefManager,
+) -> Result<Vec<u8>, String> {
+  let palette = block.get_content_palette();
+
+  let mut bulk = vec![];
+  bulk.extend_from_slice(&(palette.len() as u16).to_be_bytes());
+  for content in palette {
+    let name = &node_def_manager.get_or_unknown(*content).name;
+    bulk.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    bulk.extend_from_slice(name.as_bytes());
+  }
+
+  let nodes: Vec<Node> = (0..MAP_BLOCK_VOLUME)
+    .map(|index| block.get_node_at_index(index))
+    .collect();
+
+  for node in &nodes {
+    let index = palette
+      .iter()
+      .position(|content| *content == node.content)
+      .unwrap_or_default();
+    bulk.extend_from_slice(&(index as u16).to_be_bytes());
+  }
+  bulk.extend(nodes.iter().map(|node| node.param1));
+  bulk.extend(nodes.iter().map(|node| node.param2));
+
+  bulk.extend_from_slice(&block.get_timestamp().to_be_bytes());
+
+  let node_timers = block.get_node_timers();
+  bulk.extend_from_slice(&(node_timers.len() as u16).to_be_bytes());
+  for (index, timer) in node_timers {
+    bulk.extend_from_slice(&(*index as u16).to_be_bytes());
+    bulk.extend_from_slice(&((timer.timeout * 1000.0) as i32).to_be_bytes());
+    bulk.extend_from_slice(&((timer.elapsed * 1000.0) as i32).to_be_bytes());
+  }
+
+  let mut encoder = ZlibEncoder::new(vec![MAP_BLOCK_SERIALIZATION_VERSION], Compression::fast());
+  if let Err(e) = encoder.write_all(&bulk) {
+    return Err(format!("MapBlock won't compress. {}", e));
+  }
+  match encoder.finish() {
+    Ok(data) => Ok(data),
+    Err(e) => Err(format!("MapBlock won't compress. {}", e)),
+  }
+}
+
+///
+/// Turn bytes back into a MapBlock.
+///
+/// Nodes the game doesn't have anymore turn into unknown nodes.
+///
+pub fn deserialize_block(
+  data: &[u8],
+  node_def_manager: &NodeDefManager,
+) -> Result<MapBlock, String> {
+  let version = match data.first() {
+    Some(version) => *version,
+    None => return Err("MapBlock is empty.".to_string()),
+  };
+  if !(MAP_BLOCK_MIN_SERIALIZATION_VERSION..=MAP_BLOCK_SERIALIZATION_VERSION).contains(&version) {
+    return Err(format!("unsupported MapBlock version {}.", version));
+  }
+
+  let mut bulk = vec![];
+  if let Err(e) = ZlibDecoder::new(&data[1..]).read_to_end(&mut bulk) {
+    return Err(format!("MapBlock won't decompress. {}", e));
+  }
+
+  let too_short = || "MapBlock ends too early.".to_string();
+  let read_u16 = |position: usize| -> Result<u16, String> {
+    match bulk.get(position..position + 2) {
+      Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+      None => Err(too_short()),
+    }
+  };
+  let read_i32 = |position: usize| -> Result<i32, String> {
+    match bulk.get(position..position + 4) {
+      Some(bytes) => Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+      None => Err(too_short()),
+    }
+  };
+
+  let mut position = 0;
+  let name_count = read_u16(position)? as usize;
+  position += 2;
+
+  let mut contents: Vec<ContentId> = vec![];
+  for _ in 0..name_count {
+    let length = read_u16(position)? as usize;
+    position += 2;
+
+    let name = match bulk.get(position..position + length) {
+      Some(bytes) => String::from_utf8_lossy(bytes),
+      None => return Err(too_short()),
+    };
+    position += length;
+
+    contents.push(node_def_manager.get_id(&name).unwrap_or(CONTENT_UNKNOWN));
+  }
+
+  if bulk.len() < position + MAP_BLOCK_VOLUME * 4 {
+    return Err(too_short());
+  }
+  let param1_start = position + MAP_BLOCK_VOLUME * 2;
+  let param2_start = param1_start + MAP_BLOCK_VOLUME;
+
+  let mut block = MapBlock::new(Node::air());
+  for index in 0..MAP_BLOCK_VOLUME {
+    let name_index = read_u16(position + index * 2)? as usize;
+    let content = match contents.get(name_index) {
+      Some(content) => *content,
+      None => {
+        return Err(format!(
+          "MapBlock uses name {} which doesn't exist.",
+          name_index
+        ))
+      }
+    };
+
+    block.set_node_at_index(
+      index,
+      Node::new_with_params(
+        content,
+        bulk[param1_start + index],
+        bulk[param2_start + index],
+      ),
+    );
+  }
+
+  // Version 1 blocks have no timestamp or node timers, they stay undefined and empty.
+  if version >= 2 {
+    position = param2_start + MAP_BLOCK_VOLUME;
+    block.set_timestamp(read_i32(position)? as u32);
+    position += 4;
+
+    let timer_count = read_u16(position)?;
+    position += 2;
+    for _ in 0..timer_count {
+      let index = read_u16(position)? as usize;
+      if index >= MAP_BLOCK_VOLUME {
+        return Err(format!("MapBlock has a node timer at index {}.", index));
+      }
+
+      let timeout = read_i32(position + 2)? as f32 / 1000.0;
+      let elapsed = read_i32(position + 6)? as f32 / 1000.0;
+      block.set_node_timer(index, NodeTimer::new(timeout, elapsed));
+      position += 10;
+    }
+  }
+
+  block.compact();
+  Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::ZlibEncoder, Compression};
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::{MapBlock, NodeTimer, BLOCK_TIMESTAMP_UNDEFINED, MAP_BLOCK_VOLUME},
+    node::{Node, CONTENT_UNKNOWN},
+    node_def_manager::{new_test_node_def_manager, NodeDefinition},
+  };
+
+  use super::{deserialize_block, serialize_block};
+
+  #[test]
+  fn test_block_serialization() {
+    let node_def_manager =
+      new_test_node_def_manager(["test:stone", "test:dirt"].map(NodeDefinition::new));
+    let get_id = |name: &str| node_def_manager.get_id(name).unwrap_or(CONTENT_UNKNOWN);
+
+    let mut block = MapBlock::new(Node::new(get_id("test:stone")));
+    block.set_node(
+      IVec3::new(1, 2, 3),
+      Node::new_with_params(get_id("test:dirt"), 15, 4),
+    );
+    block.set_node(IVec3::new(15, 15, 15), Node::air());
+    block.set_timestamp(1234);
+    block.set_node_timer(5, NodeTimer::new(2.5, 0.25));
+    block.set_node_timer(4095, NodeTimer::new(10.0, 0.0));
+
+    let data = match serialize_block(&block, &node_def_manager) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    match deserialize_block(&data, &node_def_manager) {
+      Ok(loaded) => assert_eq!(loaded, block),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    // Ids get matched back up by name, even if the game changed.
+    let changed = new_test_node_def_manager(["test:dirt", "test:sand"].map(NodeDefinition::new));
+    let loaded = match deserialize_block(&data, &changed) {
+      Ok(loaded) => loaded,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(
+      loaded.get_node(IVec3::new(1, 2, 3)),
+      Node::new_with_params(
+        changed.get_id("test:dirt").unwrap_or(CONTENT_UNKNOWN),
+        15,
+        4
+      )
+    );
+    assert_eq!(loaded.get_node(IVec3::ZERO).content, CONTENT_UNKNOWN);
+
+    assert!(deserialize_block(&[], &node_def_manager).is_err());
+    assert!(deserialize_block(&[3], &node_def_manager).is_err());
+    assert!(deserialize_block(&data[0..10], &node_def_manager).is_err());
+  }
+
+  #[test]
+  fn test_block_serialization_version_1() {
+    let node_def_manager = new_test_node_def_manager(["test:stone"].map(NodeDefinition::new));
+
+    // One name, every node is it.
+    let mut bulk = vec![0, 1, 0, 10];
+    bulk.extend_from_slice(b"test:stone");
+    bulk.extend(vec![0; MAP_BLOCK_VOLUME * 2]);
+    bulk.extend(vec![7; MAP_BLOCK_VOLUME]);
+    bulk.extend(vec![0; MAP_BLOCK_VOLUME]);
+
+    let mut encoder = ZlibEncoder::new(vec![1], Compression::fast());
+    let data = match encoder.write_all(&bulk).and_then(|_| encoder.finish()) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    let block = match deserialize_block(&data, &node_def_manager) {
+      Ok(block) => block,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(
+      block.get_node(IVec3::new(4, 5, 6)),
+      Node::new_with_params(
+        node_def_manager
+          .get_id("test:stone")
+          .unwrap_or(CONTENT_UNKNOWN),
+        7,
+        0
+      )
+    );
+    assert_eq!(block.get_timestamp(), BLOCK_TIMESTAMP_UNDEFINED);
+    assert!(block.get_node_timers().is_empty());
+  }
+}
@@ -0,0 +1,753 @@
+pub mod biome;
+pub mod caves;
+pub mod decoration;
+pub mod dungeons;
+pub mod mapgen_threads;
+pub mod noise;
+pub mod ore;
+pub mod pseudo_random;
+pub mod schematic;
+
+use glam::{IVec3, Vec3};
+
+use self::{
+  biome::{Biome, BiomeDefinition, BiomeManager},
+  caves::{column_index, Caverns, Tunnels},
+  decoration::{Decoration, DecorationDefinition},
+  dungeons::Dungeons,
+  noise::{Noise, NoiseParams, NoiseType},
+  ore::{Ore, OreDefinition},
+  pseudo_random::hash_position,
+};
+
+use super::{
+  block_to_node_position,
+  map_block::{MapBlock, MAP_BLOCK_SIZE},
+  node::{ContentId, Node},
+  node_def_manager::NodeDefManager,
+};
+
+///
+/// The nodes the map generator builds terrain out of.
+///
+/// Games pick these with minetest.register_alias(), same as C++ minetest:
+/// * mapgen_stone
+/// * mapgen_dirt
+/// * mapgen_dirt_with_grass
+/// * mapgen_cobble, for dungeon walls, stone if it's missing
+/// * mapgen_mossycobble, for some of the dungeon walls, cobble if it's missing
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapgenNodes {
+  pub stone: ContentId,
+  pub dirt: ContentId,
+  pub grass: ContentId,
+  pub cobble: ContentId,
+  pub mossy_cobble: ContentId,
+}
+
+impl MapgenNodes {
+  pub fn from_node_def_manager(node_def_manager: &NodeDefManager) -> Result<Self, String> {
+    let get = |alias: &str| match node_def_manager.get_id(alias) {
+      Some(id) => Ok(id),
+      None => Err(format!(
+        "the game is missing the [{}] alias, add it with minetest.register_alias().",
+        alias
+      )),
+    };
+
+    let stone = get("mapgen_stone")?;
+    let cobble = node_def_manager.get_id("mapgen_cobble").unwrap_or(stone);
+    let mossy_cobble = node_def_manager
+      .get_id("mapgen_mossycobble")
+      .unwrap_or(cobble);
+
+    Ok(MapgenNodes {
+      stone,
+      dirt: get("mapgen_dirt")?,
+      grass: get("mapgen_dirt_with_grass")?,
+      cobble,
+      mossy_cobble,
+    })
+  }
+}
+
+///
+/// Which passes the map generator runs, from the mg_flags setting.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapgenFlags {
+  pub caves: bool,
+  pub caverns: bool,
+  pub dungeons: bool,
+  pub ores: bool,
+  pub decorations: bool,
+}
+
+impl Default for MapgenFlags {
+  fn default() -> Self {
+    MapgenFlags {
+      caves: true,
+      caverns: true,
+      dungeons: true,
+      ores: true,
+      decorations: true,
+    }
+  }
+}
+
+impl MapgenFlags {
+  ///
+  /// Parse a list like "caves, nodungeons, decorations".
+  ///
+  /// Same as C++ minetest, a flag with "no" in front turns it off
+  /// and flags that aren't listed keep their default.
+  ///
+  /// Gives back the flags it didn't know, so they can be reported.
+  ///
+  pub fn parse(text: &str) -> (Self, Vec<String>) {
+    let mut flags = MapgenFlags::default();
+    let mut unknown = vec![];
+
+    for flag in text.split(',').map(|flag| flag.trim()) {
+      if flag.is_empty() {
+        continue;
+      }
+
+      let (name, value) = match flag.strip_prefix("no") {
+        Some(name) => (name, false),
+        None => (flag, true),
+      };
+
+      match name {
+        "caves" => flags.caves = value,
+        "caverns" => flags.caverns = value,
+        "dungeons" => flags.dungeons = value,
+        "ores" => flags.ores = value,
+        "decorations" => flags.decorations = value,
+        _ => unknown.push(flag.to_string()),
+      }
+    }
+
+    (flags, unknown)
+  }
+}
+
+///
+/// Everything that shapes the terrain.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapgenParams {
+  pub seed: u64,
+  // The height of the surface.
+  pub terrain: NoiseParams,
+  // Where the biomes go, same defaults as C++ minetest.
+  pub heat: NoiseParams,
+  pub humidity: NoiseParams,
+  // How many nodes of dirt go under the grass, when no biomes are registered.
+  pub dirt_depth: i32,
+  pub flags: MapgenFlags,
+  // Caverns are carved where this is above cavern_threshold.
+  pub cavern: NoiseParams,
+  // Caverns only go below cavern_limit, shrinking away over cavern_taper nodes.
+  pub cavern_limit: i32,
+  pub cavern_taper: i32,
+  pub cavern_threshold: f32,
+  // Chance of a tunnel starting in each MapBlock.
+  pub tunnel_chance: f32,
+  // Chance of a dungeon in each MapBlock below dungeon_y_max.
+  pub dungeon_chance: f32,
+  pub dungeon_y_max: i32,
+}
+
+impl MapgenParams {
+  pub fn new(seed: u64) -> Self {
+    MapgenParams {
+      seed,
+      terrain: NoiseParams {
+        noise_type: NoiseType::Perlin,
+        offset: 4.0,
+        scale: 24.0,
+        spread: Vec3::splat(300.0),
+        seed: 82_341,
+        octaves: 5,
+        persistence: 0.5,
+        lacunarity: 2.0,
+      },
+      heat: NoiseParams {
+        noise_type: NoiseType::Perlin,
+        offset: 50.0,
+        scale: 50.0,
+        spread: Vec3::splat(1000.0),
+        seed: 5349,
+        octaves: 3,
+        persistence: 0.5,
+        lacunarity: 2.0,
+      },
+      humidity: NoiseParams {
+        noise_type: NoiseType::Perlin,
+        offset: 50.0,
+        scale: 50.0,
+        spread: Vec3::splat(1000.0),
+        seed: 842,
+        octaves: 3,
+        persistence: 0.5,
+        lacunarity: 2.0,
+      },
+      dirt_depth: 3,
+      flags: MapgenFlags::default(),
+      cavern: NoiseParams {
+        noise_type: NoiseType::Perlin,
+        offset: 0.0,
+        scale: 1.0,
+        spread: Vec3::new(384.0, 128.0, 384.0),
+        seed: 723,
+        octaves: 5,
+        persistence: 0.63,
+        lacunarity: 2.0,
+      },
+      cavern_limit: -256,
+      cavern_taper: 64,
+      cavern_threshold: 0.7,
+      tunnel_chance: 0.25,
+      dungeon_chance: 0.1,
+      dungeon_y_max: 0,
+    }
+  }
+}
+
+///
+/// Turns a world seed into MapBlocks.
+///
+/// Every block only depends on the params and it's own position, so
+/// blocks can be generated in any order, on any thread, and always
+/// come out the same.
+///
+/// A block is made in five passes:
+/// 1.) Terrain, layered by the biome of each column.
+/// 2.) Caverns and tunnels, which never break through the surface.
+/// 3.) Dungeons.
+/// 4.) Ores, in the order they were registered.
+/// 5.) Decorations, in the order they were registered.
+///
+/// Every pass but the terrain can be turned off with MapgenFlags.
+///
+pub struct Mapgen {
+  params: MapgenParams,
+  terrain_noise: Noise,
+  biome_manager: BiomeManager,
+  caverns: Caverns,
+  tunnels: Tunnels,
+  dungeons: Dungeons,
+  ores: Vec<Ore>,
+  decorations: Vec<Decoration>,
+}
+
+impl Mapgen {
+  pub fn new(params: MapgenParams, nodes: MapgenNodes) -> Result<Self, String> {
+    let terrain_noise = match Noise::new(params.terrain, params.seed) {
+      Ok(noise) => noise,
+      Err(e) => return Err(format!("Mapgen: bad terrain noise. {}", e)),
+    };
+
+    let biome_manager = BiomeManager::new(
+      Biome::from_mapgen_nodes(&nodes, params.dirt_depth),
+      params.heat,
+      params.humidity,
+      params.seed,
+    )?;
+
+    Ok(Mapgen {
+      params,
+      terrain_noise,
+      biome_manager,
+      caverns: Caverns::new(&params)?,
+      tunnels: Tunnels::new(&params),
+      dungeons: Dungeons::new(&params, nodes.cobble, nodes.mossy_cobble),
+      ores: vec![],
+      decorations: vec![],
+    })
+  }
+
+  pub fn get_params(&self) -> &MapgenParams {
+    &self.params
+  }
+
+  pub fn get_biome_manager(&self) -> &BiomeManager {
+    &self.biome_manager
+  }
+
+  ///
+  /// Every ore and decoration gets it's own seed, so two of
+  /// them with the same settings don't land in the same spots.
+  ///
+  fn get_content_seed(&self, kind: i32, index: usize) -> u64 {
+    hash_position(self.params.seed, IVec3::new(kind, index as i32, 0))
+  }
+
+  pub fn register_biome(
+    &mut self,
+    definition: &BiomeDefinition,
+    node_def_manager: &NodeDefManager,
+  ) -> Result<(), String> {
+    self
+      .biome_manager
+      .register(definition.resolve(node_def_manager)?)
+  }
+
+  pub fn register_ore(
+    &mut self,
+    definition: OreDefinition,
+    node_def_manager: &NodeDefManager,
+  ) -> Result<(), String> {
+    let seed = self.get_content_seed(1, self.ores.len());
+    self
+      .ores
+      .push(Ore::new(definition, node_def_manager, seed)?);
+    Ok(())
+  }
+
+  ///
+  /// Biomes have to be registered first, decorations check the names they use.
+  ///
+  pub fn register_decoration(
+    &mut self,
+    definition: DecorationDefinition,
+    node_def_manager: &NodeDefManager,
+  ) -> Result<(), String> {
+    let seed = self.get_content_seed(2, self.decorations.len());
+    self.decorations.push(Decoration::new(
+      definition,
+      node_def_manager,
+      &self.biome_manager,
+      seed,
+    )?);
+    Ok(())
+  }
+
+  pub fn get_ore_count(&self) -> usize {
+    self.ores.len()
+  }
+
+  pub fn get_decoration_count(&self) -> usize {
+    self.decorations.len()
+  }
+
+  ///
+  /// Get the y position of the grass at a column of the world.
+  ///
+  pub fn get_surface_height(&self, x: i32, z: i32) -> i32 {
+    self.terrain_noise.get_2d(x as f32, z as f32).floor() as i32
+  }
+
+  ///
+  /// Generate the MapBlock at a block position.
+  ///
+  pub fn generate_block(&self, block_position: IVec3) -> MapBlock {
+    let origin = block_to_node_position(block_position);
+    let mut block = MapBlock::new(Node::air());
+
+    let mut surfaces = vec![0; (MAP_BLOCK_SIZE * MAP_BLOCK_SIZE) as usize];
+    for z in 0..MAP_BLOCK_SIZE {
+      for x in 0..MAP_BLOCK_SIZE {
+        surfaces[column_index(x, z)] = self.get_surface_height(origin.x + x, origin.z + z);
+      }
+    }
+
+    for z in 0..MAP_BLOCK_SIZE {
+      for x in 0..MAP_BLOCK_SIZE {
+        let surface = surfaces[column_index(x, z)];
+
+        // Nothing but air in this column.
+        if surface < origin.y {
+          continue;
+        }
+
+        let biome = self
+          .biome_manager
+          .get_biome(origin.x + x, surface, origin.z + z);
+
+        // Everything above the surface stays air.
+        let top = (surface - origin.y).min(MAP_BLOCK_SIZE - 1);
+
+        for y in 0..=top {
+          let content = biome.get_node_at_depth(surface - (origin.y + y));
+          block.set_node(IVec3::new(x, y, z), Node::new(content));
+        }
+      }
+    }
+
+    let flags = self.params.flags;
+
+    if flags.caverns {
+      self.caverns.generate(&mut block, block_position, &surfaces);
+    }
+
+    if flags.caves {
+      self.tunnels.generate(&mut block, block_position, &surfaces);
+    }
+
+    if flags.dungeons {
+      self.dungeons.generate(&mut block, block_position, self);
+    }
+
+    if flags.ores {
+      for ore in &self.ores {
+        ore.generate(&mut block, block_position);
+      }
+    }
+
+    if flags.decorations {
+      for decoration in &self.decorations {
+        decoration.generate(&mut block, block_position, self);
+      }
+    }
+
+    block.compact();
+    block
+  }
+}
+
+///
+/// Turn the fixed_map_seed setting into a world seed.
+///
+/// Numbers are used as they are, anything else gets hashed with
+/// FNV-1a so a word like "potato" always makes the same world.
+///
+pub fn seed_from_string(seed: &str) -> u64 {
+  if let Ok(number) = seed.trim().parse::<u64>() {
+    return number;
+  }
+
+  seed.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+    (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::MAP_BLOCK_SIZE,
+    node::{Node, CONTENT_AIR},
+    node_def_manager::{NodeDefManager, NodeDefinition},
+    node_to_block_position,
+  };
+
+  use super::{
+    biome::BiomeDefinition,
+    decoration::{DecorationDefinition, DecorationType},
+    ore::{OreDefinition, OreType},
+    seed_from_string, Mapgen, MapgenFlags, MapgenNodes, MapgenParams,
+  };
+
+  const NODES: MapgenNodes = MapgenNodes {
+    stone: 1,
+    dirt: 2,
+    grass: 3,
+    cobble: 4,
+    mossy_cobble: 5,
+  };
+
+  ///
+  /// Params without caves or dungeons, so the layers are easy to check.
+  ///
+  fn solid_params(seed: u64) -> MapgenParams {
+    MapgenParams {
+      flags: MapgenFlags {
+        caves: false,
+        caverns: false,
+        dungeons: false,
+        ..MapgenFlags::default()
+      },
+      ..MapgenParams::new(seed)
+    }
+  }
+
+  fn new_mapgen(seed: u64) -> Mapgen {
+    match Mapgen::new(solid_params(seed), NODES) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+
+  #[test]
+  fn test_mapgen_layers() {
+    let mapgen = new_mapgen(1234);
+
+    for (x, z) in [(0, 0), (37, -120), (-500, 900)] {
+      let surface = mapgen.get_surface_height(x, z);
+      let position = IVec3::new(x, surface, z);
+      let block_position = node_to_block_position(position);
+      let origin = block_position * MAP_BLOCK_SIZE;
+      let block = mapgen.generate_block(block_position);
+
+      let node_at = |y: i32| {
+        let local = IVec3::new(x, y, z) - origin;
+        if (0..MAP_BLOCK_SIZE).contains(&local.y) {
+          Some(block.get_node(local))
+        } else {
+          None
+        }
+      };
+
+      assert_eq!(node_at(surface), Some(Node::new(NODES.grass)));
+      for (y, content) in [
+        (surface + 1, CONTENT_AIR),
+        (surface - 1, NODES.dirt),
+        (surface - 3, NODES.dirt),
+        (surface - 4, NODES.stone),
+      ] {
+        if let Some(node) = node_at(y) {
+          assert_eq!(node, Node::new(content));
+        }
+      }
+    }
+
+    // Far up is all air, far down is all stone.
+    assert_eq!(
+      mapgen
+        .generate_block(IVec3::new(0, 100, 0))
+        .get_content_palette(),
+      &[CONTENT_AIR]
+    );
+    assert_eq!(
+      mapgen
+        .generate_block(IVec3::new(0, -100, 0))
+        .get_content_palette(),
+      &[NODES.stone]
+    );
+  }
+
+  #[test]
+  fn test_mapgen_is_deterministic() {
+    let mapgen = new_mapgen(seed_from_string("potato"));
+    let same = new_mapgen(seed_from_string("potato"));
+    let other = new_mapgen(seed_from_string("carrot"));
+
+    let block_position = IVec3::new(3, 0, -2);
+    assert_eq!(
+      mapgen.generate_block(block_position),
+      same.generate_block(block_position)
+    );
+
+    let heights = |mapgen: &Mapgen| -> Vec<i32> {
+      (0..64)
+        .map(|i| mapgen.get_surface_height(i * 13, i * -7))
+        .collect()
+    };
+    assert_eq!(heights(&mapgen), heights(&same));
+    assert_ne!(heights(&mapgen), heights(&other));
+
+    assert_eq!(seed_from_string("42"), 42);
+  }
+
+  fn new_content_mapgen(seed: u64) -> (Mapgen, NodeDefManager) {
+    let definitions = [
+      "test:stone",
+      "test:dirt",
+      "test:grass",
+      "test:sand",
+      "test:coal",
+      "test:flower",
+    ]
+    .iter()
+    .map(|name| NodeDefinition {
+      textures: vec!["test.png".to_string()],
+      ..NodeDefinition::new(name)
+    })
+    .collect();
+
+    let mut node_def_manager = match NodeDefManager::from_definitions(definitions) {
+      Ok(node_def_manager) => node_def_manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    for (alias, original) in [
+      ("mapgen_stone", "test:stone"),
+      ("mapgen_dirt", "test:dirt"),
+      ("mapgen_dirt_with_grass", "test:grass"),
+    ] {
+      if let Err(e) = node_def_manager.register_alias(alias, original) {
+        panic!("Unit test is broken. {}", e);
+      }
+    }
+
+    let nodes = match MapgenNodes::from_node_def_manager(&node_def_manager) {
+      Ok(nodes) => nodes,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    let mut mapgen = match Mapgen::new(solid_params(seed), nodes) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    // A beach everywhere, covered in flowers, with coal under it.
+    let beach = BiomeDefinition {
+      node_top: "test:sand".to_string(),
+      depth_top: 2,
+      ..BiomeDefinition::new("beach")
+    };
+    let coal = OreDefinition::new(OreType::Scatter, "test:coal", "mapgen_stone");
+    let flowers = DecorationDefinition {
+      fill_ratio: 1.0,
+      biomes: vec!["beach".to_string()],
+      decoration: vec!["test:flower".to_string()],
+      ..DecorationDefinition::new(DecorationType::Simple, "test:sand")
+    };
+
+    let registered = mapgen
+      .register_biome(&beach, &node_def_manager)
+      .and_then(|_| mapgen.register_ore(coal, &node_def_manager))
+      .and_then(|_| mapgen.register_decoration(flowers, &node_def_manager));
+    if let Err(e) = registered {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    (mapgen, node_def_manager)
+  }
+
+  #[test]
+  fn test_mapgen_biomes_ores_decorations() {
+    let (mapgen, node_def_manager) = new_content_mapgen(99);
+    let get_id = |name: &str| node_def_manager.get_id(name).unwrap_or(CONTENT_AIR);
+
+    let surface = mapgen.get_surface_height(0, 0);
+    let block_position = node_to_block_position(IVec3::new(0, surface, 0));
+    let origin = block_position * MAP_BLOCK_SIZE;
+    let block = mapgen.generate_block(block_position);
+
+    let local = IVec3::new(0, surface, 0) - origin;
+    assert_eq!(block.get_node(local).content, get_id("test:sand"));
+    if local.y < MAP_BLOCK_SIZE - 1 {
+      assert_eq!(
+        block.get_node(local + IVec3::Y).content,
+        get_id("test:flower")
+      );
+    }
+
+    // Ores only replace stone, so a deep block is just stone and coal.
+    let deep = mapgen.generate_block(IVec3::new(0, -50, 0));
+    let mut palette = deep.get_content_palette().to_vec();
+    palette.sort();
+    let mut expected = vec![get_id("test:stone"), get_id("test:coal")];
+    expected.sort();
+    assert_eq!(palette, expected);
+
+    // Same seed and content, same blocks.
+    let (same, _) = new_content_mapgen(99);
+    assert_eq!(same.generate_block(block_position), block);
+    assert_eq!(same.generate_block(IVec3::new(0, -50, 0)), deep);
+
+    // Biomes decorations use have to exist.
+    let mut mapgen = mapgen;
+    let lost = DecorationDefinition {
+      biomes: vec!["jungle".to_string()],
+      decoration: vec!["test:flower".to_string()],
+      ..DecorationDefinition::new(DecorationType::Simple, "test:sand")
+    };
+    assert!(mapgen.register_decoration(lost, &node_def_manager).is_err());
+  }
+
+  #[test]
+  fn test_mapgen_flags() {
+    let (flags, unknown) = MapgenFlags::parse("caves, nodungeons,noores, potatoes,");
+    assert_eq!(
+      flags,
+      MapgenFlags {
+        dungeons: false,
+        ores: false,
+        ..MapgenFlags::default()
+      }
+    );
+    assert_eq!(unknown, vec!["potatoes".to_string()]);
+
+    assert_eq!(MapgenFlags::parse("").0, MapgenFlags::default());
+  }
+
+  #[test]
+  fn test_mapgen_caves_cross_blocks() {
+    let params = MapgenParams {
+      flags: MapgenFlags {
+        dungeons: false,
+        ..MapgenFlags::default()
+      },
+      tunnel_chance: 1.0,
+      ..MapgenParams::new(4321)
+    };
+    let new_caves_mapgen = || match Mapgen::new(params, NODES) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    let mapgen = new_caves_mapgen();
+    let same = new_caves_mapgen();
+
+    // Deep enough to be all stone, if it wasn't for the tunnels.
+    let positions: Vec<IVec3> = (0..3)
+      .flat_map(|x| (0..2).map(move |z| IVec3::new(x, -8, z)))
+      .collect();
+
+    let blocks: Vec<_> = positions
+      .iter()
+      .map(|position| mapgen.generate_block(*position))
+      .collect();
+    let backwards: Vec<_> = positions
+      .iter()
+      .rev()
+      .map(|position| same.generate_block(*position))
+      .collect();
+
+    for (block, other) in blocks.iter().zip(backwards.iter().rev()) {
+      assert_eq!(block, other);
+    }
+
+    // Tunnels run over the borders, air on both sides of one somewhere.
+    let is_air = |block: usize, local: IVec3| blocks[block].get_node(local).content == CONTENT_AIR;
+    let crossings = (0..MAP_BLOCK_SIZE)
+      .flat_map(|y| (0..MAP_BLOCK_SIZE).map(move |z| IVec3::new(0, y, z)))
+      .filter(|local| is_air(2, *local) && is_air(0, *local + IVec3::X * (MAP_BLOCK_SIZE - 1)))
+      .count();
+    assert!(crossings > 0);
+  }
+
+  #[test]
+  fn test_mapgen_dungeons() {
+    let params = MapgenParams {
+      flags: MapgenFlags {
+        caves: false,
+        caverns: false,
+        ..MapgenFlags::default()
+      },
+      dungeon_chance: 1.0,
+      ..MapgenParams::new(55)
+    };
+    let mapgen = match Mapgen::new(params, NODES) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    // Every block rolls a dungeon, so a deep one has rooms and the wall nodes.
+    let block = mapgen.generate_block(IVec3::new(0, -10, 0));
+    let palette = block.get_content_palette();
+    assert!(palette.contains(&CONTENT_AIR));
+    assert!(palette.contains(&NODES.cobble));
+    assert!(palette.contains(&NODES.mossy_cobble));
+    assert!(!palette.contains(&NODES.dirt));
+
+    // Never above dungeon_y_max.
+    let high = MapgenParams {
+      dungeon_y_max: -1000,
+      ..params
+    };
+    let mapgen = match Mapgen::new(high, NODES) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(
+      mapgen
+        .generate_block(IVec3::new(0, -10, 0))
+        .get_content_palette(),
+      &[NODES.stone]
+    );
+  }
+}
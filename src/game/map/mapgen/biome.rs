@@ -0,0 +1,331 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::map::{node::ContentId, node_def_manager::NodeDefManager};
+
+use super::{
+  noise::{Noise, NoiseParams},
+  MapgenNodes,
+};
+
+///
+/// A biome as a game registers it with minetest.register_biome().
+///
+/// Node names can be aliases, they default to the mapgen aliases.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BiomeDefinition {
+  pub name: String,
+  // The surface, like grass.
+  pub node_top: String,
+  pub depth_top: i32,
+  // Under the surface, like dirt.
+  pub node_filler: String,
+  pub depth_filler: i32,
+  // Everything under that.
+  pub node_stone: String,
+  pub y_min: i32,
+  pub y_max: i32,
+  // Where it sits on the heat and humidity maps, both roughly 0 to 100.
+  pub heat_point: f32,
+  pub humidity_point: f32,
+}
+
+impl BiomeDefinition {
+  ///
+  /// A definition with the defaults a mod would get.
+  ///
+  pub fn new(name: &str) -> Self {
+    BiomeDefinition {
+      name: name.to_string(),
+      node_top: "mapgen_dirt_with_grass".to_string(),
+      depth_top: 1,
+      node_filler: "mapgen_dirt".to_string(),
+      depth_filler: 3,
+      node_stone: "mapgen_stone".to_string(),
+      y_min: -31_000,
+      y_max: 31_000,
+      heat_point: 50.0,
+      humidity_point: 50.0,
+    }
+  }
+
+  ///
+  /// Turn the node names into content ids.
+  ///
+  pub fn resolve(&self, node_def_manager: &NodeDefManager) -> Result<Biome, String> {
+    if self.name.is_empty() {
+      return Err("biome has no name.".to_string());
+    }
+
+    if self.y_min > self.y_max {
+      return Err(format!(
+        "biome [{}] has y_min {} above y_max {}.",
+        self.name, self.y_min, self.y_max
+      ));
+    }
+
+    if self.depth_top < 0 || self.depth_filler < 0 {
+      return Err(format!("biome [{}] has a negative depth.", self.name));
+    }
+
+    let get = |name: &str| match node_def_manager.get_id(name) {
+      Some(id) => Ok(id),
+      None => Err(format!(
+        "biome [{}] uses [{}], which isn't a registered node.",
+        self.name, name
+      )),
+    };
+
+    Ok(Biome {
+      name: self.name.clone(),
+      node_top: get(&self.node_top)?,
+      depth_top: self.depth_top,
+      node_filler: get(&self.node_filler)?,
+      depth_filler: self.depth_filler,
+      node_stone: get(&self.node_stone)?,
+      y_min: self.y_min,
+      y_max: self.y_max,
+      heat_point: self.heat_point,
+      humidity_point: self.humidity_point,
+    })
+  }
+}
+
+///
+/// A biome with content ids, ready for the mapgen.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Biome {
+  pub name: String,
+  pub node_top: ContentId,
+  pub depth_top: i32,
+  pub node_filler: ContentId,
+  pub depth_filler: i32,
+  pub node_stone: ContentId,
+  pub y_min: i32,
+  pub y_max: i32,
+  pub heat_point: f32,
+  pub humidity_point: f32,
+}
+
+impl Biome {
+  ///
+  /// The biome used when a game doesn't register any,
+  /// made out of the mapgen aliases.
+  ///
+  pub fn from_mapgen_nodes(nodes: &MapgenNodes, dirt_depth: i32) -> Self {
+    Biome {
+      name: "default".to_string(),
+      node_top: nodes.grass,
+      depth_top: 1,
+      node_filler: nodes.dirt,
+      depth_filler: dirt_depth,
+      node_stone: nodes.stone,
+      y_min: i32::MIN,
+      y_max: i32::MAX,
+      heat_point: 50.0,
+      humidity_point: 50.0,
+    }
+  }
+
+  ///
+  /// Get what node goes at a depth under the surface, 0 being the surface.
+  ///
+  pub fn get_node_at_depth(&self, depth: i32) -> ContentId {
+    if depth < self.depth_top {
+      self.node_top
+    } else if depth < self.depth_top + self.depth_filler {
+      self.node_filler
+    } else {
+      self.node_stone
+    }
+  }
+}
+
+///
+/// Picks which biome goes where, the same way C++ minetest does.
+///
+/// Heat and humidity are 2D noises. Every column gets the biome
+/// whose heat and humidity point is closest, out of the biomes whose
+/// y range has the surface in it.
+///
+pub struct BiomeManager {
+  biomes: Vec<Biome>,
+  default_biome: Biome,
+  heat_noise: Noise,
+  humidity_noise: Noise,
+}
+
+impl BiomeManager {
+  pub fn new(
+    default_biome: Biome,
+    heat: NoiseParams,
+    humidity: NoiseParams,
+    seed: u64,
+  ) -> Result<Self, String> {
+    let heat_noise = match Noise::new(heat, seed) {
+      Ok(noise) => noise,
+      Err(e) => return Err(format!("BiomeManager: bad heat noise. {}", e)),
+    };
+
+    let humidity_noise = match Noise::new(humidity, seed) {
+      Ok(noise) => noise,
+      Err(e) => return Err(format!("BiomeManager: bad humidity noise. {}", e)),
+    };
+
+    Ok(BiomeManager {
+      biomes: vec![],
+      default_biome,
+      heat_noise,
+      humidity_noise,
+    })
+  }
+
+  ///
+  /// Add a biome, names have to be unique.
+  ///
+  pub fn register(&mut self, biome: Biome) -> Result<(), String> {
+    if self.get(&biome.name).is_some() {
+      return Err(format!("[{}] is already a registered biome.", biome.name));
+    }
+
+    self.biomes.push(biome);
+
+    Ok(())
+  }
+
+  ///
+  /// Get a registered biome by name.
+  ///
+  pub fn get(&self, name: &str) -> Option<&Biome> {
+    self.biomes.iter().find(|biome| biome.name == name)
+  }
+
+  ///
+  /// Get how many biomes are registered, the default one doesn't count.
+  ///
+  pub fn get_count(&self) -> usize {
+    self.biomes.len()
+  }
+
+  pub fn get_heat(&self, x: i32, z: i32) -> f32 {
+    self.heat_noise.get_2d(x as f32, z as f32)
+  }
+
+  pub fn get_humidity(&self, x: i32, z: i32) -> f32 {
+    self.humidity_noise.get_2d(x as f32, z as f32)
+  }
+
+  ///
+  /// Get the biome of a column, where y is the height of it's surface.
+  ///
+  pub fn get_biome(&self, x: i32, y: i32, z: i32) -> &Biome {
+    let heat = self.get_heat(x, z);
+    let humidity = self.get_humidity(x, z);
+
+    let mut closest: Option<(&Biome, f32)> = None;
+
+    for biome in &self.biomes {
+      if y < biome.y_min || y > biome.y_max {
+        continue;
+      }
+
+      let distance = (heat - biome.heat_point).powi(2) + (humidity - biome.humidity_point).powi(2);
+
+      // Ties go to whoever registered first.
+      match closest {
+        Some((_, closest_distance)) if closest_distance <= distance => (),
+        _ => closest = Some((biome, distance)),
+      }
+    }
+
+    match closest {
+      Some((biome, _)) => biome,
+      None => &self.default_biome,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::Vec3;
+
+  use crate::game::map::mapgen::{
+    noise::{NoiseParams, NoiseType},
+    MapgenNodes,
+  };
+
+  use super::{Biome, BiomeManager};
+
+  fn flat_noise(offset: f32) -> NoiseParams {
+    NoiseParams {
+      noise_type: NoiseType::Perlin,
+      offset,
+      scale: 0.0,
+      spread: Vec3::splat(100.0),
+      seed: 0,
+      octaves: 1,
+      persistence: 0.5,
+      lacunarity: 2.0,
+    }
+  }
+
+  fn biome(name: &str, heat_point: f32, humidity_point: f32, y_min: i32) -> Biome {
+    Biome {
+      name: name.to_string(),
+      heat_point,
+      humidity_point,
+      y_min,
+      ..Biome::from_mapgen_nodes(
+        &MapgenNodes {
+          stone: 1,
+          dirt: 2,
+          grass: 3,
+          cobble: 4,
+          mossy_cobble: 5,
+        },
+        3,
+      )
+    }
+  }
+
+  #[test]
+  fn test_biome_selection() {
+    // Always 80 heat and 20 humidity.
+    let default_biome = biome("default", 50.0, 50.0, i32::MIN);
+    let mut manager = match BiomeManager::new(default_biome, flat_noise(80.0), flat_noise(20.0), 1)
+    {
+      Ok(manager) => manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    assert_eq!(manager.get_biome(0, 0, 0).name, "default");
+
+    for new_biome in [
+      biome("desert", 90.0, 10.0, -100),
+      biome("tundra", 0.0, 40.0, -100),
+      biome("snowy_peaks", 90.0, 10.0, 100),
+    ] {
+      if let Err(e) = manager.register(new_biome) {
+        panic!("Unit test is broken. {}", e);
+      }
+    }
+    assert!(manager.register(biome("desert", 0.0, 0.0, 0)).is_err());
+
+    assert_eq!(manager.get_biome(5, 0, -5).name, "desert");
+    // Peaks is just as close but only starts at 100, and desert was first.
+    assert_eq!(manager.get_biome(5, 150, -5).name, "desert");
+    // Nothing reaches down here.
+    assert_eq!(manager.get_biome(5, -500, -5).name, "default");
+    assert_eq!(manager.get_count(), 3);
+  }
+
+  #[test]
+  fn test_biome_layers() {
+    let biome = biome("default", 50.0, 50.0, 0);
+    assert_eq!(biome.get_node_at_depth(0), 3);
+    assert_eq!(biome.get_node_at_depth(1), 2);
+    assert_eq!(biome.get_node_at_depth(3), 2);
+    assert_eq!(biome.get_node_at_depth(4), 1);
+  }
+}
@@ -0,0 +1,219 @@
+use std::f32::consts::TAU;
+
+use glam::{IVec3, Vec3};
+
+use crate::game::map::{
+  block_to_node_position,
+  map_block::{MapBlock, MAP_BLOCK_SIZE},
+  node::{Node, CONTENT_AIR},
+};
+
+use super::{
+  noise::Noise,
+  pseudo_random::{hash_position, PseudoRandom},
+  MapgenParams,
+};
+
+///
+/// The longest a tunnel can wander, in steps of one node.
+///
+const TUNNEL_LENGTH_MAX: i32 = 40;
+
+///
+/// The widest a tunnel can get.
+///
+const TUNNEL_RADIUS_MAX: f32 = 3.0;
+
+///
+/// How many MapBlocks away a tunnel can start and still reach a block.
+///
+const TUNNEL_CELL_REACH: i32 =
+  (TUNNEL_LENGTH_MAX + TUNNEL_RADIUS_MAX as i32 + MAP_BLOCK_SIZE - 1) / MAP_BLOCK_SIZE;
+
+///
+/// Get the index of a column in the surface heights of a block.
+///
+pub fn column_index(x: i32, z: i32) -> usize {
+  (z * MAP_BLOCK_SIZE + x) as usize
+}
+
+///
+/// Turn a node into air, if it's underground.
+///
+/// Caves never take out the surface node of a column, so decorations
+/// always have something to sit on.
+///
+fn carve(block: &mut MapBlock, local: IVec3, origin: IVec3, surfaces: &[i32]) {
+  if origin.y + local.y >= surfaces[column_index(local.x, local.z)] {
+    return;
+  }
+
+  if block.get_node(local).content != CONTENT_AIR {
+    block.set_node(local, Node::air());
+  }
+}
+
+///
+/// Huge open caverns, carved out wherever a 3D noise is high enough.
+///
+/// Every node decides for itself, so they always line up between blocks.
+///
+pub struct Caverns {
+  noise: Noise,
+  limit: i32,
+  taper: i32,
+  threshold: f32,
+}
+
+impl Caverns {
+  pub fn new(params: &MapgenParams) -> Result<Self, String> {
+    let noise = match Noise::new(params.cavern, params.seed) {
+      Ok(noise) => noise,
+      Err(e) => return Err(format!("Caverns: bad cavern noise. {}", e)),
+    };
+
+    Ok(Caverns {
+      noise,
+      limit: params.cavern_limit,
+      taper: params.cavern_taper.max(1),
+      threshold: params.cavern_threshold,
+    })
+  }
+
+  ///
+  /// Get how high the noise has to be to carve at a height.
+  ///
+  /// Caverns shrink away as they get near the limit instead of
+  /// being cut off flat.
+  ///
+  fn get_threshold(&self, y: i32) -> f32 {
+    let taper_start = self.limit - self.taper;
+    if y <= taper_start {
+      return self.threshold;
+    }
+
+    let fraction = (y - taper_start) as f32 / self.taper as f32;
+    self.threshold + (1.0 - self.threshold) * fraction
+  }
+
+  pub fn generate(&self, block: &mut MapBlock, block_position: IVec3, surfaces: &[i32]) {
+    let origin = block_to_node_position(block_position);
+
+    if origin.y >= self.limit {
+      return;
+    }
+
+    for z in 0..MAP_BLOCK_SIZE {
+      for y in 0..MAP_BLOCK_SIZE {
+        let node_y = origin.y + y;
+        if node_y >= self.limit {
+          break;
+        }
+        let threshold = self.get_threshold(node_y);
+
+        for x in 0..MAP_BLOCK_SIZE {
+          let local = IVec3::new(x, y, z);
+
+          if self.noise.get_3d((origin + local).as_vec3()) > threshold {
+            carve(block, local, origin, surfaces);
+          }
+        }
+      }
+    }
+  }
+}
+
+///
+/// Winding tunnels, made by worms taking a random walk.
+///
+/// Each MapBlock sized cell of the world rolls for a worm with it's own
+/// seed. A block walks every worm which could reach it and carves the parts
+/// that land inside, so the tunnels match up no matter which block is first.
+///
+pub struct Tunnels {
+  seed: u64,
+  chance: f32,
+}
+
+impl Tunnels {
+  pub fn new(params: &MapgenParams) -> Self {
+    Tunnels {
+      seed: hash_position(params.seed, IVec3::new(3, 0, 0)),
+      chance: params.tunnel_chance,
+    }
+  }
+
+  pub fn generate(&self, block: &mut MapBlock, block_position: IVec3, surfaces: &[i32]) {
+    let origin = block_to_node_position(block_position);
+
+    for z in -TUNNEL_CELL_REACH..=TUNNEL_CELL_REACH {
+      for y in -TUNNEL_CELL_REACH..=TUNNEL_CELL_REACH {
+        for x in -TUNNEL_CELL_REACH..=TUNNEL_CELL_REACH {
+          self.walk(
+            block,
+            origin,
+            surfaces,
+            block_position + IVec3::new(x, y, z),
+          );
+        }
+      }
+    }
+  }
+
+  fn walk(&self, block: &mut MapBlock, origin: IVec3, surfaces: &[i32], cell: IVec3) {
+    let mut random = PseudoRandom::new(hash_position(self.seed, cell));
+
+    if random.next_f32() >= self.chance {
+      return;
+    }
+
+    let cell_origin = block_to_node_position(cell).as_vec3();
+    let mut position = cell_origin
+      + Vec3::new(random.next_f32(), random.next_f32(), random.next_f32()) * MAP_BLOCK_SIZE as f32;
+
+    // Mostly sideways, caves that go straight down aren't much fun.
+    let mut yaw = random.next_f32() * TAU;
+    let mut pitch = (random.next_f32() - 0.5) * 0.6;
+    let mut radius = 1.5 + random.next_f32() * 1.5;
+    let length = random.range(TUNNEL_LENGTH_MAX / 4, TUNNEL_LENGTH_MAX);
+
+    for _ in 0..length {
+      carve_sphere(block, origin, surfaces, position, radius);
+
+      position += Vec3::new(
+        pitch.cos() * yaw.cos(),
+        pitch.sin(),
+        pitch.cos() * yaw.sin(),
+      );
+
+      yaw += (random.next_f32() - 0.5) * 0.6;
+      pitch = (pitch + (random.next_f32() - 0.5) * 0.3).clamp(-0.6, 0.6);
+      radius = (radius + (random.next_f32() - 0.5) * 0.4).clamp(1.0, TUNNEL_RADIUS_MAX);
+    }
+  }
+}
+
+///
+/// Carve out the part of a ball which lands inside of the block.
+///
+fn carve_sphere(block: &mut MapBlock, origin: IVec3, surfaces: &[i32], center: Vec3, radius: f32) {
+  let min = ((center - radius).floor().as_ivec3() - origin).max(IVec3::ZERO);
+  let max = ((center + radius).ceil().as_ivec3() - origin).min(IVec3::splat(MAP_BLOCK_SIZE - 1));
+
+  if min.x > max.x || min.y > max.y || min.z > max.z {
+    return;
+  }
+
+  for z in min.z..=max.z {
+    for y in min.y..=max.y {
+      for x in min.x..=max.x {
+        let local = IVec3::new(x, y, z);
+        let node_center = (origin + local).as_vec3() + Vec3::splat(0.5);
+
+        if node_center.distance_squared(center) <= radius * radius {
+          carve(block, local, origin, surfaces);
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,339 @@
+use glam::IVec3;
+use serde::{Deserialize, Serialize};
+
+use crate::game::map::{
+  block_to_node_position,
+  map_block::{MapBlock, MAP_BLOCK_SIZE},
+  node::{ContentId, Node, CONTENT_AIR},
+  node_def_manager::NodeDefManager,
+};
+
+use super::{
+  biome::BiomeManager,
+  pseudo_random::{hash_position, PseudoRandom},
+  schematic::Schematic,
+  Mapgen,
+};
+
+///
+/// What a decoration places.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecorationType {
+  // A short stack of one node, like grass or a cactus.
+  Simple,
+  // A whole schematic, like a tree.
+  Schematic,
+}
+
+impl DecorationType {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "simple" => Some(DecorationType::Simple),
+      "schematic" => Some(DecorationType::Schematic),
+      _ => None,
+    }
+  }
+}
+
+///
+/// A decoration as a game registers it with minetest.register_decoration().
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DecorationDefinition {
+  pub deco_type: DecorationType,
+  // What the surface has to be for it to go on top.
+  pub place_on: Vec<String>,
+  // Chance of a decoration on every surface node, 0 to 1.
+  pub fill_ratio: f32,
+  // Empty means every biome.
+  pub biomes: Vec<String>,
+  pub y_min: i32,
+  pub y_max: i32,
+  // One of these is picked for each one placed. (simple)
+  pub decoration: Vec<String>,
+  // How tall the stack is, picked between height and height_max. (simple)
+  pub height: i32,
+  pub height_max: i32,
+  pub schematic: Option<Schematic>,
+  // Center the schematic on the surface node instead of starting at it.
+  pub place_center_x: bool,
+  pub place_center_z: bool,
+}
+
+impl DecorationDefinition {
+  ///
+  /// A definition with the defaults a mod would get.
+  ///
+  pub fn new(deco_type: DecorationType, place_on: &str) -> Self {
+    DecorationDefinition {
+      deco_type,
+      place_on: vec![place_on.to_string()],
+      fill_ratio: 0.02,
+      biomes: vec![],
+      y_min: -31_000,
+      y_max: 31_000,
+      decoration: vec![],
+      height: 1,
+      height_max: 0,
+      schematic: None,
+      place_center_x: false,
+      place_center_z: false,
+    }
+  }
+
+  ///
+  /// Check the definition makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if self.y_min > self.y_max {
+      return Err(format!(
+        "decoration has y_min {} above y_max {}.",
+        self.y_min, self.y_max
+      ));
+    }
+
+    if self.place_on.is_empty() {
+      return Err("decoration has nothing to be placed on.".to_string());
+    }
+
+    if !(0.0..=1.0).contains(&self.fill_ratio) {
+      return Err(format!(
+        "decoration fill_ratio {} must be between 0 and 1.",
+        self.fill_ratio
+      ));
+    }
+
+    match self.deco_type {
+      DecorationType::Simple => {
+        if self.decoration.is_empty() {
+          return Err("simple decoration has no decoration nodes.".to_string());
+        }
+
+        if self.height < 1 || self.height > MAP_BLOCK_SIZE || self.height_max > MAP_BLOCK_SIZE {
+          return Err(format!(
+            "simple decoration height must be between 1 and {}.",
+            MAP_BLOCK_SIZE
+          ));
+        }
+      }
+      DecorationType::Schematic => match &self.schematic {
+        Some(schematic) => schematic.validate()?,
+        None => return Err("schematic decoration has no schematic.".to_string()),
+      },
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// A decoration with content ids, ready for the mapgen.
+///
+pub struct Decoration {
+  definition: DecorationDefinition,
+  place_on: Vec<ContentId>,
+  decoration: Vec<ContentId>,
+  schematic_nodes: Vec<Node>,
+  seed: u64,
+}
+
+impl Decoration {
+  ///
+  /// Resolve a DecorationDefinition, the seed should be different for every decoration.
+  ///
+  /// Biome names are checked against the biomes which are already registered.
+  ///
+  pub fn new(
+    definition: DecorationDefinition,
+    node_def_manager: &NodeDefManager,
+    biome_manager: &BiomeManager,
+    seed: u64,
+  ) -> Result<Self, String> {
+    definition.validate()?;
+
+    let get_all = |names: &[String]| {
+      names
+        .iter()
+        .map(|name| match node_def_manager.get_id(name) {
+          Some(id) => Ok(id),
+          None => Err(format!(
+            "decoration uses [{}], which isn't a registered node.",
+            name
+          )),
+        })
+        .collect::<Result<Vec<ContentId>, String>>()
+    };
+
+    if let Some(name) = definition
+      .biomes
+      .iter()
+      .find(|name| biome_manager.get(name).is_none())
+    {
+      return Err(format!(
+        "decoration uses biome [{}], which isn't registered.",
+        name
+      ));
+    }
+
+    let schematic_nodes = match &definition.schematic {
+      Some(schematic) => schematic.resolve(node_def_manager)?,
+      None => vec![],
+    };
+
+    Ok(Decoration {
+      place_on: get_all(&definition.place_on)?,
+      decoration: get_all(&definition.decoration)?,
+      schematic_nodes,
+      definition,
+      seed,
+    })
+  }
+
+  pub fn get_definition(&self) -> &DecorationDefinition {
+    &self.definition
+  }
+
+  ///
+  /// Put decorations into a MapBlock the terrain was already generated in.
+  ///
+  /// Decorations only care about the mapgen's surface, not what's in the
+  /// block, so columns from neighboring blocks can be checked too. Those
+  /// schematics get the part of them that sticks into this block placed.
+  ///
+  pub fn generate(&self, block: &mut MapBlock, block_position: IVec3, mapgen: &Mapgen) {
+    let origin = block_to_node_position(block_position);
+
+    let margin = match &self.definition.schematic {
+      Some(schematic) => schematic.size.x.max(schematic.size.z),
+      None => 0,
+    };
+
+    for z in -margin..MAP_BLOCK_SIZE + margin {
+      for x in -margin..MAP_BLOCK_SIZE + margin {
+        self.generate_column(block, origin, origin.x + x, origin.z + z, mapgen);
+      }
+    }
+  }
+
+  fn generate_column(&self, block: &mut MapBlock, origin: IVec3, x: i32, z: i32, mapgen: &Mapgen) {
+    let mut random = PseudoRandom::new(hash_position(self.seed, IVec3::new(x, 0, z)));
+
+    if random.next_f32() >= self.definition.fill_ratio {
+      return;
+    }
+
+    let surface = mapgen.get_surface_height(x, z);
+    let base = IVec3::new(x, surface + 1, z);
+
+    if base.y < self.definition.y_min || base.y > self.definition.y_max {
+      return;
+    }
+
+    // Nothing this tall can reach the block.
+    let reach = match &self.definition.schematic {
+      Some(schematic) => schematic.size.y,
+      None => self.definition.height.max(self.definition.height_max),
+    };
+    if base.y > origin.y + MAP_BLOCK_SIZE - 1 || base.y + reach <= origin.y {
+      return;
+    }
+
+    let biome = mapgen.get_biome_manager().get_biome(x, surface, z);
+
+    if !self.definition.biomes.is_empty() && !self.definition.biomes.contains(&biome.name) {
+      return;
+    }
+
+    if !self.place_on.contains(&biome.get_node_at_depth(0)) {
+      return;
+    }
+
+    match self.definition.deco_type {
+      DecorationType::Simple => self.place_simple(block, origin, base, &mut random),
+      DecorationType::Schematic => self.place_schematic(block, origin, base, &mut random),
+    }
+  }
+
+  fn place_simple(
+    &self,
+    block: &mut MapBlock,
+    origin: IVec3,
+    base: IVec3,
+    random: &mut PseudoRandom,
+  ) {
+    let content = self.decoration[random.range(0, self.decoration.len() as i32 - 1) as usize];
+    let height = random.range(self.definition.height, self.definition.height_max);
+
+    for y in 0..height {
+      place(
+        block,
+        origin,
+        base + IVec3::new(0, y, 0),
+        Node::new(content),
+        false,
+      );
+    }
+  }
+
+  fn place_schematic(
+    &self,
+    block: &mut MapBlock,
+    origin: IVec3,
+    base: IVec3,
+    random: &mut PseudoRandom,
+  ) {
+    let schematic = match &self.definition.schematic {
+      Some(schematic) => schematic,
+      None => return,
+    };
+
+    let mut corner = base;
+    if self.definition.place_center_x {
+      corner.x -= schematic.size.x / 2;
+    }
+    if self.definition.place_center_z {
+      corner.z -= schematic.size.z / 2;
+    }
+
+    for z in 0..schematic.size.z {
+      for y in 0..schematic.size.y {
+        for x in 0..schematic.size.x {
+          let offset = IVec3::new(x, y, z);
+          let index = schematic.get_index(offset);
+          let schematic_node = &schematic.nodes[index];
+
+          // Always roll, so the numbers after this stay the same.
+          let roll = random.range(0, 254);
+          if roll >= schematic_node.probability as i32 {
+            continue;
+          }
+
+          place(
+            block,
+            origin,
+            corner + offset,
+            self.schematic_nodes[index],
+            schematic_node.force_place,
+          );
+        }
+      }
+    }
+  }
+}
+
+///
+/// Put a node into the block if it's inside of it, and
+/// only over air unless it's forced.
+///
+fn place(block: &mut MapBlock, origin: IVec3, position: IVec3, node: Node, force: bool) {
+  let local = position - origin;
+
+  if local.min_element() < 0 || local.max_element() >= MAP_BLOCK_SIZE {
+    return;
+  }
+
+  if force || block.get_node(local).content == CONTENT_AIR {
+    block.set_node(local, node);
+  }
+}
@@ -0,0 +1,201 @@
+use glam::IVec3;
+
+use crate::game::map::{
+  block_to_node_position,
+  map_block::{MapBlock, MAP_BLOCK_SIZE},
+  node::{ContentId, Node, CONTENT_AIR},
+};
+
+use super::{
+  pseudo_random::{hash_position, PseudoRandom},
+  Mapgen, MapgenParams,
+};
+
+///
+/// A box of nodes, min and max are both inside of it.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct NodeBox {
+  min: IVec3,
+  max: IVec3,
+}
+
+impl NodeBox {
+  fn grow(&self, amount: i32) -> Self {
+    NodeBox {
+      min: self.min - amount,
+      max: self.max + amount,
+    }
+  }
+
+  ///
+  /// Get the part of the box inside of a block, in local positions.
+  ///
+  fn clip_to_block(&self, origin: IVec3) -> Option<(IVec3, IVec3)> {
+    let min = (self.min - origin).max(IVec3::ZERO);
+    let max = (self.max - origin).min(IVec3::splat(MAP_BLOCK_SIZE - 1));
+
+    if min.x > max.x || min.y > max.y || min.z > max.z {
+      None
+    } else {
+      Some((min, max))
+    }
+  }
+}
+
+///
+/// Simple dungeons, a few rectangular rooms in a row joined by corridors.
+///
+/// Like tunnels, every MapBlock sized cell rolls for a dungeon with it's
+/// own seed, and a block builds the parts of its neighbors' dungeons which
+/// land inside of it.
+///
+/// Walls only replace solid nodes, so caves running into a dungeon
+/// leave a hole instead of getting walled off.
+///
+pub struct Dungeons {
+  seed: u64,
+  chance: f32,
+  y_max: i32,
+  wall: ContentId,
+  alt_wall: ContentId,
+}
+
+impl Dungeons {
+  pub fn new(params: &MapgenParams, wall: ContentId, alt_wall: ContentId) -> Self {
+    Dungeons {
+      seed: hash_position(params.seed, IVec3::new(4, 0, 0)),
+      chance: params.dungeon_chance,
+      y_max: params.dungeon_y_max,
+      wall,
+      alt_wall,
+    }
+  }
+
+  pub fn generate(&self, block: &mut MapBlock, block_position: IVec3, mapgen: &Mapgen) {
+    let origin = block_to_node_position(block_position);
+
+    // Rooms are smaller than a block, so they can only reach in from next door.
+    for z in -1..=1 {
+      for y in -1..=1 {
+        for x in -1..=1 {
+          let boxes = self.plan(block_position + IVec3::new(x, y, z), mapgen);
+          self.build(block, origin, &boxes);
+        }
+      }
+    }
+  }
+
+  ///
+  /// Work out the rooms and corridors of a cell's dungeon, if it has one.
+  ///
+  fn plan(&self, cell: IVec3, mapgen: &Mapgen) -> Vec<NodeBox> {
+    let mut random = PseudoRandom::new(hash_position(self.seed, cell));
+
+    if random.next_f32() >= self.chance {
+      return vec![];
+    }
+
+    let cell_origin = block_to_node_position(cell);
+
+    // Every room shares the same floor, so corridors stay flat.
+    let floor = cell_origin.y + random.range(1, 6);
+    let room_count = random.range(2, 3);
+
+    let mut rooms = vec![];
+    for _ in 0..room_count {
+      let size = IVec3::new(random.range(4, 8), random.range(3, 5), random.range(4, 8));
+      let min = IVec3::new(
+        cell_origin.x + random.range(1, MAP_BLOCK_SIZE - 1 - size.x),
+        floor,
+        cell_origin.z + random.range(1, MAP_BLOCK_SIZE - 1 - size.z),
+      );
+
+      rooms.push(NodeBox {
+        min,
+        max: min + size - 1,
+      });
+    }
+
+    // Buried deep enough that no room pokes out of the ground.
+    for room in &rooms {
+      if room.max.y + 1 > self.y_max {
+        return vec![];
+      }
+
+      let center = (room.min + room.max) / 2;
+      if mapgen.get_surface_height(center.x, center.z) <= room.max.y + 3 {
+        return vec![];
+      }
+    }
+
+    let mut boxes = rooms.clone();
+
+    for pair in rooms.windows(2) {
+      let start = (pair[0].min + pair[0].max) / 2;
+      let end = (pair[1].min + pair[1].max) / 2;
+
+      // Along x, then along z.
+      boxes.push(NodeBox {
+        min: IVec3::new(start.x.min(end.x), floor, start.z),
+        max: IVec3::new(start.x.max(end.x), floor + 1, start.z),
+      });
+      boxes.push(NodeBox {
+        min: IVec3::new(end.x, floor, start.z.min(end.z)),
+        max: IVec3::new(end.x, floor + 1, start.z.max(end.z)),
+      });
+    }
+
+    boxes
+  }
+
+  ///
+  /// Build the parts of a dungeon inside of the block.
+  ///
+  /// Every wall goes up first, then every inside is emptied out, so
+  /// overlapping rooms and corridors open up into each other.
+  ///
+  fn build(&self, block: &mut MapBlock, origin: IVec3, boxes: &[NodeBox]) {
+    for node_box in boxes {
+      let (min, max) = match node_box.grow(1).clip_to_block(origin) {
+        Some(clipped) => clipped,
+        None => continue,
+      };
+
+      for z in min.z..=max.z {
+        for y in min.y..=max.y {
+          for x in min.x..=max.x {
+            let local = IVec3::new(x, y, z);
+            if block.get_node(local).content == CONTENT_AIR {
+              continue;
+            }
+
+            // A quarter of the walls are the other kind, picked by position.
+            let mut random = PseudoRandom::new(hash_position(self.seed, origin + local));
+            let wall = if random.next_f32() < 0.25 {
+              self.alt_wall
+            } else {
+              self.wall
+            };
+            block.set_node(local, Node::new(wall));
+          }
+        }
+      }
+    }
+
+    for node_box in boxes {
+      let (min, max) = match node_box.clip_to_block(origin) {
+        Some(clipped) => clipped,
+        None => continue,
+      };
+
+      for z in min.z..=max.z {
+        for y in min.y..=max.y {
+          for x in min.x..=max.x {
+            block.set_node(IVec3::new(x, y, z), Node::air());
+          }
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,220 @@
+use std::{
+  sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex,
+  },
+  thread::{self, JoinHandle},
+};
+
+use ahash::AHashSet;
+use glam::IVec3;
+
+use crate::game::map::map_block::MapBlock;
+
+use super::Mapgen;
+
+///
+/// A pool of worker threads which run the Mapgen, so generating
+/// terrain never holds up the server tick.
+///
+/// Block positions go in with request_block(), finished MapBlocks
+/// come back out of receive_blocks() whenever they're done.
+///
+pub struct MapgenThreads {
+  mapgen: Arc<Mapgen>,
+  job_sender: Option<Sender<IVec3>>,
+  result_receiver: Receiver<(IVec3, MapBlock)>,
+  workers: Vec<JoinHandle<()>>,
+
+  // Requested but not received yet, so nothing gets generated twice.
+  in_flight: AHashSet<IVec3>,
+}
+
+impl MapgenThreads {
+  pub fn new(mapgen: Mapgen, thread_count: usize) -> Self {
+    let mapgen = Arc::new(mapgen);
+
+    let (job_sender, job_receiver) = mpsc::channel::<IVec3>();
+    let (result_sender, result_receiver) = mpsc::channel();
+
+    // Every worker pulls from the same queue.
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+    let workers = (0..thread_count.max(1))
+      .map(|i| {
+        let mapgen = mapgen.clone();
+        let job_receiver = job_receiver.clone();
+        let result_sender = result_sender.clone();
+
+        let spawned = thread::Builder::new()
+          .name(format!("mapgen_{}", i))
+          .spawn(move || Self::work(&mapgen, &job_receiver, &result_sender));
+
+        match spawned {
+          Ok(handle) => handle,
+          Err(e) => panic!("MapgenThreads: failed to spawn thread {}. {}", i, e),
+        }
+      })
+      .collect();
+
+    MapgenThreads {
+      mapgen,
+      job_sender: Some(job_sender),
+      result_receiver,
+      workers,
+
+      in_flight: AHashSet::new(),
+    }
+  }
+
+  ///
+  /// The worker loop, runs until the job queue is dropped.
+  ///
+  fn work(
+    mapgen: &Mapgen,
+    job_receiver: &Mutex<Receiver<IVec3>>,
+    result_sender: &Sender<(IVec3, MapBlock)>,
+  ) {
+    loop {
+      // Only hold the lock while waiting, not while generating.
+      let job = match job_receiver.lock() {
+        Ok(job_receiver) => job_receiver.recv(),
+        Err(_) => return,
+      };
+
+      let block_position = match job {
+        Ok(block_position) => block_position,
+        Err(_) => return,
+      };
+
+      let block = mapgen.generate_block(block_position);
+
+      if result_sender.send((block_position, block)).is_err() {
+        return;
+      }
+    }
+  }
+
+  pub fn get_mapgen(&self) -> &Mapgen {
+    &self.mapgen
+  }
+
+  ///
+  /// Queue up a MapBlock to be generated.
+  ///
+  /// Returns false if it's already queued.
+  ///
+  pub fn request_block(&mut self, block_position: IVec3) -> bool {
+    if self.in_flight.contains(&block_position) {
+      return false;
+    }
+
+    let job_sender = match &self.job_sender {
+      Some(job_sender) => job_sender,
+      None => return false,
+    };
+
+    if let Err(e) = job_sender.send(block_position) {
+      panic!("MapgenThreads: every worker is gone. {}", e);
+    }
+
+    self.in_flight.insert(block_position);
+
+    true
+  }
+
+  ///
+  /// Check if a MapBlock is queued up or being generated.
+  ///
+  pub fn is_requested(&self, block_position: IVec3) -> bool {
+    self.in_flight.contains(&block_position)
+  }
+
+  ///
+  /// Get how many MapBlocks are waiting to be received.
+  ///
+  pub fn get_queue_size(&self) -> usize {
+    self.in_flight.len()
+  }
+
+  ///
+  /// Take every MapBlock which finished generating. (non blocking)
+  ///
+  pub fn receive_blocks(&mut self) -> Vec<(IVec3, MapBlock)> {
+    let finished: Vec<(IVec3, MapBlock)> = self.result_receiver.try_iter().collect();
+
+    for (block_position, _) in &finished {
+      self.in_flight.remove(block_position);
+    }
+
+    finished
+  }
+}
+
+impl Drop for MapgenThreads {
+  fn drop(&mut self) {
+    // Hanging up the queue is what tells the workers to stop.
+    self.job_sender = None;
+
+    for worker in self.workers.drain(..) {
+      if worker.join().is_err() {
+        println!("MapgenThreads: a worker panicked.");
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use glam::IVec3;
+
+  use crate::game::map::mapgen::{Mapgen, MapgenNodes, MapgenParams};
+
+  use super::MapgenThreads;
+
+  #[test]
+  fn test_mapgen_threads() {
+    let nodes = MapgenNodes {
+      stone: 1,
+      dirt: 2,
+      grass: 3,
+      cobble: 4,
+      mossy_cobble: 5,
+    };
+    let new_mapgen = || match Mapgen::new(MapgenParams::new(777), nodes) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    let mut threads = MapgenThreads::new(new_mapgen(), 3);
+    let reference = new_mapgen();
+
+    let mut requested = vec![];
+    for x in -2..2 {
+      for y in -2..2 {
+        let block_position = IVec3::new(x, y, 1);
+        assert!(threads.request_block(block_position));
+        requested.push(block_position);
+      }
+    }
+    assert!(!threads.request_block(IVec3::new(0, 0, 1)));
+
+    let mut received = vec![];
+    let started = Instant::now();
+    while received.len() < requested.len() {
+      if started.elapsed() > Duration::from_secs(10) {
+        panic!("Unit test is broken. mapgen threads never finished.");
+      }
+      received.append(&mut threads.receive_blocks());
+      std::thread::sleep(Duration::from_millis(1));
+    }
+
+    // Same blocks as generating them right here.
+    for (block_position, block) in received {
+      assert_eq!(block, reference.generate_block(block_position));
+    }
+    assert_eq!(threads.get_queue_size(), 0);
+  }
+}
@@ -0,0 +1,448 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use super::pseudo_random::PseudoRandom;
+
+///
+/// Which base noise function gets layered into octaves.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseType {
+  Perlin,
+  Simplex,
+}
+
+///
+/// Everything that shapes a noise, same idea as C++ minetest's NoiseParams.
+///
+/// The final value is offset + scale * (the sum of every octave).
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct NoiseParams {
+  pub noise_type: NoiseType,
+  pub offset: f32,
+  pub scale: f32,
+  // How many nodes one "wave" of the first octave is along each axis.
+  pub spread: Vec3,
+  // Added to the world seed, so two noises in one world don't look the same.
+  pub seed: i32,
+  pub octaves: u32,
+  // How much each octave's amplitude is multiplied by.
+  pub persistence: f32,
+  // How much each octave's frequency is multiplied by.
+  pub lacunarity: f32,
+}
+
+impl NoiseParams {
+  ///
+  /// Check the params make sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if self.octaves == 0 {
+      return Err("noise needs at least 1 octave.".to_string());
+    }
+
+    if self.spread.min_element() <= 0.0 {
+      return Err(format!("noise spread {} must be above 0.", self.spread));
+    }
+
+    if self.lacunarity <= 0.0 {
+      return Err(format!(
+        "noise lacunarity {} must be above 0.",
+        self.lacunarity
+      ));
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// A shuffled table of 0 to 255, twice over so lookups never need to wrap.
+///
+/// This is what makes the noise seedable.
+///
+#[derive(Clone)]
+struct Permutation {
+  table: [u8; 512],
+}
+
+impl Permutation {
+  fn new(seed: u64) -> Self {
+    let mut random = PseudoRandom::new(seed);
+
+    let mut values: [u8; 256] = [0; 256];
+    for (i, value) in values.iter_mut().enumerate() {
+      *value = i as u8;
+    }
+    for i in (1..256).rev() {
+      let j = (random.next_u64() % (i as u64 + 1)) as usize;
+      values.swap(i, j);
+    }
+
+    let mut table = [0; 512];
+    for (i, value) in table.iter_mut().enumerate() {
+      *value = values[i & 255];
+    }
+
+    Permutation { table }
+  }
+
+  fn hash(&self, i: i32) -> usize {
+    self.table[(i & 255) as usize] as usize
+  }
+
+  fn hash_2d(&self, x: i32, y: i32) -> usize {
+    self.table[self.hash(x) + (y & 255) as usize] as usize
+  }
+
+  fn hash_3d(&self, x: i32, y: i32, z: i32) -> usize {
+    self.table[self.hash_2d(x, y) + (z & 255) as usize] as usize
+  }
+
+  ///
+  /// Improved Perlin noise in 2D, roughly -1 to 1.
+  ///
+  fn perlin_2d(&self, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (xi, yi) = (x0 as i32, y0 as i32);
+    let (xf, yf) = (x - x0, y - y0);
+    let (u, v) = (fade(xf), fade(yf));
+
+    let aa = grad_2d(self.hash_2d(xi, yi), xf, yf);
+    let ba = grad_2d(self.hash_2d(xi + 1, yi), xf - 1.0, yf);
+    let ab = grad_2d(self.hash_2d(xi, yi + 1), xf, yf - 1.0);
+    let bb = grad_2d(self.hash_2d(xi + 1, yi + 1), xf - 1.0, yf - 1.0);
+
+    // The diagonal gradients top out at 1/sqrt(2), stretch it back out.
+    lerp(v, lerp(u, aa, ba), lerp(u, ab, bb)) * std::f32::consts::SQRT_2
+  }
+
+  ///
+  /// Improved Perlin noise in 3D, roughly -1 to 1.
+  ///
+  fn perlin_3d(&self, x: f32, y: f32, z: f32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let (xi, yi, zi) = (x0 as i32, y0 as i32, z0 as i32);
+    let (xf, yf, zf) = (x - x0, y - y0, z - z0);
+    let (u, v, w) = (fade(xf), fade(yf), fade(zf));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+      grad_3d(
+        self.hash_3d(xi + dx, yi + dy, zi + dz),
+        xf - dx as f32,
+        yf - dy as f32,
+        zf - dz as f32,
+      )
+    };
+
+    lerp(
+      w,
+      lerp(
+        v,
+        lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+        lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+      ),
+      lerp(
+        v,
+        lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+        lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+      ),
+    )
+  }
+
+  ///
+  /// Simplex noise in 2D, roughly -1 to 1.
+  ///
+  fn simplex_2d(&self, x: f32, y: f32) -> f32 {
+    const F2: f32 = 0.366_025_42; // (sqrt(3) - 1) / 2
+    const G2: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+
+    // Skew into the simplex grid to find which cell we're in.
+    let s = (x + y) * F2;
+    let (i, j) = ((x + s).floor(), (y + s).floor());
+    let t = (i + j) * G2;
+    let (x0, y0) = (x - (i - t), y - (j - t));
+
+    // Which of the two triangles in the cell.
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+    let (x1, y1) = (x0 - i1 as f32 + G2, y0 - j1 as f32 + G2);
+    let (x2, y2) = (x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2);
+
+    let (ii, jj) = (i as i32, j as i32);
+
+    let corner = |hash: usize, x: f32, y: f32| {
+      let t = 0.5 - x * x - y * y;
+      if t < 0.0 {
+        0.0
+      } else {
+        t * t * t * t * grad_2d(hash, x, y)
+      }
+    };
+
+    let n0 = corner(self.hash_2d(ii, jj), x0, y0);
+    let n1 = corner(self.hash_2d(ii + i1, jj + j1), x1, y1);
+    let n2 = corner(self.hash_2d(ii + 1, jj + 1), x2, y2);
+
+    70.0 * (n0 + n1 + n2)
+  }
+
+  ///
+  /// Simplex noise in 3D, roughly -1 to 1.
+  ///
+  fn simplex_3d(&self, x: f32, y: f32, z: f32) -> f32 {
+    const F3: f32 = 1.0 / 3.0;
+    const G3: f32 = 1.0 / 6.0;
+
+    let s = (x + y + z) * F3;
+    let (i, j, k) = ((x + s).floor(), (y + s).floor(), (z + s).floor());
+    let t = (i + j + k) * G3;
+    let (x0, y0, z0) = (x - (i - t), y - (j - t), z - (k - t));
+
+    // Which of the six tetrahedrons in the cell.
+    let ((i1, j1, k1), (i2, j2, k2)) = if x0 >= y0 {
+      if y0 >= z0 {
+        ((1, 0, 0), (1, 1, 0))
+      } else if x0 >= z0 {
+        ((1, 0, 0), (1, 0, 1))
+      } else {
+        ((0, 0, 1), (1, 0, 1))
+      }
+    } else if y0 < z0 {
+      ((0, 0, 1), (0, 1, 1))
+    } else if x0 < z0 {
+      ((0, 1, 0), (0, 1, 1))
+    } else {
+      ((0, 1, 0), (1, 1, 0))
+    };
+
+    let (ii, jj, kk) = (i as i32, j as i32, k as i32);
+
+    let corner = |(di, dj, dk): (i32, i32, i32), offset: f32| {
+      let x = x0 - di as f32 + offset;
+      let y = y0 - dj as f32 + offset;
+      let z = z0 - dk as f32 + offset;
+      let t = 0.6 - x * x - y * y - z * z;
+      if t < 0.0 {
+        0.0
+      } else {
+        t * t * t * t * grad_3d(self.hash_3d(ii + di, jj + dj, kk + dk), x, y, z)
+      }
+    };
+
+    32.0
+      * (corner((0, 0, 0), 0.0)
+        + corner((i1, j1, k1), G3)
+        + corner((i2, j2, k2), 2.0 * G3)
+        + corner((1, 1, 1), 3.0 * G3))
+  }
+}
+
+///
+/// Smooth the fraction so the noise has no creases at cell edges.
+///
+fn fade(t: f32) -> f32 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+  a + t * (b - a)
+}
+
+fn grad_2d(hash: usize, x: f32, y: f32) -> f32 {
+  match hash & 7 {
+    0 => x + y,
+    1 => -x + y,
+    2 => x - y,
+    3 => -x - y,
+    4 => x,
+    5 => -x,
+    6 => y,
+    _ => -y,
+  }
+}
+
+fn grad_3d(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+  match hash & 15 {
+    0 | 12 => x + y,
+    1 | 14 => -x + y,
+    2 => x - y,
+    3 => -x - y,
+    4 => x + z,
+    5 => -x + z,
+    6 => x - z,
+    7 => -x - z,
+    8 => y + z,
+    9 | 13 => -y + z,
+    10 => y - z,
+    _ => -y - z,
+  }
+}
+
+///
+/// Fractal noise, a few octaves of Perlin or simplex noise layered on
+/// top of each other.
+///
+/// Only depends on the params and the world seed, so the same position
+/// always gets the same value. That's what lets MapBlocks be generated
+/// in any order, on any thread.
+///
+#[derive(Clone)]
+pub struct Noise {
+  params: NoiseParams,
+  // Every octave gets it's own shuffle so they don't line up.
+  octaves: Vec<Permutation>,
+}
+
+impl Noise {
+  pub fn new(params: NoiseParams, world_seed: u64) -> Result<Self, String> {
+    params.validate()?;
+
+    let seed = world_seed.wrapping_add(params.seed as i64 as u64);
+
+    let octaves = (0..params.octaves)
+      .map(|octave| Permutation::new(seed.wrapping_add(octave as u64)))
+      .collect();
+
+    Ok(Noise { params, octaves })
+  }
+
+  pub fn get_params(&self) -> &NoiseParams {
+    &self.params
+  }
+
+  ///
+  /// Get the noise value at a 2D position.
+  ///
+  /// Y of the spread is ignored, 2D noise lays flat on x and z.
+  ///
+  pub fn get_2d(&self, x: f32, z: f32) -> f32 {
+    let mut x = x / self.params.spread.x;
+    let mut z = z / self.params.spread.z;
+    let mut amplitude = 1.0;
+    let mut sum = 0.0;
+
+    for permutation in &self.octaves {
+      sum += amplitude
+        * match self.params.noise_type {
+          NoiseType::Perlin => permutation.perlin_2d(x, z),
+          NoiseType::Simplex => permutation.simplex_2d(x, z),
+        };
+
+      x *= self.params.lacunarity;
+      z *= self.params.lacunarity;
+      amplitude *= self.params.persistence;
+    }
+
+    self.params.offset + self.params.scale * sum
+  }
+
+  ///
+  /// Get the noise value at a 3D position.
+  ///
+  pub fn get_3d(&self, position: Vec3) -> f32 {
+    let mut position = position / self.params.spread;
+    let mut amplitude = 1.0;
+    let mut sum = 0.0;
+
+    for permutation in &self.octaves {
+      sum += amplitude
+        * match self.params.noise_type {
+          NoiseType::Perlin => permutation.perlin_3d(position.x, position.y, position.z),
+          NoiseType::Simplex => permutation.simplex_3d(position.x, position.y, position.z),
+        };
+
+      position *= self.params.lacunarity;
+      amplitude *= self.params.persistence;
+    }
+
+    self.params.offset + self.params.scale * sum
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::Vec3;
+
+  use super::{Noise, NoiseParams, NoiseType};
+
+  fn params(noise_type: NoiseType) -> NoiseParams {
+    NoiseParams {
+      noise_type,
+      offset: 0.0,
+      scale: 1.0,
+      spread: Vec3::splat(50.0),
+      seed: 5,
+      octaves: 1,
+      persistence: 0.5,
+      lacunarity: 2.0,
+    }
+  }
+
+  fn new_noise(params: NoiseParams, world_seed: u64) -> Noise {
+    match Noise::new(params, world_seed) {
+      Ok(noise) => noise,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+
+  #[test]
+  fn test_noise_is_seeded() {
+    for noise_type in [NoiseType::Perlin, NoiseType::Simplex] {
+      let noise = new_noise(params(noise_type), 1234);
+      let same = new_noise(params(noise_type), 1234);
+      let other = new_noise(params(noise_type), 4321);
+
+      let mut differs = false;
+      for i in 0..100 {
+        let position = Vec3::new(i as f32 * 7.3, i as f32 * -3.1, i as f32 * 1.7);
+
+        assert_eq!(
+          noise.get_2d(position.x, position.z),
+          same.get_2d(position.x, position.z)
+        );
+        assert_eq!(noise.get_3d(position), same.get_3d(position));
+
+        differs |= noise.get_3d(position) != other.get_3d(position);
+      }
+      assert!(differs);
+    }
+  }
+
+  #[test]
+  fn test_noise_range() {
+    for noise_type in [NoiseType::Perlin, NoiseType::Simplex] {
+      let noise = new_noise(params(noise_type), 99);
+
+      let mut lowest: f32 = 0.0;
+      let mut highest: f32 = 0.0;
+      for x in -50..50 {
+        for z in -50..50 {
+          let value = noise.get_2d(x as f32 * 3.3, z as f32 * 3.3);
+          let value_3d = noise.get_3d(Vec3::new(x as f32 * 3.3, 17.0, z as f32 * 3.3));
+          lowest = lowest.min(value).min(value_3d);
+          highest = highest.max(value).max(value_3d);
+        }
+      }
+
+      // It actually moves, and doesn't fly off somewhere.
+      assert!(lowest < -0.2 && highest > 0.2);
+      assert!(lowest >= -1.1 && highest <= 1.1);
+    }
+  }
+
+  #[test]
+  fn test_noise_octaves() {
+    let mut octave_params = params(NoiseType::Perlin);
+    octave_params.octaves = 3;
+    octave_params.offset = 10.0;
+    octave_params.scale = 0.0;
+
+    // No scale leaves only the offset.
+    assert_eq!(new_noise(octave_params, 1).get_2d(12.5, -40.0), 10.0);
+
+    octave_params.octaves = 0;
+    assert!(Noise::new(octave_params, 1).is_err());
+  }
+}
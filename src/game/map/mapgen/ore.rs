@@ -0,0 +1,443 @@
+use glam::{IVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::game::map::{
+  block_to_node_position,
+  map_block::{MapBlock, MAP_BLOCK_SIZE, MAP_BLOCK_VOLUME},
+  node::{ContentId, Node},
+  node_def_manager::NodeDefManager,
+};
+
+use super::{
+  noise::{Noise, NoiseParams},
+  pseudo_random::{hash_position, PseudoRandom},
+};
+
+///
+/// How an ore gets spread through the ground.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OreType {
+  // Little clusters of ore sprinkled around.
+  Scatter,
+  // A flat layer, wherever the noise is high enough.
+  Sheet,
+  // Round lumps.
+  Blob,
+}
+
+impl OreType {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "scatter" => Some(OreType::Scatter),
+      "sheet" => Some(OreType::Sheet),
+      "blob" => Some(OreType::Blob),
+      _ => None,
+    }
+  }
+}
+
+///
+/// An ore as a game registers it with minetest.register_ore().
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OreDefinition {
+  pub ore_type: OreType,
+  pub ore: String,
+  // Which nodes the ore can replace.
+  pub wherein: Vec<String>,
+  // One cluster per this many nodes. (scatter and blob)
+  pub clust_scarcity: u32,
+  // How many ores are in a cluster. (scatter)
+  pub clust_num_ores: u32,
+  // How wide a cluster is. (scatter and blob)
+  pub clust_size: i32,
+  // How thick the layer is. (sheet)
+  pub column_height: i32,
+  pub y_min: i32,
+  pub y_max: i32,
+  // Scatter and blob only place clusters where this is above
+  // noise_threshold, sheet needs it to know where the layer goes.
+  pub noise_params: Option<NoiseParams>,
+  pub noise_threshold: f32,
+}
+
+impl OreDefinition {
+  ///
+  /// A definition with the defaults a mod would get.
+  ///
+  pub fn new(ore_type: OreType, ore: &str, wherein: &str) -> Self {
+    OreDefinition {
+      ore_type,
+      ore: ore.to_string(),
+      wherein: vec![wherein.to_string()],
+      clust_scarcity: 8 * 8 * 8,
+      clust_num_ores: 8,
+      clust_size: 3,
+      column_height: 1,
+      y_min: -31_000,
+      y_max: 31_000,
+      noise_params: None,
+      noise_threshold: 0.0,
+    }
+  }
+
+  ///
+  /// Check the definition makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if self.y_min > self.y_max {
+      return Err(format!(
+        "ore [{}] has y_min {} above y_max {}.",
+        self.ore, self.y_min, self.y_max
+      ));
+    }
+
+    if self.wherein.is_empty() {
+      return Err(format!("ore [{}] has nothing to go in.", self.ore));
+    }
+
+    match self.ore_type {
+      OreType::Scatter | OreType::Blob => {
+        if self.clust_scarcity == 0 {
+          return Err(format!("ore [{}] has a clust_scarcity of 0.", self.ore));
+        }
+
+        // Clusters can only reach into the next MapBlock over.
+        if self.clust_size < 1 || self.clust_size > MAP_BLOCK_SIZE {
+          return Err(format!(
+            "ore [{}] clust_size must be between 1 and {}.",
+            self.ore, MAP_BLOCK_SIZE
+          ));
+        }
+
+        if self.ore_type == OreType::Scatter && self.clust_num_ores > self.clust_size.pow(3) as u32
+        {
+          return Err(format!(
+            "ore [{}] can't fit {} ores in a cluster of size {}.",
+            self.ore, self.clust_num_ores, self.clust_size
+          ));
+        }
+      }
+      OreType::Sheet => {
+        if self.noise_params.is_none() {
+          return Err(format!("sheet ore [{}] needs noise_params.", self.ore));
+        }
+
+        if self.column_height < 1 {
+          return Err(format!(
+            "sheet ore [{}] needs a column_height of at least 1.",
+            self.ore
+          ));
+        }
+      }
+    }
+
+    if let Some(noise_params) = &self.noise_params {
+      if let Err(e) = noise_params.validate() {
+        return Err(format!("ore [{}] has bad noise_params, {}", self.ore, e));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// An ore with content ids, ready for the mapgen.
+///
+pub struct Ore {
+  definition: OreDefinition,
+  ore: ContentId,
+  wherein: Vec<ContentId>,
+  noise: Option<Noise>,
+  seed: u64,
+}
+
+impl Ore {
+  ///
+  /// Resolve an OreDefinition, the seed should be different for every ore.
+  ///
+  pub fn new(
+    definition: OreDefinition,
+    node_def_manager: &NodeDefManager,
+    seed: u64,
+  ) -> Result<Self, String> {
+    definition.validate()?;
+
+    let get = |name: &str| match node_def_manager.get_id(name) {
+      Some(id) => Ok(id),
+      None => Err(format!(
+        "ore uses [{}], which isn't a registered node.",
+        name
+      )),
+    };
+
+    let ore = get(&definition.ore)?;
+    let wherein = definition
+      .wherein
+      .iter()
+      .map(|name| get(name))
+      .collect::<Result<Vec<ContentId>, String>>()?;
+
+    let noise = match &definition.noise_params {
+      Some(noise_params) => Some(Noise::new(*noise_params, seed)?),
+      None => None,
+    };
+
+    Ok(Ore {
+      definition,
+      ore,
+      wherein,
+      noise,
+      seed,
+    })
+  }
+
+  pub fn get_definition(&self) -> &OreDefinition {
+    &self.definition
+  }
+
+  ///
+  /// Put ore into a MapBlock the terrain was already generated in.
+  ///
+  pub fn generate(&self, block: &mut MapBlock, block_position: IVec3) {
+    let origin = block_to_node_position(block_position);
+
+    if origin.y > self.definition.y_max || origin.y + MAP_BLOCK_SIZE <= self.definition.y_min {
+      return;
+    }
+
+    match self.definition.ore_type {
+      OreType::Sheet => self.generate_sheet(block, origin),
+      OreType::Scatter | OreType::Blob => {
+        // Clusters started in the next MapBlock over can reach into this one.
+        for z in -1..=1 {
+          for y in -1..=1 {
+            for x in -1..=1 {
+              self.generate_clusters(block, origin, block_position + IVec3::new(x, y, z));
+            }
+          }
+        }
+      }
+    }
+  }
+
+  fn place(&self, block: &mut MapBlock, origin: IVec3, position: IVec3) {
+    let local = position - origin;
+
+    if local.min_element() < 0 || local.max_element() >= MAP_BLOCK_SIZE {
+      return;
+    }
+
+    if position.y < self.definition.y_min || position.y > self.definition.y_max {
+      return;
+    }
+
+    if self.wherein.contains(&block.get_node(local).content) {
+      block.set_node(local, Node::new(self.ore));
+    }
+  }
+
+  fn generate_sheet(&self, block: &mut MapBlock, origin: IVec3) {
+    let noise = match &self.noise {
+      Some(noise) => noise,
+      None => return,
+    };
+
+    // The layer sits in the middle of the y range.
+    let middle = (self.definition.y_min as i64 + self.definition.y_max as i64) / 2;
+    let bottom = middle as i32 - self.definition.column_height / 2;
+
+    for z in 0..MAP_BLOCK_SIZE {
+      for x in 0..MAP_BLOCK_SIZE {
+        let position = origin + IVec3::new(x, 0, z);
+
+        if noise.get_2d(position.x as f32, position.z as f32) < self.definition.noise_threshold {
+          continue;
+        }
+
+        for y in bottom..bottom + self.definition.column_height {
+          self.place(block, origin, IVec3::new(position.x, y, position.z));
+        }
+      }
+    }
+  }
+
+  ///
+  /// Place the parts of a cell's clusters which land in this block.
+  ///
+  /// Every cell rolls the same numbers no matter which block is asking,
+  /// so a cluster crossing a block border lines up on both sides.
+  ///
+  fn generate_clusters(&self, block: &mut MapBlock, origin: IVec3, cell: IVec3) {
+    let cell_origin = block_to_node_position(cell);
+    let mut random = PseudoRandom::new(hash_position(self.seed, cell));
+
+    let clusters = MAP_BLOCK_VOLUME as f32 / self.definition.clust_scarcity as f32;
+    let mut cluster_count = clusters.floor() as u32;
+    if random.next_f32() < clusters.fract() {
+      cluster_count += 1;
+    }
+
+    let size = self.definition.clust_size;
+
+    for _ in 0..cluster_count {
+      let corner = cell_origin
+        + IVec3::new(
+          random.range(0, MAP_BLOCK_SIZE - 1),
+          random.range(0, MAP_BLOCK_SIZE - 1),
+          random.range(0, MAP_BLOCK_SIZE - 1),
+        );
+
+      let noise_allows = match &self.noise {
+        Some(noise) => noise.get_3d(corner.as_vec3()) >= self.definition.noise_threshold,
+        None => true,
+      };
+
+      match self.definition.ore_type {
+        OreType::Scatter => {
+          let chance = self.definition.clust_num_ores as f32 / size.pow(3) as f32;
+
+          for z in 0..size {
+            for y in 0..size {
+              for x in 0..size {
+                // Always roll, so the numbers after this stay the same.
+                let hit = random.next_f32() < chance;
+                if hit && noise_allows {
+                  self.place(block, origin, corner + IVec3::new(x, y, z));
+                }
+              }
+            }
+          }
+        }
+        OreType::Blob => {
+          if !noise_allows {
+            continue;
+          }
+
+          let radius = size as f32 / 2.0;
+          let center = corner.as_vec3() + Vec3::splat(radius);
+
+          for z in 0..size {
+            for y in 0..size {
+              for x in 0..size {
+                let position = corner + IVec3::new(x, y, z);
+                let offset = position.as_vec3() + Vec3::splat(0.5) - center;
+
+                if offset.length() <= radius {
+                  self.place(block, origin, position);
+                }
+              }
+            }
+          }
+        }
+        OreType::Sheet => (),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{IVec3, Vec3};
+
+  use crate::game::map::{
+    map_block::{MapBlock, MAP_BLOCK_SIZE},
+    mapgen::noise::{NoiseParams, NoiseType},
+    node::Node,
+    node_def_manager::{new_test_node_def_manager, NodeDefManager, NodeDefinition},
+  };
+
+  use super::{Ore, OreDefinition, OreType};
+
+  fn new_node_def_manager() -> NodeDefManager {
+    new_test_node_def_manager(["test:stone", "test:coal", "test:iron"].map(NodeDefinition::new))
+  }
+
+  fn new_ore(definition: OreDefinition, node_def_manager: &NodeDefManager) -> Ore {
+    match Ore::new(definition, node_def_manager, 42) {
+      Ok(ore) => ore,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+
+  fn count(block: &MapBlock, content: u16) -> usize {
+    (0..MAP_BLOCK_SIZE.pow(3) as usize)
+      .filter(|index| block.get_node_at_index(*index).content == content)
+      .count()
+  }
+
+  #[test]
+  fn test_scatter_ore() {
+    let node_def_manager = new_node_def_manager();
+    let stone = node_def_manager.get_id("test:stone").unwrap_or(0);
+    let coal = node_def_manager.get_id("test:coal").unwrap_or(0);
+
+    let ore = new_ore(
+      OreDefinition::new(OreType::Scatter, "test:coal", "test:stone"),
+      &node_def_manager,
+    );
+
+    let mut block = MapBlock::new(Node::new(stone));
+    ore.generate(&mut block, IVec3::new(2, -3, 7));
+
+    // 8 clusters of 8 ores per block on average, some overlap or stick out.
+    let coal_count = count(&block, coal);
+    assert!(coal_count > 20 && coal_count < 150, "{}", coal_count);
+
+    // Same seed, same ore.
+    let mut same_block = MapBlock::new(Node::new(stone));
+    ore.generate(&mut same_block, IVec3::new(2, -3, 7));
+    assert_eq!(block, same_block);
+
+    // Air isn't in wherein.
+    let mut air_block = MapBlock::new(Node::air());
+    ore.generate(&mut air_block, IVec3::new(2, -3, 7));
+    assert_eq!(count(&air_block, coal), 0);
+  }
+
+  #[test]
+  fn test_blob_and_sheet_ore() {
+    let node_def_manager = new_node_def_manager();
+    let stone = node_def_manager.get_id("test:stone").unwrap_or(0);
+    let iron = node_def_manager.get_id("test:iron").unwrap_or(0);
+
+    let mut blob = OreDefinition::new(OreType::Blob, "test:iron", "test:stone");
+    blob.clust_size = 5;
+    blob.y_max = -100;
+    let blob = new_ore(blob, &node_def_manager);
+
+    // Out of the y range.
+    let mut block = MapBlock::new(Node::new(stone));
+    blob.generate(&mut block, IVec3::new(0, 0, 0));
+    assert_eq!(count(&block, iron), 0);
+
+    blob.generate(&mut block, IVec3::new(0, -20, 0));
+    assert!(count(&block, iron) > 0);
+
+    let mut sheet = OreDefinition::new(OreType::Sheet, "test:iron", "test:stone");
+    assert!(Ore::new(sheet.clone(), &node_def_manager, 1).is_err());
+    sheet.noise_params = Some(NoiseParams {
+      noise_type: NoiseType::Perlin,
+      offset: 1.0,
+      scale: 0.0,
+      spread: Vec3::splat(50.0),
+      seed: 1,
+      octaves: 1,
+      persistence: 0.5,
+      lacunarity: 2.0,
+    });
+    sheet.column_height = 2;
+    sheet.y_min = 4;
+    sheet.y_max = 8;
+    let sheet = new_ore(sheet, &node_def_manager);
+
+    // A full 2 node thick layer at y 5 and 6.
+    let mut block = MapBlock::new(Node::new(stone));
+    sheet.generate(&mut block, IVec3::ZERO);
+    assert_eq!(count(&block, iron), 2 * 16 * 16);
+    assert_eq!(block.get_node(IVec3::new(3, 5, 9)), Node::new(iron));
+    assert_eq!(block.get_node(IVec3::new(3, 7, 9)), Node::new(stone));
+  }
+}
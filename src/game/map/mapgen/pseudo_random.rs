@@ -0,0 +1,99 @@
+use glam::IVec3;
+
+///
+/// A tiny splitmix64 random number generator.
+///
+/// The mapgen can't use rand, the same seed has to make the same
+/// world no matter which version of rand we're built with.
+///
+#[derive(Clone, Debug)]
+pub struct PseudoRandom {
+  state: u64,
+}
+
+impl PseudoRandom {
+  pub fn new(seed: u64) -> Self {
+    PseudoRandom { state: seed }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    mix(self.state)
+  }
+
+  ///
+  /// Get a number from min to max, both included.
+  ///
+  pub fn range(&mut self, min: i32, max: i32) -> i32 {
+    if max <= min {
+      return min;
+    }
+    let span = (max as i64 - min as i64 + 1) as u64;
+    (min as i64 + (self.next_u64() % span) as i64) as i32
+  }
+
+  ///
+  /// Get a number from 0 up to, but not including, 1.
+  ///
+  pub fn next_f32(&mut self) -> f32 {
+    (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+  }
+}
+
+///
+/// The splitmix64 finalizer, scrambles every bit of the input.
+///
+fn mix(mut z: u64) -> u64 {
+  z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+  z ^ (z >> 31)
+}
+
+///
+/// Turn a seed and a position into a seed for that position.
+///
+/// This is how every MapBlock, column or cell gets it's own
+/// random numbers without caring what order things happen in.
+///
+pub fn hash_position(seed: u64, position: IVec3) -> u64 {
+  mix(
+    seed
+      ^ (position.x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+      ^ (position.y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+      ^ (position.z as u32 as u64).wrapping_mul(0x1656_67B1_9E37_79F9),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::IVec3;
+
+  use super::{hash_position, PseudoRandom};
+
+  #[test]
+  fn test_pseudo_random() {
+    let mut random = PseudoRandom::new(5);
+    let mut same = PseudoRandom::new(5);
+
+    for _ in 0..1000 {
+      let value = random.range(-3, 3);
+      assert_eq!(value, same.range(-3, 3));
+      assert!((-3..=3).contains(&value));
+
+      let fraction = random.next_f32();
+      assert_eq!(fraction, same.next_f32());
+      assert!((0.0..1.0).contains(&fraction));
+    }
+
+    assert_eq!(random.range(7, 2), 7);
+
+    assert_eq!(
+      hash_position(1, IVec3::new(1, 2, 3)),
+      hash_position(1, IVec3::new(1, 2, 3))
+    );
+    assert_ne!(
+      hash_position(1, IVec3::new(1, 2, 3)),
+      hash_position(1, IVec3::new(3, 2, 1))
+    );
+  }
+}
@@ -0,0 +1,547 @@
+pub mod mts;
+
+use std::{collections::HashMap, path::Path};
+
+use glam::IVec3;
+use serde::{Deserialize, Serialize};
+
+use crate::game::map::{
+  lighting::update_lighting,
+  node::{Node, CONTENT_AIR, CONTENT_IGNORE},
+  node_def_manager::NodeDefManager,
+  Map,
+};
+
+use super::pseudo_random::PseudoRandom;
+
+///
+/// The biggest a schematic can be along any axis.
+///
+pub const MAX_SCHEMATIC_SIZE: i32 = 64;
+
+///
+/// One node of a schematic.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SchematicNode {
+  pub name: String,
+  // Chance of being placed, 0 is never and 255 is always.
+  pub probability: u8,
+  pub param2: u8,
+  // Replace whatever is already there, not just air.
+  pub force_place: bool,
+}
+
+impl SchematicNode {
+  pub fn new(name: &str) -> Self {
+    SchematicNode {
+      name: name.to_string(),
+      probability: 255,
+      param2: 0,
+      force_place: false,
+    }
+  }
+}
+
+///
+/// Which way a schematic gets turned when it's placed, clockwise
+/// looking down from above.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+  None,
+  Clockwise90,
+  Clockwise180,
+  Clockwise270,
+}
+
+impl Rotation {
+  ///
+  /// Parse the rotation names minetest.place_schematic() takes,
+  /// "random" picks one with the PseudoRandom.
+  ///
+  pub fn from_name(name: &str, random: &mut PseudoRandom) -> Option<Self> {
+    match name {
+      "" | "0" => Some(Rotation::None),
+      "90" => Some(Rotation::Clockwise90),
+      "180" => Some(Rotation::Clockwise180),
+      "270" => Some(Rotation::Clockwise270),
+      "random" => Some(
+        [
+          Rotation::None,
+          Rotation::Clockwise90,
+          Rotation::Clockwise180,
+          Rotation::Clockwise270,
+        ][random.range(0, 3) as usize],
+      ),
+      _ => None,
+    }
+  }
+}
+
+///
+/// A little structure of nodes, like a tree, that can be stamped into the map.
+///
+/// Nodes are stored in z, y, x order, same as C++ minetest.
+///
+/// Schematics can be loaded from and saved to C++ minetest's .mts
+/// files, or JSON files with the same fields as this struct.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Schematic {
+  pub size: IVec3,
+  pub nodes: Vec<SchematicNode>,
+  // Chance of each layer being placed, bottom up. Empty places every layer.
+  #[serde(default)]
+  pub y_slice_probabilities: Vec<u8>,
+}
+
+impl Schematic {
+  ///
+  /// A schematic which doesn't place anything.
+  ///
+  pub fn new(size: IVec3) -> Self {
+    let mut air = SchematicNode::new("air");
+    air.probability = 0;
+
+    Schematic {
+      size,
+      nodes: vec![air; volume(size)],
+      y_slice_probabilities: vec![],
+    }
+  }
+
+  ///
+  /// Copy a box of the map into a schematic, corners included.
+  ///
+  /// Nodes in MapBlocks which don't exist yet are never placed.
+  ///
+  pub fn from_map(
+    map: &Map,
+    node_def_manager: &NodeDefManager,
+    corner_1: IVec3,
+    corner_2: IVec3,
+  ) -> Result<Self, String> {
+    let min = corner_1.min(corner_2);
+    let mut schematic = Schematic::new(corner_1.max(corner_2) - min + 1);
+    schematic.validate()?;
+
+    for z in 0..schematic.size.z {
+      for y in 0..schematic.size.y {
+        for x in 0..schematic.size.x {
+          let offset = IVec3::new(x, y, z);
+          let node = map.get_node_or_ignore(min + offset);
+          if node.content == CONTENT_IGNORE {
+            continue;
+          }
+
+          let index = schematic.get_index(offset);
+          schematic.nodes[index] = SchematicNode {
+            param2: node.param2,
+            ..SchematicNode::new(&node_def_manager.get_or_unknown(node.content).name)
+          };
+        }
+      }
+    }
+
+    Ok(schematic)
+  }
+
+  ///
+  /// Load a schematic from a .mts file, or a .json file.
+  ///
+  pub fn load_file(path: &str) -> Result<Self, String> {
+    let schematic = if is_json_path(path) {
+      let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return Err(format!("can't read schematic [{}]. {}", path, e)),
+      };
+      match serde_json::from_str::<Schematic>(&text) {
+        Ok(schematic) => schematic,
+        Err(e) => return Err(format!("schematic [{}] is broken. {}", path, e)),
+      }
+    } else {
+      let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(format!("can't read schematic [{}]. {}", path, e)),
+      };
+      match mts::read_mts(&bytes) {
+        Ok(schematic) => schematic,
+        Err(e) => return Err(format!("schematic [{}] is broken. {}", path, e)),
+      }
+    };
+
+    schematic.validate()?;
+    Ok(schematic)
+  }
+
+  ///
+  /// Save a schematic to a .mts file, or a .json file.
+  ///
+  pub fn save_file(&self, path: &str) -> Result<(), String> {
+    self.validate()?;
+
+    let bytes = if is_json_path(path) {
+      match serde_json::to_vec_pretty(self) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(format!("can't turn schematic into JSON. {}", e)),
+      }
+    } else {
+      mts::write_mts(self)?
+    };
+
+    match std::fs::write(path, bytes) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("can't write schematic [{}]. {}", path, e)),
+    }
+  }
+
+  ///
+  /// Get the index of a position inside the schematic.
+  ///
+  pub fn get_index(&self, position: IVec3) -> usize {
+    ((position.z * self.size.y + position.y) * self.size.x + position.x) as usize
+  }
+
+  ///
+  /// Check the schematic makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if self.size.min_element() < 1 || self.size.max_element() > MAX_SCHEMATIC_SIZE {
+      return Err(format!(
+        "schematic size {} must be between 1 and {}.",
+        self.size, MAX_SCHEMATIC_SIZE
+      ));
+    }
+
+    let volume = volume(self.size);
+    if self.nodes.len() != volume {
+      return Err(format!(
+        "schematic of size {} needs {} nodes, it has {}.",
+        self.size,
+        volume,
+        self.nodes.len()
+      ));
+    }
+
+    let slices = self.y_slice_probabilities.len();
+    if slices != 0 && slices != self.size.y as usize {
+      return Err(format!(
+        "schematic of size {} needs {} y slice probabilities, it has {}.",
+        self.size, self.size.y, slices
+      ));
+    }
+
+    Ok(())
+  }
+
+  ///
+  /// Get the chance of a layer being placed, 0 is never and 255 is always.
+  ///
+  pub fn get_y_slice_probability(&self, y: i32) -> u8 {
+    self
+      .y_slice_probabilities
+      .get(y as usize)
+      .copied()
+      .unwrap_or(255)
+  }
+
+  ///
+  /// Get the size of the schematic after it's been turned.
+  ///
+  pub fn get_rotated_size(&self, rotation: Rotation) -> IVec3 {
+    match rotation {
+      Rotation::None | Rotation::Clockwise180 => self.size,
+      Rotation::Clockwise90 | Rotation::Clockwise270 => {
+        IVec3::new(self.size.z, self.size.y, self.size.x)
+      }
+    }
+  }
+
+  ///
+  /// Get where a position inside the schematic ends up after it's been turned.
+  ///
+  pub fn rotate_position(&self, position: IVec3, rotation: Rotation) -> IVec3 {
+    let size = self.size;
+    match rotation {
+      Rotation::None => position,
+      Rotation::Clockwise90 => IVec3::new(position.z, position.y, size.x - 1 - position.x),
+      Rotation::Clockwise180 => {
+        IVec3::new(size.x - 1 - position.x, position.y, size.z - 1 - position.z)
+      }
+      Rotation::Clockwise270 => IVec3::new(size.z - 1 - position.z, position.y, position.x),
+    }
+  }
+
+  ///
+  /// Stamp the schematic into the map with it's smallest corner at position.
+  ///
+  /// Replacements swap node names for other ones, like {"default:tree" = "default:jungletree"}.
+  ///
+  /// Nodes only go over air, unless the node is force_place or force_placement is on.
+  /// Nodes landing in MapBlocks which don't exist yet are skipped.
+  /// The light around the schematic gets fixed up afterwards,
+  /// and every placed node is queued up for liquids to flow.
+  ///
+  /// Gives back how many nodes were placed.
+  ///
+  #[allow(clippy::too_many_arguments)]
+  pub fn place_on_map(
+    &self,
+    map: &mut Map,
+    node_def_manager: &NodeDefManager,
+    position: IVec3,
+    rotation: Rotation,
+    replacements: &HashMap<String, String>,
+    force_placement: bool,
+    random: &mut PseudoRandom,
+  ) -> Result<usize, String> {
+    self.validate()?;
+
+    let mut resolved = HashMap::new();
+    let mut changes = vec![];
+
+    for y in 0..self.size.y {
+      if random.range(0, 254) >= self.get_y_slice_probability(y) as i32 {
+        continue;
+      }
+
+      for z in 0..self.size.z {
+        for x in 0..self.size.x {
+          let offset = IVec3::new(x, y, z);
+          let schematic_node = &self.nodes[self.get_index(offset)];
+
+          if random.range(0, 254) >= schematic_node.probability as i32 {
+            continue;
+          }
+
+          let name = replacements
+            .get(&schematic_node.name)
+            .unwrap_or(&schematic_node.name);
+          let content = match resolved.get(name) {
+            Some(content) => *content,
+            None => match node_def_manager.get_id(name) {
+              Some(content) => {
+                resolved.insert(name.clone(), content);
+                content
+              }
+              None => {
+                return Err(format!(
+                  "schematic uses [{}], which isn't a registered node.",
+                  name
+                ))
+              }
+            },
+          };
+
+          let world_position = position + self.rotate_position(offset, rotation);
+          let force = force_placement || schematic_node.force_place;
+
+          match map.get_node(world_position) {
+            Some(node) if force || node.content == CONTENT_AIR => {
+              map.set_node(
+                world_position,
+                Node::new_with_params(content, 0, schematic_node.param2),
+              );
+              changes.push((world_position, node));
+            }
+            _ => (),
+          }
+        }
+      }
+    }
+
+    update_lighting(map, node_def_manager, &changes);
+    for (position, _) in &changes {
+      map.queue_liquid_update(*position);
+    }
+
+    Ok(changes.len())
+  }
+
+  ///
+  /// Turn every node name into a Node with a content id.
+  ///
+  pub fn resolve(&self, node_def_manager: &NodeDefManager) -> Result<Vec<Node>, String> {
+    self.validate()?;
+
+    self
+      .nodes
+      .iter()
+      .map(|node| match node_def_manager.get_id(&node.name) {
+        Some(id) => Ok(Node::new_with_params(id, 0, node.param2)),
+        None => Err(format!(
+          "schematic uses [{}], which isn't a registered node.",
+          node.name
+        )),
+      })
+      .collect()
+  }
+}
+
+///
+/// Schematics ending in .json are JSON, anything else is .mts.
+///
+fn is_json_path(path: &str) -> bool {
+  Path::new(path)
+    .extension()
+    .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+///
+/// How many nodes fit in a schematic of this size.
+///
+fn volume(size: IVec3) -> usize {
+  let size = size.max(IVec3::ZERO);
+  (size.x * size.y * size.z) as usize
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use glam::IVec3;
+
+  use crate::game::map::{
+    map_block::MapBlock,
+    mapgen::pseudo_random::PseudoRandom,
+    node::{Node, CONTENT_AIR},
+    node_def_manager::{new_test_node_def_manager, NodeDefManager, NodeDefinition},
+    Map,
+  };
+
+  use super::{Rotation, Schematic, SchematicNode};
+
+  fn new_node_def_manager() -> NodeDefManager {
+    new_test_node_def_manager(["test:log", "test:leaves", "test:stone"].map(NodeDefinition::new))
+  }
+
+  ///
+  /// A 3x1x2 schematic, a log at the smallest corner and leaves everywhere else.
+  ///
+  fn new_schematic() -> Schematic {
+    let mut schematic = Schematic::new(IVec3::new(3, 1, 2));
+    for node in schematic.nodes.iter_mut() {
+      *node = SchematicNode::new("test:leaves");
+    }
+    schematic.nodes[0] = SchematicNode::new("test:log");
+    schematic
+  }
+
+  #[test]
+  fn test_schematic_rotation() {
+    let schematic = new_schematic();
+    assert_eq!(
+      schematic.get_rotated_size(Rotation::Clockwise90),
+      IVec3::new(2, 1, 3)
+    );
+
+    // Every position lands inside of the turned size, and none land on each other.
+    for rotation in [
+      Rotation::None,
+      Rotation::Clockwise90,
+      Rotation::Clockwise180,
+      Rotation::Clockwise270,
+    ] {
+      let size = schematic.get_rotated_size(rotation);
+      let mut rotated = vec![];
+      for z in 0..2 {
+        for x in 0..3 {
+          let position = schematic.rotate_position(IVec3::new(x, 0, z), rotation);
+          assert!(position.min_element() >= 0 && (position - size).max_element() < 0);
+          rotated.push(position);
+        }
+      }
+      rotated.sort_by_key(|position| position.to_array());
+      rotated.dedup();
+      assert_eq!(rotated.len(), 6);
+    }
+
+    assert_eq!(
+      schematic.rotate_position(IVec3::ZERO, Rotation::Clockwise180),
+      IVec3::new(2, 0, 1)
+    );
+  }
+
+  #[test]
+  fn test_schematic_place_and_create() {
+    let node_def_manager = new_node_def_manager();
+    let get_id = |name: &str| node_def_manager.get_id(name).unwrap_or(CONTENT_AIR);
+    let mut random = PseudoRandom::new(1);
+
+    let mut map = Map::new();
+    map.insert_block(IVec3::ZERO, MapBlock::new(Node::air()));
+    let stone = Node::new(get_id("test:stone"));
+    map.set_node(IVec3::new(2, 5, 2), stone);
+
+    let replacements = HashMap::from([("test:leaves".to_string(), "test:stone".to_string())]);
+    // The stone already there stays, it's not forced.
+    let position = IVec3::new(1, 5, 1);
+    let placed = schematic_place(
+      &mut map,
+      &node_def_manager,
+      position,
+      &replacements,
+      &mut random,
+    );
+    assert_eq!(placed, 5);
+    assert_eq!(map.get_node(position), Some(Node::new(get_id("test:log"))));
+    assert_eq!(map.get_node(IVec3::new(3, 5, 2)), Some(stone));
+
+    // Copy it back out, and through a JSON file.
+    let copied = match Schematic::from_map(&map, &node_def_manager, IVec3::new(3, 5, 2), position) {
+      Ok(copied) => copied,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(copied.size, IVec3::new(3, 1, 2));
+    assert_eq!(copied.nodes[0].name, "test:log");
+    assert_eq!(copied.nodes[5].name, "test:stone");
+
+    let path = std::env::temp_dir().join(format!("schematic_test_{}.json", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+    let loaded = copied
+      .save_file(&path)
+      .and_then(|_| Schematic::load_file(&path));
+    let _ = std::fs::remove_file(&path);
+    match loaded {
+      Ok(loaded) => assert_eq!(loaded, copied),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    // Names have to exist.
+    let mut broken = new_schematic();
+    broken.nodes[1].name = "test:nothing".to_string();
+    assert!(broken
+      .place_on_map(
+        &mut map,
+        &node_def_manager,
+        position,
+        Rotation::None,
+        &HashMap::new(),
+        false,
+        &mut random,
+      )
+      .is_err());
+  }
+
+  fn schematic_place(
+    map: &mut Map,
+    node_def_manager: &NodeDefManager,
+    position: IVec3,
+    replacements: &HashMap<String, String>,
+    random: &mut PseudoRandom,
+  ) -> usize {
+    match new_schematic().place_on_map(
+      map,
+      node_def_manager,
+      position,
+      Rotation::None,
+      replacements,
+      false,
+      random,
+    ) {
+      Ok(placed) => placed,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+}
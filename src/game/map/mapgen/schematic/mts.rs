@@ -0,0 +1,300 @@
+///
+/// Reads and writes C++ minetest's .mts schematic files.
+///
+/// A .mts file looks like this, numbers are big endian:
+/// * "MTSM"
+/// * version - u16, 1 to 4
+/// * size - 3 u16s, x y z
+/// * y slice probabilities - u8 for every layer, version 3 and up
+/// * name count - u16
+/// * names - u16 length then the bytes, for every name
+/// * nodes - zlib compressed, every param0 as a u16 index into the names,
+///   then every param1, then every param2
+///
+/// In version 4 param1 is the probability from 0 to 127, with the top bit
+/// for force_place. Before that it was 0 to 255 with no force_place.
+///
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use glam::IVec3;
+
+use super::{volume, Schematic, SchematicNode};
+
+const MTS_SIGNATURE: &[u8; 4] = b"MTSM";
+const MTS_VERSION: u16 = 4;
+
+const PROBABILITY_MASK: u8 = 0x7F;
+const FORCE_PLACE: u8 = 0x80;
+
+///
+/// Walks through the bytes of a .mts file.
+///
+struct MtsReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> MtsReader<'a> {
+  fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+    if self.data.len() - self.position < count {
+      return Err("file ends too early.".to_string());
+    }
+
+    let bytes = &self.data[self.position..self.position + count];
+    self.position += count;
+    Ok(bytes)
+  }
+
+  fn read_u16(&mut self) -> Result<u16, String> {
+    let bytes = self.take(2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+  }
+
+  fn rest(&self) -> &'a [u8] {
+    &self.data[self.position..]
+  }
+}
+
+///
+/// Turn the bytes of a .mts file into a Schematic.
+///
+pub fn read_mts(data: &[u8]) -> Result<Schematic, String> {
+  let mut reader = MtsReader { data, position: 0 };
+
+  if reader.take(4)? != MTS_SIGNATURE {
+    return Err("not a .mts file, it doesn't start with MTSM.".to_string());
+  }
+
+  let version = reader.read_u16()?;
+  if !(1..=MTS_VERSION).contains(&version) {
+    return Err(format!("unsupported .mts version {}.", version));
+  }
+
+  let size = IVec3::new(
+    reader.read_u16()? as i32,
+    reader.read_u16()? as i32,
+    reader.read_u16()? as i32,
+  );
+  let mut schematic = Schematic::new(size);
+  schematic.validate()?;
+
+  if version >= 3 {
+    schematic.y_slice_probabilities = reader.take(size.y as usize)?.to_vec();
+  }
+
+  let mut names = vec![];
+  for _ in 0..reader.read_u16()? {
+    let length = reader.read_u16()? as usize;
+    match std::str::from_utf8(reader.take(length)?) {
+      Ok(name) => names.push(name.to_string()),
+      Err(e) => return Err(format!("node name isn't UTF-8. {}", e)),
+    }
+  }
+
+  let count = volume(size);
+  let mut bulk = vec![];
+  if let Err(e) = ZlibDecoder::new(reader.rest()).read_to_end(&mut bulk) {
+    return Err(format!("node data won't decompress. {}", e));
+  }
+  if bulk.len() < count * 4 {
+    return Err(format!(
+      "node data is {} bytes, it needs {}.",
+      bulk.len(),
+      count * 4
+    ));
+  }
+
+  for (index, node) in schematic.nodes.iter_mut().enumerate() {
+    let name_index = u16::from_be_bytes([bulk[index * 2], bulk[index * 2 + 1]]) as usize;
+    let name = match names.get(name_index) {
+      Some(name) => name,
+      None => {
+        return Err(format!(
+          "node uses name {} which doesn't exist.",
+          name_index
+        ))
+      }
+    };
+    let mut param1 = bulk[count * 2 + index];
+    let param2 = bulk[count * 3 + index];
+
+    if version < 4 {
+      param1 >>= 1;
+    }
+
+    // Version 1 used 0 to mean always.
+    if version == 1 && param1 == 0 {
+      param1 = PROBABILITY_MASK;
+    }
+
+    *node = SchematicNode {
+      probability: probability_from_param1(param1),
+      param2,
+      force_place: version >= 4 && param1 & FORCE_PLACE != 0,
+      ..SchematicNode::new(name)
+    };
+
+    // Ignore means leave whatever is there alone.
+    if node.name == "ignore" {
+      node.name = "air".to_string();
+      node.probability = 0;
+    }
+  }
+
+  Ok(schematic)
+}
+
+///
+/// Turn a Schematic into the bytes of a version 4 .mts file.
+///
+pub fn write_mts(schematic: &Schematic) -> Result<Vec<u8>, String> {
+  schematic.validate()?;
+
+  let mut data = MTS_SIGNATURE.to_vec();
+  data.extend_from_slice(&MTS_VERSION.to_be_bytes());
+  for axis in schematic.size.to_array() {
+    data.extend_from_slice(&(axis as u16).to_be_bytes());
+  }
+
+  for y in 0..schematic.size.y {
+    data.push(schematic.get_y_slice_probability(y));
+  }
+
+  // Every name once, in the order they show up.
+  let mut names: Vec<&str> = vec![];
+  let mut name_indices = vec![];
+  for node in &schematic.nodes {
+    let index = match names.iter().position(|name| *name == node.name) {
+      Some(index) => index,
+      None => {
+        names.push(&node.name);
+        names.len() - 1
+      }
+    };
+    name_indices.push(index as u16);
+  }
+
+  if names.len() > u16::MAX as usize {
+    return Err("schematic uses too many different nodes for .mts.".to_string());
+  }
+
+  data.extend_from_slice(&(names.len() as u16).to_be_bytes());
+  for name in names {
+    data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    data.extend_from_slice(name.as_bytes());
+  }
+
+  let mut bulk = vec![];
+  for index in name_indices {
+    bulk.extend_from_slice(&index.to_be_bytes());
+  }
+  for node in &schematic.nodes {
+    let force_place = if node.force_place { FORCE_PLACE } else { 0 };
+    bulk.push(node.probability >> 1 | force_place);
+  }
+  for node in &schematic.nodes {
+    bulk.push(node.param2);
+  }
+
+  let mut encoder = ZlibEncoder::new(data, Compression::default());
+  if let Err(e) = encoder.write_all(&bulk) {
+    return Err(format!("node data won't compress. {}", e));
+  }
+  match encoder.finish() {
+    Ok(data) => Ok(data),
+    Err(e) => Err(format!("node data won't compress. {}", e)),
+  }
+}
+
+///
+/// Turn the 0 to 127 probability of .mts back into 0 to 255.
+///
+fn probability_from_param1(param1: u8) -> u8 {
+  match param1 & PROBABILITY_MASK {
+    PROBABILITY_MASK => 255,
+    probability => probability << 1,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use flate2::{write::ZlibEncoder, Compression};
+  use glam::IVec3;
+
+  use super::{read_mts, write_mts, Schematic, SchematicNode};
+
+  #[test]
+  fn test_mts_round_trip() {
+    let mut schematic = Schematic::new(IVec3::new(2, 3, 1));
+    schematic.nodes = vec![
+      SchematicNode::new("test:tree"),
+      SchematicNode {
+        probability: 100,
+        param2: 3,
+        ..SchematicNode::new("test:leaves")
+      },
+      SchematicNode {
+        force_place: true,
+        ..SchematicNode::new("test:tree")
+      },
+      SchematicNode::new("air"),
+      SchematicNode::new("test:leaves"),
+      SchematicNode {
+        probability: 0,
+        ..SchematicNode::new("air")
+      },
+    ];
+    schematic.y_slice_probabilities = vec![255, 128, 0];
+
+    let data = match write_mts(&schematic) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(&data[0..6], b"MTSM\x00\x04");
+
+    match read_mts(&data) {
+      Ok(read) => assert_eq!(read, schematic),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    assert!(read_mts(b"MTSX\x00\x04").is_err());
+    assert!(read_mts(&data[0..20]).is_err());
+  }
+
+  #[test]
+  fn test_mts_version_3() {
+    // A 1x2x1 stick, with 0 to 255 probabilities and an ignore on top.
+    let mut data = b"MTSM\x00\x03\x00\x01\x00\x02\x00\x01".to_vec();
+    data.extend_from_slice(&[255, 255]);
+    data.extend_from_slice(b"\x00\x02\x00\x0Atest:stick\x00\x06ignore");
+
+    let mut encoder = ZlibEncoder::new(data, Compression::default());
+    let written = encoder.write_all(&[0, 0, 0, 1, 200, 255, 0, 0]);
+    let data = match written.and_then(|_| encoder.finish()) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    let schematic = match read_mts(&data) {
+      Ok(schematic) => schematic,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(schematic.size, IVec3::new(1, 2, 1));
+    assert_eq!(
+      schematic.nodes,
+      vec![
+        SchematicNode {
+          probability: 200,
+          ..SchematicNode::new("test:stick")
+        },
+        SchematicNode {
+          probability: 0,
+          ..SchematicNode::new("air")
+        },
+      ]
+    );
+  }
+}
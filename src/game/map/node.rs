@@ -0,0 +1,64 @@
+///
+/// The id of a registered node type.
+///
+/// These only mean something together with the name to id
+/// mapping they came from.
+///
+pub type ContentId = u16;
+
+// Same reserved ids as C++ minetest.
+pub const CONTENT_UNKNOWN: ContentId = 125;
+pub const CONTENT_AIR: ContentId = 126;
+pub const CONTENT_IGNORE: ContentId = 127;
+
+///
+/// A single node in the map.
+///
+/// * content - What kind of node this is.
+/// * param1 - Light. Day light in the low 4 bits, night light in the high 4.
+/// * param2 - Whatever the node type wants, usually rotation or facing.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Node {
+  pub content: ContentId,
+  pub param1: u8,
+  pub param2: u8,
+}
+
+impl Node {
+  pub fn new(content: ContentId) -> Self {
+    Node {
+      content,
+      param1: 0,
+      param2: 0,
+    }
+  }
+
+  pub fn new_with_params(content: ContentId, param1: u8, param2: u8) -> Self {
+    Node {
+      content,
+      param1,
+      param2,
+    }
+  }
+
+  ///
+  /// Air, the empty node.
+  ///
+  pub fn air() -> Self {
+    Self::new(CONTENT_AIR)
+  }
+
+  ///
+  /// Ignore, a node which is not loaded or generated yet.
+  ///
+  pub fn ignore() -> Self {
+    Self::new(CONTENT_IGNORE)
+  }
+}
+
+impl Default for Node {
+  fn default() -> Self {
+    Self::air()
+  }
+}
@@ -0,0 +1,617 @@
+use ahash::AHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::node::{ContentId, CONTENT_AIR, CONTENT_IGNORE, CONTENT_UNKNOWN};
+
+///
+/// The brightest a node can light itself up, same as C++ minetest.
+///
+pub const LIGHT_MAX: u8 = 14;
+
+///
+/// The most textures a node can have, one per side.
+///
+pub const MAX_TEXTURES: usize = 6;
+
+///
+/// How a node gets drawn.
+///
+/// Matches minetest.draw_type in api.lua.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawType {
+  Air = 0,
+  Regular = 1,
+  BlockBox = 2,
+  Mesh = 3,
+}
+
+impl DrawType {
+  pub fn from_number(number: i64) -> Option<Self> {
+    match number {
+      0 => Some(DrawType::Air),
+      1 => Some(DrawType::Regular),
+      2 => Some(DrawType::BlockBox),
+      3 => Some(DrawType::Mesh),
+      _ => None,
+    }
+  }
+}
+
+///
+/// The most nodes a liquid can flow away from it's source.
+///
+pub const LIQUID_RANGE_MAX: u8 = 8;
+
+///
+/// If a node is a liquid, and which part of one.
+///
+/// Matches liquidtype in Lua.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiquidType {
+  None,
+  Source,
+  Flowing,
+}
+
+impl LiquidType {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "none" => Some(LiquidType::None),
+      "source" => Some(LiquidType::Source),
+      "flowing" => Some(LiquidType::Flowing),
+      _ => None,
+    }
+  }
+}
+
+///
+/// Everything the engine knows about a type of node.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NodeDefinition {
+  pub name: String,
+  pub description: String,
+  pub drawtype: DrawType,
+  pub textures: Vec<String>,
+  pub walkable: bool,
+  pub light_source: u8,
+  // Light can go through it, paramtype = "light" in Lua.
+  pub light_propagates: bool,
+  // Sunlight goes straight down through it without fading.
+  pub sunlight_propagates: bool,
+  pub liquidtype: LiquidType,
+  // The other halves of a liquid, by name.
+  pub liquid_alternative_flowing: String,
+  pub liquid_alternative_source: String,
+  // How many nodes it flows away from a source.
+  pub liquid_range: u8,
+  // Two sources make a new one between them.
+  pub liquid_renewable: bool,
+  pub groups: Vec<(String, i64)>,
+}
+
+impl NodeDefinition {
+  ///
+  /// A definition with the defaults a mod would get.
+  ///
+  pub fn new(name: &str) -> Self {
+    NodeDefinition {
+      name: name.to_string(),
+      description: String::new(),
+      drawtype: DrawType::Regular,
+      textures: vec![],
+      walkable: true,
+      light_source: 0,
+      light_propagates: false,
+      sunlight_propagates: false,
+      liquidtype: LiquidType::None,
+      liquid_alternative_flowing: String::new(),
+      liquid_alternative_source: String::new(),
+      liquid_range: LIQUID_RANGE_MAX,
+      liquid_renewable: true,
+      groups: vec![],
+    }
+  }
+
+  ///
+  /// Get the rating of a group, 0 if it's not in it.
+  ///
+  pub fn get_group(&self, group: &str) -> i64 {
+    self
+      .groups
+      .iter()
+      .find(|(name, _)| name == group)
+      .map_or(0, |(_, rating)| *rating)
+  }
+
+  ///
+  /// Check the definition makes sense.
+  ///
+  pub fn validate(&self) -> Result<(), String> {
+    if !is_valid_node_name(&self.name) {
+      return Err(format!(
+        "[{}] is not a valid node name, it must look like [modname:nodename].",
+        self.name
+      ));
+    }
+
+    match self.drawtype {
+      DrawType::Air => (),
+      _ if self.textures.is_empty() || self.textures.len() > MAX_TEXTURES => {
+        return Err(format!(
+          "[{}] needs between 1 and {} textures, it has {}.",
+          self.name,
+          MAX_TEXTURES,
+          self.textures.len()
+        ))
+      }
+      _ => (),
+    }
+
+    if let Some(texture) = self.textures.iter().find(|texture| texture.is_empty()) {
+      return Err(format!(
+        "[{}] has an empty texture [{}].",
+        self.name, texture
+      ));
+    }
+
+    if self.light_source > LIGHT_MAX {
+      return Err(format!(
+        "[{}] has light_source {}, the max is {}.",
+        self.name, self.light_source, LIGHT_MAX
+      ));
+    }
+
+    if self.sunlight_propagates && !self.light_propagates {
+      return Err(format!(
+        "[{}] has sunlight_propagates, so it needs paramtype \"light\" too.",
+        self.name
+      ));
+    }
+
+    if self.liquidtype != LiquidType::None {
+      if !is_valid_node_name(&self.liquid_alternative_flowing)
+        || !is_valid_node_name(&self.liquid_alternative_source)
+      {
+        return Err(format!(
+          "[{}] is a liquid, it needs liquid_alternative_flowing and liquid_alternative_source.",
+          self.name
+        ));
+      }
+
+      if self.liquid_range > LIQUID_RANGE_MAX {
+        return Err(format!(
+          "[{}] has liquid_range {}, the max is {}.",
+          self.name, self.liquid_range, LIQUID_RANGE_MAX
+        ));
+      }
+    }
+
+    for (group, _) in &self.groups {
+      if group.is_empty() {
+        return Err(format!("[{}] has a group with no name.", self.name));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+///
+/// Check if a node name looks like "modname:nodename".
+///
+pub fn is_valid_node_name(name: &str) -> bool {
+  let valid_part = |part: &str| {
+    !part.is_empty()
+      && part
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+  };
+
+  match name.split_once(':') {
+    Some((mod_name, node_name)) => valid_part(mod_name) && valid_part(node_name),
+    None => false,
+  }
+}
+
+///
+/// The registry of every node type, and the ids they're stored with.
+///
+/// The engine nodes always have the same ids as in C++ minetest:
+/// * air - 126
+/// * ignore - 127
+/// * unknown - 125
+///
+/// Mod nodes get ids in name order, so the same set of
+/// mods always ends up with the same ids.
+///
+/// Aliases are extra names for a node, like "mapgen_stone".
+///
+pub struct NodeDefManager {
+  definitions: AHashMap<ContentId, NodeDefinition>,
+  name_to_id: AHashMap<String, ContentId>,
+  aliases: AHashMap<String, String>,
+  next_id: ContentId,
+}
+
+impl NodeDefManager {
+  pub fn new() -> Self {
+    let mut new_manager = NodeDefManager {
+      definitions: AHashMap::new(),
+      name_to_id: AHashMap::new(),
+      aliases: AHashMap::new(),
+      next_id: 0,
+    };
+
+    new_manager.register_engine_nodes();
+
+    new_manager
+  }
+
+  ///
+  /// Create a NodeDefManager from the definitions mods registered.
+  ///
+  pub fn from_definitions(mut definitions: Vec<NodeDefinition>) -> Result<Self, String> {
+    let mut new_manager = Self::new();
+
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for definition in definitions {
+      new_manager.register(definition)?;
+    }
+
+    Ok(new_manager)
+  }
+
+  fn register_engine_nodes(&mut self) {
+    let air = NodeDefinition {
+      description: "Air".to_string(),
+      drawtype: DrawType::Air,
+      walkable: false,
+      light_propagates: true,
+      sunlight_propagates: true,
+      ..NodeDefinition::new("air")
+    };
+
+    // Not loaded yet, nothing should ever touch it.
+    let ignore = NodeDefinition {
+      description: "Ignore".to_string(),
+      drawtype: DrawType::Air,
+      walkable: false,
+      ..NodeDefinition::new("ignore")
+    };
+
+    // A node from a mod which isn't loaded anymore.
+    let unknown = NodeDefinition {
+      description: "Unknown Node".to_string(),
+      textures: vec!["unknown_node.png".to_string()],
+      ..NodeDefinition::new("unknown")
+    };
+
+    for (id, definition) in [
+      (CONTENT_AIR, air),
+      (CONTENT_IGNORE, ignore),
+      (CONTENT_UNKNOWN, unknown),
+    ] {
+      self.name_to_id.insert(definition.name.clone(), id);
+      self.definitions.insert(id, definition);
+    }
+  }
+
+  fn is_reserved_id(id: ContentId) -> bool {
+    matches!(id, CONTENT_UNKNOWN | CONTENT_AIR | CONTENT_IGNORE)
+  }
+
+  ///
+  /// Register a node type and get the id it's stored with.
+  ///
+  pub fn register(&mut self, definition: NodeDefinition) -> Result<ContentId, String> {
+    definition.validate()?;
+
+    if self.name_to_id.contains_key(&definition.name) {
+      return Err(format!(
+        "[{}] is already a registered node.",
+        definition.name
+      ));
+    }
+
+    while Self::is_reserved_id(self.next_id) {
+      self.next_id += 1;
+    }
+
+    if self.next_id == ContentId::MAX {
+      return Err("NodeDefManager: ran out of content ids.".to_string());
+    }
+
+    let id = self.next_id;
+    self.next_id += 1;
+
+    self.name_to_id.insert(definition.name.clone(), id);
+    self.definitions.insert(id, definition);
+
+    Ok(id)
+  }
+
+  ///
+  /// Register another name for a node.
+  ///
+  pub fn register_alias(&mut self, alias: &str, original: &str) -> Result<(), String> {
+    if self.name_to_id.contains_key(alias) {
+      return Err(format!(
+        "[{}] is a registered node, it can't be an alias.",
+        alias
+      ));
+    }
+
+    if !self.name_to_id.contains_key(original) {
+      return Err(format!(
+        "alias [{}] points to [{}], which isn't a registered node.",
+        alias, original
+      ));
+    }
+
+    self.aliases.insert(alias.to_string(), original.to_string());
+
+    Ok(())
+  }
+
+  ///
+  /// Get the definition of a content id.
+  ///
+  pub fn get(&self, id: ContentId) -> Option<&NodeDefinition> {
+    self.definitions.get(&id)
+  }
+
+  ///
+  /// Get the definition of a content id, or unknown's if there is none.
+  ///
+  pub fn get_or_unknown(&self, id: ContentId) -> &NodeDefinition {
+    match self.definitions.get(&id) {
+      Some(definition) => definition,
+      None => match self.definitions.get(&CONTENT_UNKNOWN) {
+        Some(definition) => definition,
+        None => panic!("NodeDefManager: unknown was never registered."),
+      },
+    }
+  }
+
+  ///
+  /// Get the content id of a node name or alias.
+  ///
+  pub fn get_id(&self, name: &str) -> Option<ContentId> {
+    match self.name_to_id.get(name) {
+      Some(id) => Some(*id),
+      None => self
+        .aliases
+        .get(name)
+        .and_then(|original| self.name_to_id.get(original))
+        .copied(),
+    }
+  }
+
+  ///
+  /// Get the content ids of a node name, an alias, or every node
+  /// in a group when it's like "group:flammable".
+  ///
+  pub fn get_ids(&self, name: &str) -> Vec<ContentId> {
+    match name.strip_prefix("group:") {
+      Some(group) => {
+        let mut ids: Vec<ContentId> = self
+          .definitions
+          .iter()
+          .filter(|(_, definition)| definition.get_group(group) != 0)
+          .map(|(id, _)| *id)
+          .collect();
+        ids.sort();
+        ids
+      }
+      None => self.get_id(name).into_iter().collect(),
+    }
+  }
+
+  ///
+  /// Get how many node types are registered, engine ones included.
+  ///
+  pub fn get_count(&self) -> usize {
+    self.definitions.len()
+  }
+
+  ///
+  /// Get the definitions mods registered, sorted by name.
+  ///
+  /// Giving these to from_definitions makes a NodeDefManager
+  /// with the same ids, which is how clients get them.
+  ///
+  pub fn get_definitions(&self) -> Vec<NodeDefinition> {
+    let mut definitions: Vec<NodeDefinition> = self
+      .definitions
+      .iter()
+      .filter(|(id, _)| !Self::is_reserved_id(**id))
+      .map(|(_, definition)| definition.clone())
+      .collect();
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+    definitions
+  }
+
+  ///
+  /// Get every id and the name it belongs to, sorted by id.
+  ///
+  /// This is what gets saved with the map so ids can be matched
+  /// back up to names when it's loaded again.
+  ///
+  pub fn get_name_id_mapping(&self) -> Vec<(ContentId, String)> {
+    let mut mapping: Vec<(ContentId, String)> = self
+      .name_to_id
+      .iter()
+      .map(|(name, id)| (*id, name.clone()))
+      .collect();
+    mapping.sort();
+    mapping
+  }
+}
+
+impl Default for NodeDefManager {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+///
+/// Build a NodeDefManager for unit tests.
+///
+/// Definitions without textures get "test.png", so node names
+/// can be passed in with NodeDefinition::new.
+///
+#[cfg(test)]
+pub(crate) fn new_test_node_def_manager(
+  definitions: impl IntoIterator<Item = NodeDefinition>,
+) -> NodeDefManager {
+  let definitions = definitions
+    .into_iter()
+    .map(|mut definition| {
+      if definition.textures.is_empty() {
+        definition.textures = vec!["test.png".to_string()];
+      }
+      definition
+    })
+    .collect();
+
+  match NodeDefManager::from_definitions(definitions) {
+    Ok(node_def_manager) => node_def_manager,
+    Err(e) => panic!("Unit test is broken. {}", e),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::game::map::node::{CONTENT_AIR, CONTENT_IGNORE};
+
+  use super::{DrawType, LiquidType, NodeDefManager, NodeDefinition};
+
+  fn stone(name: &str) -> NodeDefinition {
+    NodeDefinition {
+      textures: vec!["default_stone.png".to_string()],
+      groups: vec![("cracky".to_string(), 3)],
+      ..NodeDefinition::new(name)
+    }
+  }
+
+  #[test]
+  fn test_node_def_manager() {
+    let definitions = vec![
+      stone("test:stone"),
+      stone("test:dirt"),
+      stone("other:grass"),
+    ];
+
+    let manager = match NodeDefManager::from_definitions(definitions.clone()) {
+      Ok(manager) => manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    // Same mods, same ids, no matter what order they registered in.
+    let mut reversed = definitions;
+    reversed.reverse();
+    let other_manager = match NodeDefManager::from_definitions(reversed) {
+      Ok(manager) => manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(
+      manager.get_name_id_mapping(),
+      other_manager.get_name_id_mapping()
+    );
+
+    // Handing the definitions to a client gives it the same ids too.
+    let client_manager = match NodeDefManager::from_definitions(manager.get_definitions()) {
+      Ok(manager) => manager,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(manager.get_definitions().len(), 3);
+    assert_eq!(
+      manager.get_name_id_mapping(),
+      client_manager.get_name_id_mapping()
+    );
+
+    assert_eq!(manager.get_id("air"), Some(CONTENT_AIR));
+    assert_eq!(manager.get_id("ignore"), Some(CONTENT_IGNORE));
+    assert_eq!(manager.get_id("other:grass"), Some(0));
+    assert_eq!(manager.get_count(), 6);
+
+    let dirt = manager.get_or_unknown(manager.get_id("test:dirt").unwrap_or(0));
+    assert_eq!(dirt.name, "test:dirt");
+    assert_eq!(dirt.get_group("cracky"), 3);
+    assert_eq!(dirt.get_group("crumbly"), 0);
+    assert_eq!(manager.get_or_unknown(5000).name, "unknown");
+
+    // Groups hold every node in them, engine nodes are in none.
+    assert_eq!(manager.get_ids("group:cracky"), vec![0, 1, 2]);
+    assert!(manager.get_ids("group:crumbly").is_empty());
+    assert_eq!(manager.get_ids("air"), vec![CONTENT_AIR]);
+    assert!(manager.get_ids("test:nothing").is_empty());
+  }
+
+  #[test]
+  fn test_node_def_manager_ids_skip_reserved() {
+    let mut manager = NodeDefManager::new();
+
+    for i in 0..130 {
+      let id = match manager.register(stone(&format!("test:node_{}", i))) {
+        Ok(id) => id,
+        Err(e) => panic!("Unit test is broken. {}", e),
+      };
+      assert!(!(125..=127).contains(&id));
+    }
+  }
+
+  #[test]
+  fn test_node_definition_validation() {
+    let mut manager = NodeDefManager::new();
+
+    assert!(manager.register(stone("test:stone")).is_ok());
+    assert!(manager.register(stone("test:stone")).is_err());
+    assert!(manager.register(stone("no_mod_name")).is_err());
+    assert!(manager.register(stone("test:Capital")).is_err());
+
+    let no_textures = NodeDefinition::new("test:no_textures");
+    assert!(manager.register(no_textures).is_err());
+
+    let mut too_bright = stone("test:too_bright");
+    too_bright.light_source = 15;
+    assert!(manager.register(too_bright).is_err());
+
+    let mut lonely_liquid = stone("test:lonely_liquid");
+    lonely_liquid.liquidtype = LiquidType::Source;
+    assert!(manager.register(lonely_liquid).is_err());
+
+    let mut sunlight_only = stone("test:sunlight_only");
+    sunlight_only.sunlight_propagates = true;
+    assert!(manager.register(sunlight_only).is_err());
+
+    let mut invisible = NodeDefinition::new("test:invisible");
+    invisible.drawtype = DrawType::Air;
+    assert!(manager.register(invisible).is_ok());
+  }
+
+  #[test]
+  fn test_node_aliases() {
+    let mut manager = NodeDefManager::new();
+    let stone_id = match manager.register(stone("test:stone")) {
+      Ok(id) => id,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    assert!(manager.register_alias("mapgen_stone", "test:stone").is_ok());
+    assert_eq!(manager.get_id("mapgen_stone"), Some(stone_id));
+
+    assert!(manager.register_alias("mapgen_dirt", "test:dirt").is_err());
+    assert!(manager.register_alias("test:stone", "air").is_err());
+
+    // Aliases aren't saved with the map.
+    assert!(manager
+      .get_name_id_mapping()
+      .iter()
+      .all(|(_, name)| name != "mapgen_stone"));
+  }
+}
@@ -0,0 +1,266 @@
+///
+/// A fixed size array which stores each different value only once.
+///
+/// Every entry is an index into a palette of values, and the indices
+/// are bit packed as tightly as the palette size allows. An array
+/// where every entry is the same doesn't store any indices at all.
+///
+/// Indices never straddle two words, so a few bits in
+/// each word can go unused.
+///
+#[derive(Clone, Debug)]
+pub struct PaletteArray<T: Copy + PartialEq> {
+  length: usize,
+  palette: Vec<T>,
+  bits_per_index: u32,
+  words: Vec<u64>,
+}
+
+impl<T: Copy + PartialEq> PaletteArray<T> {
+  ///
+  /// Create an array where every entry is the same value.
+  ///
+  pub fn new(length: usize, value: T) -> Self {
+    PaletteArray {
+      length,
+      palette: vec![value],
+      bits_per_index: 0,
+      words: vec![],
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.length
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.length == 0
+  }
+
+  ///
+  /// Check if every entry is the same value.
+  ///
+  /// This is only guaranteed to be right after compact().
+  ///
+  pub fn is_uniform(&self) -> bool {
+    self.palette.len() == 1
+  }
+
+  ///
+  /// Get the different values in the array.
+  ///
+  pub fn get_palette(&self) -> &[T] {
+    &self.palette
+  }
+
+  ///
+  /// Get roughly how many bytes this array takes up.
+  ///
+  pub fn get_memory_usage(&self) -> usize {
+    std::mem::size_of::<Self>()
+      + self.palette.capacity() * std::mem::size_of::<T>()
+      + self.words.capacity() * std::mem::size_of::<u64>()
+  }
+
+  fn entries_per_word(&self) -> usize {
+    (u64::BITS / self.bits_per_index) as usize
+  }
+
+  fn get_index(&self, position: usize) -> usize {
+    if self.bits_per_index == 0 {
+      return 0;
+    }
+
+    let entries_per_word = self.entries_per_word();
+    let word = self.words[position / entries_per_word];
+    let shift = (position % entries_per_word) as u32 * self.bits_per_index;
+    let mask = (1_u64 << self.bits_per_index) - 1;
+
+    ((word >> shift) & mask) as usize
+  }
+
+  fn set_index(&mut self, position: usize, index: usize) {
+    let entries_per_word = self.entries_per_word();
+    let word = &mut self.words[position / entries_per_word];
+    let shift = (position % entries_per_word) as u32 * self.bits_per_index;
+    let mask = (1_u64 << self.bits_per_index) - 1;
+
+    *word = (*word & !(mask << shift)) | ((index as u64 & mask) << shift);
+  }
+
+  ///
+  /// Repack every index with a new bit width.
+  ///
+  fn resize_indices(&mut self, bits_per_index: u32) {
+    let indices: Vec<usize> = (0..self.length).map(|i| self.get_index(i)).collect();
+
+    self.bits_per_index = bits_per_index;
+
+    if bits_per_index == 0 {
+      self.words = vec![];
+      return;
+    }
+
+    let entries_per_word = self.entries_per_word();
+    self.words = vec![0; self.length.div_ceil(entries_per_word)];
+
+    for (position, index) in indices.into_iter().enumerate() {
+      self.set_index(position, index);
+    }
+  }
+
+  ///
+  /// Get the value at a position.
+  ///
+  pub fn get(&self, position: usize) -> T {
+    if position >= self.length {
+      panic!(
+        "PaletteArray: position {} is out of bounds ({}).",
+        position, self.length
+      );
+    }
+
+    self.palette[self.get_index(position)]
+  }
+
+  ///
+  /// Set the value at a position.
+  ///
+  pub fn set(&mut self, position: usize, value: T) {
+    if position >= self.length {
+      panic!(
+        "PaletteArray: position {} is out of bounds ({}).",
+        position, self.length
+      );
+    }
+
+    let index = match self.palette.iter().position(|existing| *existing == value) {
+      Some(index) => index,
+      None => {
+        self.palette.push(value);
+        let needed_bits = bits_needed(self.palette.len());
+        if needed_bits > self.bits_per_index {
+          self.resize_indices(needed_bits);
+        }
+        self.palette.len() - 1
+      }
+    };
+
+    if self.bits_per_index > 0 {
+      self.set_index(position, index);
+    }
+  }
+
+  ///
+  /// Set every entry to the same value.
+  ///
+  pub fn fill(&mut self, value: T) {
+    self.palette = vec![value];
+    self.bits_per_index = 0;
+    self.words = vec![];
+  }
+
+  ///
+  /// Drop palette values which aren't used anymore, and
+  /// shrink the indices to match.
+  ///
+  pub fn compact(&mut self) {
+    let mut used = vec![false; self.palette.len()];
+    for position in 0..self.length {
+      used[self.get_index(position)] = true;
+    }
+
+    if used.iter().all(|used| *used) {
+      return;
+    }
+
+    // Where every old index ends up.
+    let mut remap = vec![0; self.palette.len()];
+    let mut new_palette = vec![];
+    for (old_index, value) in self.palette.iter().enumerate() {
+      if used[old_index] {
+        remap[old_index] = new_palette.len();
+        new_palette.push(*value);
+      }
+    }
+
+    let indices: Vec<usize> = (0..self.length)
+      .map(|position| remap[self.get_index(position)])
+      .collect();
+
+    self.palette = new_palette;
+    self.bits_per_index = 0;
+    self.words = vec![];
+
+    let bits_per_index = bits_needed(self.palette.len());
+    if bits_per_index > 0 {
+      self.resize_indices(bits_per_index);
+      for (position, index) in indices.into_iter().enumerate() {
+        self.set_index(position, index);
+      }
+    }
+  }
+}
+
+impl<T: Copy + PartialEq> PartialEq for PaletteArray<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.length == other.length && (0..self.length).all(|i| self.get(i) == other.get(i))
+  }
+}
+
+///
+/// How many bits it takes to index a palette of a size.
+///
+fn bits_needed(palette_size: usize) -> u32 {
+  match palette_size {
+    0 | 1 => 0,
+    size => usize::BITS - (size - 1).leading_zeros(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use super::{bits_needed, PaletteArray};
+
+  #[test]
+  fn test_bits_needed() {
+    assert_eq!(bits_needed(1), 0);
+    assert_eq!(bits_needed(2), 1);
+    assert_eq!(bits_needed(3), 2);
+    assert_eq!(bits_needed(16), 4);
+    assert_eq!(bits_needed(17), 5);
+    assert_eq!(bits_needed(4096), 12);
+  }
+
+  #[test]
+  fn test_palette_array() {
+    let mut array = PaletteArray::new(4096, 0_u16);
+    let mut expected = vec![0_u16; 4096];
+    let mut random = StdRng::seed_from_u64(5318008);
+
+    // Grow through every bit width.
+    for palette_size in [2, 3, 17, 300, 4096] {
+      for _ in 0..4096 {
+        let position = random.gen_range(0..4096);
+        let value = random.gen_range(0..palette_size);
+        array.set(position, value);
+        expected[position] = value;
+      }
+
+      for (position, value) in expected.iter().enumerate() {
+        assert_eq!(array.get(position), *value);
+      }
+    }
+
+    // Compacting doesn't change anything but the size.
+    array.fill(7);
+    array.set(10, 8);
+    array.set(10, 7);
+    assert!(!array.is_uniform());
+    array.compact();
+    assert!(array.is_uniform());
+    assert!((0..4096).all(|i| array.get(i) == 7));
+  }
+}
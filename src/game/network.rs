@@ -0,0 +1,37 @@
+pub mod channel_layer;
+pub mod loopback_transport;
+pub mod split_packet;
+pub mod udp_transport;
+
+///
+/// The engine side ID of a remote peer.
+///
+/// Transports hand these out so the ServerConnection and ClientConnection
+/// never have to care about what is actually carrying the data.
+///
+pub type PeerId = u64;
+
+///
+/// Something that can carry raw bytes between peers.
+///
+/// This is what lets a ServerConnection and ClientConnection talk to each
+/// other over UDP, or directly through memory in singleplayer.
+///
+pub trait NetworkTransport {
+  ///
+  /// Send raw data to a peer.
+  ///
+  fn send(&mut self, peer_id: PeerId, data: &[u8]);
+
+  ///
+  /// Non-blocking receiver for raw data.
+  ///
+  /// Returns None when there is nothing left to receive this tick.
+  ///
+  fn receive(&mut self) -> Option<(PeerId, Vec<u8>)>;
+
+  ///
+  /// Get a readable address of a peer for logging.
+  ///
+  fn peer_address(&self, peer_id: PeerId) -> String;
+}
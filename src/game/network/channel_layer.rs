@@ -0,0 +1,824 @@
+use std::collections::VecDeque;
+
+use ahash::AHashMap;
+
+use super::{
+  split_packet::{PacketAssembler, PacketSplitter, MTU},
+  NetworkTransport, PeerId,
+};
+
+///
+/// How many independent channels each peer has.
+///
+/// Ordering is only guaranteed inside of a channel, so a huge pile
+/// of map data can't hold up a chat message.
+///
+pub const CHANNEL_COUNT: usize = 3;
+
+///
+/// How many reliable frames can be in flight on a channel at once.
+///
+/// Anything further ahead than this on the receiving side gets dropped
+/// and has to be resent. This keeps a peer from filling our memory.
+///
+pub const RELIABLE_WINDOW_SIZE: u16 = 1024;
+
+///
+/// How many reliable frames can wait behind the window on a channel.
+///
+/// This fits the biggest split payload with room to spare. Past this
+/// the peer isn't keeping up, so anything new gets refused.
+///
+pub const MAX_WAITING_FRAMES: usize = 16384;
+
+///
+/// How many times a frame gets resent before the peer counts as gone.
+///
+/// With the backoff that's close to a minute without a single ack.
+///
+pub const MAX_RESENDS: u32 = 20;
+
+// Frame types.
+const FRAME_UNRELIABLE: u8 = 0;
+const FRAME_RELIABLE: u8 = 1;
+const FRAME_ACK: u8 = 2;
+
+// type + channel
+const UNRELIABLE_HEADER_SIZE: usize = 2;
+// type + channel + sequence number
+const RELIABLE_HEADER_SIZE: usize = 4;
+
+// Resend timing in seconds.
+const INITIAL_RESEND_TIMEOUT: f64 = 0.5;
+const MIN_RESEND_TIMEOUT: f64 = 0.1;
+const MAX_RESEND_TIMEOUT: f64 = 3.0;
+
+///
+/// Get how far ahead (positive) or behind (negative) a sequence
+/// number is from the base. Handles wrapping around u16::MAX.
+///
+fn sequence_offset(sequence: u16, base: u16) -> i16 {
+  sequence.wrapping_sub(base) as i16
+}
+
+///
+/// Build a raw frame that's ready to be handed to the transport.
+///
+fn build_frame(frame_type: u8, channel_id: u8, sequence: Option<u16>, payload: &[u8]) -> Vec<u8> {
+  let mut frame = Vec::with_capacity(RELIABLE_HEADER_SIZE + payload.len());
+  frame.push(frame_type);
+  frame.push(channel_id);
+  if let Some(sequence) = sequence {
+    frame.extend_from_slice(&sequence.to_le_bytes());
+  }
+  frame.extend_from_slice(payload);
+  frame
+}
+
+///
+/// A reliable frame which was sent but has not been acknowledged yet.
+///
+struct InFlightFrame {
+  sequence: u16,
+  frame: Vec<u8>,
+  time_since_sent: f64,
+  resend_count: u32,
+}
+
+///
+/// One ordered stream of data between two peers.
+///
+/// Works just like the reliable channels in C++ minetest:
+/// sequence numbers, acknowledgements, resending, ordering and
+/// duplicate suppression.
+///
+struct Channel {
+  id: u8,
+
+  // Outgoing.
+  next_outgoing_sequence: u16,
+  in_flight: VecDeque<InFlightFrame>,
+  waiting: VecDeque<Vec<u8>>,
+
+  // Incoming.
+  next_incoming_sequence: u16,
+  out_of_order: AHashMap<u16, Vec<u8>>,
+}
+
+impl Channel {
+  fn new(id: u8) -> Self {
+    Channel {
+      id,
+
+      next_outgoing_sequence: 0,
+      in_flight: VecDeque::new(),
+      waiting: VecDeque::new(),
+
+      next_incoming_sequence: 0,
+      out_of_order: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Check if this many more reliable payloads fit in the queue.
+  ///
+  fn has_room(&self, count: usize) -> bool {
+    self.waiting.len() + count <= MAX_WAITING_FRAMES
+  }
+
+  ///
+  /// Queue up a reliable payload.
+  ///
+  /// Returns the frames which can go out right now.
+  ///
+  fn queue_reliable(&mut self, payload: &[u8]) -> Vec<Vec<u8>> {
+    self.waiting.push_back(payload.to_vec());
+    self.fill_window()
+  }
+
+  ///
+  /// Move waiting payloads into flight while the window has room.
+  ///
+  fn fill_window(&mut self) -> Vec<Vec<u8>> {
+    let mut frames = vec![];
+
+    while self.in_flight.len() < RELIABLE_WINDOW_SIZE as usize {
+      let payload = match self.waiting.pop_front() {
+        Some(payload) => payload,
+        None => break,
+      };
+
+      let sequence = self.next_outgoing_sequence;
+      self.next_outgoing_sequence = self.next_outgoing_sequence.wrapping_add(1);
+
+      let frame = build_frame(FRAME_RELIABLE, self.id, Some(sequence), &payload);
+      frames.push(frame.clone());
+
+      self.in_flight.push_back(InFlightFrame {
+        sequence,
+        frame,
+        time_since_sent: 0.0,
+        resend_count: 0,
+      });
+    }
+
+    frames
+  }
+
+  ///
+  /// The other side got one of our reliable frames.
+  ///
+  /// Returns a round trip time sample if the frame was never resent,
+  /// since we can't know which copy a resent frame's ack belongs to.
+  ///
+  fn acknowledge(&mut self, sequence: u16) -> Option<f64> {
+    let index = self
+      .in_flight
+      .iter()
+      .position(|in_flight| in_flight.sequence == sequence)?;
+
+    let acked = self.in_flight.remove(index)?;
+
+    match acked.resend_count {
+      0 => Some(acked.time_since_sent),
+      _ => None,
+    }
+  }
+
+  ///
+  /// Take in a reliable frame.
+  ///
+  /// Returns if the frame should be acknowledged, and the payloads
+  /// which are now ready to be delivered in order.
+  ///
+  fn receive_reliable(&mut self, sequence: u16, payload: Vec<u8>) -> (bool, Vec<Vec<u8>>) {
+    let offset = sequence_offset(sequence, self.next_incoming_sequence);
+
+    // Already delivered, the ack probably got lost. Ack it again.
+    if offset < 0 {
+      return (true, vec![]);
+    }
+
+    // Way too far ahead. Don't ack it so it gets resent later.
+    if offset >= RELIABLE_WINDOW_SIZE as i16 {
+      return (false, vec![]);
+    }
+
+    // Duplicates of frames waiting on a gap get ignored.
+    self.out_of_order.entry(sequence).or_insert(payload);
+
+    let mut ready = vec![];
+    while let Some(payload) = self.out_of_order.remove(&self.next_incoming_sequence) {
+      ready.push(payload);
+      self.next_incoming_sequence = self.next_incoming_sequence.wrapping_add(1);
+    }
+
+    (true, ready)
+  }
+
+  ///
+  /// Tick the resend timers.
+  ///
+  /// Returns the frames which are due to be resent.
+  /// Each resend of the same frame waits twice as long as the last.
+  ///
+  fn resend(&mut self, delta: f64, resend_timeout: f64) -> Vec<Vec<u8>> {
+    let mut frames = vec![];
+
+    for in_flight in self.in_flight.iter_mut() {
+      in_flight.time_since_sent += delta;
+
+      let backoff = 2.0_f64.powi(in_flight.resend_count.min(8) as i32);
+      let timeout = (resend_timeout * backoff).min(MAX_RESEND_TIMEOUT);
+
+      if in_flight.time_since_sent >= timeout {
+        in_flight.time_since_sent = 0.0;
+        in_flight.resend_count += 1;
+        frames.push(in_flight.frame.clone());
+      }
+    }
+
+    frames
+  }
+
+  ///
+  /// Check if a frame was resent so many times the peer must be gone.
+  ///
+  fn has_given_up(&self) -> bool {
+    self
+      .in_flight
+      .iter()
+      .any(|in_flight| in_flight.resend_count >= MAX_RESENDS)
+  }
+
+  ///
+  /// Check if everything sent on this channel has been acknowledged.
+  ///
+  fn is_idle(&self) -> bool {
+    self.in_flight.is_empty() && self.waiting.is_empty()
+  }
+}
+
+///
+/// All the channels to a single peer, along with it's round trip time.
+///
+pub struct PeerChannels {
+  channels: Vec<Channel>,
+  round_trip_time: Option<f64>,
+}
+
+impl PeerChannels {
+  fn new() -> Self {
+    PeerChannels {
+      channels: (0..CHANNEL_COUNT as u8).map(Channel::new).collect(),
+      round_trip_time: None,
+    }
+  }
+
+  ///
+  /// Get the smoothed round trip time to this peer in seconds.
+  ///
+  /// Returns None if no reliable frame has been acknowledged yet.
+  ///
+  pub fn get_round_trip_time(&self) -> Option<f64> {
+    self.round_trip_time
+  }
+
+  ///
+  /// Mix a new round trip time sample into the estimate.
+  ///
+  fn add_round_trip_sample(&mut self, sample: f64) {
+    self.round_trip_time = match self.round_trip_time {
+      Some(round_trip_time) => Some(round_trip_time * 0.875 + sample * 0.125),
+      None => Some(sample),
+    };
+  }
+
+  ///
+  /// How long to wait for an ack before resending.
+  ///
+  fn get_resend_timeout(&self) -> f64 {
+    match self.round_trip_time {
+      Some(round_trip_time) => {
+        (round_trip_time * 2.0).clamp(MIN_RESEND_TIMEOUT, MAX_RESEND_TIMEOUT)
+      }
+      None => INITIAL_RESEND_TIMEOUT,
+    }
+  }
+}
+
+///
+/// The reliable UDP layer.
+///
+/// This sits between a connection and it's NetworkTransport. Data can
+/// be sent reliably (resent until acknowledged, delivered once and in order)
+/// or unreliably (fire and forget) on any of the channels.
+///
+/// Data bigger than the MTU is split up before it goes out
+/// and put back together before it's handed back.
+///
+pub struct ChannelLayer {
+  transport: Box<dyn NetworkTransport>,
+  peers: AHashMap<PeerId, PeerChannels>,
+  ready: VecDeque<(PeerId, Vec<u8>)>,
+  timed_out_peers: Vec<PeerId>,
+
+  splitter: PacketSplitter,
+  assembler: PacketAssembler,
+}
+
+impl ChannelLayer {
+  pub fn new(transport: Box<dyn NetworkTransport>) -> Self {
+    ChannelLayer {
+      transport,
+      peers: AHashMap::new(),
+      ready: VecDeque::new(),
+      timed_out_peers: vec![],
+
+      splitter: PacketSplitter::new(),
+      assembler: PacketAssembler::new(),
+    }
+  }
+
+  ///
+  /// Get the channels of a peer, creating them if this is a new peer.
+  ///
+  fn get_peer(&mut self, peer_id: PeerId) -> &mut PeerChannels {
+    self.peers.entry(peer_id).or_insert_with(PeerChannels::new)
+  }
+
+  ///
+  /// Send data to a peer on a channel.
+  ///
+  /// Reliable data is refused while the peer has too much waiting on
+  /// the channel, nothing of it goes out then. So is data too big to split.
+  ///
+  pub fn send(
+    &mut self,
+    peer_id: PeerId,
+    channel_id: u8,
+    reliable: bool,
+    payload: &[u8],
+  ) -> Result<(), String> {
+    if channel_id as usize >= CHANNEL_COUNT {
+      panic!(
+        "ChannelLayer: tried to send on channel [{}] which does not exist.",
+        channel_id
+      );
+    }
+
+    let chunks = self.splitter.split(payload, MTU - RELIABLE_HEADER_SIZE)?;
+
+    if !reliable {
+      for chunk in chunks {
+        let frame = build_frame(FRAME_UNRELIABLE, channel_id, None, &chunk);
+        self.transport.send(peer_id, &frame);
+      }
+      return Ok(());
+    }
+
+    let channel = &mut self.get_peer(peer_id).channels[channel_id as usize];
+    if !channel.has_room(chunks.len()) {
+      return Err(format!(
+        "peer [{}] has too much waiting on channel [{}].",
+        peer_id, channel_id
+      ));
+    }
+
+    let mut frames = vec![];
+    for chunk in chunks {
+      frames.extend(channel.queue_reliable(&chunk));
+    }
+    for frame in frames {
+      self.transport.send(peer_id, &frame);
+    }
+
+    Ok(())
+  }
+
+  ///
+  /// Process a raw frame which came out of the transport.
+  ///
+  fn process_frame(&mut self, peer_id: PeerId, frame: Vec<u8>) {
+    if frame.len() < UNRELIABLE_HEADER_SIZE {
+      println!(
+        "ChannelLayer: peer [{}] sent a frame with no header.",
+        peer_id
+      );
+      return;
+    }
+
+    let frame_type = frame[0];
+    let channel_id = frame[1];
+
+    if channel_id as usize >= CHANNEL_COUNT {
+      println!(
+        "ChannelLayer: peer [{}] sent a frame on channel [{}] which does not exist.",
+        peer_id, channel_id
+      );
+      return;
+    }
+
+    if frame_type == FRAME_UNRELIABLE {
+      self
+        .ready
+        .push_back((peer_id, frame[UNRELIABLE_HEADER_SIZE..].to_vec()));
+      return;
+    }
+
+    if frame.len() < RELIABLE_HEADER_SIZE {
+      println!(
+        "ChannelLayer: peer [{}] sent a frame with no sequence number.",
+        peer_id
+      );
+      return;
+    }
+
+    let sequence = u16::from_le_bytes([frame[2], frame[3]]);
+
+    match frame_type {
+      FRAME_RELIABLE => {
+        let payload = frame[RELIABLE_HEADER_SIZE..].to_vec();
+        let (should_ack, ready) =
+          self.get_peer(peer_id).channels[channel_id as usize].receive_reliable(sequence, payload);
+
+        if should_ack {
+          let ack = build_frame(FRAME_ACK, channel_id, Some(sequence), &[]);
+          self.transport.send(peer_id, &ack);
+        }
+
+        for payload in ready {
+          self.ready.push_back((peer_id, payload));
+        }
+      }
+      FRAME_ACK => {
+        // We never sent anything to it, or already forgot about it.
+        let peer = match self.peers.get_mut(&peer_id) {
+          Some(peer) => peer,
+          None => return,
+        };
+        let channel = &mut peer.channels[channel_id as usize];
+
+        if let Some(sample) = channel.acknowledge(sequence) {
+          peer.add_round_trip_sample(sample);
+        }
+
+        // The window might have room for more now.
+        let frames = peer.channels[channel_id as usize].fill_window();
+        for frame in frames {
+          self.transport.send(peer_id, &frame);
+        }
+      }
+      _ => println!(
+        "ChannelLayer: peer [{}] sent unknown frame type [{}].",
+        peer_id, frame_type
+      ),
+    }
+  }
+
+  ///
+  /// Non-blocking receiver.
+  ///
+  /// Returns the next payload which is ready to be used.
+  ///
+  pub fn receive(&mut self) -> Option<(PeerId, Vec<u8>)> {
+    loop {
+      while self.ready.is_empty() {
+        let (peer_id, frame) = self.transport.receive()?;
+        self.process_frame(peer_id, frame);
+      }
+
+      let (peer_id, chunk) = self.ready.pop_front()?;
+
+      match self.assembler.insert(peer_id, chunk) {
+        Ok(Some(payload)) => return Some((peer_id, payload)),
+        Ok(None) => (),
+        Err(e) => println!(
+          "ChannelLayer: dropped split packet from peer [{}]. {}",
+          peer_id, e
+        ),
+      }
+    }
+  }
+
+  ///
+  /// Tick the resend timers of every peer, and drop stale split packets.
+  ///
+  /// Peers which stopped acknowledging are forgotten,
+  /// they come out of take_timed_out_peers().
+  ///
+  pub fn update(&mut self, delta: f64) {
+    self.assembler.update(delta);
+
+    let mut timed_out = vec![];
+
+    for (peer_id, peer) in self.peers.iter_mut() {
+      let resend_timeout = peer.get_resend_timeout();
+
+      for channel in peer.channels.iter_mut() {
+        for frame in channel.resend(delta, resend_timeout) {
+          self.transport.send(*peer_id, &frame);
+        }
+      }
+
+      if peer.channels.iter().any(|channel| channel.has_given_up()) {
+        timed_out.push(*peer_id);
+      }
+    }
+
+    for peer_id in timed_out {
+      println!(
+        "ChannelLayer: peer [{}] stopped acknowledging, giving up on it.",
+        peer_id
+      );
+      self.remove_peer(peer_id);
+      self.timed_out_peers.push(peer_id);
+    }
+  }
+
+  ///
+  /// Take every peer which was given up on since the last call.
+  ///
+  /// UDP has no connections, so this is how a peer that vanished
+  /// without saying goodbye gets noticed.
+  ///
+  pub fn take_timed_out_peers(&mut self) -> Vec<PeerId> {
+    std::mem::take(&mut self.timed_out_peers)
+  }
+
+  ///
+  /// Forget everything about a peer.
+  ///
+  pub fn remove_peer(&mut self, peer_id: PeerId) {
+    self.peers.remove(&peer_id);
+    self.assembler.remove_peer(peer_id);
+  }
+
+  ///
+  /// Get the channels of a peer, if we've ever talked to it.
+  ///
+  pub fn get_peer_channels(&self, peer_id: PeerId) -> Option<&PeerChannels> {
+    self.peers.get(&peer_id)
+  }
+
+  ///
+  /// Check if every reliable frame sent to a peer has been acknowledged.
+  ///
+  pub fn is_peer_idle(&self, peer_id: PeerId) -> bool {
+    match self.peers.get(&peer_id) {
+      Some(peer) => peer.channels.iter().all(|channel| channel.is_idle()),
+      None => true,
+    }
+  }
+
+  ///
+  /// Get a readable address of a peer for logging.
+  ///
+  pub fn peer_address(&self, peer_id: PeerId) -> String {
+    self.transport.peer_address(peer_id)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use crate::game::network::{NetworkTransport, PeerId};
+
+  use super::{
+    ChannelLayer, MAX_RESENDS, MAX_RESEND_TIMEOUT, MAX_WAITING_FRAMES, MTU, RELIABLE_WINDOW_SIZE,
+  };
+
+  ///
+  /// A simulated network which loses, duplicates and reorders frames.
+  ///
+  struct LossyNetwork {
+    rng: StdRng,
+    loss_chance: f64,
+    duplicate_chance: f64,
+    // Index 0 is going to side A, index 1 is going to side B.
+    queues: [VecDeque<Vec<u8>>; 2],
+  }
+
+  struct LossyTransport {
+    network: Rc<RefCell<LossyNetwork>>,
+    side: usize,
+  }
+
+  impl NetworkTransport for LossyTransport {
+    fn send(&mut self, peer_id: PeerId, data: &[u8]) {
+      let network = &mut *self.network.borrow_mut();
+      let target = 1 - self.side;
+
+      if network.rng.gen_bool(network.loss_chance) {
+        return;
+      }
+
+      let copies = if network.rng.gen_bool(network.duplicate_chance) {
+        2
+      } else {
+        1
+      };
+
+      for _ in 0..copies {
+        // Reorder by sliding the frame somewhere into the last few queued.
+        let length = network.queues[target].len();
+        let index = length - network.rng.gen_range(0..=length.min(4));
+        network.queues[target].insert(index, data.to_vec());
+      }
+    }
+
+    fn receive(&mut self) -> Option<(PeerId, Vec<u8>)> {
+      let mut network = self.network.borrow_mut();
+      network.queues[self.side].pop_front().map(|data| (0, data))
+    }
+
+    fn peer_address(&self, peer_id: PeerId) -> String {
+      "lossy".to_string()
+    }
+  }
+
+  fn lossy_pair(loss_chance: f64, duplicate_chance: f64) -> (ChannelLayer, ChannelLayer) {
+    let network = Rc::new(RefCell::new(LossyNetwork {
+      rng: StdRng::seed_from_u64(5318008),
+      loss_chance,
+      duplicate_chance,
+      queues: [VecDeque::new(), VecDeque::new()],
+    }));
+
+    (
+      ChannelLayer::new(Box::new(LossyTransport {
+        network: network.clone(),
+        side: 0,
+      })),
+      ChannelLayer::new(Box::new(LossyTransport { network, side: 1 })),
+    )
+  }
+
+  #[test]
+  fn test_reliable_over_lossy_network() {
+    let (mut side_a, mut side_b) = lossy_pair(0.3, 0.1);
+
+    let total: u32 = 500;
+    for i in 0..total {
+      assert!(side_a
+        .send(0, (i % 3) as u8, true, &i.to_le_bytes())
+        .is_ok());
+    }
+
+    let mut received: [Vec<u32>; 3] = [vec![], vec![], vec![]];
+    let mut ticks = 0;
+
+    while received.iter().map(|channel| channel.len()).sum::<usize>() < total as usize {
+      ticks += 1;
+      assert!(ticks < 10_000, "reliable delivery never finished");
+
+      side_a.update(0.05);
+      side_b.update(0.05);
+
+      while let Some((_, payload)) = side_b.receive() {
+        let value = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        received[(value % 3) as usize].push(value);
+      }
+
+      // Side A has to read the acks.
+      while side_a.receive().is_some() {}
+    }
+
+    // Everything arrived exactly once, in order, per channel.
+    for (channel_id, channel) in received.iter().enumerate() {
+      let expected: Vec<u32> = (0..total).filter(|i| i % 3 == channel_id as u32).collect();
+      assert_eq!(*channel, expected);
+    }
+
+    // Let the last acks make it back.
+    for _ in 0..1000 {
+      side_a.update(0.05);
+      side_b.update(0.05);
+      while side_b.receive().is_some() {}
+      while side_a.receive().is_some() {}
+    }
+    assert!(side_a.is_peer_idle(0));
+    assert!(side_a
+      .get_peer_channels(0)
+      .and_then(|peer| peer.get_round_trip_time())
+      .is_some());
+  }
+
+  #[test]
+  fn test_split_over_lossy_network() {
+    let (mut side_a, mut side_b) = lossy_pair(0.2, 0.1);
+
+    // Something like a few map blocks.
+    let payloads: Vec<Vec<u8>> = (0..4_usize)
+      .map(|i| (0..(MTU * 20 + i * 333)).map(|j| (i + j) as u8).collect())
+      .collect();
+
+    for payload in payloads.iter() {
+      assert!(side_a.send(0, 2, true, payload).is_ok());
+    }
+
+    let mut received = vec![];
+    let mut ticks = 0;
+
+    while received.len() < payloads.len() {
+      ticks += 1;
+      assert!(ticks < 10_000, "split delivery never finished");
+
+      side_a.update(0.05);
+      side_b.update(0.05);
+
+      while let Some((_, payload)) = side_b.receive() {
+        received.push(payload);
+      }
+      while side_a.receive().is_some() {}
+    }
+
+    assert_eq!(received, payloads);
+  }
+
+  #[test]
+  fn test_unreliable_is_not_resent() {
+    let (mut side_a, mut side_b) = lossy_pair(0.5, 0.0);
+
+    for i in 0..100_u32 {
+      assert!(side_a.send(0, 1, false, &i.to_le_bytes()).is_ok());
+    }
+
+    for _ in 0..100 {
+      side_a.update(0.05);
+    }
+
+    let mut received = 0;
+    while side_b.receive().is_some() {
+      received += 1;
+    }
+
+    // Some got lost, and they stay lost.
+    assert!(received > 0 && received < 100);
+    assert!(side_a.is_peer_idle(0));
+  }
+
+  #[test]
+  fn test_sequence_wrap_around() {
+    let (mut side_a, mut side_b) = lossy_pair(0.0, 0.0);
+
+    // Go all the way around u16 a bit more than once.
+    let total = u16::MAX as u32 + 100;
+    let mut received = 0_u32;
+
+    for i in 0..total {
+      assert!(side_a.send(0, 0, true, &i.to_le_bytes()).is_ok());
+
+      while let Some((_, payload)) = side_b.receive() {
+        let value = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        assert_eq!(value, received);
+        received += 1;
+      }
+      while side_a.receive().is_some() {}
+    }
+
+    assert_eq!(received, total);
+  }
+
+  #[test]
+  fn test_unresponsive_peer() {
+    let network = Rc::new(RefCell::new(LossyNetwork {
+      rng: StdRng::seed_from_u64(5318008),
+      loss_chance: 1.0,
+      duplicate_chance: 0.0,
+      queues: [VecDeque::new(), VecDeque::new()],
+    }));
+    let mut channels = ChannelLayer::new(Box::new(LossyTransport {
+      network: network.clone(),
+      side: 0,
+    }));
+
+    // A stray ack from nobody doesn't make up a peer.
+    network.borrow_mut().queues[0].push_back(vec![2, 0, 0, 0]);
+    assert!(channels.receive().is_none());
+    assert!(channels.get_peer_channels(0).is_none());
+
+    // Nothing gets through, so the window fills and then the queue.
+    let total = RELIABLE_WINDOW_SIZE as usize + MAX_WAITING_FRAMES;
+    for i in 0..total {
+      if let Err(e) = channels.send(0, 0, true, &i.to_le_bytes()) {
+        panic!("Unit test is broken. {}", e);
+      }
+    }
+    assert!(channels.send(0, 0, true, &[1]).is_err());
+    assert!(channels.send(0, 0, false, &[1]).is_ok());
+
+    // Then it gets given up on.
+    for _ in 1..MAX_RESENDS {
+      channels.update(MAX_RESEND_TIMEOUT);
+      assert!(channels.take_timed_out_peers().is_empty());
+    }
+    channels.update(MAX_RESEND_TIMEOUT);
+    assert_eq!(channels.take_timed_out_peers(), vec![0]);
+    assert!(channels.get_peer_channels(0).is_none());
+    assert!(channels.take_timed_out_peers().is_empty());
+  }
+}
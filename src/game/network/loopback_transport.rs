@@ -0,0 +1,73 @@
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+
+use super::{NetworkTransport, PeerId};
+
+///
+/// In singleplayer there is only ever one peer on the other side.
+///
+pub const LOOPBACK_PEER_ID: PeerId = 0;
+
+///
+/// An in-memory transport.
+///
+/// This is used for singleplayer so the Client can talk to the Server
+/// in the same process without opening a UDP port.
+///
+/// These are always created in pairs, one for each side.
+///
+pub struct LoopbackTransport {
+  sender: Sender<Vec<u8>>,
+  receiver: Receiver<Vec<u8>>,
+}
+
+impl LoopbackTransport {
+  ///
+  /// Create a connected pair of LoopbackTransports.
+  ///
+  /// Data sent into one comes out of the other.
+  ///
+  pub fn new_pair() -> (LoopbackTransport, LoopbackTransport) {
+    let (sender_a, receiver_b) = channel();
+    let (sender_b, receiver_a) = channel();
+
+    (
+      LoopbackTransport {
+        sender: sender_a,
+        receiver: receiver_a,
+      },
+      LoopbackTransport {
+        sender: sender_b,
+        receiver: receiver_b,
+      },
+    )
+  }
+}
+
+impl NetworkTransport for LoopbackTransport {
+  fn send(&mut self, peer_id: PeerId, data: &[u8]) {
+    if peer_id != LOOPBACK_PEER_ID {
+      println!(
+        "LoopbackTransport: tried to send to unknown peer [{}].",
+        peer_id
+      );
+      return;
+    }
+
+    // The other side hanging up is not our problem, it's shutting down.
+    if self.sender.send(data.to_vec()).is_err() {
+      println!("LoopbackTransport: other side has hung up.");
+    }
+  }
+
+  fn receive(&mut self) -> Option<(PeerId, Vec<u8>)> {
+    match self.receiver.try_recv() {
+      Ok(data) => Some((LOOPBACK_PEER_ID, data)),
+      Err(TryRecvError::Empty) => None,
+      Err(TryRecvError::Disconnected) => None,
+    }
+  }
+
+  fn peer_address(&self, peer_id: PeerId) -> String {
+    "loopback".to_string()
+  }
+}
@@ -0,0 +1,496 @@
+use std::mem::size_of;
+
+use ahash::AHashMap;
+
+use super::PeerId;
+
+///
+/// The largest datagram we ever hand to a transport.
+///
+/// Stays under the usual 1500 byte ethernet MTU with room
+/// for the IP and UDP headers.
+///
+pub const MTU: usize = 1400;
+
+///
+/// How long an incomplete transfer is kept around, in seconds.
+///
+pub const SPLIT_TIMEOUT: f64 = 30.0;
+
+///
+/// How much reassembly memory a single peer is allowed to use, in bytes.
+///
+/// This has to fit at least one of the biggest Packets.
+///
+pub const MAX_PEER_SPLIT_MEMORY: usize = 32 * 1024 * 1024;
+
+///
+/// How many incomplete transfers a single peer can have at once.
+///
+pub const MAX_PEER_SPLIT_TRANSFERS: usize = 1024;
+
+///
+/// The largest a reassembled payload is allowed to be, in bytes.
+///
+pub const MAX_SPLIT_PAYLOAD_SIZE: usize = 17 * 1024 * 1024;
+
+// Chunk kinds.
+const KIND_WHOLE: u8 = 0;
+const KIND_FRAGMENT: u8 = 1;
+
+// kind
+const WHOLE_HEADER_SIZE: usize = 1;
+// kind + transfer id + fragment index + fragment count
+const FRAGMENT_HEADER_SIZE: usize = 9;
+
+///
+/// Cuts up payloads which are too big to go out in one datagram.
+///
+/// Every chunk starts with a kind byte. Fragments also carry
+/// a transfer id, their index, and how many fragments there are.
+///
+pub struct PacketSplitter {
+  next_transfer_id: u32,
+}
+
+impl PacketSplitter {
+  pub fn new() -> Self {
+    PacketSplitter {
+      next_transfer_id: 0,
+    }
+  }
+
+  ///
+  /// Split a payload into chunks which are at most max_chunk_size bytes.
+  ///
+  /// A payload that already fits is sent whole.
+  /// One which needs more fragments than a u16 can count is an error.
+  ///
+  pub fn split(&mut self, payload: &[u8], max_chunk_size: usize) -> Result<Vec<Vec<u8>>, String> {
+    if payload.len() + WHOLE_HEADER_SIZE <= max_chunk_size {
+      let mut chunk = Vec::with_capacity(WHOLE_HEADER_SIZE + payload.len());
+      chunk.push(KIND_WHOLE);
+      chunk.extend_from_slice(payload);
+      return Ok(vec![chunk]);
+    }
+
+    let data_size = max_chunk_size - FRAGMENT_HEADER_SIZE;
+    let fragment_count = payload.len().div_ceil(data_size);
+
+    if fragment_count > u16::MAX as usize {
+      return Err(format!(
+        "payload of {} bytes needs {} fragments, the limit is {}.",
+        payload.len(),
+        fragment_count,
+        u16::MAX
+      ));
+    }
+
+    let transfer_id = self.next_transfer_id;
+    self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+
+    Ok(
+      payload
+        .chunks(data_size)
+        .enumerate()
+        .map(|(index, data)| {
+          let mut chunk = Vec::with_capacity(FRAGMENT_HEADER_SIZE + data.len());
+          chunk.push(KIND_FRAGMENT);
+          chunk.extend_from_slice(&transfer_id.to_le_bytes());
+          chunk.extend_from_slice(&(index as u16).to_le_bytes());
+          chunk.extend_from_slice(&(fragment_count as u16).to_le_bytes());
+          chunk.extend_from_slice(data);
+          chunk
+        })
+        .collect(),
+    )
+  }
+}
+
+///
+/// A payload which is still being put back together.
+///
+struct Transfer {
+  fragments: Vec<Option<Vec<u8>>>,
+  received: usize,
+  memory: usize,
+  age: f64,
+  // Counts up per peer, the lowest one is the oldest.
+  started: u64,
+}
+
+///
+/// Every transfer coming in from a single peer.
+///
+struct PeerTransfers {
+  transfers: AHashMap<u32, Transfer>,
+  memory: usize,
+  next_started: u64,
+}
+
+impl PeerTransfers {
+  ///
+  /// Drop the transfer which has been waiting the longest, other than keep.
+  ///
+  /// Returns false if there's nothing else to drop.
+  ///
+  fn drop_oldest(&mut self, peer_id: PeerId, keep: u32) -> bool {
+    let oldest = self
+      .transfers
+      .iter()
+      .filter(|(transfer_id, _)| **transfer_id != keep)
+      .min_by_key(|(_, transfer)| transfer.started)
+      .map(|(transfer_id, _)| *transfer_id);
+
+    let transfer_id = match oldest {
+      Some(transfer_id) => transfer_id,
+      None => return false,
+    };
+
+    if let Some(transfer) = self.transfers.remove(&transfer_id) {
+      println!(
+        "PacketAssembler: dropped transfer [{}] from peer [{}] with {}/{} fragments, it's over the limit.",
+        transfer_id,
+        peer_id,
+        transfer.received,
+        transfer.fragments.len()
+      );
+      self.memory -= transfer.memory;
+    }
+
+    true
+  }
+}
+
+///
+/// Puts split payloads back together.
+///
+/// Incomplete transfers are dropped after SPLIT_TIMEOUT seconds.
+/// Each peer can only hold MAX_PEER_SPLIT_MEMORY bytes in
+/// MAX_PEER_SPLIT_TRANSFERS transfers, past that their oldest
+/// incomplete transfers get dropped to make room.
+///
+pub struct PacketAssembler {
+  peers: AHashMap<PeerId, PeerTransfers>,
+}
+
+impl PacketAssembler {
+  pub fn new() -> Self {
+    PacketAssembler {
+      peers: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Take in a chunk made by a PacketSplitter.
+  ///
+  /// Returns the full payload once every fragment has arrived.
+  ///
+  pub fn insert(&mut self, peer_id: PeerId, chunk: Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+    match chunk.first() {
+      Some(&KIND_WHOLE) => return Ok(Some(chunk[WHOLE_HEADER_SIZE..].to_vec())),
+      Some(&KIND_FRAGMENT) => (),
+      Some(kind) => return Err(format!("unknown chunk kind [{}].", kind)),
+      None => return Err("empty chunk.".to_string()),
+    }
+
+    if chunk.len() < FRAGMENT_HEADER_SIZE {
+      return Err("fragment is missing it's header.".to_string());
+    }
+
+    let transfer_id = u32::from_le_bytes([chunk[1], chunk[2], chunk[3], chunk[4]]);
+    let index = u16::from_le_bytes([chunk[5], chunk[6]]) as usize;
+    let fragment_count = u16::from_le_bytes([chunk[7], chunk[8]]) as usize;
+    let data = &chunk[FRAGMENT_HEADER_SIZE..];
+
+    if index >= fragment_count {
+      return Err(format!(
+        "fragment index {} is out of range of {} fragments.",
+        index, fragment_count
+      ));
+    }
+
+    if fragment_count * data.len() > MAX_SPLIT_PAYLOAD_SIZE {
+      return Err(format!(
+        "transfer of {} fragments is over the {} byte limit.",
+        fragment_count, MAX_SPLIT_PAYLOAD_SIZE
+      ));
+    }
+
+    let peer = self.peers.entry(peer_id).or_insert_with(|| PeerTransfers {
+      transfers: AHashMap::new(),
+      memory: 0,
+      next_started: 0,
+    });
+
+    // Keeping track of the fragment slots costs memory too.
+    let transfer_is_new = !peer.transfers.contains_key(&transfer_id);
+    let mut memory_needed = data.len();
+    if transfer_is_new {
+      memory_needed += fragment_count * size_of::<Option<Vec<u8>>>();
+    }
+
+    while peer.memory + memory_needed > MAX_PEER_SPLIT_MEMORY
+      || (transfer_is_new && peer.transfers.len() >= MAX_PEER_SPLIT_TRANSFERS)
+    {
+      if !peer.drop_oldest(peer_id, transfer_id) {
+        return Err(format!(
+          "transfer [{}] is over the {} byte reassembly limit.",
+          transfer_id, MAX_PEER_SPLIT_MEMORY
+        ));
+      }
+    }
+
+    let started = peer.next_started;
+    let transfer = peer
+      .transfers
+      .entry(transfer_id)
+      .or_insert_with(|| Transfer {
+        fragments: vec![None; fragment_count],
+        received: 0,
+        memory: fragment_count * size_of::<Option<Vec<u8>>>(),
+        age: 0.0,
+        started,
+      });
+    if transfer_is_new {
+      peer.next_started += 1;
+    }
+
+    if transfer.fragments.len() != fragment_count {
+      return Err(format!(
+        "transfer [{}] changed it's fragment count.",
+        transfer_id
+      ));
+    }
+
+    // Already have this one.
+    if transfer.fragments[index].is_some() {
+      return Ok(None);
+    }
+
+    transfer.fragments[index] = Some(data.to_vec());
+    transfer.received += 1;
+    transfer.memory += data.len();
+    peer.memory += memory_needed;
+
+    if transfer.received < fragment_count {
+      return Ok(None);
+    }
+
+    let transfer = match peer.transfers.remove(&transfer_id) {
+      Some(transfer) => transfer,
+      None => return Ok(None),
+    };
+    peer.memory -= transfer.memory;
+
+    Ok(Some(
+      transfer.fragments.into_iter().flatten().flatten().collect(),
+    ))
+  }
+
+  ///
+  /// Age every transfer and drop the ones which took too long.
+  ///
+  pub fn update(&mut self, delta: f64) {
+    for (peer_id, peer) in self.peers.iter_mut() {
+      let mut freed = 0;
+
+      peer.transfers.retain(|transfer_id, transfer| {
+        transfer.age += delta;
+
+        if transfer.age < SPLIT_TIMEOUT {
+          return true;
+        }
+
+        println!(
+          "PacketAssembler: transfer [{}] from peer [{}] timed out with {}/{} fragments.",
+          transfer_id,
+          peer_id,
+          transfer.received,
+          transfer.fragments.len()
+        );
+        freed += transfer.memory;
+        false
+      });
+
+      peer.memory -= freed;
+    }
+
+    self.peers.retain(|_, peer| !peer.transfers.is_empty());
+  }
+
+  ///
+  /// Forget every transfer from a peer.
+  ///
+  pub fn remove_peer(&mut self, peer_id: PeerId) {
+    self.peers.remove(&peer_id);
+  }
+
+  ///
+  /// Get how many bytes of reassembly memory a peer is using.
+  ///
+  pub fn get_peer_memory(&self, peer_id: PeerId) -> usize {
+    match self.peers.get(&peer_id) {
+      Some(peer) => peer.memory,
+      None => 0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    PacketAssembler, PacketSplitter, FRAGMENT_HEADER_SIZE, MAX_PEER_SPLIT_MEMORY,
+    MAX_PEER_SPLIT_TRANSFERS, MTU, SPLIT_TIMEOUT,
+  };
+
+  fn test_payload(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+  }
+
+  fn split(splitter: &mut PacketSplitter, payload: &[u8], max_chunk_size: usize) -> Vec<Vec<u8>> {
+    match splitter.split(payload, max_chunk_size) {
+      Ok(chunks) => chunks,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+
+  #[test]
+  fn test_split_and_reassemble() {
+    let mut splitter = PacketSplitter::new();
+    let mut assembler = PacketAssembler::new();
+
+    // Small payloads go out whole.
+    let small = test_payload(100);
+    let chunks = split(&mut splitter, &small, MTU);
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(assembler.insert(0, chunks[0].clone()), Ok(Some(small)));
+
+    // Big ones get split, and can show up in any order with duplicates.
+    let big = test_payload(MTU * 10 + 7);
+    let mut chunks = split(&mut splitter, &big, MTU);
+    assert!(chunks.len() > 10);
+    assert!(chunks.iter().all(|chunk| chunk.len() <= MTU));
+
+    chunks.reverse();
+    let duplicate = chunks[3].clone();
+    chunks.insert(5, duplicate);
+
+    let mut result = None;
+    for chunk in chunks {
+      match assembler.insert(0, chunk) {
+        Ok(Some(payload)) => result = Some(payload),
+        Ok(None) => (),
+        Err(e) => panic!("Unit test is broken. {}", e),
+      }
+    }
+
+    assert_eq!(result, Some(big));
+    assert_eq!(assembler.get_peer_memory(0), 0);
+
+    // More fragments than a u16 can count is refused, not a crash.
+    let tiny_chunk_size = FRAGMENT_HEADER_SIZE + 1;
+    assert!(splitter
+      .split(&test_payload(u16::MAX as usize + 1), tiny_chunk_size)
+      .is_err());
+    assert!(splitter
+      .split(&test_payload(u16::MAX as usize), tiny_chunk_size)
+      .is_ok());
+  }
+
+  #[test]
+  fn test_incomplete_transfers_time_out() {
+    let mut splitter = PacketSplitter::new();
+    let mut assembler = PacketAssembler::new();
+
+    let chunks = split(&mut splitter, &test_payload(MTU * 4), MTU);
+    assert_eq!(assembler.insert(7, chunks[0].clone()), Ok(None));
+    assert!(assembler.get_peer_memory(7) > 0);
+
+    assembler.update(SPLIT_TIMEOUT / 2.0);
+    assert!(assembler.get_peer_memory(7) > 0);
+
+    assembler.update(SPLIT_TIMEOUT / 2.0);
+    assert_eq!(assembler.get_peer_memory(7), 0);
+
+    // The rest of the transfer can't complete it anymore.
+    for chunk in chunks.into_iter().skip(1) {
+      assert_eq!(assembler.insert(7, chunk), Ok(None));
+    }
+  }
+
+  ///
+  /// A fragment of a transfer which never finishes.
+  ///
+  fn fragment(transfer_id: u32, fragment_count: u16, size: usize) -> Vec<u8> {
+    let mut chunk = vec![1];
+    chunk.extend_from_slice(&transfer_id.to_le_bytes());
+    chunk.extend_from_slice(&0_u16.to_le_bytes());
+    chunk.extend_from_slice(&fragment_count.to_le_bytes());
+    chunk.extend_from_slice(&vec![0; size]);
+    chunk
+  }
+
+  #[test]
+  fn test_peer_limits() {
+    let mut assembler = PacketAssembler::new();
+
+    // Too many transfers, the oldest ones make room.
+    for transfer_id in 0..MAX_PEER_SPLIT_TRANSFERS as u32 * 2 {
+      if let Err(e) = assembler.insert(1, fragment(transfer_id, 1000, 1000)) {
+        panic!("Unit test is broken. {}", e);
+      }
+    }
+
+    let transfers = match assembler.peers.get(&1) {
+      Some(peer) => &peer.transfers,
+      None => panic!("Unit test is broken. peer 1 has no transfers."),
+    };
+    assert_eq!(transfers.len(), MAX_PEER_SPLIT_TRANSFERS);
+    assert!(!transfers.contains_key(&0));
+    assert!(transfers.contains_key(&(MAX_PEER_SPLIT_TRANSFERS as u32 * 2 - 1)));
+    assert!(assembler.get_peer_memory(1) <= MAX_PEER_SPLIT_MEMORY);
+
+    // Too many bytes, same thing.
+    for transfer_id in 0..100 {
+      if let Err(e) = assembler.insert(2, fragment(transfer_id, u16::MAX, 200)) {
+        panic!("Unit test is broken. {}", e);
+      }
+    }
+
+    let transfers = match assembler.peers.get(&2) {
+      Some(peer) => &peer.transfers,
+      None => panic!("Unit test is broken. peer 2 has no transfers."),
+    };
+    assert!(transfers.len() < 100);
+    assert!(transfers.contains_key(&99));
+    assert!(assembler.get_peer_memory(2) <= MAX_PEER_SPLIT_MEMORY);
+
+    // A real transfer still makes it through after all that.
+    let mut splitter = PacketSplitter::new();
+    let payload = test_payload(MTU * 3);
+    let mut result = None;
+    for chunk in split(&mut splitter, &payload, MTU) {
+      match assembler.insert(1, chunk) {
+        Ok(Some(done)) => result = Some(done),
+        Ok(None) => (),
+        Err(e) => panic!("Unit test is broken. {}", e),
+      }
+    }
+    assert_eq!(result, Some(payload));
+  }
+
+  #[test]
+  fn test_malformed_fragments() {
+    let mut assembler = PacketAssembler::new();
+
+    assert!(assembler.insert(0, vec![]).is_err());
+    assert!(assembler.insert(0, vec![9, 1, 2]).is_err());
+    assert!(assembler.insert(0, vec![1, 0, 0]).is_err());
+
+    // Index past the fragment count.
+    assert!(assembler
+      .insert(0, vec![1, 0, 0, 0, 0, 5, 0, 2, 0, 42])
+      .is_err());
+  }
+}
@@ -0,0 +1,188 @@
+use std::{net::ToSocketAddrs, time::Duration};
+
+use ahash::AHashMap;
+use message_io::{
+  events::EventReceiver,
+  network::{Endpoint, ToRemoteAddr, Transport},
+  node::{self, NodeHandler, NodeTask, StoredNetEvent, StoredNodeEvent},
+};
+use unique_64::Unique64;
+
+use super::{NetworkTransport, PeerId};
+
+///
+/// The UDP transport, powered by message-io.
+///
+/// message-io hands out Endpoints, these get mapped into PeerIds
+/// so nothing above this needs to know about message-io.
+///
+pub struct UdpTransport {
+  task: NodeTask,
+  handler: NodeHandler<()>,
+  event_receiver: EventReceiver<StoredNodeEvent<()>>,
+
+  id_dispatcher: Unique64,
+  peer_to_end_point: AHashMap<PeerId, Endpoint>,
+  end_point_to_peer: AHashMap<Endpoint, PeerId>,
+}
+
+impl UdpTransport {
+  ///
+  /// Construct the address & port into a parsable socket string.
+  ///
+  pub fn get_socket(address: &str, port: i32) -> String {
+    let mut socket = address.to_owned();
+    socket.push(':');
+    socket.push_str(port.to_string().as_str());
+
+    socket
+  }
+
+  ///
+  /// Create a UdpTransport which listens for clients.
+  ///
+  pub fn listen(address: &str, port: i32) -> Self {
+    let socket_address = match Self::get_socket(address, port).to_socket_addrs() {
+      Ok(mut iter) => match iter.next() {
+        Some(socket_address) => socket_address,
+        None => panic!("UdpTransport: Failed to get socket address. None available."),
+      },
+      Err(e) => panic!(
+        "UdpTransport: Failed to apply address and port into socket address. {}",
+        e
+      ),
+    };
+
+    let (handler, listener) = node::split::<()>();
+
+    // todo: If this fails, the server probably doesn't have a network
+    // todo: adapter! Why is it a server?!
+    match handler.network().listen(Transport::Udp, socket_address) {
+      Ok((id, real_address)) => {
+        println!(
+          "UdpTransport: listening at id [{}], real address [{}]",
+          id, real_address
+        );
+      }
+      Err(e) => panic!("{}", e),
+    }
+
+    let (task, event_receiver) = listener.enqueue();
+
+    UdpTransport {
+      task,
+      handler,
+      event_receiver,
+
+      id_dispatcher: Unique64::new(),
+      peer_to_end_point: AHashMap::new(),
+      end_point_to_peer: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Create a UdpTransport which talks to a server.
+  ///
+  /// Returns the PeerId of the server along with the transport.
+  /// A bad address or a missing network adapter is an error, not a crash.
+  ///
+  pub fn connect(address: &str, port: i32) -> Result<(Self, PeerId), String> {
+    let remote_address = match Self::get_socket(address, port).to_remote_addr() {
+      Ok(address) => address,
+      Err(e) => return Err(format!("invalid server address. {}", e)),
+    };
+
+    let (handler, listener) = node::split::<()>();
+
+    let end_point = match handler.network().connect(Transport::Udp, remote_address) {
+      Ok((end_point, local_address)) => {
+        // UDP is connectionless, but it's still good to know it's working.
+        println!(
+          "UdpTransport: established connection to server at id [{}], local address [{}]",
+          end_point, local_address
+        );
+        end_point
+      }
+      Err(e) => {
+        NodeHandler::stop(&handler);
+        return Err(format!("failed to open a socket. {}", e));
+      }
+    };
+
+    let (task, event_receiver) = listener.enqueue();
+
+    let mut new_transport = UdpTransport {
+      task,
+      handler,
+      event_receiver,
+
+      id_dispatcher: Unique64::new(),
+      peer_to_end_point: AHashMap::new(),
+      end_point_to_peer: AHashMap::new(),
+    };
+
+    let server_peer_id = new_transport.get_peer_id(end_point);
+
+    Ok((new_transport, server_peer_id))
+  }
+
+  ///
+  /// Get the PeerId of an Endpoint, dispatching a new one if it was never seen.
+  ///
+  fn get_peer_id(&mut self, end_point: Endpoint) -> PeerId {
+    if let Some(peer_id) = self.end_point_to_peer.get(&end_point) {
+      return *peer_id;
+    }
+
+    let peer_id = self.id_dispatcher.get_next();
+    self.end_point_to_peer.insert(end_point, peer_id);
+    self.peer_to_end_point.insert(peer_id, end_point);
+
+    peer_id
+  }
+}
+
+impl NetworkTransport for UdpTransport {
+  fn send(&mut self, peer_id: PeerId, data: &[u8]) {
+    match self.peer_to_end_point.get(&peer_id) {
+      Some(end_point) => {
+        self.handler.network().send(*end_point, data);
+      }
+      None => println!("UdpTransport: tried to send to unknown peer [{}].", peer_id),
+    }
+  }
+
+  fn receive(&mut self) -> Option<(PeerId, Vec<u8>)> {
+    // We want to grind through events until we hit actual data.
+    while let Some(event) = self.event_receiver.receive_timeout(Duration::new(0, 0)) {
+      match event {
+        // We don't need to match the rest, we're using UDP which is connectionless.
+        StoredNodeEvent::Network(StoredNetEvent::Message(end_point, raw_message)) => {
+          return Some((self.get_peer_id(end_point), raw_message));
+        }
+        StoredNodeEvent::Network(_) => (),
+        // Signals are events the handler sends itself, nothing here sends any.
+        StoredNodeEvent::Signal(_) => println!("UdpTransport: ignored an unexpected signal."),
+      }
+    }
+
+    None
+  }
+
+  fn peer_address(&self, peer_id: PeerId) -> String {
+    match self.peer_to_end_point.get(&peer_id) {
+      Some(end_point) => end_point.addr().to_string(),
+      None => "unknown".to_string(),
+    }
+  }
+}
+
+impl Drop for UdpTransport {
+  fn drop(&mut self) {
+    // The handler entity must be stopped or the Client/Server
+    // will not shut down.
+    println!("UdpTransport: Shutting down network handler.");
+    NodeHandler::stop(&self.handler);
+    println!("UdpTransport dropped!");
+  }
+}
@@ -0,0 +1,139 @@
+use glam::{IVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::map::node_def_manager::NodeDefinition;
+
+///
+/// The version of the network protocol.
+///
+/// Bump this every time a Packet changes shape.
+/// A Server and Client with different versions will refuse to talk.
+///
+pub const PROTOCOL_VERSION: u16 = 4;
+
+///
+/// Every message the Server and Client can send each other.
+///
+/// This is shared by ServerConnection and ClientConnection so the
+/// two sides can never disagree on what a message looks like.
+///
+/// Packets are turned into bytes with serial::serialize()
+/// and back with serial::deserialize().
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Packet {
+  // Connection.
+  Handshake {
+    player_name: String,
+  },
+  HandshakeConfirmed,
+  PingRequest,
+  PingConfirmation,
+  ShutdownRequest,
+  Disconnect {
+    reason: String,
+  },
+
+  // Authentication, SRP-6a.
+  AuthRequest {
+    registered: bool,
+  },
+  AuthRegister {
+    #[serde(with = "serde_bytes")]
+    salt: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    verifier: Vec<u8>,
+  },
+  AuthStart {
+    #[serde(with = "serde_bytes")]
+    client_public: Vec<u8>,
+  },
+  AuthChallenge {
+    #[serde(with = "serde_bytes")]
+    salt: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    server_public: Vec<u8>,
+  },
+  AuthProof {
+    #[serde(with = "serde_bytes")]
+    proof: Vec<u8>,
+  },
+  AuthServerProof {
+    #[serde(with = "serde_bytes")]
+    proof: Vec<u8>,
+  },
+  AuthRejected {
+    reason: String,
+  },
+
+  // Players.
+  ChatMessage {
+    sender: String,
+    message: String,
+  },
+  PlayerMove {
+    position: Vec3,
+    rotation: Vec3,
+  },
+
+  // Map.
+  // Every node type the server has, sent before any MapBlock.
+  NodeDefinitions {
+    definitions: Vec<NodeDefinition>,
+  },
+  // A whole MapBlock, made with map_serialization::serialize_block().
+  BlockData {
+    position: IVec3,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+  },
+  // One node in a MapBlock the client already has.
+  NodeChanged {
+    position: IVec3,
+    name: String,
+    param1: u8,
+    param2: u8,
+  },
+}
+
+impl Packet {
+  ///
+  /// Which ChannelLayer channel this Packet travels on.
+  ///
+  /// * 0 - connection
+  /// * 1 - players
+  /// * 2 - map
+  ///
+  pub fn channel(&self) -> u8 {
+    match self {
+      Packet::Handshake { .. }
+      | Packet::HandshakeConfirmed
+      | Packet::PingRequest
+      | Packet::PingConfirmation
+      | Packet::ShutdownRequest
+      | Packet::Disconnect { .. }
+      | Packet::AuthRequest { .. }
+      | Packet::AuthRegister { .. }
+      | Packet::AuthStart { .. }
+      | Packet::AuthChallenge { .. }
+      | Packet::AuthProof { .. }
+      | Packet::AuthServerProof { .. }
+      | Packet::AuthRejected { .. } => 0,
+      Packet::ChatMessage { .. } | Packet::PlayerMove { .. } => 1,
+      Packet::NodeDefinitions { .. } | Packet::BlockData { .. } | Packet::NodeChanged { .. } => 2,
+    }
+  }
+
+  ///
+  /// If this Packet must arrive.
+  ///
+  /// Player movement goes out constantly so a lost one doesn't matter.
+  /// Pings have their own timeout logic.
+  ///
+  pub fn is_reliable(&self) -> bool {
+    !matches!(
+      self,
+      Packet::PlayerMove { .. } | Packet::PingRequest | Packet::PingConfirmation
+    )
+  }
+}
@@ -1,22 +1,253 @@
+use std::fmt::Display;
+
+use bincode::Options;
+
+use super::protocol::{Packet, PROTOCOL_VERSION};
+
 ///
-/// Todo:
-///
-/// This is where we shall implement any Serde serialization
+/// This is where we implement the Serde serialization
 /// and deserialization components purely functionally.
 ///
 /// This will clean up code as it's easier to read these two
 /// functions encapsulating a component than it is to read
 /// the (possibly) complex process it _might_ be to utilize serde.
 ///
-/// ! This is highly subjective at the moment and will possibly be
-/// ! dropped at any moment due to not having any implementation
-/// ! in this stage of development.
+/// Every Packet on the wire looks like this:
+/// * protocol version - u16 little endian
+/// * payload length   - u32 little endian
+/// * payload          - the bincode encoded Packet
+///
+const VERSION_SIZE: usize = 2;
+const LENGTH_SIZE: usize = 4;
+pub const HEADER_SIZE: usize = VERSION_SIZE + LENGTH_SIZE;
+
+///
+/// The largest payload we will ever try to decode.
+///
+/// Anything bigger than this is somebody trying to make us allocate.
+///
+pub const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+///
+/// Everything that can go wrong when turning bytes back into a Packet.
 ///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+  TooShort { received: usize },
+  VersionMismatch { expected: u16, received: u16 },
+  LengthMismatch { expected: usize, received: usize },
+  TooLarge { size: usize },
+  Malformed(String),
+}
 
-pub fn serialize() {
-  println!("serial");
+impl Display for ProtocolError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ProtocolError::TooShort { received } => write!(
+        f,
+        "packet is too short. Needs at least {} bytes, got {}.",
+        HEADER_SIZE, received
+      ),
+      ProtocolError::VersionMismatch { expected, received } => write!(
+        f,
+        "protocol version mismatch. Expected {}, got {}.",
+        expected, received
+      ),
+      ProtocolError::LengthMismatch { expected, received } => write!(
+        f,
+        "length prefix mismatch. Header says {} bytes, got {}.",
+        expected, received
+      ),
+      ProtocolError::TooLarge { size } => write!(
+        f,
+        "packet payload of {} bytes is over the {} byte limit.",
+        size, MAX_PAYLOAD_SIZE
+      ),
+      ProtocolError::Malformed(e) => write!(f, "malformed packet. {}", e),
+    }
+  }
 }
 
-pub fn deserialize() {
-  println!("deserial")
+impl std::error::Error for ProtocolError {}
+
+///
+/// The bincode configuration both sides must agree on.
+///
+fn bincode_options() -> impl Options {
+  bincode::DefaultOptions::new()
+    .with_little_endian()
+    .with_limit(MAX_PAYLOAD_SIZE as u64)
+}
+
+///
+/// Turn a Packet into bytes which are ready to be sent.
+///
+pub fn serialize(packet: &Packet) -> Result<Vec<u8>, ProtocolError> {
+  let payload = match bincode_options().serialize(packet) {
+    Ok(payload) => payload,
+    Err(e) => return Err(ProtocolError::Malformed(e.to_string())),
+  };
+
+  if payload.len() > MAX_PAYLOAD_SIZE {
+    return Err(ProtocolError::TooLarge {
+      size: payload.len(),
+    });
+  }
+
+  let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+  data.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+  data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+  data.extend_from_slice(&payload);
+
+  Ok(data)
+}
+
+///
+/// Turn raw received bytes back into a Packet.
+///
+/// Anything that doesn't look exactly like a Packet gets rejected.
+///
+pub fn deserialize(data: &[u8]) -> Result<Packet, ProtocolError> {
+  if data.len() < HEADER_SIZE {
+    return Err(ProtocolError::TooShort {
+      received: data.len(),
+    });
+  }
+
+  let (version_bytes, rest) = data.split_at(VERSION_SIZE);
+  let (length_bytes, payload) = rest.split_at(LENGTH_SIZE);
+
+  let version = u16::from_le_bytes([version_bytes[0], version_bytes[1]]);
+  if version != PROTOCOL_VERSION {
+    return Err(ProtocolError::VersionMismatch {
+      expected: PROTOCOL_VERSION,
+      received: version,
+    });
+  }
+
+  let length = u32::from_le_bytes([
+    length_bytes[0],
+    length_bytes[1],
+    length_bytes[2],
+    length_bytes[3],
+  ]) as usize;
+
+  if length > MAX_PAYLOAD_SIZE {
+    return Err(ProtocolError::TooLarge { size: length });
+  }
+
+  if length != payload.len() {
+    return Err(ProtocolError::LengthMismatch {
+      expected: length,
+      received: payload.len(),
+    });
+  }
+
+  match bincode_options().deserialize(payload) {
+    Ok(packet) => Ok(packet),
+    Err(e) => Err(ProtocolError::Malformed(e.to_string())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{IVec3, Vec3};
+
+  use crate::game::{
+    map::node_def_manager::NodeDefinition,
+    protocol::{Packet, PROTOCOL_VERSION},
+  };
+
+  use super::{deserialize, serialize, ProtocolError, HEADER_SIZE};
+
+  fn round_trip(packet: Packet) {
+    let data = match serialize(&packet) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+    assert_eq!(deserialize(&data), Ok(packet));
+  }
+
+  #[test]
+  fn test_packet_round_trip() {
+    round_trip(Packet::Handshake {
+      player_name: "singleplayer".to_string(),
+    });
+    round_trip(Packet::PingRequest);
+    round_trip(Packet::AuthChallenge {
+      salt: vec![1, 2, 3],
+      server_public: vec![4, 5, 6],
+    });
+    round_trip(Packet::ShutdownRequest);
+    round_trip(Packet::ChatMessage {
+      sender: "sam".to_string(),
+      message: "hello!".to_string(),
+    });
+    round_trip(Packet::PlayerMove {
+      position: Vec3::new(1.0, 2.0, 3.0),
+      rotation: Vec3::new(0.0, 1.5, 0.0),
+    });
+    round_trip(Packet::NodeDefinitions {
+      definitions: vec![NodeDefinition {
+        textures: vec!["default_stone.png".to_string()],
+        groups: vec![("cracky".to_string(), 3)],
+        ..NodeDefinition::new("minetest:stone")
+      }],
+    });
+    round_trip(Packet::BlockData {
+      position: IVec3::new(-1, 0, 4),
+      data: vec![0, 1, 2, 3, 255],
+    });
+    round_trip(Packet::NodeChanged {
+      position: IVec3::new(-20, 5, 300),
+      name: "minetest:stone".to_string(),
+      param1: 15,
+      param2: 3,
+    });
+  }
+
+  #[test]
+  fn test_malformed_packets() {
+    // Too short to even have a header.
+    assert_eq!(
+      deserialize(&[1, 0]),
+      Err(ProtocolError::TooShort { received: 2 })
+    );
+
+    let good = match serialize(&Packet::PingRequest) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    // Wrong version.
+    let mut wrong_version = good.clone();
+    wrong_version[0] = wrong_version[0].wrapping_add(1);
+    assert_eq!(
+      deserialize(&wrong_version),
+      Err(ProtocolError::VersionMismatch {
+        expected: PROTOCOL_VERSION,
+        received: PROTOCOL_VERSION.wrapping_add(1),
+      })
+    );
+
+    // Payload longer than the length prefix says.
+    let mut too_long = good.clone();
+    too_long.push(0);
+    assert!(matches!(
+      deserialize(&too_long),
+      Err(ProtocolError::LengthMismatch { .. })
+    ));
+
+    // Garbage payload with a valid header.
+    let mut garbage = good[..HEADER_SIZE].to_vec();
+    garbage[2..6].copy_from_slice(&4_u32.to_le_bytes());
+    garbage.extend_from_slice(&[255, 255, 255, 255]);
+    assert!(matches!(
+      deserialize(&garbage),
+      Err(ProtocolError::Malformed(_))
+    ));
+
+    // Plain old text, like the old protocol used.
+    assert!(deserialize("MINETEST_PING_REQUEST".as_bytes()).is_err());
+  }
 }
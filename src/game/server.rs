@@ -1,8 +1,76 @@
+mod active_block_list;
+mod block_sender;
+mod client_session;
+mod env_meta;
+mod player_database;
+mod privileges;
+mod server_authentication;
 mod server_connection;
+mod world_meta;
 
-use self::server_connection::ServerConnection;
+use std::{
+  cell::{Ref, RefCell},
+  collections::{HashMap, VecDeque},
+  rc::Rc,
+  time::Instant,
+};
 
-use super::lua_engine::LuaEngine;
+use glam::{IVec3, Vec3};
+use mlua::{Error as LuaError, Table, Value};
+
+use crate::{
+  file_utilities::get_mod_write_path,
+  game::{
+    item::item_def_manager::ItemDefManager,
+    map::{
+      block_modifier::{ActiveBlockModifiers, LoadingBlockModifiers, ModifierTrigger},
+      block_to_node_position,
+      legacy_map_serialization::{deserialize_legacy_block, is_legacy_block},
+      lighting::{light_block, update_lighting},
+      liquid::transform_liquids,
+      map_block::{NodeTimer, BLOCK_TIMESTAMP_UNDEFINED, MAP_BLOCK_SIZE},
+      map_database::MapDatabase,
+      map_serialization::{deserialize_block, serialize_block},
+      mapgen::{
+        mapgen_threads::MapgenThreads,
+        pseudo_random::PseudoRandom,
+        schematic::{Rotation, Schematic},
+        seed_from_string, Mapgen, MapgenFlags, MapgenNodes, MapgenParams,
+      },
+      node::Node,
+      node_def_manager::NodeDefManager,
+      node_to_block_position, Map,
+    },
+    protocol::Packet,
+  },
+};
+
+use self::{
+  active_block_list::ActiveBlockList,
+  block_sender::{look_direction, BlockSender},
+  client_session::SessionEvent,
+  env_meta::EnvMeta,
+  player_database::{PlayerData, PlayerDatabase},
+  privileges::{parse_privilege_list, PrivilegeDefinition, Privileges},
+  server_authentication::ServerAuthentication,
+  server_connection::ServerConnection,
+  world_meta::WorldMeta,
+};
+
+use super::{
+  lua_engine::{
+    mapgen_tables::{schematic_from_value, vec3_from_table},
+    LuaEngine, GAMES_DIR,
+  },
+  network::loopback_transport::LoopbackTransport,
+  settings::Settings,
+};
+
+///
+/// How long blocks nobody is around stay loaded for,
+/// if the game.conf doesn't say. Same as C++ minetest.
+///
+const DEFAULT_HIBERNATION_TIMEOUT: f64 = 29.0;
 
 ///
 /// The Server component for the engine.
@@ -10,54 +78,1323 @@ use super::lua_engine::LuaEngine;
 /// The Server component has 4 jobs:
 /// 1.) Processes LuaEngine just as LuaJIT does in Minetest C++'s server.
 /// 2.) Hold a ServerConnection component which will handle talking to clients.
-/// * 3.) [in the future] Be the main handler for ServerAuthentication.
-/// *  - ServerAuthentication does exactly what you think it does.
-/// *  - It handles the client auth for the server.
+/// 3.) Be the main handler for ServerAuthentication.
+///  - ServerAuthentication does exactly what you think it does.
+///  - It holds the auth database, ServerConnection uses it to log clients in.
+///  - It also holds what every player is allowed to do (Privileges).
 /// ? 4.) Handle GameConfig as a component to be utilized during runtime.
 /// ?  - Marked with ? because it's still being thought out at the moment.
 ///
 pub struct Server {
   lua_engine: LuaEngine,
+  authentication: Rc<RefCell<ServerAuthentication>>,
+  privileges: Rc<RefCell<Privileges>>,
+  // Shared with the LuaEngine, so mods can read and write the map.
+  node_def_manager: Rc<RefCell<NodeDefManager>>,
+  item_def_manager: ItemDefManager,
+  map: Rc<RefCell<Map>>,
   connection: ServerConnection,
   shutdown_approved: bool,
+
+  // Singleplayer gets every privilege with give_to_singleplayer.
+  singleplayer: bool,
+  // The player named in the admin_name setting gets every privilege.
+  admin_name: String,
+
+  world_seed: u64,
+  mapgen_flags: MapgenFlags,
+  emerge_thread_count: usize,
+  // Doesn't exist until a game is loaded, it needs the game's nodes.
+  mapgen_threads: Option<MapgenThreads>,
+  // Where new players show up.
+  spawn_position: Vec3,
+
+  // Mods read schematics from the world and game folders,
+  // and write them into the world's schems folder.
+  schematic_directories: Vec<String>,
+  schematic_save_directory: String,
+
+  // The map.sqlite and players.sqlite in the world folder.
+  map_database: MapDatabase,
+  player_database: PlayerDatabase,
+  // Every player in the game right now.
+  players: HashMap<String, PlayerData>,
+  // Dirty MapBlocks and players get saved this often, in seconds.
+  map_save_interval: f64,
+  map_save_timer: f64,
+
+  // Liquid flows one step every liquid_update seconds,
+  // doing at most liquid_loop_max updates at a time.
+  liquid_update_interval: f64,
+  liquid_loop_max: usize,
+  liquid_timer: f64,
+
+  // Seconds since the world was created, kept in env_meta.txt
+  // along with when every LBM first showed up.
+  env_meta_path: String,
+  game_time: f64,
+  lbm_introduction_times: HashMap<String, u32>,
+
+  // ABMs and node timers run in the blocks this many blocks around players,
+  // objects would in the ones active_object_range blocks around them.
+  active_block_range: i32,
+  active_object_range: i32,
+  active_block_list: ActiveBlockList,
+  // Active blocks which couldn't be loaded or generated, tried again every update.
+  failed_emerges: Vec<IVec3>,
+  // Blocks which aren't in the active block list for this many seconds
+  // get saved and unloaded. Comes from the game's game.conf.
+  hibernation_timeout: f64,
+  // The game time every loaded block outside of the active block list left it.
+  inactive_blocks: HashMap<IVec3, f64>,
+
+  active_block_modifiers: ActiveBlockModifiers,
+  loading_block_modifiers: LoadingBlockModifiers,
+  // Whatever ABMs, LBMs and node timers have to run on,
+  // which didn't fit into a tick's time budget yet.
+  abm_queue: VecDeque<ModifierTrigger>,
+  // ABMs whose interval came up while the last round was still queued.
+  pending_abms: Vec<usize>,
+  // With how long the block was unloaded for.
+  lbm_queue: VecDeque<(ModifierTrigger, u32)>,
+  node_timer_queue: VecDeque<(IVec3, NodeTimer)>,
+  abm_time_budget: f64,
+  lbm_time_budget: f64,
+  node_timer_time_budget: f64,
+  // Picks which nodes ABMs go off on.
+  random: PseudoRandom,
+
+  // Streams the map to every player in the game.
+  block_senders: HashMap<String, BlockSender>,
+  viewing_range: f32,
+  max_block_sends: usize,
+  max_block_send_bytes: usize,
 }
 
 impl Server {
-  pub fn new(address: String, port: i32, game_name: String) -> Self {
+  pub fn new(settings: &Settings) -> Self {
+    let authentication = Self::open_authentication(settings);
+
     // Create a connection.
-    let connection = ServerConnection::new(address, port);
+    let connection = ServerConnection::new(
+      settings.get_string("address"),
+      settings.get_int("port") as i32,
+      authentication.clone(),
+      settings.get_float("client_timeout"),
+    );
 
+    Self::new_with_connection(settings, authentication, connection, false)
+  }
+
+  ///
+  /// Create a Server which runs inside the same process as the Client.
+  ///
+  /// The Client talks to it through the other side of the LoopbackTransport.
+  ///
+  pub fn new_singleplayer(settings: &Settings, transport: LoopbackTransport) -> Self {
+    let authentication = Self::open_authentication(settings);
+
+    let connection = ServerConnection::new_loopback(
+      transport,
+      authentication.clone(),
+      settings.get_float("client_timeout"),
+    );
+
+    Self::new_with_connection(settings, authentication, connection, true)
+  }
+
+  ///
+  /// Open the auth database which lives in the world folder.
+  ///
+  fn open_authentication(settings: &Settings) -> Rc<RefCell<ServerAuthentication>> {
+    let world_path = settings.get_string("world_path");
+
+    if let Err(e) = std::fs::create_dir_all(&world_path) {
+      panic!(
+        "Server: failed to create world folder [{}]. {}",
+        world_path, e
+      );
+    }
+
+    let mut authentication = ServerAuthentication::new(&format!("{}/auth.sqlite", world_path));
+    authentication
+      .set_default_privileges(parse_privilege_list(&settings.get_string("default_privs")));
+
+    Rc::new(RefCell::new(authentication))
+  }
+
+  fn new_with_connection(
+    settings: &Settings,
+    authentication: Rc<RefCell<ServerAuthentication>>,
+    connection: ServerConnection,
+    singleplayer: bool,
+  ) -> Self {
     // Create the base Luau virtual machine.
     let lua_engine = LuaEngine::new(true);
 
+    let world_path = settings.get_string("world_path");
+    let world_meta = Self::open_world_meta(settings, &world_path);
+
+    let env_meta_path = format!("{}/env_meta.txt", world_path);
+    let env_meta = match EnvMeta::load(&env_meta_path) {
+      Ok(env_meta) => env_meta,
+      Err(e) => panic!("Server: {}", e),
+    };
+
     let mut new_server = Server {
       lua_engine,
+      authentication,
+      privileges: Rc::new(RefCell::new(Privileges::new())),
+      node_def_manager: Rc::new(RefCell::new(NodeDefManager::new())),
+      item_def_manager: ItemDefManager::new(),
+      map: Rc::new(RefCell::new(Map::new())),
       connection,
       shutdown_approved: false,
+
+      singleplayer,
+      admin_name: settings.get_string("admin_name"),
+
+      world_seed: world_meta.seed,
+      mapgen_flags: Self::get_mapgen_flags(&world_meta.mg_flags),
+      emerge_thread_count: settings.get_int("num_emerge_threads").max(1) as usize,
+      mapgen_threads: None,
+      spawn_position: Vec3::ZERO,
+
+      schematic_directories: vec![
+        world_path.clone(),
+        format!("{}/{}", GAMES_DIR, world_meta.game_id),
+      ],
+      schematic_save_directory: format!("{}/schems", world_path),
+
+      map_database: MapDatabase::new(&format!("{}/map.sqlite", world_path)),
+      player_database: PlayerDatabase::new(&format!("{}/players.sqlite", world_path)),
+      players: HashMap::new(),
+      map_save_interval: settings.get_float("server_map_save_interval").max(0.0),
+      map_save_timer: 0.0,
+
+      liquid_update_interval: settings.get_float("liquid_update").max(0.0),
+      liquid_loop_max: settings.get_int("liquid_loop_max").max(1) as usize,
+      liquid_timer: 0.0,
+
+      env_meta_path,
+      game_time: env_meta.game_time as f64,
+      lbm_introduction_times: env_meta.lbm_introduction_times,
+
+      active_block_range: settings.get_int("active_block_range").max(0) as i32,
+      active_object_range: settings.get_int("active_object_send_range_blocks").max(0) as i32,
+      active_block_list: ActiveBlockList::new(),
+      failed_emerges: vec![],
+      hibernation_timeout: DEFAULT_HIBERNATION_TIMEOUT,
+      inactive_blocks: HashMap::new(),
+      active_block_modifiers: ActiveBlockModifiers::new(),
+      loading_block_modifiers: LoadingBlockModifiers::new(),
+      abm_queue: VecDeque::new(),
+      pending_abms: vec![],
+      lbm_queue: VecDeque::new(),
+      node_timer_queue: VecDeque::new(),
+      abm_time_budget: settings.get_float("abm_time_budget").max(0.0),
+      lbm_time_budget: settings.get_float("lbm_time_budget").max(0.0),
+      node_timer_time_budget: settings.get_float("nodetimer_time_budget").max(0.0),
+      random: PseudoRandom::new(rand::random()),
+
+      block_senders: HashMap::new(),
+      viewing_range: settings.get_int("viewing_range").max(0) as f32,
+      max_block_sends: settings
+        .get_int("max_simultaneous_block_sends_per_client")
+        .max(1) as usize,
+      max_block_send_bytes: settings.get_int("max_block_send_bytes_per_client").max(1) as usize,
     };
 
     // Automatically create a new Server LuaEngine.
     new_server.reset_lua_vm();
 
-    // Automatically load up the requested game into memory.
-    new_server.load_game(game_name);
+    // Automatically load up the world's game into memory.
+    new_server.load_game(world_meta.game_id);
 
     new_server
   }
 
+  ///
+  /// Load the world.mt of the world, or write one for a new world.
+  ///
+  /// A new world takes it's game, seed and mapgen flags from the settings,
+  /// after that the world.mt has the final say.
+  ///
+  fn open_world_meta(settings: &Settings, world_path: &str) -> WorldMeta {
+    let path = format!("{}/world.mt", world_path);
+
+    let world_meta = match WorldMeta::load(&path) {
+      Ok(Some(world_meta)) => world_meta,
+      Ok(None) => {
+        let world_meta = WorldMeta {
+          game_id: settings.get_string("default_game"),
+          seed: Self::get_world_seed(settings),
+          mg_flags: settings.get_string("mg_flags"),
+        };
+
+        if let Err(e) = world_meta.save(&path) {
+          panic!("Server: {}", e);
+        }
+        println!("Server: created a new world in [{}].", world_path);
+
+        world_meta
+      }
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    println!("Server: world seed is [{}].", world_meta.seed);
+
+    world_meta
+  }
+
+  ///
+  /// Get the seed from fixed_map_seed, or roll a new one.
+  ///
+  fn get_world_seed(settings: &Settings) -> u64 {
+    let fixed_map_seed = settings.get_string("fixed_map_seed");
+
+    if fixed_map_seed.trim().is_empty() {
+      rand::random()
+    } else {
+      seed_from_string(&fixed_map_seed)
+    }
+  }
+
+  ///
+  /// Get which mapgen passes to run from a list like mg_flags.
+  ///
+  fn get_mapgen_flags(mg_flags: &str) -> MapgenFlags {
+    let (flags, unknown) = MapgenFlags::parse(mg_flags);
+
+    for flag in unknown {
+      println!(
+        "Server: unknown mapgen flag [{}] in mg_flags, ignoring it.",
+        flag
+      );
+    }
+
+    flags
+  }
+
   ///
   /// Wipe the memory of the lua VM.
   /// Automatically regenerates a blank server VM.
   ///
   pub fn reset_lua_vm(&mut self) {
     self.lua_engine = LuaEngine::new(true);
+
+    // Mods will register their privileges all over again.
+    *self.privileges.borrow_mut() = Privileges::new();
+    self.register_privilege_api();
+    self.register_schematic_api();
+    self.register_map_api();
+  }
+
+  ///
+  /// Give the server LuaEngine access to privileges.
+  ///
+  /// api.lua wraps these into minetest.register_privilege(),
+  /// minetest.check_player_privs() and friends.
+  ///
+  fn register_privilege_api(&self) {
+    let privileges = self.privileges.clone();
+    self.lua_engine.register_function(
+      "engine_register_privilege",
+      move |_, (name, description, give_to_singleplayer): (String, String, bool)| {
+        let definition = PrivilegeDefinition {
+          description,
+          give_to_singleplayer,
+        };
+        privileges
+          .borrow_mut()
+          .register(&name, definition)
+          .map_err(LuaError::RuntimeError)?;
+
+        println!("minetest: registered privilege [{}]", name);
+        Ok(())
+      },
+    );
+
+    let authentication = self.authentication.clone();
+    self
+      .lua_engine
+      .register_function("engine_get_player_privs", move |_, player_name: String| {
+        Ok(authentication.borrow().get_privileges(&player_name))
+      });
+
+    let authentication = self.authentication.clone();
+    self.lua_engine.register_function(
+      "engine_check_player_privs",
+      move |_, (player_name, wanted): (String, Vec<String>)| {
+        let missing = authentication
+          .borrow()
+          .get_missing_privileges(&player_name, &wanted);
+        Ok((missing.is_empty(), missing))
+      },
+    );
+
+    let authentication = self.authentication.clone();
+    let privileges = self.privileges.clone();
+    self.lua_engine.register_function(
+      "engine_set_player_privs",
+      move |_, (player_name, new_privileges): (String, Vec<String>)| {
+        for privilege in &new_privileges {
+          if !privileges.borrow().is_registered(privilege) {
+            println!(
+              "Server: giving [{}] the unknown privilege [{}].",
+              player_name, privilege
+            );
+          }
+        }
+
+        authentication
+          .borrow_mut()
+          .set_privileges(&player_name, &new_privileges)
+          .map_err(LuaError::RuntimeError)
+      },
+    );
+  }
+
+  ///
+  /// Give the server LuaEngine access to schematics and the map.
+  ///
+  /// api.lua wraps these into minetest.place_schematic()
+  /// and minetest.create_schematic().
+  ///
+  fn register_schematic_api(&self) {
+    let map = self.map.clone();
+    let node_def_manager = self.node_def_manager.clone();
+    let directories = self.schematic_directories.clone();
+    self.lua_engine.register_function(
+      "engine_place_schematic",
+      move |_,
+            (position, schematic, rotation, replacements, force_placement): (
+        Table,
+        Value,
+        Option<String>,
+        Option<Table>,
+        Option<bool>,
+      )| {
+        let position = vec3_from_table(&position)?.round().as_ivec3();
+        let schematic = schematic_from_value(schematic, &directories)?;
+
+        let mut random = PseudoRandom::new(rand::random());
+        let rotation_name = rotation.unwrap_or_default();
+        let rotation = match Rotation::from_name(&rotation_name, &mut random) {
+          Some(rotation) => rotation,
+          None => {
+            return Err(LuaError::RuntimeError(format!(
+              "unknown schematic rotation [{}].",
+              rotation_name
+            )))
+          }
+        };
+
+        let mut replacement_names = HashMap::new();
+        if let Some(replacements) = replacements {
+          for pair in replacements.pairs::<String, String>() {
+            let (original, replacement) = pair?;
+            replacement_names.insert(original, replacement);
+          }
+        }
+
+        schematic
+          .place_on_map(
+            &mut map.borrow_mut(),
+            &node_def_manager.borrow(),
+            position,
+            rotation,
+            &replacement_names,
+            force_placement.unwrap_or(false),
+            &mut random,
+          )
+          .map_err(LuaError::RuntimeError)?;
+
+        Ok(true)
+      },
+    );
+
+    let map = self.map.clone();
+    let node_def_manager = self.node_def_manager.clone();
+    let save_directory = self.schematic_save_directory.clone();
+    self.lua_engine.register_function(
+      "engine_create_schematic",
+      move |_,
+            (corner_1, corner_2, probabilities, path, slice_probabilities): (
+        Table,
+        Table,
+        Option<Table>,
+        String,
+        Option<Table>,
+      )| {
+        let corner_1 = vec3_from_table(&corner_1)?.round().as_ivec3();
+        let corner_2 = vec3_from_table(&corner_2)?.round().as_ivec3();
+        let min = corner_1.min(corner_2);
+
+        let mut schematic = Schematic::from_map(
+          &map.borrow(),
+          &node_def_manager.borrow(),
+          corner_1,
+          corner_2,
+        )
+        .map_err(LuaError::RuntimeError)?;
+
+        // Like {{pos = {x = 1, y = 2, z = 3}, prob = 127}}, in world positions.
+        if let Some(probabilities) = probabilities {
+          for entry in probabilities.sequence_values::<Table>() {
+            let entry = entry?;
+            let offset = vec3_from_table(&entry.get("pos")?)?.round().as_ivec3() - min;
+            if offset.min_element() < 0 || (offset - schematic.size).max_element() >= 0 {
+              continue;
+            }
+
+            let index = schematic.get_index(offset);
+            schematic.nodes[index].probability = entry.get("prob")?;
+          }
+        }
+
+        // Like {{ypos = 2, prob = 127}}, from the bottom of the schematic.
+        if let Some(slice_probabilities) = slice_probabilities {
+          schematic.y_slice_probabilities = vec![255; schematic.size.y as usize];
+          for entry in slice_probabilities.sequence_values::<Table>() {
+            let entry = entry?;
+            let y: i32 = entry.get("ypos")?;
+            if let Some(probability) = schematic.y_slice_probabilities.get_mut(y as usize) {
+              *probability = entry.get("prob")?;
+            }
+          }
+        }
+
+        let path = get_mod_write_path(&path, &save_directory)
+          .map_err(LuaError::RuntimeError)?
+          .to_string_lossy()
+          .to_string();
+        schematic.save_file(&path).map_err(LuaError::RuntimeError)?;
+
+        println!("Server: saved schematic [{}].", path);
+        Ok(true)
+      },
+    );
+  }
+
+  ///
+  /// Give the server LuaEngine access to the nodes in the map.
+  ///
+  /// api.lua wraps these into minetest.get_node(), minetest.set_node()
+  /// and minetest.get_node_timer().
+  ///
+  fn register_map_api(&self) {
+    let map = self.map.clone();
+    let node_def_manager = self.node_def_manager.clone();
+    self
+      .lua_engine
+      .register_function("engine_get_node", move |_, position: Table| {
+        let position = vec3_from_table(&position)?.round().as_ivec3();
+        let node = map.borrow().get_node_or_ignore(position);
+        let name = node_def_manager
+          .borrow()
+          .get_or_unknown(node.content)
+          .name
+          .clone();
+        Ok((name, node.param1, node.param2))
+      });
+
+    let map = self.map.clone();
+    let node_def_manager = self.node_def_manager.clone();
+    self.lua_engine.register_function(
+      "engine_set_node",
+      move |_, (position, name, param1, param2): (Table, String, u8, u8)| {
+        let position = vec3_from_table(&position)?.round().as_ivec3();
+        let node_def_manager = node_def_manager.borrow();
+        let content = match node_def_manager.get_id(&name) {
+          Some(content) => content,
+          None => {
+            return Err(LuaError::RuntimeError(format!(
+              "[{}] is not a registered node.",
+              name
+            )))
+          }
+        };
+
+        let mut map = map.borrow_mut();
+        let old_node = match map.get_node(position) {
+          Some(old_node) => old_node,
+          None => return Ok(false),
+        };
+
+        // A new node doesn't keep the old one's timer.
+        map.set_node(position, Node::new_with_params(content, param1, param2));
+        map.remove_node_timer(position);
+        map.queue_liquid_update(position);
+        update_lighting(&mut map, &node_def_manager, &[(position, old_node)]);
+
+        Ok(true)
+      },
+    );
+
+    let map = self.map.clone();
+    self.lua_engine.register_function(
+      "engine_set_node_timer",
+      move |_, (position, timeout, elapsed): (Table, f32, f32)| {
+        let position = vec3_from_table(&position)?.round().as_ivec3();
+        Ok(
+          map
+            .borrow_mut()
+            .set_node_timer(position, NodeTimer::new(timeout, elapsed)),
+        )
+      },
+    );
+
+    let map = self.map.clone();
+    self
+      .lua_engine
+      .register_function("engine_get_node_timer", move |_, position: Table| {
+        let position = vec3_from_table(&position)?.round().as_ivec3();
+        // A stopped timer is at 0.
+        Ok(match map.borrow().get_node_timer(position) {
+          Some(timer) => (timer.timeout, timer.elapsed, true),
+          None => (0.0, 0.0, false),
+        })
+      });
+
+    let map = self.map.clone();
+    self
+      .lua_engine
+      .register_function("engine_stop_node_timer", move |_, position: Table| {
+        let position = vec3_from_table(&position)?.round().as_ivec3();
+        map.borrow_mut().remove_node_timer(position);
+        Ok(())
+      });
   }
 
   ///
   /// Chain initial game load into LuaEngine to clean up new() implemenetation.
   ///
   pub fn load_game(&mut self, game_name: String) {
-    self.lua_engine.load_game(game_name)
+    self.lua_engine.load_game(game_name);
+
+    if let Some(hibernation_timeout) = self.lua_engine.get_game_setting("hibernation_timeout") {
+      match hibernation_timeout.parse::<f64>() {
+        Ok(hibernation_timeout) => self.hibernation_timeout = hibernation_timeout.max(0.0),
+        Err(e) => println!(
+          "Server: game.conf has a bad hibernation_timeout [{}], ignoring it. {}",
+          hibernation_timeout, e
+        ),
+      }
+    }
+
+    // Now that every mod is loaded, the node definitions are final.
+    let definitions = match self.lua_engine.get_registered_blocks() {
+      Ok(definitions) => definitions,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    let mut node_def_manager = match NodeDefManager::from_definitions(definitions) {
+      Ok(node_def_manager) => node_def_manager,
+      Err(e) => panic!("Server: failed to register nodes. {}", e),
+    };
+
+    let definitions = match self.lua_engine.get_registered_items() {
+      Ok(definitions) => definitions,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    let aliases = match self.lua_engine.get_registered_aliases() {
+      Ok(aliases) => aliases,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    for (alias, original) in aliases {
+      if let Err(e) = node_def_manager.register_alias(&alias, &original) {
+        panic!("Server: failed to register alias. {}", e);
+      }
+    }
+
+    self.item_def_manager = match ItemDefManager::from_definitions(definitions, &node_def_manager) {
+      Ok(item_def_manager) => item_def_manager,
+      Err(e) => panic!("Server: failed to register items. {}", e),
+    };
+
+    println!(
+      "Server: registered {} nodes and {} items.",
+      node_def_manager.get_count(),
+      self.item_def_manager.get_count()
+    );
+
+    *self.node_def_manager.borrow_mut() = node_def_manager;
+
+    // LBMs have to be ready before the first block loads.
+    self.load_block_modifiers();
+
+    self.start_mapgen();
+  }
+
+  ///
+  /// Set up the ABMs and LBMs the game registered.
+  ///
+  /// LBMs the world hasn't seen before are introduced right now.
+  ///
+  fn load_block_modifiers(&mut self) {
+    let node_def_manager = self.node_def_manager.borrow();
+
+    let definitions = match self.lua_engine.get_registered_abms() {
+      Ok(definitions) => definitions,
+      Err(e) => panic!("Server: {}", e),
+    };
+    self.active_block_modifiers =
+      match ActiveBlockModifiers::from_definitions(&definitions, &node_def_manager) {
+        Ok(active_block_modifiers) => active_block_modifiers,
+        Err(e) => panic!("Server: failed to register ABMs. {}", e),
+      };
+
+    let definitions = match self.lua_engine.get_registered_lbms() {
+      Ok(definitions) => definitions,
+      Err(e) => panic!("Server: {}", e),
+    };
+    self.loading_block_modifiers = match LoadingBlockModifiers::from_definitions(
+      &definitions,
+      &node_def_manager,
+      &mut self.lbm_introduction_times,
+      self.game_time as u32,
+    ) {
+      Ok(loading_block_modifiers) => loading_block_modifiers,
+      Err(e) => panic!("Server: failed to register LBMs. {}", e),
+    };
+
+    println!(
+      "Server: registered {} ABMs and {} LBMs.",
+      self.active_block_modifiers.get_count(),
+      self.loading_block_modifiers.get_count()
+    );
+  }
+
+  ///
+  /// Spin up the mapgen threads with the nodes the game picked,
+  /// and get the area around spawn going.
+  ///
+  fn start_mapgen(&mut self) {
+    let nodes = match MapgenNodes::from_node_def_manager(&self.node_def_manager.borrow()) {
+      Ok(nodes) => nodes,
+      Err(e) => panic!("Server: can't generate a map, {}", e),
+    };
+
+    let params = MapgenParams {
+      flags: self.mapgen_flags,
+      ..MapgenParams::new(self.world_seed)
+    };
+
+    let mut mapgen = match Mapgen::new(params, nodes) {
+      Ok(mapgen) => mapgen,
+      Err(e) => panic!("Server: {}", e),
+    };
+
+    self.register_mapgen_content(&mut mapgen);
+
+    let spawn = IVec3::new(0, mapgen.get_surface_height(0, 0), 0);
+    self.spawn_position = (spawn + IVec3::Y).as_vec3();
+
+    self.mapgen_threads = Some(MapgenThreads::new(mapgen, self.emerge_thread_count));
+
+    let spawn_block = node_to_block_position(spawn);
+    for z in -1..=1 {
+      for y in -1..=1 {
+        for x in -1..=1 {
+          self.emerge_block(spawn_block + IVec3::new(x, y, z));
+        }
+      }
+    }
+  }
+
+  ///
+  /// Hand the biomes, ores and decorations the game registered to the mapgen.
+  ///
+  /// Biomes go first, decorations check the biome names they use.
+  ///
+  fn register_mapgen_content(&self, mapgen: &mut Mapgen) {
+    let node_def_manager = self.node_def_manager.borrow();
+
+    let biomes = match self.lua_engine.get_registered_biomes() {
+      Ok(biomes) => biomes,
+      Err(e) => panic!("Server: {}", e),
+    };
+    for definition in &biomes {
+      if let Err(e) = mapgen.register_biome(definition, &node_def_manager) {
+        panic!("Server: failed to register biome. {}", e);
+      }
+    }
+
+    let ores = match self.lua_engine.get_registered_ores() {
+      Ok(ores) => ores,
+      Err(e) => panic!("Server: {}", e),
+    };
+    for definition in ores {
+      if let Err(e) = mapgen.register_ore(definition, &node_def_manager) {
+        panic!("Server: failed to register ore. {}", e);
+      }
+    }
+
+    let decorations = match self
+      .lua_engine
+      .get_registered_decorations(&self.schematic_directories)
+    {
+      Ok(decorations) => decorations,
+      Err(e) => panic!("Server: {}", e),
+    };
+    for definition in decorations {
+      if let Err(e) = mapgen.register_decoration(definition, &node_def_manager) {
+        panic!("Server: failed to register decoration. {}", e);
+      }
+    }
+
+    println!(
+      "Server: registered {} biomes, {} ores and {} decorations.",
+      mapgen.get_biome_manager().get_count(),
+      mapgen.get_ore_count(),
+      mapgen.get_decoration_count()
+    );
+  }
+
+  ///
+  /// Bring a MapBlock into the map, if it's not in there yet.
+  ///
+  /// Blocks which were saved before are loaded right away,
+  /// anything else gets queued up to be generated.
+  ///
+  /// Returns false if there's nothing to do.
+  ///
+  pub fn emerge_block(&mut self, block_position: IVec3) -> bool {
+    if self.map.borrow().has_block(block_position) {
+      return false;
+    }
+
+    if self.load_block(block_position) {
+      return true;
+    }
+
+    match &mut self.mapgen_threads {
+      Some(mapgen_threads) => mapgen_threads.request_block(block_position),
+      None => false,
+    }
+  }
+
+  ///
+  /// Load a MapBlock from the map database into the map.
+  ///
+  /// Worlds made by C++ minetest get their blocks converted as they load.
+  ///
+  /// LBMs which go off on it get queued up to run.
+  ///
+  /// Returns false if it was never saved, or is too broken to load.
+  ///
+  fn load_block(&mut self, block_position: IVec3) -> bool {
+    let data = match self.map_database.load_block(block_position) {
+      Ok(Some(data)) => data,
+      Ok(None) => return false,
+      Err(e) => {
+        println!("Server: {}", e);
+        return false;
+      }
+    };
+
+    let node_def_manager = self.node_def_manager.borrow();
+    let block = if is_legacy_block(&data) {
+      deserialize_legacy_block(&data, &node_def_manager)
+    } else {
+      deserialize_block(&data, &node_def_manager)
+    };
+
+    match block {
+      Ok(block) => {
+        let triggers = self
+          .loading_block_modifiers
+          .find_triggers(&block, block_position);
+        let timestamp = block.get_timestamp();
+
+        let mut map = self.map.borrow_mut();
+        map.insert_loaded_block(block_position, block);
+
+        if !triggers.is_empty() {
+          let game_time = self.game_time as u32;
+          let dtime = match timestamp {
+            BLOCK_TIMESTAMP_UNDEFINED => 0,
+            timestamp => game_time.saturating_sub(timestamp),
+          };
+
+          // Saved with the new timestamp, so they don't go off on it again.
+          map.set_block_timestamp(block_position, game_time);
+          map.mark_block_dirty(block_position);
+
+          self
+            .lbm_queue
+            .extend(triggers.into_iter().map(|trigger| (trigger, dtime)));
+        }
+        true
+      }
+      Err(e) => {
+        println!(
+          "Server: MapBlock {} is broken, it will be generated again. {}",
+          block_position, e
+        );
+        false
+      }
+    }
+  }
+
+  ///
+  /// Write every dirty MapBlock into the map database.
+  ///
+  fn save_map(&mut self) {
+    let block_positions = self.map.borrow_mut().take_dirty_blocks();
+
+    if !self.save_blocks(&block_positions) {
+      // Try again next time.
+      let mut map = self.map.borrow_mut();
+      for block_position in block_positions {
+        map.mark_block_dirty(block_position);
+      }
+    }
+  }
+
+  ///
+  /// Write some MapBlocks into the map database.
+  ///
+  /// Returns false if the database wouldn't take them.
+  ///
+  fn save_blocks(&mut self, block_positions: &[IVec3]) -> bool {
+    let mut map = self.map.borrow_mut();
+    let node_def_manager = self.node_def_manager.borrow();
+
+    let mut blocks = vec![];
+    for block_position in block_positions.iter().copied() {
+      map.set_block_timestamp(block_position, self.game_time as u32);
+      let block = match map.get_block(block_position) {
+        Some(block) => block,
+        None => continue,
+      };
+
+      match serialize_block(block, &node_def_manager) {
+        Ok(data) => blocks.push((block_position, data)),
+        Err(e) => println!("Server: can't save MapBlock {}. {}", block_position, e),
+      }
+    }
+
+    if blocks.is_empty() {
+      return true;
+    }
+
+    match self.map_database.save_blocks(blocks) {
+      Ok(_) => true,
+      Err(e) => {
+        println!("Server: failed to save the map. {}", e);
+        false
+      }
+    }
+  }
+
+  ///
+  /// Write the game time and LBM introduction times into env_meta.txt.
+  ///
+  fn save_env_meta(&self) {
+    let env_meta = EnvMeta {
+      game_time: self.game_time as u32,
+      lbm_introduction_times: self.lbm_introduction_times.clone(),
+    };
+
+    if let Err(e) = env_meta.save(&self.env_meta_path) {
+      println!("Server: {}", e);
+    }
+  }
+
+  ///
+  /// Write every player in the game into the player database.
+  ///
+  fn save_players(&self) {
+    for (player_name, player_data) in &self.players {
+      if let Err(e) = self.player_database.save_player(player_name, player_data) {
+        println!("Server: failed to save [{}]. {}", player_name, e);
+      }
+    }
+  }
+
+  ///
+  /// Put every MapBlock the mapgen threads finished into the map, and light it.
+  ///
+  /// Columns without a block above are lit by the sky if they're above the ground.
+  ///
+  fn receive_generated_blocks(&mut self) {
+    let mapgen_threads = match &mut self.mapgen_threads {
+      Some(mapgen_threads) => mapgen_threads,
+      None => return,
+    };
+
+    let blocks = mapgen_threads.receive_blocks();
+    let mapgen = mapgen_threads.get_mapgen();
+
+    let mut map = self.map.borrow_mut();
+    let node_def_manager = self.node_def_manager.borrow();
+    for (block_position, block) in blocks {
+      map.insert_block(block_position, block);
+      map.set_block_timestamp(block_position, self.game_time as u32);
+
+      let top = block_to_node_position(block_position).y + MAP_BLOCK_SIZE - 1;
+      light_block(&mut map, &node_def_manager, block_position, |x, z| {
+        top >= mapgen.get_surface_height(x, z)
+      });
+    }
+  }
+
+  ///
+  /// Work out which blocks are active around the players again,
+  /// and bring the ones which just became active into the map.
+  ///
+  /// Ones which didn't make it in last time get another try,
+  /// as long as they're still active.
+  ///
+  fn update_active_blocks(&mut self) {
+    let player_positions: Vec<Vec3> = self
+      .players
+      .values()
+      .map(|player_data| player_data.position)
+      .collect();
+
+    let added = self.active_block_list.update_around_players(
+      &player_positions,
+      self.active_block_range,
+      self.active_object_range,
+    );
+
+    let retrying: Vec<IVec3> = std::mem::take(&mut self.failed_emerges)
+      .into_iter()
+      .filter(|block_position| self.active_block_list.contains(*block_position))
+      .collect();
+
+    for block_position in added.into_iter().chain(retrying) {
+      if self.emerge_block(block_position) || self.map.borrow().has_block(block_position) {
+        continue;
+      }
+
+      let requested = self
+        .mapgen_threads
+        .as_ref()
+        .is_some_and(|mapgen_threads| mapgen_threads.is_requested(block_position));
+      if !requested {
+        self.failed_emerges.push(block_position);
+      }
+    }
+  }
+
+  ///
+  /// Save and unload every MapBlock which nobody has been
+  /// around for hibernation_timeout seconds.
+  ///
+  fn unload_inactive_blocks(&mut self) {
+    let block_positions = self.map.borrow().get_block_positions();
+
+    let mut unloading = vec![];
+    for block_position in block_positions {
+      if self.active_block_list.contains(block_position) {
+        self.inactive_blocks.remove(&block_position);
+        continue;
+      }
+
+      let inactive_since = *self
+        .inactive_blocks
+        .entry(block_position)
+        .or_insert(self.game_time);
+      if self.game_time - inactive_since >= self.hibernation_timeout {
+        unloading.push(block_position);
+      }
+    }
+
+    if unloading.is_empty() {
+      return;
+    }
+
+    let dirty: Vec<IVec3> = {
+      let map = self.map.borrow();
+      unloading
+        .iter()
+        .copied()
+        .filter(|block_position| map.is_block_dirty(*block_position))
+        .collect()
+    };
+
+    // They stay around until they can be saved.
+    if !self.save_blocks(&dirty) {
+      return;
+    }
+
+    let mut map = self.map.borrow_mut();
+    for block_position in &unloading {
+      map.remove_block(*block_position);
+      self.inactive_blocks.remove(block_position);
+    }
+
+    println!(
+      "Server: unloaded {} MapBlocks, {} are loaded and {} are active.",
+      unloading.len(),
+      map.get_block_count(),
+      self.active_block_list.get_active_block_count()
+    );
+  }
+
+  ///
+  /// Get how many MapBlocks are close enough to a player to run ABMs and node timers.
+  ///
+  pub fn get_active_block_count(&self) -> usize {
+    self.active_block_list.get_active_block_count()
+  }
+
+  ///
+  /// Get how many MapBlocks are close enough to a player for objects to be active in.
+  ///
+  pub fn get_active_object_block_count(&self) -> usize {
+    self.active_block_list.get_active_object_block_count()
+  }
+
+  ///
+  /// Get how many MapBlocks are loaded, active or not.
+  ///
+  pub fn get_loaded_block_count(&self) -> usize {
+    self.map.borrow().get_block_count()
+  }
+
+  ///
+  /// Run ABMs, LBMs and node timers.
+  ///
+  /// Each one gets it's own time budget every tick, whatever
+  /// doesn't fit waits in it's queue for the next tick.
+  /// A round of ABMs which comes up while the last one is
+  /// still waiting goes once the queue is empty. Each ABM only
+  /// ever has one round waiting, so they never pile up.
+  ///
+  fn step_block_modifiers(&mut self, delta: f64) {
+    let active_blocks: Vec<IVec3> = {
+      let map = self.map.borrow();
+      self
+        .active_block_list
+        .get_active_blocks()
+        .iter()
+        .copied()
+        .filter(|block_position| map.has_block(*block_position))
+        .collect()
+    };
+
+    {
+      let mut map = self.map.borrow_mut();
+      for block_position in &active_blocks {
+        self
+          .node_timer_queue
+          .extend(map.step_node_timers(*block_position, delta as f32));
+      }
+    }
+
+    for abm in self.active_block_modifiers.step(delta) {
+      if !self.pending_abms.contains(&abm) {
+        self.pending_abms.push(abm);
+      }
+    }
+
+    if !self.pending_abms.is_empty() && self.abm_queue.is_empty() {
+      let due = std::mem::take(&mut self.pending_abms);
+      let map = self.map.borrow();
+      for block_position in &active_blocks {
+        self
+          .abm_queue
+          .extend(self.active_block_modifiers.find_triggers(
+            &map,
+            *block_position,
+            &due,
+            &mut self.random,
+          ));
+      }
+    }
+
+    self.run_lbms();
+    self.run_abms();
+    self.run_node_timers();
+  }
+
+  ///
+  /// Get the node at a position and it's name, if it's still loaded.
+  ///
+  fn get_node_with_name(&self, position: IVec3) -> Option<(Node, String)> {
+    let node = self.map.borrow().get_node(position)?;
+    let name = self
+      .node_def_manager
+      .borrow()
+      .get_or_unknown(node.content)
+      .name
+      .clone();
+    Some((node, name))
+  }
+
+  fn run_abms(&mut self) {
+    let start = Instant::now();
+
+    while let Some(trigger) = self.abm_queue.pop_front() {
+      // The node might have changed since it was picked.
+      match self.get_node_with_name(trigger.position) {
+        Some((node, name))
+          if self
+            .active_block_modifiers
+            .matches(trigger.modifier, node.content) =>
+        {
+          self
+            .lua_engine
+            .run_abm(trigger.modifier, trigger.position, &name, node)
+        }
+        _ => continue,
+      }
+
+      if start.elapsed().as_secs_f64() >= self.abm_time_budget {
+        break;
+      }
+    }
+  }
+
+  fn run_lbms(&mut self) {
+    let start = Instant::now();
+
+    while let Some((trigger, dtime)) = self.lbm_queue.pop_front() {
+      match self.get_node_with_name(trigger.position) {
+        Some((node, name))
+          if self
+            .loading_block_modifiers
+            .matches(trigger.modifier, node.content) =>
+        {
+          self
+            .lua_engine
+            .run_lbm(trigger.modifier, trigger.position, &name, node, dtime)
+        }
+        _ => continue,
+      }
+
+      if start.elapsed().as_secs_f64() >= self.lbm_time_budget {
+        break;
+      }
+    }
+  }
+
+  fn run_node_timers(&mut self) {
+    let start = Instant::now();
+
+    while let Some((position, timer)) = self.node_timer_queue.pop_front() {
+      let name = match self.get_node_with_name(position) {
+        Some((_, name)) => name,
+        None => continue,
+      };
+
+      if self
+        .lua_engine
+        .run_node_timer(position, &name, timer.elapsed)
+      {
+        self
+          .map
+          .borrow_mut()
+          .set_node_timer(position, NodeTimer::new(timer.timeout, 0.0));
+      }
+
+      if start.elapsed().as_secs_f64() >= self.node_timer_time_budget {
+        break;
+      }
+    }
+  }
+
+  ///
+  /// Stream the map out to every player.
+  ///
+  /// Everyone gets told about nodes which changed in blocks they have,
+  /// then gets the blocks around them they don't have yet. Blocks they
+  /// want which aren't loaded get brought in for a later tick.
+  ///
+  fn send_blocks(&mut self) {
+    let (changed_nodes, replaced_blocks) = {
+      let mut map = self.map.borrow_mut();
+      (map.take_changed_nodes(), map.take_replaced_blocks())
+    };
+
+    let mut packets = vec![];
+    let mut missing = vec![];
+    {
+      let map = self.map.borrow();
+      let node_def_manager = self.node_def_manager.borrow();
+
+      for (player_name, block_sender) in self.block_senders.iter_mut() {
+        block_sender.queue_changes(&changed_nodes, &replaced_blocks);
+
+        let (player_data, peer_id) = match (
+          self.players.get(player_name),
+          self.connection.get_peer_by_name(player_name),
+        ) {
+          (Some(player_data), Some(peer_id)) => (player_data, peer_id),
+          _ => continue,
+        };
+
+        let wanted = block_sender.prioritize(
+          player_data.position,
+          look_direction(player_data.pitch, player_data.yaw),
+          self.viewing_range,
+        );
+
+        for packet in block_sender.send(
+          &map,
+          &node_def_manager,
+          &wanted,
+          self.max_block_sends,
+          self.max_block_send_bytes,
+        ) {
+          packets.push((peer_id, packet));
+        }
+
+        let mapgen_threads = self.mapgen_threads.as_ref();
+        missing.extend(
+          wanted
+            .into_iter()
+            .filter(|block_position| {
+              !map.has_block(*block_position)
+                && !mapgen_threads.is_some_and(|threads| threads.is_requested(*block_position))
+            })
+            .take(self.max_block_sends),
+        );
+      }
+    }
+
+    for (peer_id, packet) in packets {
+      self.connection.send_packet(peer_id, &packet);
+    }
+
+    for block_position in missing {
+      self.emerge_block(block_position);
+    }
+  }
+
+  ///
+  /// Get how many seconds the world has been running for.
+  ///
+  pub fn get_game_time(&self) -> f64 {
+    self.game_time
+  }
+
+  ///
+  /// Get the map the server holds.
+  ///
+  pub fn get_map(&self) -> Ref<'_, Map> {
+    self.map.borrow()
+  }
+
+  ///
+  /// Get every node type the loaded game registered.
+  ///
+  pub fn get_node_def_manager(&self) -> Ref<'_, NodeDefManager> {
+    self.node_def_manager.borrow()
+  }
+
+  ///
+  /// Get every item type the loaded game registered.
+  ///
+  pub fn get_item_def_manager(&self) -> &ItemDefManager {
+    &self.item_def_manager
   }
 
   ///
@@ -69,21 +1406,133 @@ impl Server {
   }
 
   ///
-  /// ! (will) [not implemented yet]
-  /// todo: implement this somehow
-  /// Automatically validate and accept/deny shutdown requests
+  /// Automatically validate and accept/deny shutdown requests.
+  ///
+  /// Only players with the server privilege can shut the server down.
   ///
   fn check_shutdown_requests(&mut self) {
     // Let's clear out the entire list so we don't cause a memory leak.
-    // ! This NEEDS to check the database for a bad actor.
-    while !self.connection.shutdown_requests.is_empty() {
-      println!("looping");
-      if let Some(shutdown_requester) = self.connection.shutdown_requests.pop() {
-        println!(
-          "Server: shutdown requested by [{}]",
-          shutdown_requester.addr()
-        );
-        self.shutdown_approved = true
+    while let Some(shutdown_requester) = self.connection.shutdown_requests.pop() {
+      let player_name = match self.connection.get_player_name(shutdown_requester) {
+        Some(player_name) => player_name.clone(),
+        None => continue,
+      };
+
+      println!(
+        "Server: shutdown requested by [{}] from [{}]",
+        player_name,
+        self.connection.get_peer_address(shutdown_requester)
+      );
+
+      let missing = self
+        .authentication
+        .borrow()
+        .get_missing_privileges(&player_name, &["server".to_string()]);
+
+      if missing.is_empty() {
+        self.shutdown_approved = true;
+        continue;
+      }
+
+      println!("Server: denied shutdown request from [{}].", player_name);
+      self.connection.send_packet(
+        shutdown_requester,
+        &Packet::ChatMessage {
+          sender: String::new(),
+          message: format!(
+            "You don't have permission to shut down the server. (missing privileges: {})",
+            missing.join(", ")
+          ),
+        },
+      );
+    }
+  }
+
+  ///
+  /// Give a player who just joined the privileges they always have.
+  ///
+  fn grant_automatic_privileges(&mut self, player_name: &str) {
+    let automatic = if self.singleplayer {
+      self.privileges.borrow().get_singleplayer_names()
+    } else if !self.admin_name.is_empty() && player_name == self.admin_name {
+      self.privileges.borrow().get_names()
+    } else {
+      return;
+    };
+
+    if let Err(e) = self
+      .authentication
+      .borrow_mut()
+      .grant_privileges(player_name, &automatic)
+    {
+      println!("Server: {}", e);
+    }
+  }
+
+  ///
+  /// Hand players joining and leaving over to Lua.
+  ///
+  fn process_session_events(&mut self) {
+    let events: Vec<SessionEvent> = self.connection.session_events.drain(..).collect();
+
+    for event in events {
+      match event {
+        SessionEvent::Join {
+          peer_id,
+          player_name,
+        } => {
+          println!("Server: [{}] joined the game.", player_name);
+
+          // The client needs these before any MapBlock makes sense.
+          let definitions = self.node_def_manager.borrow().get_definitions();
+          self
+            .connection
+            .send_packet(peer_id, &Packet::NodeDefinitions { definitions });
+
+          let player_data = match self.player_database.load_player(&player_name) {
+            Ok(Some(player_data)) => player_data,
+            Ok(None) => PlayerData::new(self.spawn_position),
+            Err(e) => {
+              println!("Server: failed to load [{}]. {}", player_name, e);
+              PlayerData::new(self.spawn_position)
+            }
+          };
+          self.players.insert(player_name.clone(), player_data);
+          self
+            .block_senders
+            .insert(player_name.clone(), BlockSender::new());
+
+          self.grant_automatic_privileges(&player_name);
+          self.lua_engine.on_join_player(&player_name);
+        }
+        SessionEvent::Leave {
+          player_name,
+          timed_out,
+          ..
+        } => {
+          println!("Server: [{}] left the game.", player_name);
+
+          self.block_senders.remove(&player_name);
+          if let Some(player_data) = self.players.remove(&player_name) {
+            if let Err(e) = self.player_database.save_player(&player_name, &player_data) {
+              println!("Server: failed to save [{}]. {}", player_name, e);
+            }
+          }
+
+          self.lua_engine.on_leave_player(&player_name, timed_out);
+        }
+        SessionEvent::Move {
+          player_name,
+          position,
+          rotation,
+          ..
+        } => {
+          if let Some(player_data) = self.players.get_mut(&player_name) {
+            player_data.position = position;
+            player_data.pitch = rotation.x;
+            player_data.yaw = rotation.y;
+          }
+        }
       }
     }
   }
@@ -101,19 +1550,50 @@ impl Server {
   pub fn on_tick(&mut self, delta: f64) {
     // Process any incoming network traffic. (non blocking)
 
-    self.connection.receive();
+    self.connection.receive(delta);
+    self.process_session_events();
+
+    self.game_time += delta;
+    self.update_active_blocks();
+    self.receive_generated_blocks();
+    self.unload_inactive_blocks();
+
+    self.liquid_timer += delta;
+    if self.liquid_timer >= self.liquid_update_interval {
+      self.liquid_timer = 0.0;
+      transform_liquids(
+        &mut self.map.borrow_mut(),
+        &self.node_def_manager.borrow(),
+        self.liquid_loop_max,
+      );
+    }
+
+    self.map_save_timer += delta;
+    if self.map_save_timer >= self.map_save_interval {
+      self.map_save_timer = 0.0;
+      self.save_map();
+      self.save_players();
+      self.save_env_meta();
+    }
 
     self.check_shutdown_requests();
     if self.shutdown_approved {
       return;
     }
 
+    self.step_block_modifiers(delta);
     self.lua_engine.on_tick(delta);
+    self.send_blocks();
   }
 }
 
 impl Drop for Server {
   fn drop(&mut self) {
+    // Nothing gets lost on the way out.
+    self.save_map();
+    self.save_players();
+    self.save_env_meta();
+
     println!("Server dropped!");
   }
 }
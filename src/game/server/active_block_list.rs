@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use glam::{IVec3, Vec3};
+
+use crate::game::map::node_to_block_position;
+
+///
+/// Which MapBlocks are active, the ones close enough to a player
+/// for something to be happening in them.
+///
+/// Active blocks are the ones within active_block_range of a player,
+/// ABMs and node timers run in them. Active object blocks are the ones
+/// within active_object_send_range_blocks, where objects would be
+/// simulated. Both are kept loaded.
+///
+/// Both ranges are spheres around the block each player is in.
+///
+pub struct ActiveBlockList {
+  active_blocks: HashSet<IVec3>,
+  active_object_blocks: HashSet<IVec3>,
+}
+
+impl ActiveBlockList {
+  pub fn new() -> Self {
+    ActiveBlockList {
+      active_blocks: HashSet::new(),
+      active_object_blocks: HashSet::new(),
+    }
+  }
+
+  ///
+  /// Work the sets out again from the blocks players are in.
+  ///
+  /// Returns every block which wasn't in either set before.
+  ///
+  pub fn update(
+    &mut self,
+    player_blocks: &[IVec3],
+    active_block_range: i32,
+    active_object_range: i32,
+  ) -> Vec<IVec3> {
+    let active_blocks = fill_radius(player_blocks, active_block_range);
+    let active_object_blocks = fill_radius(player_blocks, active_object_range);
+
+    let mut added: Vec<IVec3> = active_blocks
+      .union(&active_object_blocks)
+      .filter(|block_position| !self.contains(**block_position))
+      .copied()
+      .collect();
+    added.sort_by_key(|block_position| (block_position.x, block_position.y, block_position.z));
+
+    self.active_blocks = active_blocks;
+    self.active_object_blocks = active_object_blocks;
+
+    added
+  }
+
+  ///
+  /// Work the sets out again from where the players are in the world.
+  ///
+  /// Returns every block which wasn't in either set before.
+  ///
+  pub fn update_around_players(
+    &mut self,
+    player_positions: &[Vec3],
+    active_block_range: i32,
+    active_object_range: i32,
+  ) -> Vec<IVec3> {
+    let player_blocks: Vec<IVec3> = player_positions
+      .iter()
+      .map(|position| node_to_block_position(position.round().as_ivec3()))
+      .collect();
+
+    self.update(&player_blocks, active_block_range, active_object_range)
+  }
+
+  ///
+  /// Check if a block is in either set, so it has to stay loaded.
+  ///
+  pub fn contains(&self, block_position: IVec3) -> bool {
+    self.active_blocks.contains(&block_position)
+      || self.active_object_blocks.contains(&block_position)
+  }
+
+  ///
+  /// Check if a block is close enough to a player to run ABMs and node timers.
+  ///
+  pub fn is_active(&self, block_position: IVec3) -> bool {
+    self.active_blocks.contains(&block_position)
+  }
+
+  ///
+  /// Get every active block.
+  ///
+  pub fn get_active_blocks(&self) -> &HashSet<IVec3> {
+    &self.active_blocks
+  }
+
+  pub fn get_active_block_count(&self) -> usize {
+    self.active_blocks.len()
+  }
+
+  pub fn get_active_object_block_count(&self) -> usize {
+    self.active_object_blocks.len()
+  }
+}
+
+impl Default for ActiveBlockList {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+///
+/// Get every block within a radius of any of the centers.
+///
+fn fill_radius(centers: &[IVec3], radius: i32) -> HashSet<IVec3> {
+  let mut blocks = HashSet::new();
+
+  for center in centers {
+    for z in -radius..=radius {
+      for y in -radius..=radius {
+        for x in -radius..=radius {
+          let offset = IVec3::new(x, y, z);
+          if offset.length_squared() <= radius * radius {
+            blocks.insert(*center + offset);
+          }
+        }
+      }
+    }
+  }
+
+  blocks
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{IVec3, Vec3};
+
+  use super::ActiveBlockList;
+
+  #[test]
+  fn test_active_block_list() {
+    let mut list = ActiveBlockList::new();
+
+    // Range 0 is only the block the player is in.
+    let added = list.update(&[IVec3::ZERO], 0, 1);
+    assert_eq!(added.len(), 7);
+    assert_eq!(list.get_active_block_count(), 1);
+    assert_eq!(list.get_active_object_block_count(), 7);
+    assert!(list.is_active(IVec3::ZERO));
+    assert!(!list.is_active(IVec3::X));
+    assert!(list.contains(IVec3::X));
+    assert!(!list.contains(IVec3::new(1, 1, 0)));
+
+    // Nothing new if nobody moved.
+    assert!(list.update(&[IVec3::ZERO], 0, 1).is_empty());
+
+    // Moving one block over only adds the far side.
+    let added = list.update(&[IVec3::X], 0, 1);
+    assert_eq!(
+      added,
+      vec![
+        IVec3::new(1, -1, 0),
+        IVec3::new(1, 0, -1),
+        IVec3::new(1, 0, 1),
+        IVec3::new(1, 1, 0),
+        IVec3::new(2, 0, 0)
+      ]
+    );
+    assert!(!list.contains(IVec3::NEG_X));
+
+    // Two players far apart each get their own.
+    list.update(&[IVec3::ZERO, IVec3::new(100, 0, 0)], 2, 2);
+    assert_eq!(list.get_active_block_count(), 2 * 33);
+    assert!(list.is_active(IVec3::new(98, 0, 0)));
+
+    // Blocks go active around where the player actually is.
+    let added = list.update_around_players(&[Vec3::new(-40.0, 20.0, 100.0)], 1, 1);
+    assert_eq!(added.len(), 7);
+    assert!(list.is_active(IVec3::new(-3, 1, 6)));
+    assert!(list.is_active(IVec3::new(-3, 1, 7)));
+    assert!(!list.is_active(IVec3::new(2, -2, -7)));
+    assert!(!list.contains(IVec3::ZERO));
+
+    // Nobody online, nothing is active.
+    list.update(&[], 2, 2);
+    assert!(list.get_active_blocks().is_empty());
+  }
+}
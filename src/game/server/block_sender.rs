@@ -0,0 +1,423 @@
+use ahash::{AHashMap, AHashSet};
+use glam::{IVec3, Vec3};
+
+use crate::game::{
+  map::{
+    block_to_node_position, map_block::MAP_BLOCK_SIZE, map_serialization::serialize_block,
+    node_def_manager::NodeDefManager, node_to_block_position, Map,
+  },
+  protocol::Packet,
+};
+
+///
+/// A MapBlock with more changed nodes than this waiting
+/// to go out gets sent whole again instead.
+///
+pub const MAX_NODE_CHANGES_PER_BLOCK: usize = 64;
+
+///
+/// Blocks the client has stay tracked until they're this many nodes
+/// past viewing_range, so walking back and forth on the edge
+/// doesn't send them over and over.
+///
+const FORGET_MARGIN: f32 = 2.0 * MAP_BLOCK_SIZE as f32;
+
+///
+/// Roughly how many bytes a NodeChanged takes, not counting the name.
+///
+const NODE_CHANGED_SIZE: usize = 24;
+
+///
+/// Get which way a camera with this pitch and yaw looks.
+///
+/// Same as the client's camera, a yaw of 0 looks down -Z
+/// and a positive pitch looks down.
+///
+pub fn look_direction(pitch: f32, yaw: f32) -> Vec3 {
+  Vec3::new(
+    yaw.sin() * pitch.cos(),
+    -pitch.sin(),
+    -yaw.cos() * pitch.cos(),
+  )
+}
+
+///
+/// Get the position of the middle of a MapBlock.
+///
+fn block_center(block_position: IVec3) -> Vec3 {
+  block_to_node_position(block_position).as_vec3() + Vec3::splat((MAP_BLOCK_SIZE - 1) as f32 / 2.0)
+}
+
+///
+/// Work out how soon a block should be sent, lower goes first.
+///
+/// It's the distance to the block, doubled for blocks off to the side
+/// and tripled for blocks behind the camera. Blocks right around
+/// the camera are close whichever way it looks.
+///
+fn block_priority(offset: Vec3, direction: Vec3) -> f32 {
+  let distance = offset.length();
+  if distance < MAP_BLOCK_SIZE as f32 {
+    return distance;
+  }
+
+  let facing = direction.normalize_or_zero().dot(offset / distance);
+  distance * (2.0 - facing)
+}
+
+///
+/// Streams the map to one client.
+///
+/// The first time a client gets a MapBlock it gets the whole thing,
+/// after that only the nodes which change in it. The client holds on
+/// to every block it was sent until it wanders far enough away.
+///
+/// Every tick only gets so many packets and bytes,
+/// whatever doesn't fit waits for the next one.
+///
+pub struct BlockSender {
+  sent_blocks: AHashSet<IVec3>,
+  // Nodes which changed in blocks the client has, by block.
+  pending_nodes: AHashMap<IVec3, Vec<IVec3>>,
+}
+
+impl BlockSender {
+  pub fn new() -> Self {
+    BlockSender {
+      sent_blocks: AHashSet::new(),
+      pending_nodes: AHashMap::new(),
+    }
+  }
+
+  ///
+  /// Check if the client was sent a MapBlock.
+  ///
+  pub fn has_block(&self, block_position: IVec3) -> bool {
+    self.sent_blocks.contains(&block_position)
+  }
+
+  ///
+  /// Get how many MapBlocks the client is holding.
+  ///
+  pub fn get_sent_block_count(&self) -> usize {
+    self.sent_blocks.len()
+  }
+
+  ///
+  /// Get how many node changes are waiting to go out.
+  ///
+  pub fn get_pending_node_count(&self) -> usize {
+    self
+      .pending_nodes
+      .values()
+      .map(|pending| pending.len())
+      .sum()
+  }
+
+  ///
+  /// Act like the client never got a MapBlock, so it gets sent whole again.
+  ///
+  fn forget_block(&mut self, block_position: IVec3) {
+    self.sent_blocks.remove(&block_position);
+    self.pending_nodes.remove(&block_position);
+  }
+
+  ///
+  /// Take in the nodes and blocks which changed in the map.
+  ///
+  /// Only the ones in blocks the client has matter. A block which
+  /// was replaced, or has too many changes, gets sent whole again.
+  ///
+  pub fn queue_changes(&mut self, changed_nodes: &[IVec3], replaced_blocks: &[IVec3]) {
+    for block_position in replaced_blocks {
+      self.forget_block(*block_position);
+    }
+
+    for position in changed_nodes {
+      let block_position = node_to_block_position(*position);
+      if !self.sent_blocks.contains(&block_position) {
+        continue;
+      }
+
+      let pending = self.pending_nodes.entry(block_position).or_default();
+      if !pending.contains(position) {
+        pending.push(*position);
+      }
+
+      if pending.len() > MAX_NODE_CHANGES_PER_BLOCK {
+        self.forget_block(block_position);
+      }
+    }
+  }
+
+  ///
+  /// Get every block within viewing_range the client doesn't have yet,
+  /// in the order they should go out.
+  ///
+  /// Blocks the client has which are well past viewing_range are
+  /// forgotten, they get sent again if it comes back.
+  ///
+  pub fn prioritize(&mut self, position: Vec3, direction: Vec3, viewing_range: f32) -> Vec<IVec3> {
+    let forget_range = viewing_range + FORGET_MARGIN;
+    let far: Vec<IVec3> = self
+      .sent_blocks
+      .iter()
+      .copied()
+      .filter(|block_position| block_center(*block_position).distance(position) > forget_range)
+      .collect();
+    for block_position in far {
+      self.forget_block(block_position);
+    }
+
+    let center = node_to_block_position(position.round().as_ivec3());
+    let radius = (viewing_range / MAP_BLOCK_SIZE as f32).ceil() as i32 + 1;
+
+    let mut wanted = vec![];
+    for z in -radius..=radius {
+      for y in -radius..=radius {
+        for x in -radius..=radius {
+          let block_position = center + IVec3::new(x, y, z);
+          if self.sent_blocks.contains(&block_position) {
+            continue;
+          }
+
+          let offset = block_center(block_position) - position;
+          if offset.length() > viewing_range {
+            continue;
+          }
+
+          wanted.push((block_priority(offset, direction), block_position));
+        }
+      }
+    }
+
+    wanted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    wanted
+      .into_iter()
+      .map(|(_, block_position)| block_position)
+      .collect()
+  }
+
+  ///
+  /// Build the packets for this tick, without going over max_packets or max_bytes.
+  ///
+  /// Node changes go first, they're small and the client already
+  /// has everything around them. Then whole blocks from wanted,
+  /// in order. Blocks which aren't loaded are skipped.
+  ///
+  pub fn send(
+    &mut self,
+    map: &Map,
+    node_def_manager: &NodeDefManager,
+    wanted: &[IVec3],
+    max_packets: usize,
+    max_bytes: usize,
+  ) -> Vec<Packet> {
+    let mut packets = vec![];
+    let mut bytes = 0;
+    let has_room =
+      |packets: &Vec<Packet>, bytes: usize| packets.len() < max_packets && bytes < max_bytes;
+
+    let mut pending_blocks: Vec<IVec3> = self.pending_nodes.keys().copied().collect();
+    pending_blocks
+      .sort_by_key(|block_position| (block_position.x, block_position.y, block_position.z));
+
+    for block_position in pending_blocks {
+      if !has_room(&packets, bytes) {
+        return packets;
+      }
+
+      // It was unloaded since, the client has to get the whole thing later.
+      if !map.has_block(block_position) {
+        self.forget_block(block_position);
+        continue;
+      }
+
+      let mut pending = self
+        .pending_nodes
+        .remove(&block_position)
+        .unwrap_or_default();
+      while has_room(&packets, bytes) {
+        let position = match pending.pop() {
+          Some(position) => position,
+          None => break,
+        };
+
+        let node = map.get_node_or_ignore(position);
+        let name = node_def_manager.get_or_unknown(node.content).name.clone();
+        bytes += NODE_CHANGED_SIZE + name.len();
+        packets.push(Packet::NodeChanged {
+          position,
+          name,
+          param1: node.param1,
+          param2: node.param2,
+        });
+      }
+
+      if !pending.is_empty() {
+        self.pending_nodes.insert(block_position, pending);
+      }
+    }
+
+    for block_position in wanted {
+      if !has_room(&packets, bytes) {
+        break;
+      }
+      if self.sent_blocks.contains(block_position) {
+        continue;
+      }
+
+      let block = match map.get_block(*block_position) {
+        Some(block) => block,
+        None => continue,
+      };
+
+      let data = match serialize_block(block, node_def_manager) {
+        Ok(data) => data,
+        Err(e) => {
+          println!("BlockSender: can't send MapBlock {}. {}", block_position, e);
+          continue;
+        }
+      };
+
+      bytes += data.len();
+      packets.push(Packet::BlockData {
+        position: *block_position,
+        data,
+      });
+      self.sent_blocks.insert(*block_position);
+    }
+
+    packets
+  }
+}
+
+impl Default for BlockSender {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::{EulerRot, IVec3, Mat4, Vec3};
+
+  use crate::game::{
+    map::{
+      map_block::MapBlock,
+      node::{Node, CONTENT_UNKNOWN},
+      node_def_manager::{new_test_node_def_manager, NodeDefinition},
+      Map,
+    },
+    protocol::Packet,
+  };
+
+  use super::{look_direction, BlockSender, MAX_NODE_CHANGES_PER_BLOCK};
+
+  fn sent_blocks(packets: &[Packet]) -> Vec<IVec3> {
+    packets
+      .iter()
+      .filter_map(|packet| match packet {
+        Packet::BlockData { position, .. } => Some(*position),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_block_priority() {
+    let mut sender = BlockSender::new();
+    let position = Vec3::new(8.0, 8.0, 8.0);
+
+    // Looking down +X, the block in front beats the one behind.
+    let forward = look_direction(0.0, std::f32::consts::FRAC_PI_2);
+    assert!(forward.distance(Vec3::X) < 0.001);
+
+    let wanted = sender.prioritize(position, forward, 40.0);
+    assert_eq!(wanted[0], IVec3::ZERO);
+    let in_front = wanted
+      .iter()
+      .position(|block| *block == IVec3::new(2, 0, 0));
+    let behind = wanted
+      .iter()
+      .position(|block| *block == IVec3::new(-2, 0, 0));
+    assert!(in_front < behind);
+
+    // Nothing past viewing_range.
+    assert!(wanted.iter().all(|block| block.abs().max_element() <= 3));
+    assert!(!wanted.contains(&IVec3::new(3, 3, 3)));
+  }
+
+  #[test]
+  fn test_look_direction() {
+    // The client's camera looks down -Z in view space, after this rotation.
+    for (pitch, yaw) in [(0.0, 0.0), (0.3, 1.2), (-0.8, -2.5), (1.5, 3.0)] {
+      let rotation = Mat4::from_euler(EulerRot::XYZ, pitch, yaw, 0.0);
+      let forward = rotation.inverse().transform_vector3(Vec3::NEG_Z);
+      assert!(look_direction(pitch, yaw).distance(forward) < 0.001);
+    }
+  }
+
+  #[test]
+  fn test_block_sending() {
+    let node_def_manager = new_test_node_def_manager([NodeDefinition::new("test:stone")]);
+    let stone = Node::new(
+      node_def_manager
+        .get_id("test:stone")
+        .unwrap_or(CONTENT_UNKNOWN),
+    );
+
+    let mut map = Map::new();
+    for x in 0..3 {
+      map.insert_loaded_block(IVec3::new(x, 0, 0), MapBlock::new(Node::air()));
+    }
+
+    let mut sender = BlockSender::new();
+    let position = Vec3::new(8.0, 8.0, 8.0);
+    let wanted = sender.prioritize(position, Vec3::X, 40.0);
+
+    // Only 2 packets a tick, and only loaded blocks go out.
+    let packets = sender.send(&map, &node_def_manager, &wanted, 2, usize::MAX);
+    assert_eq!(sent_blocks(&packets), vec![IVec3::ZERO, IVec3::X]);
+    let wanted = sender.prioritize(position, Vec3::X, 40.0);
+    assert!(!wanted.contains(&IVec3::ZERO));
+
+    let packets = sender.send(&map, &node_def_manager, &wanted, 10, usize::MAX);
+    assert_eq!(sent_blocks(&packets), vec![IVec3::new(2, 0, 0)]);
+    assert_eq!(sender.get_sent_block_count(), 3);
+
+    // The byte budget lets one block through, then stops.
+    let mut other = BlockSender::new();
+    let packets = other.send(&map, &node_def_manager, &wanted, 10, 1);
+    assert_eq!(packets.len(), 1);
+
+    // After that, only what changed.
+    map.set_node(IVec3::new(1, 2, 3), stone);
+    map.set_node(IVec3::new(-100, 0, 0), stone);
+    sender.queue_changes(&map.take_changed_nodes(), &map.take_replaced_blocks());
+    assert_eq!(
+      sender.send(&map, &node_def_manager, &[], 10, usize::MAX),
+      vec![Packet::NodeChanged {
+        position: IVec3::new(1, 2, 3),
+        name: "test:stone".to_string(),
+        param1: 0,
+        param2: 0,
+      }]
+    );
+
+    // Too many changes, or a new block, and it goes out whole again.
+    for x in 0..=MAX_NODE_CHANGES_PER_BLOCK as i32 {
+      map.set_node(IVec3::new(16 + x % 16, x / 16, 0), stone);
+    }
+    map.insert_block(IVec3::new(2, 0, 0), MapBlock::new(stone));
+    sender.queue_changes(&map.take_changed_nodes(), &map.take_replaced_blocks());
+    assert_eq!(sender.get_pending_node_count(), 0);
+    assert!(!sender.has_block(IVec3::X));
+    assert!(!sender.has_block(IVec3::new(2, 0, 0)));
+    assert!(sender.has_block(IVec3::ZERO));
+
+    // Walking away forgets about them.
+    sender.prioritize(Vec3::new(1000.0, 0.0, 0.0), Vec3::X, 40.0);
+    assert_eq!(sender.get_sent_block_count(), 0);
+  }
+}
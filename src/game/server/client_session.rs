@@ -0,0 +1,168 @@
+use glam::Vec3;
+
+use crate::game::{network::PeerId, srp::SrpServer};
+
+///
+/// How long a Disconnecting session gets to deliver it's
+/// last reliable packets before it's removed, in seconds.
+///
+pub const DISCONNECT_LINGER_TIME: f64 = 5.0;
+
+///
+/// The longest a player name can be.
+///
+pub const PLAYER_NAME_MAX_LENGTH: usize = 20;
+
+///
+/// Where a client is in it's life on the server.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+  // We've heard from it, but it has not shaken hands yet.
+  Connecting,
+  // It shook hands and now has to prove who it is.
+  Authenticating,
+  // It's in the game.
+  Active,
+  // It's on the way out.
+  Disconnecting,
+}
+
+///
+/// Something which happened to a session that the Server should know about.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionEvent {
+  Join {
+    peer_id: PeerId,
+    player_name: String,
+  },
+  Leave {
+    peer_id: PeerId,
+    player_name: String,
+    timed_out: bool,
+  },
+  Move {
+    peer_id: PeerId,
+    player_name: String,
+    position: Vec3,
+    rotation: Vec3,
+  },
+}
+
+///
+/// Everything the ServerConnection knows about a single client.
+///
+pub struct ClientSession {
+  state: SessionState,
+  player_name: Option<String>,
+  time_since_last_seen: f64,
+  round_trip_time: Option<f64>,
+  disconnect_timer: f64,
+
+  // The SRP login in progress while Authenticating.
+  srp: Option<SrpServer>,
+}
+
+impl ClientSession {
+  pub fn new() -> Self {
+    ClientSession {
+      state: SessionState::Connecting,
+      player_name: None,
+      time_since_last_seen: 0.0,
+      round_trip_time: None,
+      disconnect_timer: 0.0,
+
+      srp: None,
+    }
+  }
+
+  pub fn get_state(&self) -> SessionState {
+    self.state
+  }
+
+  pub fn set_state(&mut self, state: SessionState) {
+    if state == SessionState::Disconnecting {
+      self.disconnect_timer = 0.0;
+    }
+    self.state = state;
+  }
+
+  ///
+  /// Get the name of the player, if it shook hands.
+  ///
+  pub fn get_player_name(&self) -> Option<&String> {
+    self.player_name.as_ref()
+  }
+
+  pub fn set_player_name(&mut self, player_name: String) {
+    self.player_name = Some(player_name);
+  }
+
+  ///
+  /// Get how long it's been since this client sent anything, in seconds.
+  ///
+  pub fn get_time_since_last_seen(&self) -> f64 {
+    self.time_since_last_seen
+  }
+
+  ///
+  /// The client just sent something.
+  ///
+  pub fn touch(&mut self) {
+    self.time_since_last_seen = 0.0;
+  }
+
+  ///
+  /// Get the round trip time estimate to the client in seconds.
+  ///
+  pub fn get_round_trip_time(&self) -> Option<f64> {
+    self.round_trip_time
+  }
+
+  pub fn set_round_trip_time(&mut self, round_trip_time: Option<f64>) {
+    self.round_trip_time = round_trip_time;
+  }
+
+  pub fn set_srp(&mut self, srp: SrpServer) {
+    self.srp = Some(srp);
+  }
+
+  ///
+  /// Take the SRP login in progress, it can only be used once.
+  ///
+  pub fn take_srp(&mut self) -> Option<SrpServer> {
+    self.srp.take()
+  }
+
+  ///
+  /// Tick the session timers.
+  ///
+  pub fn update(&mut self, delta: f64) {
+    self.time_since_last_seen += delta;
+
+    if self.state == SessionState::Disconnecting {
+      self.disconnect_timer += delta;
+    }
+  }
+
+  ///
+  /// Check if a Disconnecting session has waited long enough.
+  ///
+  pub fn disconnect_expired(&self) -> bool {
+    self.state == SessionState::Disconnecting && self.disconnect_timer >= DISCONNECT_LINGER_TIME
+  }
+}
+
+///
+/// Check if a player name is allowed.
+///
+/// Same rules as C++ minetest: letters, numbers, - and _.
+///
+pub fn is_valid_player_name(player_name: &str) -> bool {
+  !player_name.is_empty()
+    && player_name.len() <= PLAYER_NAME_MAX_LENGTH
+    && player_name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
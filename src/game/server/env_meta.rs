@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use crate::file_utilities::{file_exists, read_file_to_string};
+
+///
+/// C++ minetest ends the env_meta.txt settings with this line.
+///
+const END_LINE: &str = "EnvArgsEnd";
+
+///
+/// The env_meta.txt of a world, which keeps track of time.
+///
+/// The game time is how many seconds the world has been running for.
+/// LBMs remember when they first showed up, so they only go off
+/// on blocks which were last around before that.
+///
+/// Same layout as C++ minetest:
+/// ```text
+/// game_time = 1234
+/// lbm_introduction_times = mod:lbm~100;other:lbm~1200;
+/// EnvArgsEnd
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EnvMeta {
+  pub game_time: u32,
+  pub lbm_introduction_times: HashMap<String, u32>,
+}
+
+impl EnvMeta {
+  ///
+  /// Load an env_meta.txt, or start the clock for a new world.
+  ///
+  pub fn load(path: &str) -> Result<Self, String> {
+    let mut env_meta = EnvMeta::default();
+    if !file_exists(path) {
+      return Ok(env_meta);
+    }
+
+    for line in read_file_to_string(path)?.lines() {
+      let line = line.trim();
+      if line == END_LINE {
+        break;
+      }
+
+      let (key, value) = match line.split_once('=') {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => continue,
+      };
+
+      match key {
+        "game_time" => {
+          env_meta.game_time = match value.parse::<u64>() {
+            Ok(game_time) => game_time.min(u32::MAX as u64 - 1) as u32,
+            Err(e) => return Err(format!("[{}] has a bad game_time [{}]. {}", path, value, e)),
+          }
+        }
+        "lbm_introduction_times" => {
+          for entry in value.split(';').filter(|entry| !entry.is_empty()) {
+            let introduced = entry
+              .split_once('~')
+              .and_then(|(name, time)| Some((name.to_string(), time.parse::<u32>().ok()?)));
+
+            match introduced {
+              Some((name, time)) => {
+                env_meta.lbm_introduction_times.insert(name, time);
+              }
+              None => return Err(format!("[{}] has a bad LBM time [{}].", path, entry)),
+            }
+          }
+        }
+        _ => (),
+      }
+    }
+
+    Ok(env_meta)
+  }
+
+  ///
+  /// Write the env_meta.txt.
+  ///
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let mut introduction_times: Vec<(&String, &u32)> = self.lbm_introduction_times.iter().collect();
+    introduction_times.sort();
+
+    let introduction_times: String = introduction_times
+      .iter()
+      .map(|(name, time)| format!("{}~{};", name, time))
+      .collect();
+
+    let text = format!(
+      "game_time = {}\nlbm_introduction_times = {}\n{}\n",
+      self.game_time, introduction_times, END_LINE
+    );
+
+    match std::fs::write(path, text) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("failed to write [{}]. {}", path, e)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::EnvMeta;
+
+  #[test]
+  fn test_env_meta() {
+    let path = std::env::temp_dir().join(format!("env_meta_test_{}.txt", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+
+    match EnvMeta::load(&path) {
+      Ok(env_meta) => assert_eq!(env_meta, EnvMeta::default()),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    let env_meta = EnvMeta {
+      game_time: 1234,
+      lbm_introduction_times: HashMap::from([
+        ("test:convert".to_string(), 100),
+        ("test:other".to_string(), 0),
+      ]),
+    };
+    let loaded = env_meta.save(&path).and_then(|_| EnvMeta::load(&path));
+
+    // What C++ minetest writes.
+    let written = std::fs::write(
+      &path,
+      "game_time = 77\ntime_of_day = 6000\nlbm_introduction_times = mod:a~5;\nday_count = 1\nEnvArgsEnd\n",
+    );
+    let cpp_loaded = written.map(|_| EnvMeta::load(&path));
+    let _ = std::fs::remove_file(&path);
+
+    match loaded {
+      Ok(loaded) => assert_eq!(loaded, env_meta),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+    match cpp_loaded {
+      Ok(Ok(loaded)) => assert_eq!(
+        loaded,
+        EnvMeta {
+          game_time: 77,
+          lbm_introduction_times: HashMap::from([("mod:a".to_string(), 5)]),
+        }
+      ),
+      Ok(Err(e)) => panic!("Unit test is broken. {}", e),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+}
@@ -0,0 +1,224 @@
+use glam::Vec3;
+use rusqlite::Connection;
+use sea_query::{ColumnDef, Expr, Iden, OnConflict, Query, SqliteQueryBuilder, Table, Values};
+
+use crate::game::database::{execute, open_database, query, unix_time};
+
+///
+/// The player table, same names as C++ minetest's players.sqlite.
+///
+#[derive(Iden)]
+enum Player {
+  Table,
+  Name,
+  Pitch,
+  Yaw,
+  #[iden = "posX"]
+  PosX,
+  #[iden = "posY"]
+  PosY,
+  #[iden = "posZ"]
+  PosZ,
+  CreationDate,
+  ModificationDate,
+}
+
+///
+/// Everything about a player that lives on after they leave.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerData {
+  pub position: Vec3,
+  pub pitch: f32,
+  pub yaw: f32,
+}
+
+impl PlayerData {
+  pub fn new(position: Vec3) -> Self {
+    PlayerData {
+      position,
+      pitch: 0.0,
+      yaw: 0.0,
+    }
+  }
+}
+
+///
+/// The players.sqlite of a world.
+///
+pub struct PlayerDatabase {
+  database: Connection,
+}
+
+impl PlayerDatabase {
+  ///
+  /// Open (or create) the player database at a path.
+  ///
+  pub fn new(path: &str) -> Self {
+    let database = match open_database(path) {
+      Ok(database) => database,
+      Err(e) => panic!("PlayerDatabase: {}", e),
+    };
+
+    println!("PlayerDatabase: using [{}].", path);
+
+    Self::new_with_database(database)
+  }
+
+  ///
+  /// Create a player database which only lives in memory.
+  ///
+  pub fn new_in_memory() -> Self {
+    let database = match Connection::open_in_memory() {
+      Ok(database) => database,
+      Err(e) => panic!("PlayerDatabase: failed to open memory database. {}", e),
+    };
+
+    Self::new_with_database(database)
+  }
+
+  fn new_with_database(database: Connection) -> Self {
+    let new_player_database = PlayerDatabase { database };
+    new_player_database.create_tables();
+    new_player_database
+  }
+
+  fn create_tables(&self) {
+    let statement = Table::create()
+      .table(Player::Table)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(Player::Name)
+          .string()
+          .not_null()
+          .primary_key(),
+      )
+      .col(ColumnDef::new(Player::Pitch).float().not_null())
+      .col(ColumnDef::new(Player::Yaw).float().not_null())
+      .col(ColumnDef::new(Player::PosX).float().not_null())
+      .col(ColumnDef::new(Player::PosY).float().not_null())
+      .col(ColumnDef::new(Player::PosZ).float().not_null())
+      .col(
+        ColumnDef::new(Player::CreationDate)
+          .big_integer()
+          .not_null(),
+      )
+      .col(
+        ColumnDef::new(Player::ModificationDate)
+          .big_integer()
+          .not_null(),
+      )
+      .build(SqliteQueryBuilder);
+
+    if let Err(e) = execute(&self.database, (statement, Values(vec![]))) {
+      panic!("PlayerDatabase: {}", e);
+    }
+  }
+
+  ///
+  /// Save a player, creating them if they're new.
+  ///
+  pub fn save_player(&self, player_name: &str, player_data: &PlayerData) -> Result<(), String> {
+    let now = unix_time();
+
+    let statement = Query::insert()
+      .into_table(Player::Table)
+      .columns([
+        Player::Name,
+        Player::Pitch,
+        Player::Yaw,
+        Player::PosX,
+        Player::PosY,
+        Player::PosZ,
+        Player::CreationDate,
+        Player::ModificationDate,
+      ])
+      .values_panic([
+        player_name.into(),
+        player_data.pitch.into(),
+        player_data.yaw.into(),
+        player_data.position.x.into(),
+        player_data.position.y.into(),
+        player_data.position.z.into(),
+        now.into(),
+        now.into(),
+      ])
+      .on_conflict(
+        OnConflict::column(Player::Name)
+          .update_columns([
+            Player::Pitch,
+            Player::Yaw,
+            Player::PosX,
+            Player::PosY,
+            Player::PosZ,
+            Player::ModificationDate,
+          ])
+          .to_owned(),
+      )
+      .build(SqliteQueryBuilder);
+
+    execute(&self.database, statement)?;
+    Ok(())
+  }
+
+  ///
+  /// Load a player, if they've ever been saved.
+  ///
+  pub fn load_player(&self, player_name: &str) -> Result<Option<PlayerData>, String> {
+    let statement = Query::select()
+      .columns([
+        Player::Pitch,
+        Player::Yaw,
+        Player::PosX,
+        Player::PosY,
+        Player::PosZ,
+      ])
+      .from(Player::Table)
+      .and_where(Expr::col(Player::Name).eq(player_name))
+      .build(SqliteQueryBuilder);
+
+    let mut rows = query(&self.database, statement, |row| {
+      Ok(PlayerData {
+        pitch: row.get(0)?,
+        yaw: row.get(1)?,
+        position: Vec3::new(row.get(2)?, row.get(3)?, row.get(4)?),
+      })
+    })?;
+
+    Ok(rows.pop())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use glam::Vec3;
+
+  use super::{PlayerData, PlayerDatabase};
+
+  #[test]
+  fn test_player_database() {
+    let database = PlayerDatabase::new_in_memory();
+
+    match database.load_player("singleplayer") {
+      Ok(player_data) => assert_eq!(player_data, None),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    let mut player_data = PlayerData::new(Vec3::new(1.5, 20.0, -3.25));
+    if let Err(e) = database.save_player("singleplayer", &player_data) {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    // Saving again moves them.
+    player_data.position.y = 4.0;
+    player_data.yaw = 1.25;
+    if let Err(e) = database.save_player("singleplayer", &player_data) {
+      panic!("Unit test is broken. {}", e);
+    }
+
+    match database.load_player("singleplayer") {
+      Ok(loaded) => assert_eq!(loaded, Some(player_data)),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+}
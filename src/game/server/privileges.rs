@@ -0,0 +1,178 @@
+use ahash::AHashMap;
+
+///
+/// What a privilege is for.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrivilegeDefinition {
+  pub description: String,
+  // Singleplayer gets every privilege which has this on.
+  pub give_to_singleplayer: bool,
+}
+
+///
+/// Every privilege the Server knows about.
+///
+/// The engine registers the core ones, mods can register more
+/// through minetest.register_privilege().
+///
+pub struct Privileges {
+  definitions: AHashMap<String, PrivilegeDefinition>,
+}
+
+impl Privileges {
+  pub fn new() -> Self {
+    let mut new_privileges = Privileges {
+      definitions: AHashMap::new(),
+    };
+
+    new_privileges.register_core_privileges();
+
+    new_privileges
+  }
+
+  fn register_core_privileges(&mut self) {
+    let core_privileges = [
+      (
+        "server",
+        "Can do server maintenance, like shutting it down.",
+        false,
+      ),
+      ("shout", "Can speak in chat.", true),
+      ("interact", "Can interact with things.", true),
+      ("fly", "Can use fly mode.", true),
+    ];
+
+    for (name, description, give_to_singleplayer) in core_privileges {
+      let definition = PrivilegeDefinition {
+        description: description.to_string(),
+        give_to_singleplayer,
+      };
+
+      if let Err(e) = self.register(name, definition) {
+        panic!("Privileges: {}", e);
+      }
+    }
+  }
+
+  ///
+  /// Register a new privilege.
+  ///
+  pub fn register(&mut self, name: &str, definition: PrivilegeDefinition) -> Result<(), String> {
+    if !is_valid_privilege_name(name) {
+      return Err(format!("[{}] is not a valid privilege name.", name));
+    }
+
+    if self.definitions.contains_key(name) {
+      return Err(format!("[{}] is already a registered privilege.", name));
+    }
+
+    self.definitions.insert(name.to_string(), definition);
+
+    Ok(())
+  }
+
+  ///
+  /// Check if a privilege was registered.
+  ///
+  pub fn is_registered(&self, name: &str) -> bool {
+    self.definitions.contains_key(name)
+  }
+
+  ///
+  /// Get the definition of a privilege.
+  ///
+  pub fn get_definition(&self, name: &str) -> Option<&PrivilegeDefinition> {
+    self.definitions.get(name)
+  }
+
+  ///
+  /// Get the names of every registered privilege, sorted.
+  ///
+  pub fn get_names(&self) -> Vec<String> {
+    let mut names: Vec<String> = self.definitions.keys().cloned().collect();
+    names.sort();
+    names
+  }
+
+  ///
+  /// Get the names of every privilege singleplayer gets, sorted.
+  ///
+  pub fn get_singleplayer_names(&self) -> Vec<String> {
+    let mut names: Vec<String> = self
+      .definitions
+      .iter()
+      .filter(|(_, definition)| definition.give_to_singleplayer)
+      .map(|(name, _)| name.clone())
+      .collect();
+    names.sort();
+    names
+  }
+}
+
+///
+/// Check if a privilege name is allowed.
+///
+/// Same rules as player names, but lowercase only.
+///
+pub fn is_valid_privilege_name(name: &str) -> bool {
+  !name.is_empty()
+    && name
+      .chars()
+      .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+///
+/// Parse a privilege list setting like "interact, shout".
+///
+pub fn parse_privilege_list(list: &str) -> Vec<String> {
+  let mut names = vec![];
+
+  for name in list.split(',').map(|name| name.trim()) {
+    if name.is_empty() || names.iter().any(|existing| existing == name) {
+      continue;
+    }
+
+    if !is_valid_privilege_name(name) {
+      println!("Privileges: ignoring invalid privilege [{}].", name);
+      continue;
+    }
+
+    names.push(name.to_string());
+  }
+
+  names
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_privilege_list, PrivilegeDefinition, Privileges};
+
+  #[test]
+  fn test_privileges() {
+    let mut privileges = Privileges::new();
+
+    assert!(privileges.is_registered("server"));
+    assert_eq!(
+      privileges.get_singleplayer_names(),
+      vec!["fly", "interact", "shout"]
+    );
+
+    let definition = PrivilegeDefinition {
+      description: "Can teleport.".to_string(),
+      give_to_singleplayer: true,
+    };
+    assert!(privileges.register("teleport", definition.clone()).is_ok());
+    assert!(privileges.register("teleport", definition.clone()).is_err());
+    assert!(privileges.register("Not Valid", definition).is_err());
+    assert_eq!(
+      privileges.get_names(),
+      vec!["fly", "interact", "server", "shout", "teleport"]
+    );
+
+    assert_eq!(
+      parse_privilege_list(" interact,shout , ,interact, BAD"),
+      vec!["interact", "shout"]
+    );
+  }
+}
@@ -0,0 +1,436 @@
+use rusqlite::Connection;
+use sea_query::{
+  ColumnDef, Expr, Func, Iden, Index, OnConflict, Order, Query, SqliteQueryBuilder, Table, Values,
+};
+
+use crate::game::database::{execute, open_database, query, unix_time};
+
+///
+/// The auth table, same name as C++ minetest's.
+///
+#[derive(Iden)]
+enum Auth {
+  Table,
+  Id,
+  Name,
+  Salt,
+  Verifier,
+  LastLogin,
+}
+
+///
+/// The privileges of every player, same as C++ minetest's.
+///
+/// Id is the player's id in the auth table.
+///
+#[derive(Iden)]
+enum UserPrivileges {
+  Table,
+  Id,
+  Privilege,
+}
+
+///
+/// A player's row in the auth table.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthEntry {
+  pub name: String,
+  pub salt: Vec<u8>,
+  pub verifier: Vec<u8>,
+  pub last_login: i64,
+}
+
+///
+/// The Server's auth database.
+///
+/// Only SRP verifiers are stored, never passwords.
+///
+/// This also keeps track of what each player is allowed to do.
+///
+pub struct ServerAuthentication {
+  database: Connection,
+
+  // What new players get when they register.
+  default_privileges: Vec<String>,
+}
+
+impl ServerAuthentication {
+  ///
+  /// Open (or create) the auth database at a path.
+  ///
+  pub fn new(path: &str) -> Self {
+    let database = match open_database(path) {
+      Ok(database) => database,
+      Err(e) => panic!("ServerAuthentication: {}", e),
+    };
+
+    println!("ServerAuthentication: using [{}].", path);
+
+    Self::new_with_database(database)
+  }
+
+  ///
+  /// Create an auth database which only lives in memory.
+  ///
+  pub fn new_in_memory() -> Self {
+    let database = match Connection::open_in_memory() {
+      Ok(database) => database,
+      Err(e) => panic!(
+        "ServerAuthentication: failed to open memory database. {}",
+        e
+      ),
+    };
+
+    Self::new_with_database(database)
+  }
+
+  fn new_with_database(database: Connection) -> Self {
+    let new_authentication = ServerAuthentication {
+      database,
+      default_privileges: vec![],
+    };
+    new_authentication.create_tables();
+    new_authentication
+  }
+
+  ///
+  /// Borrow the underlying database.
+  ///
+  pub fn get_database(&self) -> &Connection {
+    &self.database
+  }
+
+  ///
+  /// Set the privileges new players get when they register.
+  ///
+  pub fn set_default_privileges(&mut self, default_privileges: Vec<String>) {
+    self.default_privileges = default_privileges;
+  }
+
+  fn create_tables(&self) {
+    let statement = Table::create()
+      .table(Auth::Table)
+      .if_not_exists()
+      .col(
+        ColumnDef::new(Auth::Id)
+          .integer()
+          .not_null()
+          .auto_increment()
+          .primary_key(),
+      )
+      .col(ColumnDef::new(Auth::Name).string().not_null().unique_key())
+      .col(ColumnDef::new(Auth::Salt).binary().not_null())
+      .col(ColumnDef::new(Auth::Verifier).binary().not_null())
+      .col(ColumnDef::new(Auth::LastLogin).big_integer().not_null())
+      .build(SqliteQueryBuilder);
+
+    if let Err(e) = execute(&self.database, (statement, Values(vec![]))) {
+      panic!("ServerAuthentication: {}", e);
+    }
+
+    let statement = Table::create()
+      .table(UserPrivileges::Table)
+      .if_not_exists()
+      .col(ColumnDef::new(UserPrivileges::Id).integer().not_null())
+      .col(
+        ColumnDef::new(UserPrivileges::Privilege)
+          .string()
+          .not_null(),
+      )
+      .primary_key(
+        Index::create()
+          .col(UserPrivileges::Id)
+          .col(UserPrivileges::Privilege),
+      )
+      .build(SqliteQueryBuilder);
+
+    if let Err(e) = execute(&self.database, (statement, Values(vec![]))) {
+      panic!("ServerAuthentication: {}", e);
+    }
+  }
+
+  ///
+  /// Get the id of a player in the auth table.
+  ///
+  fn get_auth_id(&self, player_name: &str) -> Option<i64> {
+    let statement = Query::select()
+      .column(Auth::Id)
+      .from(Auth::Table)
+      .and_where(Expr::col(Auth::Name).eq(player_name))
+      .build(SqliteQueryBuilder);
+
+    match query(&self.database, statement, |row| row.get::<_, i64>(0)) {
+      Ok(rows) => rows.into_iter().next(),
+      Err(e) => {
+        println!("ServerAuthentication: {}", e);
+        None
+      }
+    }
+  }
+
+  ///
+  /// Get the auth entry of a player.
+  ///
+  pub fn get_auth(&self, player_name: &str) -> Option<AuthEntry> {
+    let statement = Query::select()
+      .columns([Auth::Name, Auth::Salt, Auth::Verifier, Auth::LastLogin])
+      .from(Auth::Table)
+      .and_where(Expr::col(Auth::Name).eq(player_name))
+      .build(SqliteQueryBuilder);
+
+    let rows = query(&self.database, statement, |row| {
+      Ok(AuthEntry {
+        name: row.get(0)?,
+        salt: row.get(1)?,
+        verifier: row.get(2)?,
+        last_login: row.get(3)?,
+      })
+    });
+
+    match rows {
+      Ok(rows) => rows.into_iter().next(),
+      Err(e) => {
+        println!("ServerAuthentication: {}", e);
+        None
+      }
+    }
+  }
+
+  ///
+  /// Check if a player is registered.
+  ///
+  pub fn is_registered(&self, player_name: &str) -> bool {
+    self.get_auth(player_name).is_some()
+  }
+
+  ///
+  /// Find a registered name which only differs from this one by case.
+  ///
+  /// "Sam" and "sam" would be two different players who
+  /// look the same in chat, so this is not allowed.
+  ///
+  pub fn find_colliding_name(&self, player_name: &str) -> Option<String> {
+    let statement = Query::select()
+      .column(Auth::Name)
+      .from(Auth::Table)
+      .and_where(Expr::expr(Func::lower(Expr::col(Auth::Name))).eq(player_name.to_lowercase()))
+      .and_where(Expr::col(Auth::Name).ne(player_name))
+      .build(SqliteQueryBuilder);
+
+    match query(&self.database, statement, |row| row.get::<_, String>(0)) {
+      Ok(rows) => rows.into_iter().next(),
+      Err(e) => {
+        println!("ServerAuthentication: {}", e);
+        None
+      }
+    }
+  }
+
+  ///
+  /// Register a new player.
+  ///
+  pub fn create_auth(
+    &mut self,
+    player_name: &str,
+    salt: &[u8],
+    verifier: &[u8],
+  ) -> Result<(), String> {
+    let statement = Query::insert()
+      .into_table(Auth::Table)
+      .columns([Auth::Name, Auth::Salt, Auth::Verifier, Auth::LastLogin])
+      .values_panic([
+        player_name.into(),
+        salt.to_vec().into(),
+        verifier.to_vec().into(),
+        unix_time().into(),
+      ])
+      .build(SqliteQueryBuilder);
+
+    execute(&self.database, statement)?;
+
+    let default_privileges = self.default_privileges.clone();
+    self.set_privileges(player_name, &default_privileges)
+  }
+
+  ///
+  /// Get the privileges of a player, sorted.
+  ///
+  pub fn get_privileges(&self, player_name: &str) -> Vec<String> {
+    let id = match self.get_auth_id(player_name) {
+      Some(id) => id,
+      None => return vec![],
+    };
+
+    let statement = Query::select()
+      .column(UserPrivileges::Privilege)
+      .from(UserPrivileges::Table)
+      .and_where(Expr::col(UserPrivileges::Id).eq(id))
+      .order_by(UserPrivileges::Privilege, Order::Asc)
+      .build(SqliteQueryBuilder);
+
+    match query(&self.database, statement, |row| row.get::<_, String>(0)) {
+      Ok(rows) => rows,
+      Err(e) => {
+        println!("ServerAuthentication: {}", e);
+        vec![]
+      }
+    }
+  }
+
+  ///
+  /// Check if a player has a privilege.
+  ///
+  pub fn has_privilege(&self, player_name: &str, privilege: &str) -> bool {
+    self
+      .get_privileges(player_name)
+      .iter()
+      .any(|existing| existing == privilege)
+  }
+
+  ///
+  /// Get which of these privileges a player is missing.
+  ///
+  pub fn get_missing_privileges(&self, player_name: &str, privileges: &[String]) -> Vec<String> {
+    let existing = self.get_privileges(player_name);
+
+    privileges
+      .iter()
+      .filter(|privilege| !existing.contains(privilege))
+      .cloned()
+      .collect()
+  }
+
+  ///
+  /// Replace all the privileges of a player.
+  ///
+  pub fn set_privileges(&mut self, player_name: &str, privileges: &[String]) -> Result<(), String> {
+    let id = match self.get_auth_id(player_name) {
+      Some(id) => id,
+      None => return Err(format!("[{}] is not a registered player.", player_name)),
+    };
+
+    let statement = Query::delete()
+      .from_table(UserPrivileges::Table)
+      .and_where(Expr::col(UserPrivileges::Id).eq(id))
+      .build(SqliteQueryBuilder);
+
+    execute(&self.database, statement)?;
+
+    for privilege in privileges {
+      let statement = Query::insert()
+        .into_table(UserPrivileges::Table)
+        .columns([UserPrivileges::Id, UserPrivileges::Privilege])
+        .values_panic([id.into(), privilege.into()])
+        .on_conflict(
+          OnConflict::columns([UserPrivileges::Id, UserPrivileges::Privilege])
+            .do_nothing()
+            .to_owned(),
+        )
+        .build(SqliteQueryBuilder);
+
+      execute(&self.database, statement)?;
+    }
+
+    Ok(())
+  }
+
+  ///
+  /// Give a player some privileges on top of what they have.
+  ///
+  pub fn grant_privileges(
+    &mut self,
+    player_name: &str,
+    privileges: &[String],
+  ) -> Result<(), String> {
+    let mut combined = self.get_privileges(player_name);
+
+    for privilege in privileges {
+      if !combined.contains(privilege) {
+        combined.push(privilege.clone());
+      }
+    }
+
+    self.set_privileges(player_name, &combined)
+  }
+
+  ///
+  /// Remember when a player last logged in.
+  ///
+  pub fn record_login(&mut self, player_name: &str) {
+    let statement = Query::update()
+      .table(Auth::Table)
+      .value(Auth::LastLogin, unix_time())
+      .and_where(Expr::col(Auth::Name).eq(player_name))
+      .build(SqliteQueryBuilder);
+
+    if let Err(e) = execute(&self.database, statement) {
+      println!("ServerAuthentication: {}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ServerAuthentication;
+
+  #[test]
+  fn test_auth_database() {
+    let mut authentication = ServerAuthentication::new_in_memory();
+
+    assert!(!authentication.is_registered("sam"));
+    assert_eq!(authentication.create_auth("sam", &[1, 2], &[3, 4]), Ok(()));
+
+    let entry = match authentication.get_auth("sam") {
+      Some(entry) => entry,
+      None => panic!("Unit test is broken. sam was not registered."),
+    };
+    assert_eq!(entry.salt, vec![1, 2]);
+    assert_eq!(entry.verifier, vec![3, 4]);
+
+    // Names are unique.
+    assert!(authentication.create_auth("sam", &[5], &[6]).is_err());
+
+    // And can't collide by case.
+    assert_eq!(
+      authentication.find_colliding_name("SAM"),
+      Some("sam".to_string())
+    );
+    assert_eq!(authentication.find_colliding_name("sam"), None);
+    assert_eq!(authentication.find_colliding_name("bob"), None);
+  }
+
+  #[test]
+  fn test_privileges_database() {
+    let mut authentication = ServerAuthentication::new_in_memory();
+    authentication.set_default_privileges(vec!["shout".to_string(), "interact".to_string()]);
+
+    // New players get the defaults.
+    assert_eq!(authentication.create_auth("sam", &[1], &[2]), Ok(()));
+    assert_eq!(
+      authentication.get_privileges("sam"),
+      vec!["interact", "shout"]
+    );
+    assert!(authentication.has_privilege("sam", "shout"));
+    assert!(!authentication.has_privilege("sam", "server"));
+
+    let wanted = vec!["server".to_string(), "shout".to_string()];
+    assert_eq!(
+      authentication.get_missing_privileges("sam", &wanted),
+      vec!["server"]
+    );
+
+    assert_eq!(authentication.grant_privileges("sam", &wanted), Ok(()));
+    assert_eq!(
+      authentication.get_privileges("sam"),
+      vec!["interact", "server", "shout"]
+    );
+
+    assert_eq!(authentication.set_privileges("sam", &[]), Ok(()));
+    assert!(authentication.get_privileges("sam").is_empty());
+
+    // Only registered players have privileges.
+    assert!(authentication.set_privileges("bob", &wanted).is_err());
+    assert!(authentication.get_privileges("bob").is_empty());
+  }
+}
@@ -1,10 +1,20 @@
-use std::{net::ToSocketAddrs, time::Duration};
+use std::{cell::RefCell, rc::Rc};
 
 use ahash::AHashMap;
-use message_io::{
-  events::EventReceiver,
-  network::{Endpoint, Transport},
-  node::{self, NodeHandler, NodeTask, StoredNetEvent, StoredNodeEvent},
+
+use crate::game::{
+  network::{
+    channel_layer::ChannelLayer, loopback_transport::LoopbackTransport,
+    udp_transport::UdpTransport, NetworkTransport, PeerId,
+  },
+  protocol::Packet,
+  serial::{deserialize, serialize},
+  srp::SrpServer,
+};
+
+use super::{
+  client_session::{is_valid_player_name, ClientSession, SessionEvent, SessionState},
+  server_authentication::ServerAuthentication,
 };
 
 ///
@@ -13,159 +23,904 @@ use message_io::{
 /// This is the connection component for the Server.
 ///
 pub struct ServerConnection {
-  address: String,
-  port: i32,
+  channels: ChannelLayer,
+  sessions: AHashMap<PeerId, ClientSession>,
+
+  // Shared with the Server.
+  authentication: Rc<RefCell<ServerAuthentication>>,
 
-  task: NodeTask,
-  handler: NodeHandler<()>,
-  event_receiver: EventReceiver<StoredNodeEvent<()>>,
-  pub clients: AHashMap<Endpoint, String>,
+  // How long a client can be silent before it gets kicked, in seconds.
+  client_timeout: f64,
 
-  // Multiple shutdown requests from valid endpoints can be sent in the same tick.
+  // Multiple shutdown requests from valid peers can be sent in the same tick.
   // We want to process them all.
-  pub shutdown_requests: Vec<Endpoint>,
+  pub shutdown_requests: Vec<PeerId>,
+
+  // Players joining and leaving, the Server hands these to Lua.
+  pub session_events: Vec<SessionEvent>,
 }
 
 impl ServerConnection {
-  pub fn new(address: String, port: i32) -> Self {
-    let socket_address = match Self::get_socket(&address, port).to_socket_addrs() {
-      Ok(mut iter) => match iter.next() {
-        Some(socket_address) => socket_address,
-        None => panic!("ServerConnection: Failed to get socket address. None available."),
-      },
-      Err(e) => panic!(
-        "ServerConnection: Failed to apply address and port into socket address. {}",
+  pub fn new(
+    address: String,
+    port: i32,
+    authentication: Rc<RefCell<ServerAuthentication>>,
+    client_timeout: f64,
+  ) -> Self {
+    Self::new_with_transport(
+      Box::new(UdpTransport::listen(&address, port)),
+      authentication,
+      client_timeout,
+    )
+  }
+
+  ///
+  /// Create a ServerConnection which only talks to an in-process Client.
+  ///
+  /// This is used for singleplayer.
+  ///
+  pub fn new_loopback(
+    transport: LoopbackTransport,
+    authentication: Rc<RefCell<ServerAuthentication>>,
+    client_timeout: f64,
+  ) -> Self {
+    println!("ServerConnection: running in singleplayer loopback mode.");
+    Self::new_with_transport(Box::new(transport), authentication, client_timeout)
+  }
+
+  fn new_with_transport(
+    transport: Box<dyn NetworkTransport>,
+    authentication: Rc<RefCell<ServerAuthentication>>,
+    client_timeout: f64,
+  ) -> Self {
+    ServerConnection {
+      channels: ChannelLayer::new(transport),
+      sessions: AHashMap::new(),
+
+      authentication,
+
+      client_timeout,
+
+      shutdown_requests: vec![],
+      session_events: vec![],
+    }
+  }
+
+  ///
+  /// Get a readable address of a peer for logging.
+  ///
+  pub fn get_peer_address(&self, peer_id: PeerId) -> String {
+    self.channels.peer_address(peer_id)
+  }
+
+  ///
+  /// Get the session of a peer.
+  ///
+  pub fn get_session(&self, peer_id: PeerId) -> Option<&ClientSession> {
+    self.sessions.get(&peer_id)
+  }
+
+  ///
+  /// Get the name of an Active player.
+  ///
+  pub fn get_player_name(&self, peer_id: PeerId) -> Option<&String> {
+    match self.sessions.get(&peer_id) {
+      Some(session) if session.get_state() == SessionState::Active => session.get_player_name(),
+      _ => None,
+    }
+  }
+
+  ///
+  /// Find the peer an Active player is connected on.
+  ///
+  pub fn get_peer_by_name(&self, player_name: &str) -> Option<PeerId> {
+    self.sessions.iter().find_map(|(peer_id, session)| {
+      let is_player = session.get_state() == SessionState::Active
+        && session.get_player_name().map(|name| name.as_str()) == Some(player_name);
+
+      match is_player {
+        true => Some(*peer_id),
+        false => None,
+      }
+    })
+  }
+
+  ///
+  /// Send a Packet to a peer (ClientConnection).
+  ///
+  pub fn send_packet(&mut self, peer_id: PeerId, packet: &Packet) {
+    let data = match serialize(packet) {
+      Ok(data) => data,
+      Err(e) => {
+        println!("ServerConnection: failed to serialize {:?}. {}", packet, e);
+        return;
+      }
+    };
+
+    if let Err(e) = self
+      .channels
+      .send(peer_id, packet.channel(), packet.is_reliable(), &data)
+    {
+      println!(
+        "ServerConnection: failed to send to [{}]. {}",
+        self.get_peer_address(peer_id),
         e
-      ),
+      );
+    }
+  }
+
+  ///
+  /// Kick a peer off of the server.
+  ///
+  /// The session sticks around for a moment so the Disconnect
+  /// packet can make it to the client.
+  ///
+  pub fn disconnect_peer(&mut self, peer_id: PeerId, reason: &str) {
+    let session = match self.sessions.get_mut(&peer_id) {
+      Some(session) => session,
+      None => return,
     };
-    let transport_protocol = Transport::Udp;
 
-    let (handler, listener) = node::split::<()>();
+    if session.get_state() == SessionState::Disconnecting {
+      return;
+    }
 
-    // todo: fixme: this is udp, why are we doing a match here?
-    // todo: If this fails, the server probably doesn't have a network
-    // todo: adapter! Why is it a server?!
-    match handler.network().listen(transport_protocol, socket_address) {
-      Ok((id, real_address)) => {
-        println!(
-          "ServerConnection connection created at id [{}], real address [{}]",
-          id, real_address
-        );
+    let was_active = session.get_state() == SessionState::Active;
+    session.set_state(SessionState::Disconnecting);
+
+    if was_active {
+      if let Some(player_name) = session.get_player_name() {
+        self.session_events.push(SessionEvent::Leave {
+          peer_id,
+          player_name: player_name.clone(),
+          timed_out: false,
+        });
       }
-      Err(e) => panic!("{}", e),
     }
 
-    let (task, event_receiver) = listener.enqueue();
+    println!(
+      "ServerConnection: disconnecting [{}]. {}",
+      self.get_peer_address(peer_id),
+      reason
+    );
 
-    ServerConnection {
-      address,
-      port,
+    self.send_packet(
+      peer_id,
+      &Packet::Disconnect {
+        reason: reason.to_string(),
+      },
+    );
+  }
 
-      task,
-      handler,
-      event_receiver,
-      clients: AHashMap::new(),
+  ///
+  /// A client shook hands with us.
+  ///
+  fn handshake_reaction(&mut self, peer_id: PeerId, player_name: String) {
+    match self.sessions.get(&peer_id) {
+      Some(session) if session.get_state() == SessionState::Connecting => (),
+      // Our answer probably hasn't made it yet, it will get there.
+      _ => return,
+    }
 
-      shutdown_requests: vec![],
+    println!(
+      "ServerConnection: [{}] is shaking hands from [{}].",
+      player_name,
+      self.get_peer_address(peer_id)
+    );
+
+    if !is_valid_player_name(&player_name) {
+      self.disconnect_peer(peer_id, "Invalid player name.");
+      return;
+    }
+
+    // "Sam" and "sam" can't be joining at the same time either.
+    // A player already in the game is probably reconnecting after
+    // losing their connection, activate_session() replaces them.
+    let name_taken = self.sessions.iter().any(|(other_peer_id, session)| {
+      *other_peer_id != peer_id
+        && matches!(
+          session.get_state(),
+          SessionState::Connecting | SessionState::Authenticating
+        )
+        && session
+          .get_player_name()
+          .is_some_and(|name| name.eq_ignore_ascii_case(&player_name))
+    });
+
+    if name_taken {
+      self.disconnect_peer(peer_id, "A player with that name is already connected.");
+      return;
+    }
+
+    let (registered, colliding_name) = {
+      let authentication = self.authentication.borrow();
+      match authentication.is_registered(&player_name) {
+        true => (true, None),
+        false => (false, authentication.find_colliding_name(&player_name)),
+      }
+    };
+
+    if let Some(colliding_name) = colliding_name {
+      self.disconnect_peer(
+        peer_id,
+        &format!(
+          "Your name collides with an existing player's name: {}",
+          colliding_name
+        ),
+      );
+      return;
+    }
+
+    if let Some(session) = self.sessions.get_mut(&peer_id) {
+      session.set_player_name(player_name);
+      session.set_state(SessionState::Authenticating);
     }
+
+    self.send_packet(peer_id, &Packet::AuthRequest { registered });
   }
 
   ///
-  /// Change the address that the server connection will utilize.
+  /// Get the name of a player which is Authenticating.
   ///
-  pub fn set_address(&mut self, new_address: String) {
-    self.address = new_address;
+  fn get_authenticating_name(&self, peer_id: PeerId) -> Option<String> {
+    match self.sessions.get(&peer_id) {
+      Some(session) if session.get_state() == SessionState::Authenticating => {
+        session.get_player_name().cloned()
+      }
+      _ => None,
+    }
   }
 
   ///
-  /// Change the port that the server connection will utilize.
+  /// A new player sent the verifier for their password.
   ///
-  pub fn set_port(&mut self, new_port: i32) {
-    self.port = new_port;
+  fn auth_register_reaction(&mut self, peer_id: PeerId, salt: Vec<u8>, verifier: Vec<u8>) {
+    let player_name = match self.get_authenticating_name(peer_id) {
+      Some(player_name) => player_name,
+      None => return,
+    };
+
+    let result = {
+      let mut authentication = self.authentication.borrow_mut();
+      match authentication.is_registered(&player_name) {
+        true => Err("That player is already registered.".to_string()),
+        false => authentication.create_auth(&player_name, &salt, &verifier),
+      }
+    };
+
+    match result {
+      Ok(()) => {
+        println!("ServerConnection: registered new player [{}].", player_name);
+        self.activate_session(peer_id);
+      }
+      Err(e) => {
+        println!(
+          "ServerConnection: failed to register [{}]. {}",
+          player_name, e
+        );
+        self.disconnect_peer(peer_id, "Failed to register.");
+      }
+    }
   }
 
   ///
-  /// Construct the address & port into a parsable socket string.
+  /// A registered player started logging in.
   ///
-  pub fn get_socket(address: &str, port: i32) -> String {
-    let mut socket = address.to_owned();
-    socket.push(':');
-    socket.push_str(port.to_string().as_str());
+  fn auth_start_reaction(&mut self, peer_id: PeerId, client_public: Vec<u8>) {
+    let player_name = match self.get_authenticating_name(peer_id) {
+      Some(player_name) => player_name,
+      None => return,
+    };
+
+    let entry = self.authentication.borrow().get_auth(&player_name);
+    let entry = match entry {
+      Some(entry) => entry,
+      None => {
+        self.disconnect_peer(peer_id, "That player is not registered.");
+        return;
+      }
+    };
 
-    socket
+    let srp = match SrpServer::new(&player_name, &entry.salt, &entry.verifier, &client_public) {
+      Ok(srp) => srp,
+      Err(e) => {
+        println!("ServerConnection: [{}] {}", player_name, e);
+        self.disconnect_peer(peer_id, "Invalid login attempt.");
+        return;
+      }
+    };
+
+    let server_public = srp.get_public_ephemeral();
+
+    if let Some(session) = self.sessions.get_mut(&peer_id) {
+      session.set_srp(srp);
+    }
+
+    self.send_packet(
+      peer_id,
+      &Packet::AuthChallenge {
+        salt: entry.salt,
+        server_public,
+      },
+    );
   }
 
   ///
-  /// Send raw data to an EndPoint (ClientConnection).
+  /// A registered player proved (or failed to prove) they know the password.
   ///
-  fn send_data(&self, end_point: Endpoint, data: &str) {
-    self.handler.network().send(end_point, data.as_bytes());
+  fn auth_proof_reaction(&mut self, peer_id: PeerId, proof: Vec<u8>) {
+    let player_name = match self.get_authenticating_name(peer_id) {
+      Some(player_name) => player_name,
+      None => return,
+    };
+
+    let server_proof = self
+      .sessions
+      .get_mut(&peer_id)
+      .and_then(|session| session.take_srp())
+      .and_then(|srp| srp.verify_client_proof(&proof));
+
+    match server_proof {
+      Some(server_proof) => {
+        self.send_packet(
+          peer_id,
+          &Packet::AuthServerProof {
+            proof: server_proof,
+          },
+        );
+        self.authentication.borrow_mut().record_login(&player_name);
+        self.activate_session(peer_id);
+      }
+      None => {
+        println!(
+          "ServerConnection: [{}] gave the wrong password.",
+          player_name
+        );
+
+        // The client drops the connection on it's own when rejected.
+        self.send_packet(
+          peer_id,
+          &Packet::AuthRejected {
+            reason: "Wrong password.".to_string(),
+          },
+        );
+        if let Some(session) = self.sessions.get_mut(&peer_id) {
+          session.set_state(SessionState::Disconnecting);
+        }
+      }
+    }
   }
 
   ///
-  /// A procedure to react to a network event.
+  /// An Authenticating session made it in.
   ///
-  /// Returns if the connection received the shutdown signal from a client
+  fn activate_session(&mut self, peer_id: PeerId) {
+    let player_name = match self.get_authenticating_name(peer_id) {
+      Some(player_name) => player_name,
+      None => return,
+    };
+
+    // They got the password right, so the old session is a stale one.
+    let stale: Vec<PeerId> = self
+      .sessions
+      .iter()
+      .filter(|(other_peer_id, session)| {
+        **other_peer_id != peer_id
+          && session.get_state() == SessionState::Active
+          && session
+            .get_player_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case(&player_name))
+      })
+      .map(|(other_peer_id, _)| *other_peer_id)
+      .collect();
+
+    for other_peer_id in stale {
+      self.disconnect_peer(other_peer_id, "Logged in from somewhere else.");
+    }
+
+    let session = match self.sessions.get_mut(&peer_id) {
+      Some(session) => session,
+      None => return,
+    };
+
+    session.set_state(SessionState::Active);
+
+    if let Some(player_name) = session.get_player_name() {
+      self.session_events.push(SessionEvent::Join {
+        peer_id,
+        player_name: player_name.clone(),
+      });
+    }
+
+    self.send_packet(peer_id, &Packet::HandshakeConfirmed);
+  }
+
+  ///
+  /// A procedure to react to a network message.
   ///
-  pub fn event_reaction(&mut self, event: StoredNetEvent) {
-    // We don't need to match, we're using UDP which is connectionless.
-    if let StoredNetEvent::Message(end_point, raw_message) = event {
-      // todo: use https://github.com/serde-rs/bytes
-      let receieved_string = match String::from_utf8(raw_message) {
-        Ok(new_string) => new_string,
-        Err(_) => {
-          println!("ServerConnection message buffer attack detected, bailing on deserialization!");
-          "".to_string()
+  pub fn event_reaction(&mut self, peer_id: PeerId, raw_message: Vec<u8>) {
+    let packet = match deserialize(&raw_message) {
+      Ok(packet) => packet,
+      Err(e) => {
+        println!(
+          "ServerConnection: rejected packet from [{}]. {}",
+          self.get_peer_address(peer_id),
+          e
+        );
+        // Nothing gets kept around for a peer that never made sense.
+        if !self.sessions.contains_key(&peer_id) {
+          self.channels.remove_peer(peer_id);
+        }
+        return;
+      }
+    };
+
+    let session = self
+      .sessions
+      .entry(peer_id)
+      .or_insert_with(ClientSession::new);
+    session.touch();
+    let state = session.get_state();
+
+    // Nothing more to say to a client on the way out.
+    if state == SessionState::Disconnecting {
+      return;
+    }
+
+    match packet {
+      Packet::Handshake { player_name } => self.handshake_reaction(peer_id, player_name),
+      Packet::PingRequest => self.send_packet(peer_id, &Packet::PingConfirmation),
+      Packet::Disconnect { reason } => {
+        println!(
+          "ServerConnection: [{}] left. {}",
+          self.get_peer_address(peer_id),
+          reason
+        );
+        self.remove_session(peer_id, false);
+      }
+      Packet::AuthRegister { salt, verifier } => {
+        self.auth_register_reaction(peer_id, salt, verifier)
+      }
+      Packet::AuthStart { client_public } => self.auth_start_reaction(peer_id, client_public),
+      Packet::AuthProof { proof } => self.auth_proof_reaction(peer_id, proof),
+      // Everything past here needs a player in the game.
+      _ if state != SessionState::Active => println!(
+        "ServerConnection: [{}] sent {:?} before joining.",
+        self.get_peer_address(peer_id),
+        packet
+      ),
+      Packet::ShutdownRequest => self.shutdown_requests.push(peer_id),
+      Packet::ChatMessage { message, .. } => {
+        let player_name = match self.get_player_name(peer_id) {
+          Some(player_name) => player_name.clone(),
+          None => return,
+        };
+
+        if !self
+          .authentication
+          .borrow()
+          .has_privilege(&player_name, "shout")
+        {
+          self.send_packet(
+            peer_id,
+            &Packet::ChatMessage {
+              sender: String::new(),
+              message: "You don't have permission to shout.".to_string(),
+            },
+          );
+          return;
         }
-      };
 
-      println!("ServerConnection Server received message: {}", receieved_string);
+        println!("ServerConnection: <{}> {}", player_name, message)
+      }
+      Packet::PlayerMove { position, rotation } => {
+        if let Some(player_name) = self.get_player_name(peer_id) {
+          self.session_events.push(SessionEvent::Move {
+            peer_id,
+            player_name: player_name.clone(),
+            position,
+            rotation,
+          });
+        }
+      }
+      // These only ever go from the server to the client.
+      Packet::HandshakeConfirmed
+      | Packet::PingConfirmation
+      | Packet::AuthRequest { .. }
+      | Packet::AuthChallenge { .. }
+      | Packet::AuthServerProof { .. }
+      | Packet::AuthRejected { .. }
+      | Packet::NodeDefinitions { .. }
+      | Packet::BlockData { .. }
+      | Packet::NodeChanged { .. } => {
+        println!(
+          "ServerConnection: [{}] sent a server-only packet.",
+          self.get_peer_address(peer_id)
+        )
+      }
+    }
+  }
 
-      match receieved_string.as_str() {
-        "hi" => self.send_data(end_point, "hi there!"),
-        "MINETEST_HAND_SHAKE" => self.send_data(end_point, "MINETEST_HAND_SHAKE_CONFIRMED"),
-        "MINETEST_PING_REQUEST" => {
-          println!("ServerConnection ServerConnection got ping request, sending confirmation to ClientConnection.");
-          self.send_data(end_point, "MINETEST_PING_CONFIRMATION")
+  ///
+  /// Completely forget about a session.
+  ///
+  fn remove_session(&mut self, peer_id: PeerId, timed_out: bool) {
+    if let Some(session) = self.sessions.remove(&peer_id) {
+      if session.get_state() == SessionState::Active {
+        if let Some(player_name) = session.get_player_name() {
+          self.session_events.push(SessionEvent::Leave {
+            peer_id,
+            player_name: player_name.clone(),
+            timed_out,
+          });
         }
-        "MINETEST_SHUT_DOWN_REQUEST" => self.shutdown_requests.push(end_point),
-        _ => (),
       }
     }
+
+    self.channels.remove_peer(peer_id);
   }
 
   ///
-  /// Non-blocking event receiver for network events.
+  /// Tick every session, evicting the silent ones and
+  /// cleaning up the ones which finished disconnecting.
   ///
-  /// Returns the EndPoint (ClientConnection) that requested to shut
-  /// down the server.
-  ///
-  pub fn receive(&mut self) {
-    let mut has_new_event = true;
-
-    // We want to grind through ALL the events.
-    while has_new_event {
-      if let Some(event) = self.event_receiver.receive_timeout(Duration::new(0, 0)) {
-        match event {
-          StoredNodeEvent::Network(new_event) => {
-            self.event_reaction(new_event.clone());
-          }
-          // todo: figure out what a signal is!
-          StoredNodeEvent::Signal(_) => todo!(),
+  fn update_sessions(&mut self, delta: f64) {
+    // The ChannelLayer already gave up on these.
+    let mut timed_out: Vec<PeerId> = self
+      .channels
+      .take_timed_out_peers()
+      .into_iter()
+      .filter(|peer_id| self.sessions.contains_key(peer_id))
+      .collect();
+    let mut finished = vec![];
+
+    for (peer_id, session) in self.sessions.iter_mut() {
+      session.update(delta);
+      session.set_round_trip_time(
+        self
+          .channels
+          .get_peer_channels(*peer_id)
+          .and_then(|peer| peer.get_round_trip_time()),
+      );
+
+      if session.get_state() == SessionState::Disconnecting {
+        if session.disconnect_expired() || self.channels.is_peer_idle(*peer_id) {
+          finished.push(*peer_id);
         }
-      } else {
-        has_new_event = false;
+      } else if session.get_time_since_last_seen() >= self.client_timeout
+        && !timed_out.contains(peer_id)
+      {
+        timed_out.push(*peer_id);
       }
     }
+
+    for peer_id in timed_out {
+      println!(
+        "ServerConnection: [{}] timed out.",
+        self.get_peer_address(peer_id)
+      );
+      self.remove_session(peer_id, true);
+    }
+
+    for peer_id in finished {
+      self.remove_session(peer_id, false);
+    }
+  }
+
+  ///
+  /// Non-blocking event receiver for network events.
+  ///
+  pub fn receive(&mut self, delta: f64) {
+    self.channels.update(delta);
+    self.update_sessions(delta);
+
+    // We want to grind through ALL the messages.
+    while let Some((peer_id, raw_message)) = self.channels.receive() {
+      self.event_reaction(peer_id, raw_message);
+    }
   }
 }
 
 impl Drop for ServerConnection {
   fn drop(&mut self) {
-    // ServerConnection must stop the handler entity or the Server
-    // will not shut down.
-    println!("ServerConnection: Shutting down network handler.");
-    NodeHandler::stop(&self.handler);
     println!("ServerConnection dropped!");
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use crate::game::{
+    network::{
+      channel_layer::ChannelLayer,
+      loopback_transport::{LoopbackTransport, LOOPBACK_PEER_ID},
+    },
+    protocol::Packet,
+    serial::{deserialize, serialize},
+    server::{
+      client_session::{ClientSession, SessionEvent, SessionState},
+      server_authentication::ServerAuthentication,
+    },
+    srp::{compute_verifier, generate_salt, SrpClient},
+  };
+
+  use super::ServerConnection;
+
+  fn send(client: &mut ChannelLayer, packet: &Packet) {
+    let data = match serialize(packet) {
+      Ok(data) => data,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    if let Err(e) = client.send(
+      LOOPBACK_PEER_ID,
+      packet.channel(),
+      packet.is_reliable(),
+      &data,
+    ) {
+      panic!("Unit test is broken. {}", e);
+    }
+  }
+
+  fn receive_all(client: &mut ChannelLayer) -> Vec<Packet> {
+    let mut packets = vec![];
+    while let Some((_, data)) = client.receive() {
+      match deserialize(&data) {
+        Ok(packet) => packets.push(packet),
+        Err(e) => panic!("Unit test is broken. {}", e),
+      }
+    }
+    packets
+  }
+
+  fn new_pair(
+    authentication: Rc<RefCell<ServerAuthentication>>,
+    client_timeout: f64,
+  ) -> (ServerConnection, ChannelLayer) {
+    let (server_side, client_side) = LoopbackTransport::new_pair();
+    (
+      ServerConnection::new_loopback(server_side, authentication, client_timeout),
+      ChannelLayer::new(Box::new(client_side)),
+    )
+  }
+
+  fn new_authentication() -> Rc<RefCell<ServerAuthentication>> {
+    Rc::new(RefCell::new(ServerAuthentication::new_in_memory()))
+  }
+
+  ///
+  /// Do what a ClientConnection does to get in.
+  ///
+  /// Returns the last packets the server sent.
+  ///
+  fn join(
+    server: &mut ServerConnection,
+    client: &mut ChannelLayer,
+    player_name: &str,
+    password: &str,
+  ) -> Vec<Packet> {
+    send(
+      client,
+      &Packet::Handshake {
+        player_name: player_name.to_string(),
+      },
+    );
+    server.receive(0.1);
+
+    match receive_all(client).as_slice() {
+      [Packet::AuthRequest { registered: false }] => {
+        let salt = generate_salt();
+        let verifier = compute_verifier(player_name, password, &salt);
+        send(client, &Packet::AuthRegister { salt, verifier });
+        server.receive(0.1);
+        receive_all(client)
+      }
+      [Packet::AuthRequest { registered: true }] => {
+        let srp = SrpClient::new(player_name);
+        send(
+          client,
+          &Packet::AuthStart {
+            client_public: srp.get_public_ephemeral(),
+          },
+        );
+        server.receive(0.1);
+
+        let (salt, server_public) = match receive_all(client).as_slice() {
+          [Packet::AuthChallenge {
+            salt,
+            server_public,
+          }] => (salt.clone(), server_public.clone()),
+          packets => panic!("Unit test is broken. Got {:?}", packets),
+        };
+
+        let proof = match srp.process_challenge(password, &salt, &server_public) {
+          Ok((proof, _)) => proof,
+          Err(e) => panic!("Unit test is broken. {}", e),
+        };
+        send(client, &Packet::AuthProof { proof });
+        server.receive(0.1);
+        receive_all(client)
+      }
+      packets => packets.to_vec(),
+    }
+  }
+
+  fn get_state(server: &ServerConnection) -> Option<SessionState> {
+    server.get_session(LOOPBACK_PEER_ID).map(|s| s.get_state())
+  }
+
+  #[test]
+  fn test_session_join_and_timeout() {
+    let (mut server, mut client) = new_pair(new_authentication(), 2.0);
+
+    assert_eq!(
+      join(&mut server, &mut client, "sam", "hunter2"),
+      vec![Packet::HandshakeConfirmed]
+    );
+    assert_eq!(get_state(&server), Some(SessionState::Active));
+    assert_eq!(server.get_peer_by_name("sam"), Some(LOOPBACK_PEER_ID));
+    assert_eq!(
+      server.session_events.drain(..).collect::<Vec<_>>(),
+      vec![SessionEvent::Join {
+        peer_id: LOOPBACK_PEER_ID,
+        player_name: "sam".to_string(),
+      }]
+    );
+
+    // Pings keep it alive.
+    for _ in 0..3 {
+      send(&mut client, &Packet::PingRequest);
+      server.receive(1.0);
+    }
+    assert!(server.get_session(LOOPBACK_PEER_ID).is_some());
+
+    // Silence gets it evicted.
+    server.receive(1.0);
+    server.receive(1.0);
+    assert!(server.get_session(LOOPBACK_PEER_ID).is_none());
+    assert_eq!(
+      server.session_events.drain(..).collect::<Vec<_>>(),
+      vec![SessionEvent::Leave {
+        peer_id: LOOPBACK_PEER_ID,
+        player_name: "sam".to_string(),
+        timed_out: true,
+      }]
+    );
+  }
+
+  #[test]
+  fn test_session_rejections() {
+    let (mut server, mut client) = new_pair(new_authentication(), 30.0);
+
+    // Can't do anything before shaking hands.
+    send(&mut client, &Packet::ShutdownRequest);
+    server.receive(0.1);
+    assert!(server.shutdown_requests.is_empty());
+    assert_eq!(get_state(&server), Some(SessionState::Connecting));
+
+    assert!(matches!(
+      join(&mut server, &mut client, "not a valid name!", "").as_slice(),
+      [Packet::Disconnect { .. }]
+    ));
+    assert_eq!(get_state(&server), Some(SessionState::Disconnecting));
+    assert!(server.session_events.is_empty());
+
+    // Gets cleaned up once the Disconnect is acknowledged.
+    server.receive(0.1);
+    server.receive(0.1);
+    assert!(server.get_session(LOOPBACK_PEER_ID).is_none());
+  }
+
+  #[test]
+  fn test_authentication() {
+    let authentication = new_authentication();
+
+    // First join registers.
+    let (mut server, mut client) = new_pair(authentication.clone(), 30.0);
+    assert_eq!(
+      join(&mut server, &mut client, "sam", "hunter2"),
+      vec![Packet::HandshakeConfirmed]
+    );
+    assert!(authentication.borrow().is_registered("sam"));
+
+    // After that the password has to match.
+    let (mut server, mut client) = new_pair(authentication.clone(), 30.0);
+    assert!(matches!(
+      join(&mut server, &mut client, "sam", "hunter2").as_slice(),
+      [Packet::AuthServerProof { .. }, Packet::HandshakeConfirmed]
+    ));
+    assert_eq!(get_state(&server), Some(SessionState::Active));
+
+    let (mut server, mut client) = new_pair(authentication.clone(), 30.0);
+    assert_eq!(
+      join(&mut server, &mut client, "sam", "hunter3"),
+      vec![Packet::AuthRejected {
+        reason: "Wrong password.".to_string(),
+      }]
+    );
+    assert_eq!(get_state(&server), Some(SessionState::Disconnecting));
+    assert!(server.session_events.is_empty());
+
+    // Nobody gets to pretend to be sam by changing the case.
+    let (mut server, mut client) = new_pair(authentication.clone(), 30.0);
+    assert_eq!(
+      join(&mut server, &mut client, "SAM", "hunter2"),
+      vec![Packet::Disconnect {
+        reason: "Your name collides with an existing player's name: sam".to_string(),
+      }]
+    );
+    assert!(!authentication.borrow().is_registered("SAM"));
+  }
+
+  #[test]
+  fn test_chat_needs_shout() {
+    let authentication = new_authentication();
+    authentication
+      .borrow_mut()
+      .set_default_privileges(vec!["interact".to_string()]);
+
+    let (mut server, mut client) = new_pair(authentication.clone(), 30.0);
+    join(&mut server, &mut client, "sam", "hunter2");
+
+    let chat = Packet::ChatMessage {
+      sender: "sam".to_string(),
+      message: "hi".to_string(),
+    };
+
+    send(&mut client, &chat);
+    server.receive(0.1);
+    assert_eq!(
+      receive_all(&mut client),
+      vec![Packet::ChatMessage {
+        sender: String::new(),
+        message: "You don't have permission to shout.".to_string(),
+      }]
+    );
+
+    let privileges = vec!["shout".to_string()];
+    assert_eq!(
+      authentication
+        .borrow_mut()
+        .grant_privileges("sam", &privileges),
+      Ok(())
+    );
+
+    send(&mut client, &chat);
+    server.receive(0.1);
+    assert!(receive_all(&mut client).is_empty());
+  }
+
+  #[test]
+  fn test_reconnect_replaces_stale_session() {
+    let (mut server, mut client) = new_pair(new_authentication(), 30.0);
+
+    // Sam's old connection died, the server doesn't know yet.
+    let stale_peer_id = LOOPBACK_PEER_ID + 1;
+    let mut stale = ClientSession::new();
+    stale.set_player_name("sam".to_string());
+    stale.set_state(SessionState::Active);
+    server.sessions.insert(stale_peer_id, stale);
+
+    assert_eq!(
+      join(&mut server, &mut client, "sam", "hunter2"),
+      vec![Packet::HandshakeConfirmed]
+    );
+    assert_eq!(get_state(&server), Some(SessionState::Active));
+    assert_eq!(server.get_peer_by_name("sam"), Some(LOOPBACK_PEER_ID));
+    assert_eq!(
+      server.session_events.drain(..).collect::<Vec<_>>(),
+      vec![
+        SessionEvent::Leave {
+          peer_id: stale_peer_id,
+          player_name: "sam".to_string(),
+          timed_out: false,
+        },
+        SessionEvent::Join {
+          peer_id: LOOPBACK_PEER_ID,
+          player_name: "sam".to_string(),
+        },
+      ]
+    );
+  }
+}
@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use configparser::ini::Ini;
+
+use crate::file_utilities::{file_exists, read_file_to_string};
+
+///
+/// world.mt has no sections, so configparser puts
+/// everything into it's default section.
+///
+const DEFAULT_SECTION: &str = "default";
+
+///
+/// The world.mt of a world, which says how to load the rest of it.
+///
+/// A world folder looks like this:
+/// * world.mt - this
+/// * map.sqlite - every MapBlock
+/// * players.sqlite - where every player is
+/// * auth.sqlite - logins and privileges
+///
+/// The seed and mapgen flags are kept here so a world keeps
+/// generating the same terrain, whatever minetest.conf says later.
+///
+/// C++ minetest keeps the seed in map_meta.txt instead,
+/// so that's where it gets looked for when world.mt has none.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldMeta {
+  pub game_id: String,
+  pub seed: u64,
+  pub mg_flags: String,
+}
+
+impl WorldMeta {
+  ///
+  /// Load a world.mt, if the world has one yet.
+  ///
+  pub fn load(path: &str) -> Result<Option<Self>, String> {
+    if !file_exists(path) {
+      return Ok(None);
+    }
+
+    let mut config = Ini::new();
+    if let Err(e) = config.read(read_file_to_string(path)?) {
+      return Err(format!("[{}] is broken. {}", path, e));
+    }
+
+    let get = |key: &str| match config.get(DEFAULT_SECTION, key) {
+      Some(value) => Ok(value),
+      None => Err(format!("[{}] is missing [{}].", path, key)),
+    };
+
+    match config.get(DEFAULT_SECTION, "backend") {
+      Some(backend) if backend != "sqlite3" => {
+        return Err(format!(
+          "[{}] uses the {} backend, only sqlite3 is supported.",
+          path, backend
+        ))
+      }
+      _ => (),
+    }
+
+    let seed = match config.get(DEFAULT_SECTION, "seed") {
+      Some(seed) => seed,
+      None => Self::load_legacy_seed(path)?,
+    };
+    let seed = match seed.parse::<u64>() {
+      Ok(seed) => seed,
+      Err(e) => return Err(format!("[{}] has a bad seed [{}]. {}", path, seed, e)),
+    };
+
+    Ok(Some(WorldMeta {
+      game_id: get("gameid")?,
+      seed,
+      mg_flags: config.get(DEFAULT_SECTION, "mg_flags").unwrap_or_default(),
+    }))
+  }
+
+  ///
+  /// Get the seed out of the map_meta.txt next to a world.mt.
+  ///
+  fn load_legacy_seed(path: &str) -> Result<String, String> {
+    let map_meta_path = Path::new(path).with_file_name("map_meta.txt");
+    let map_meta_path = map_meta_path.to_string_lossy();
+
+    if !file_exists(&map_meta_path) {
+      return Err(format!("[{}] is missing [seed].", path));
+    }
+
+    let mut config = Ini::new();
+    if let Err(e) = config.read(read_file_to_string(&map_meta_path)?) {
+      return Err(format!("[{}] is broken. {}", map_meta_path, e));
+    }
+
+    match config.get(DEFAULT_SECTION, "seed") {
+      Some(seed) => Ok(seed),
+      None => Err(format!("[{}] is missing [seed].", map_meta_path)),
+    }
+  }
+
+  ///
+  /// Write the world.mt.
+  ///
+  /// The backends are always SQLite, they're written down
+  /// so C++ minetest can tell what it's looking at.
+  ///
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let text = format!(
+      "gameid = {}\nbackend = sqlite3\nplayer_backend = sqlite3\nauth_backend = sqlite3\nseed = {}\nmg_flags = {}\n",
+      self.game_id, self.seed, self.mg_flags
+    );
+
+    match std::fs::write(path, text) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(format!("failed to write [{}]. {}", path, e)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::WorldMeta;
+
+  #[test]
+  fn test_world_meta() {
+    let path = std::env::temp_dir().join(format!("world_meta_test_{}.mt", std::process::id()));
+    let path = path.to_string_lossy().to_string();
+
+    match WorldMeta::load(&path) {
+      Ok(world_meta) => assert_eq!(world_meta, None),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+
+    let world_meta = WorldMeta {
+      game_id: "minetest".to_string(),
+      seed: u64::MAX,
+      mg_flags: "caves, nodungeons".to_string(),
+    };
+    let loaded = world_meta.save(&path).and_then(|_| WorldMeta::load(&path));
+    let _ = std::fs::remove_file(&path);
+
+    match loaded {
+      Ok(loaded) => assert_eq!(loaded, Some(world_meta)),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+
+  #[test]
+  fn test_world_meta_from_cpp() {
+    let folder = std::env::temp_dir().join(format!("world_meta_cpp_test_{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir_all(&folder) {
+      panic!("Unit test is broken. {}", e);
+    }
+    let path = folder.join("world.mt").to_string_lossy().to_string();
+
+    // What C++ minetest writes.
+    let written = std::fs::write(&path, "gameid = minetest\nbackend = sqlite3\n").and_then(|_| {
+      std::fs::write(
+        folder.join("map_meta.txt"),
+        "mg_name = v7\nseed = 1234\n[end_of_params]\n",
+      )
+    });
+    if let Err(e) = written {
+      panic!("Unit test is broken. {}", e);
+    }
+    let loaded = WorldMeta::load(&path);
+
+    let leveldb = std::fs::write(&path, "gameid = minetest\nbackend = leveldb\n");
+    let leveldb_loaded = leveldb.map(|_| WorldMeta::load(&path));
+    let _ = std::fs::remove_dir_all(&folder);
+
+    match loaded {
+      Ok(loaded) => assert_eq!(
+        loaded,
+        Some(WorldMeta {
+          game_id: "minetest".to_string(),
+          seed: 1234,
+          mg_flags: String::new(),
+        })
+      ),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+    match leveldb_loaded {
+      Ok(loaded) => assert!(loaded.is_err()),
+      Err(e) => panic!("Unit test is broken. {}", e),
+    }
+  }
+}
@@ -0,0 +1,568 @@
+use std::fmt::Display;
+
+use ahash::AHashMap;
+use configparser::ini::Ini;
+
+use crate::{
+  command_line::CommandLineInterface,
+  file_utilities::{file_exists, read_file_to_string},
+};
+
+///
+/// minetest.conf has no sections, so configparser puts
+/// everything into it's default section.
+///
+const DEFAULT_SECTION: &str = "default";
+
+///
+/// Settings which only ever come from the command line.
+///
+/// These are never read from or written into minetest.conf,
+/// a password has no business sitting on disk.
+///
+const SESSION_ONLY_KEYS: [&str; 1] = ["password"];
+
+///
+/// A single typed value in the Settings registry.
+///
+/// The type of a setting is decided by the default value
+/// it was registered with. It can never change after that.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingValue {
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Text(String),
+}
+
+impl SettingValue {
+  ///
+  /// Get the human readable name of the type this value holds.
+  ///
+  pub fn type_name(&self) -> &str {
+    match self {
+      SettingValue::Bool(_) => "bool",
+      SettingValue::Int(_) => "integer",
+      SettingValue::Float(_) => "float",
+      SettingValue::Text(_) => "string",
+    }
+  }
+
+  ///
+  /// Parse raw minetest.conf text into the same type as this value.
+  ///
+  pub fn parse_same_type(&self, raw_value: &str) -> Result<SettingValue, String> {
+    let raw_value = raw_value.trim();
+
+    match self {
+      SettingValue::Bool(_) => match raw_value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(SettingValue::Bool(true)),
+        "false" | "no" | "off" | "0" => Ok(SettingValue::Bool(false)),
+        _ => Err(format!("[{}] is not a bool", raw_value)),
+      },
+      SettingValue::Int(_) => match raw_value.parse::<i64>() {
+        Ok(int) => Ok(SettingValue::Int(int)),
+        Err(e) => Err(format!("[{}] is not an integer. {}", raw_value, e)),
+      },
+      SettingValue::Float(_) => match raw_value.parse::<f64>() {
+        Ok(float) => Ok(SettingValue::Float(float)),
+        Err(e) => Err(format!("[{}] is not a float. {}", raw_value, e)),
+      },
+      SettingValue::Text(_) => Ok(SettingValue::Text(raw_value.to_string())),
+    }
+  }
+
+  ///
+  /// Check if another value holds the same type as this one.
+  ///
+  pub fn is_same_type(&self, other: &SettingValue) -> bool {
+    std::mem::discriminant(self) == std::mem::discriminant(other)
+  }
+}
+
+impl Display for SettingValue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SettingValue::Bool(bool) => write!(f, "{}", bool),
+      SettingValue::Int(int) => write!(f, "{}", int),
+      SettingValue::Float(float) => write!(f, "{}", float),
+      SettingValue::Text(text) => write!(f, "{}", text),
+    }
+  }
+}
+
+///
+/// The typed settings registry.
+///
+/// This is the engine side of minetest.conf.
+///
+/// Settings are looked up in this order:
+/// 1.) Command line overrides. (never written back into minetest.conf)
+/// 2.) Values parsed from minetest.conf or set during runtime.
+/// 3.) The registered default value.
+///
+/// Every setting the engine uses must be registered in
+/// register_engine_settings() so it's type is known.
+///
+pub struct Settings {
+  path: String,
+  config: Ini,
+
+  defaults: AHashMap<String, SettingValue>,
+  values: AHashMap<String, SettingValue>,
+  overrides: AHashMap<String, SettingValue>,
+
+  // Unknown keys and type errors found while loading.
+  problems: Vec<String>,
+}
+
+impl Settings {
+  pub fn new(path: &str) -> Self {
+    let mut new_settings = Settings {
+      path: path.to_owned(),
+      config: Ini::new(),
+
+      defaults: AHashMap::new(),
+      values: AHashMap::new(),
+      overrides: AHashMap::new(),
+
+      problems: vec![],
+    };
+
+    new_settings.register_engine_settings();
+
+    new_settings
+  }
+
+  ///
+  /// All the settings the engine knows about, with their defaults.
+  ///
+  fn register_engine_settings(&mut self) {
+    // Player & connection.
+    self.register("name", SettingValue::Text("singleplayer".to_string()));
+    self.register("address", SettingValue::Text("127.0.0.1".to_string()));
+    self.register("port", SettingValue::Int(30_001));
+    self.register("default_game", SettingValue::Text("minetest".to_string()));
+    // Only ever set with --password.
+    self.register("password", SettingValue::Text("".to_string()));
+    self.register("client_timeout", SettingValue::Float(30.0));
+    self.register(
+      "world_path",
+      SettingValue::Text("./worlds/world".to_string()),
+    );
+
+    // Map generation.
+    // An empty seed picks a random one.
+    self.register("fixed_map_seed", SettingValue::Text("".to_string()));
+    self.register("num_emerge_threads", SettingValue::Int(2));
+    // How often changed MapBlocks and players are saved, in seconds.
+    self.register("server_map_save_interval", SettingValue::Float(5.3));
+    // How often liquid flows one node further, in seconds.
+    self.register("liquid_update", SettingValue::Float(1.0));
+    // The most liquid updates in one go, the rest wait for the next one.
+    self.register("liquid_loop_max", SettingValue::Int(10000));
+    // ABMs and node timers run in the blocks this many blocks around players.
+    self.register("active_block_range", SettingValue::Int(4));
+    // Objects stay active this many blocks around players.
+    // Blocks within either range are kept loaded.
+    self.register("active_object_send_range_blocks", SettingValue::Int(8));
+    // How long ABMs, LBMs and node timers can each run for every tick, in seconds.
+    // Whatever doesn't fit waits for the next tick.
+    self.register("abm_time_budget", SettingValue::Float(0.01));
+    self.register("lbm_time_budget", SettingValue::Float(0.01));
+    self.register("nodetimer_time_budget", SettingValue::Float(0.01));
+    // MapBlocks this many nodes around a player get sent to them.
+    self.register("viewing_range", SettingValue::Int(100));
+    // The most map packets and bytes each client gets every tick.
+    // Whatever doesn't fit waits for the next tick.
+    self.register(
+      "max_simultaneous_block_sends_per_client",
+      SettingValue::Int(40),
+    );
+    self.register("max_block_send_bytes_per_client", SettingValue::Int(65_536));
+    // Turn a pass off with "no" in front, like "nodungeons".
+    self.register(
+      "mg_flags",
+      SettingValue::Text("caves, caverns, dungeons, ores, decorations".to_string()),
+    );
+
+    // Privileges.
+    self.register(
+      "default_privs",
+      SettingValue::Text("interact, shout".to_string()),
+    );
+    // The player with this name gets every privilege on a server.
+    // Empty means nobody does.
+    self.register("admin_name", SettingValue::Text("".to_string()));
+
+    // Main loop timing.
+    self.register("fps_max", SettingValue::Float(60.0));
+    self.register("tps_max", SettingValue::Float(20.0));
+
+    // vsync can be:
+    // off    - (0)
+    // on     - (1)
+    // double - (2)
+    // triple - (3)
+    self.register("vsync", SettingValue::Int(0));
+
+    // Window.
+    self.register("screen_w", SettingValue::Int(512));
+    self.register("screen_h", SettingValue::Int(512));
+
+    // Input & camera.
+    self.register("mouse_sensitivity", SettingValue::Float(0.01));
+    self.register("fov", SettingValue::Float(72.0));
+
+    // Map meshing.
+    // Merge the faces of regular nodes into bigger ones and give them
+    // smooth lighting, instead of drawing every face on it's own.
+    // Off by default until it's been tested more.
+    self.register("greedy_meshing", SettingValue::Bool(false));
+  }
+
+  ///
+  /// Register a setting with it's default value.
+  ///
+  /// If minetest.conf was already loaded, the raw value
+  /// for this key gets parsed right away.
+  ///
+  pub fn register(&mut self, key: &str, default: SettingValue) {
+    if self.defaults.contains_key(key) {
+      panic!("Settings: [{}] was registered twice.", key);
+    }
+
+    if let Some(raw_value) = self.config.get(DEFAULT_SECTION, key) {
+      self.parse_raw_value(key, &default, &raw_value);
+    }
+
+    self.defaults.insert(key.to_owned(), default);
+  }
+
+  ///
+  /// Parse a raw minetest.conf value into the values layer.
+  ///
+  /// Type errors are reported and the default is kept.
+  ///
+  fn parse_raw_value(&mut self, key: &str, default: &SettingValue, raw_value: &str) {
+    match default.parse_same_type(raw_value) {
+      Ok(value) => {
+        self.values.insert(key.to_owned(), value);
+      }
+      Err(e) => self.report_problem(format!(
+        "Settings: [{}] expects a {}, {}. Using default [{}].",
+        key,
+        default.type_name(),
+        e,
+        default
+      )),
+    }
+  }
+
+  ///
+  /// Store and print out a problem found in minetest.conf.
+  ///
+  fn report_problem(&mut self, problem: String) {
+    println!("{}", problem);
+    self.problems.push(problem);
+  }
+
+  ///
+  /// Load minetest.conf from the path this Settings was created with.
+  ///
+  /// A missing file is not an error, it will be created on save().
+  ///
+  pub fn load(&mut self) {
+    if !file_exists(&self.path) {
+      println!("Settings: [{}] does not exist. Using defaults.", &self.path);
+      return;
+    }
+
+    match read_file_to_string(&self.path) {
+      Ok(raw_config_string) => self.read_string(raw_config_string),
+      Err(e) => self.report_problem(format!("Settings: {}", e)),
+    }
+  }
+
+  ///
+  /// Parse raw minetest.conf text.
+  ///
+  pub fn read_string(&mut self, raw_config_string: String) {
+    let config_map = match self.config.read(raw_config_string) {
+      Ok(config_map) => config_map,
+      Err(e) => {
+        self.report_problem(format!("Settings: failed to parse [{}]. {}", &self.path, e));
+        return;
+      }
+    };
+
+    for (section, section_map) in config_map {
+      if section != DEFAULT_SECTION {
+        self.report_problem(format!(
+          "Settings: minetest.conf has no sections, ignoring [{}].",
+          section
+        ));
+        continue;
+      }
+
+      for (key, raw_value) in section_map {
+        if SESSION_ONLY_KEYS.contains(&key.as_str()) {
+          self.config.remove_key(DEFAULT_SECTION, &key);
+          self.report_problem(format!(
+            "Settings: [{}] can't be kept in minetest.conf, ignoring it. Use the command line.",
+            key
+          ));
+          continue;
+        }
+
+        let default = match self.defaults.get(&key) {
+          Some(default) => default.clone(),
+          None => {
+            self.report_problem(format!("Settings: unknown key [{}].", key));
+            continue;
+          }
+        };
+
+        // A key with no value is treated as a blank string.
+        let raw_value = raw_value.unwrap_or_default();
+
+        self.parse_raw_value(&key, &default, &raw_value);
+      }
+    }
+  }
+
+  ///
+  /// Merge the command line options on top of minetest.conf.
+  ///
+  /// These are not written back into minetest.conf.
+  ///
+  pub fn apply_command_line(&mut self, cli: &CommandLineInterface) {
+    if let Some(address) = &cli.address {
+      self.set_override("address", SettingValue::Text(address.clone()));
+    }
+    if let Some(port) = cli.port {
+      self.set_override("port", SettingValue::Int(port as i64));
+    }
+    if let Some(game) = &cli.game {
+      self.set_override("default_game", SettingValue::Text(game.clone()));
+    }
+    if let Some(client_name) = &cli.client_name {
+      self.set_override("name", SettingValue::Text(client_name.clone()));
+    }
+    if let Some(password) = &cli.password {
+      self.set_override("password", SettingValue::Text(password.clone()));
+    }
+  }
+
+  ///
+  /// Make sure a value can be stored into a setting.
+  ///
+  fn check_type(&self, key: &str, value: &SettingValue) -> Result<(), String> {
+    match self.defaults.get(key) {
+      Some(default) => {
+        if default.is_same_type(value) {
+          Ok(())
+        } else {
+          Err(format!(
+            "Settings: [{}] expects a {}, got a {}.",
+            key,
+            default.type_name(),
+            value.type_name()
+          ))
+        }
+      }
+      None => Err(format!("Settings: unknown key [{}].", key)),
+    }
+  }
+
+  ///
+  /// Set a setting. This will be written back into minetest.conf.
+  ///
+  pub fn set(&mut self, key: &str, value: SettingValue) -> Result<(), String> {
+    self.check_type(key, &value)?;
+
+    if SESSION_ONLY_KEYS.contains(&key) {
+      return Err(format!(
+        "Settings: [{}] can't be saved into minetest.conf.",
+        key
+      ));
+    }
+
+    self
+      .config
+      .set(DEFAULT_SECTION, key, Some(value.to_string()));
+    self.values.insert(key.to_owned(), value);
+
+    Ok(())
+  }
+
+  ///
+  /// Set a setting for this session only.
+  ///
+  pub fn set_override(&mut self, key: &str, value: SettingValue) {
+    match self.check_type(key, &value) {
+      Ok(_) => {
+        self.overrides.insert(key.to_owned(), value);
+      }
+      Err(e) => self.report_problem(e),
+    }
+  }
+
+  ///
+  /// Get the raw SettingValue of a setting.
+  ///
+  /// ! Asking for a setting that was never registered is a bug in the engine.
+  ///
+  pub fn get(&self, key: &str) -> &SettingValue {
+    if let Some(value) = self.overrides.get(key) {
+      return value;
+    }
+    if let Some(value) = self.values.get(key) {
+      return value;
+    }
+    match self.defaults.get(key) {
+      Some(value) => value,
+      None => panic!("Settings: tried to get unregistered setting [{}].", key),
+    }
+  }
+
+  ///
+  /// Get a bool setting.
+  ///
+  pub fn get_bool(&self, key: &str) -> bool {
+    match self.get(key) {
+      SettingValue::Bool(bool) => *bool,
+      other => panic!(
+        "Settings: [{}] is a {}, not a bool.",
+        key,
+        other.type_name()
+      ),
+    }
+  }
+
+  ///
+  /// Get an integer setting.
+  ///
+  pub fn get_int(&self, key: &str) -> i64 {
+    match self.get(key) {
+      SettingValue::Int(int) => *int,
+      other => panic!(
+        "Settings: [{}] is a {}, not an integer.",
+        key,
+        other.type_name()
+      ),
+    }
+  }
+
+  ///
+  /// Get a float setting.
+  ///
+  pub fn get_float(&self, key: &str) -> f64 {
+    match self.get(key) {
+      SettingValue::Float(float) => *float,
+      other => panic!(
+        "Settings: [{}] is a {}, not a float.",
+        key,
+        other.type_name()
+      ),
+    }
+  }
+
+  ///
+  /// Get a string setting.
+  ///
+  pub fn get_string(&self, key: &str) -> String {
+    match self.get(key) {
+      SettingValue::Text(text) => text.clone(),
+      other => panic!(
+        "Settings: [{}] is a {}, not a string.",
+        key,
+        other.type_name()
+      ),
+    }
+  }
+
+  ///
+  /// Get all the problems found while loading minetest.conf.
+  ///
+  pub fn get_problems(&self) -> &Vec<String> {
+    &self.problems
+  }
+
+  ///
+  /// Write minetest.conf back to disk.
+  ///
+  /// Unknown keys and values with type errors are written back untouched,
+  /// so a typo never eats a player's configuration.
+  ///
+  pub fn save(&self) {
+    match self.config.write(&self.path) {
+      Ok(_) => println!("Settings: saved [{}].", &self.path),
+      Err(e) => println!("Settings: failed to save [{}]. {}", &self.path, e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{SettingValue, Settings};
+
+  #[test]
+  fn test_settings_parsing() {
+    let mut settings = Settings::new("minetest_test.conf");
+    settings.read_string("fps_max = 144\nvsync = 1\nname = sam\n".to_string());
+
+    assert_eq!(settings.get_float("fps_max"), 144.0);
+    assert_eq!(settings.get_int("vsync"), 1);
+    assert_eq!(settings.get_string("name"), "sam");
+    // Untouched settings keep their defaults.
+    assert_eq!(settings.get_int("port"), 30_001);
+    assert!(settings.get_problems().is_empty());
+  }
+
+  #[test]
+  fn test_settings_problems() {
+    let mut settings = Settings::new("minetest_test.conf");
+    settings.read_string("fps_max = fast\nnot_a_setting = 1\n".to_string());
+
+    // The bad value falls back to the default.
+    assert_eq!(settings.get_float("fps_max"), 60.0);
+    assert_eq!(settings.get_problems().len(), 2);
+  }
+
+  #[test]
+  fn test_settings_layers() {
+    let mut settings = Settings::new("minetest_test.conf");
+    settings.read_string("port = 30005\n".to_string());
+
+    settings.set_override("port", SettingValue::Int(40_000));
+    assert_eq!(settings.get_int("port"), 40_000);
+
+    // Wrong types are rejected.
+    assert!(settings
+      .set("port", SettingValue::Text("nope".to_string()))
+      .is_err());
+    assert!(settings.set("fov", SettingValue::Float(90.0)).is_ok());
+    assert_eq!(settings.get_float("fov"), 90.0);
+  }
+
+  #[test]
+  fn test_settings_password() {
+    let mut settings = Settings::new("minetest_test.conf");
+    settings.read_string("name = sam\npassword = hunter2\n".to_string());
+
+    // It's never taken from minetest.conf, or written back into it.
+    assert_eq!(settings.get_string("password"), "");
+    assert_eq!(settings.get_problems().len(), 1);
+    assert!(settings
+      .set("password", SettingValue::Text("hunter2".to_string()))
+      .is_err());
+    assert!(!settings.config.writes().contains("hunter2"));
+    assert!(settings.config.writes().contains("sam"));
+
+    settings.set_override("password", SettingValue::Text("hunter2".to_string()));
+    assert_eq!(settings.get_string("password"), "hunter2");
+    assert!(!settings.config.writes().contains("hunter2"));
+  }
+}
@@ -0,0 +1,372 @@
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+///
+/// SRP-6a, the password authentication C++ minetest uses.
+///
+/// The server never sees the password, only a verifier made from it.
+/// Both sides prove they know the same secret without ever sending it.
+///
+/// Uses the 2048 bit group from RFC 5054 with SHA-256.
+///
+const GROUP_PRIME_HEX: &str = "\
+AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050\
+A37329CBB4A099ED8193E0757767A13DD52312AB4B03310DCD7F48A9DA04FD50\
+E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9962F0B93B8\
+55F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773B\
+CA97B43A23FB801676BD207A436C6481F1D2B9078717461A5B9D32E688F87748\
+544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52FB3786160279004E57AE6\
+AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB6\
+94B5C803D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73";
+
+const GROUP_GENERATOR: u32 = 2;
+
+// Sizes of the random values in bytes.
+pub const SALT_SIZE: usize = 16;
+const EPHEMERAL_SIZE: usize = 32;
+
+// The group prime is 2048 bits.
+const GROUP_SIZE: usize = 256;
+
+fn group_prime() -> BigUint {
+  let hex: String = GROUP_PRIME_HEX
+    .chars()
+    .filter(|c| c.is_ascii_hexdigit())
+    .collect();
+
+  match BigUint::parse_bytes(hex.as_bytes(), 16) {
+    Some(prime) => prime,
+    None => panic!("SRP: the group prime is broken."),
+  }
+}
+
+fn group_generator() -> BigUint {
+  BigUint::from(GROUP_GENERATOR)
+}
+
+fn random_bytes(size: usize) -> Vec<u8> {
+  let mut bytes = vec![0; size];
+  rand::thread_rng().fill_bytes(&mut bytes);
+  bytes
+}
+
+///
+/// Left pad a number with zeros to the size of the group prime.
+///
+fn pad(number: &BigUint) -> Vec<u8> {
+  let bytes = number.to_bytes_be();
+  let mut padded = vec![0; GROUP_SIZE.saturating_sub(bytes.len())];
+  padded.extend_from_slice(&bytes);
+  padded
+}
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+  let mut hasher = Sha256::new();
+  for part in parts {
+    hasher.update(part);
+  }
+  hasher.finalize().to_vec()
+}
+
+fn hash_to_number(parts: &[&[u8]]) -> BigUint {
+  BigUint::from_bytes_be(&hash(parts))
+}
+
+///
+/// The multiplier parameter. k = H(N | PAD(g))
+///
+fn multiplier() -> BigUint {
+  hash_to_number(&[&group_prime().to_bytes_be(), &pad(&group_generator())])
+}
+
+///
+/// The private key. x = H(s | H(I | ":" | P))
+///
+/// Names are lowercase so the verifier doesn't depend on how the
+/// player typed their name, just like C++ minetest.
+///
+fn private_key(player_name: &str, password: &str, salt: &[u8]) -> BigUint {
+  let identity = hash(&[
+    player_name.to_lowercase().as_bytes(),
+    b":",
+    password.as_bytes(),
+  ]);
+  hash_to_number(&[salt, &identity])
+}
+
+///
+/// The scrambling parameter. u = H(PAD(A) | PAD(B))
+///
+fn scrambler(client_public: &BigUint, server_public: &BigUint) -> BigUint {
+  hash_to_number(&[&pad(client_public), &pad(server_public)])
+}
+
+///
+/// The client's proof. M1 = H(H(N) xor H(g) | H(I) | s | A | B | K)
+///
+fn client_proof(
+  player_name: &str,
+  salt: &[u8],
+  client_public: &BigUint,
+  server_public: &BigUint,
+  session_key: &[u8],
+) -> Vec<u8> {
+  let prime_hash = hash(&[&group_prime().to_bytes_be()]);
+  let generator_hash = hash(&[&group_generator().to_bytes_be()]);
+  let group_hash: Vec<u8> = prime_hash
+    .iter()
+    .zip(generator_hash.iter())
+    .map(|(a, b)| a ^ b)
+    .collect();
+
+  hash(&[
+    &group_hash,
+    &hash(&[player_name.to_lowercase().as_bytes()]),
+    salt,
+    &client_public.to_bytes_be(),
+    &server_public.to_bytes_be(),
+    session_key,
+  ])
+}
+
+///
+/// The server's proof. M2 = H(A | M1 | K)
+///
+fn server_proof(client_public: &BigUint, client_proof: &[u8], session_key: &[u8]) -> Vec<u8> {
+  hash(&[&client_public.to_bytes_be(), client_proof, session_key])
+}
+
+///
+/// Compare two proofs without bailing out at the first difference.
+///
+fn proofs_match(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+///
+/// Make a fresh random salt for a new player.
+///
+pub fn generate_salt() -> Vec<u8> {
+  random_bytes(SALT_SIZE)
+}
+
+///
+/// Make the verifier the server stores for a player. v = g^x mod N
+///
+pub fn compute_verifier(player_name: &str, password: &str, salt: &[u8]) -> Vec<u8> {
+  let x = private_key(player_name, password, salt);
+  group_generator().modpow(&x, &group_prime()).to_bytes_be()
+}
+
+///
+/// The client side of an SRP login.
+///
+pub struct SrpClient {
+  player_name: String,
+  private_ephemeral: BigUint,
+  public_ephemeral: BigUint,
+}
+
+impl SrpClient {
+  pub fn new(player_name: &str) -> Self {
+    let private_ephemeral = BigUint::from_bytes_be(&random_bytes(EPHEMERAL_SIZE));
+    let public_ephemeral = group_generator().modpow(&private_ephemeral, &group_prime());
+
+    SrpClient {
+      player_name: player_name.to_string(),
+      private_ephemeral,
+      public_ephemeral,
+    }
+  }
+
+  ///
+  /// Get A, which gets sent to the server.
+  ///
+  pub fn get_public_ephemeral(&self) -> Vec<u8> {
+    self.public_ephemeral.to_bytes_be()
+  }
+
+  ///
+  /// Answer the server's challenge.
+  ///
+  /// Returns the proof to send to the server, and the proof
+  /// the server must answer with.
+  ///
+  pub fn process_challenge(
+    &self,
+    password: &str,
+    salt: &[u8],
+    server_public: &[u8],
+  ) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let prime = group_prime();
+    let server_public = BigUint::from_bytes_be(server_public);
+
+    if (&server_public % &prime) == BigUint::from(0_u32) {
+      return Err("server sent an invalid public ephemeral.".to_string());
+    }
+
+    let u = scrambler(&self.public_ephemeral, &server_public);
+    if u == BigUint::from(0_u32) {
+      return Err("scrambling parameter is zero.".to_string());
+    }
+
+    let x = private_key(&self.player_name, password, salt);
+    let k = multiplier();
+
+    // S = (B - k * g^x) ^ (a + u * x) mod N
+    let kgx = (k * group_generator().modpow(&x, &prime)) % &prime;
+    let base = ((&server_public % &prime) + &prime - kgx) % &prime;
+    let exponent = &self.private_ephemeral + u * x;
+    let secret = base.modpow(&exponent, &prime);
+
+    let session_key = hash(&[&pad(&secret)]);
+
+    let proof = client_proof(
+      &self.player_name,
+      salt,
+      &self.public_ephemeral,
+      &server_public,
+      &session_key,
+    );
+    let expected_server_proof = server_proof(&self.public_ephemeral, &proof, &session_key);
+
+    Ok((proof, expected_server_proof))
+  }
+
+  ///
+  /// Check the server really knew our verifier.
+  ///
+  pub fn verify_server_proof(expected: &[u8], received: &[u8]) -> bool {
+    proofs_match(expected, received)
+  }
+}
+
+///
+/// The server side of an SRP login.
+///
+pub struct SrpServer {
+  public_ephemeral: BigUint,
+  expected_client_proof: Vec<u8>,
+  server_proof: Vec<u8>,
+}
+
+impl SrpServer {
+  ///
+  /// Start a login with the client's public ephemeral (A).
+  ///
+  pub fn new(
+    player_name: &str,
+    salt: &[u8],
+    verifier: &[u8],
+    client_public: &[u8],
+  ) -> Result<Self, String> {
+    let prime = group_prime();
+    let client_public = BigUint::from_bytes_be(client_public);
+
+    if (&client_public % &prime) == BigUint::from(0_u32) {
+      return Err("client sent an invalid public ephemeral.".to_string());
+    }
+
+    let verifier = BigUint::from_bytes_be(verifier);
+    let private_ephemeral = BigUint::from_bytes_be(&random_bytes(EPHEMERAL_SIZE));
+
+    // B = (k * v + g^b) mod N
+    let public_ephemeral =
+      (multiplier() * &verifier + group_generator().modpow(&private_ephemeral, &prime)) % &prime;
+
+    let u = scrambler(&client_public, &public_ephemeral);
+
+    // S = (A * v^u) ^ b mod N
+    let secret =
+      ((&client_public * verifier.modpow(&u, &prime)) % &prime).modpow(&private_ephemeral, &prime);
+
+    let session_key = hash(&[&pad(&secret)]);
+
+    let expected_client_proof = client_proof(
+      player_name,
+      salt,
+      &client_public,
+      &public_ephemeral,
+      &session_key,
+    );
+    let server_proof = server_proof(&client_public, &expected_client_proof, &session_key);
+
+    Ok(SrpServer {
+      public_ephemeral,
+      expected_client_proof,
+      server_proof,
+    })
+  }
+
+  ///
+  /// Get B, which gets sent to the client.
+  ///
+  pub fn get_public_ephemeral(&self) -> Vec<u8> {
+    self.public_ephemeral.to_bytes_be()
+  }
+
+  ///
+  /// Check the client's proof.
+  ///
+  /// Returns the server's proof if the client knew the password.
+  ///
+  pub fn verify_client_proof(&self, proof: &[u8]) -> Option<Vec<u8>> {
+    match proofs_match(&self.expected_client_proof, proof) {
+      true => Some(self.server_proof.clone()),
+      false => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{compute_verifier, generate_salt, SrpClient, SrpServer};
+
+  fn login(registered_password: &str, login_password: &str) -> bool {
+    let salt = generate_salt();
+    let verifier = compute_verifier("Sam", registered_password, &salt);
+
+    // The name's case doesn't matter.
+    let client = SrpClient::new("sam");
+    let server = match SrpServer::new("SAM", &salt, &verifier, &client.get_public_ephemeral()) {
+      Ok(server) => server,
+      Err(e) => panic!("Unit test is broken. {}", e),
+    };
+
+    let (proof, expected_server_proof) =
+      match client.process_challenge(login_password, &salt, &server.get_public_ephemeral()) {
+        Ok(proofs) => proofs,
+        Err(e) => panic!("Unit test is broken. {}", e),
+      };
+
+    match server.verify_client_proof(&proof) {
+      Some(server_proof) => {
+        assert!(SrpClient::verify_server_proof(
+          &expected_server_proof,
+          &server_proof
+        ));
+        true
+      }
+      None => false,
+    }
+  }
+
+  #[test]
+  fn test_srp_login() {
+    assert!(login("hunter2", "hunter2"));
+    assert!(login("", ""));
+    assert!(!login("hunter2", "hunter3"));
+    assert!(!login("", "hunter2"));
+  }
+
+  #[test]
+  fn test_srp_rejects_zero_ephemeral() {
+    let salt = generate_salt();
+    let verifier = compute_verifier("sam", "pass", &salt);
+    assert!(SrpServer::new("sam", &salt, &verifier, &[0]).is_err());
+
+    let client = SrpClient::new("sam");
+    assert!(client.process_challenge("pass", &salt, &[0]).is_err());
+  }
+}